@@ -0,0 +1,294 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{bail, ensure, Result};
+use image::{ImageBuffer, Luma, Rgb, RgbImage};
+use std::{fmt, path::Path};
+use terrain::tile::{TilePixelFormat, TILE_PHYSICAL_SIZE, TILE_SAMPLES};
+
+const SIDE: usize = TILE_PHYSICAL_SIZE;
+
+/// The samples of one tile.
+pub enum DecodedTile {
+    Heights(Vec<i16>),
+    Normals(Vec<[i16; 2]>),
+    Colors(RgbImage),
+}
+
+impl DecodedTile {
+    /// Version 1 layer packs do not say what kind of data they hold, but each kind had a
+    /// different size, so without a format we can tell from the size of the data.
+    pub fn from_bytes(data: &[u8], format: Option<TilePixelFormat>) -> Result<Self> {
+        let format = match format {
+            Some(format) => format,
+            None => match data.len() / (SIDE * SIDE) {
+                2 => TilePixelFormat::R16Sint,
+                3 => TilePixelFormat::Rgb8Unorm,
+                4 => TilePixelFormat::Rg16Sint,
+                _ => bail!(
+                    "tile of {} bytes is not heights, normals, or colors",
+                    data.len()
+                ),
+            },
+        };
+        ensure!(
+            data.len() == SIDE * SIDE * format.bytes_per_pixel(),
+            "tile of {} bytes is not {:?}",
+            data.len(),
+            format
+        );
+        // Tiles are written straight from memory, so are in native byte order.
+        let words = || {
+            data.chunks_exact(2)
+                .map(|w| i16::from_ne_bytes([w[0], w[1]]))
+        };
+        Ok(match format {
+            TilePixelFormat::R16Sint => Self::Heights(words().collect()),
+            TilePixelFormat::Rg16Sint => {
+                let words = words().collect::<Vec<_>>();
+                Self::Normals(words.chunks_exact(2).map(|n| [n[0], n[1]]).collect())
+            }
+            TilePixelFormat::Rgb8Unorm | TilePixelFormat::Rgba8Unorm => Self::Colors(
+                RgbImage::from_raw(
+                    SIDE as u32,
+                    SIDE as u32,
+                    format.convert(data, TilePixelFormat::Rgb8Unorm)?,
+                )
+                .expect("color tile size"),
+            ),
+        })
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Heights(_) => "heights",
+            Self::Normals(_) => "normals",
+            Self::Colors(_) => "colors",
+        }
+    }
+
+    /// The values of each channel of the sample at the given row (south up) and column.
+    pub fn channels(&self, row: usize, col: usize) -> Vec<i32> {
+        match self {
+            Self::Heights(v) => vec![v[row * SIDE + col] as i32],
+            Self::Normals(v) => {
+                let n = v[row * SIDE + col];
+                vec![n[0] as i32, n[1] as i32]
+            }
+            Self::Colors(img) => img
+                .get_pixel(col as u32, row as u32)
+                .0
+                .iter()
+                .map(|&c| c as i32)
+                .collect(),
+        }
+    }
+
+    /// Save as a PNG, north up. Heights are scaled so that 0..max_height fills the range.
+    pub fn save_png(&self, max_height: i16, path: &Path) -> Result<()> {
+        match self {
+            Self::Heights(v) => {
+                let high = max_height.max(1) as f32;
+                let pic: ImageBuffer<Luma<u8>, Vec<u8>> =
+                    ImageBuffer::from_fn(SIDE as u32, SIDE as u32, |x, y| {
+                        let h = v[(SIDE - y as usize - 1) * SIDE + x as usize];
+                        Luma([(h.max(0) as f32 / high * 255f32) as u8])
+                    });
+                pic.save(path)?;
+            }
+            Self::Normals(v) => {
+                let pic: ImageBuffer<Rgb<u8>, Vec<u8>> =
+                    ImageBuffer::from_fn(SIDE as u32, SIDE as u32, |x, y| {
+                        let n = v[(SIDE - y as usize - 1) * SIDE + x as usize];
+                        Rgb([((n[0] / 256) + 128) as u8, ((n[1] / 256) + 128) as u8, 0])
+                    });
+                pic.save(path)?;
+            }
+            Self::Colors(img) => {
+                let pic: RgbImage = ImageBuffer::from_fn(SIDE as u32, SIDE as u32, |x, y| {
+                    *img.get_pixel(x, SIDE as u32 - y - 1)
+                });
+                pic.save(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Only count the samples that belong to the tile; the outer ring duplicates its neighbors.
+fn own_samples() -> impl Iterator<Item = (usize, usize)> {
+    (1..=TILE_SAMPLES as usize)
+        .flat_map(|row| (1..=TILE_SAMPLES as usize).map(move |col| (row, col)))
+}
+
+/// Per channel extremes and mean over a set of tiles.
+#[derive(Debug, Default)]
+pub struct TileStats {
+    kind: Option<&'static str>,
+    tile_count: usize,
+    sample_count: u64,
+    min: Vec<i32>,
+    max: Vec<i32>,
+    sum: Vec<i64>,
+}
+
+impl TileStats {
+    pub fn add(&mut self, tile: &DecodedTile) -> Result<()> {
+        if let Some(kind) = self.kind {
+            if kind != tile.kind() {
+                bail!("layer pack mixes {} and {} tiles", kind, tile.kind());
+            }
+        }
+        self.kind = Some(tile.kind());
+        self.tile_count += 1;
+        for (row, col) in own_samples() {
+            let channels = tile.channels(row, col);
+            if self.sum.is_empty() {
+                self.min = vec![i32::MAX; channels.len()];
+                self.max = vec![i32::MIN; channels.len()];
+                self.sum = vec![0; channels.len()];
+            }
+            for (i, &v) in channels.iter().enumerate() {
+                self.min[i] = self.min[i].min(v);
+                self.max[i] = self.max[i].max(v);
+                self.sum[i] += v as i64;
+            }
+            self.sample_count += 1;
+        }
+        Ok(())
+    }
+
+    /// The largest height seen, for scaling height images.
+    pub fn max_height(&self) -> Option<i16> {
+        if self.kind == Some("heights") {
+            Some(self.max[0] as i16)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for TileStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            Some(kind) => kind,
+            None => return write!(f, "no tiles"),
+        };
+        writeln!(
+            f,
+            "{} tiles of {}, {} samples",
+            self.tile_count, kind, self.sample_count
+        )?;
+        let names: &[&str] = match kind {
+            "heights" => &["elevation (m)"],
+            "normals" => &["normal lat", "normal lon"],
+            _ => &["red", "green", "blue"],
+        };
+        for (i, name) in names.iter().enumerate() {
+            writeln!(
+                f,
+                "  {:>14}: min {:>6}, max {:>6}, mean {:.02}",
+                name,
+                self.min[i],
+                self.max[i],
+                self.sum[i] as f64 / self.sample_count as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// How two tiles with the same base differ, if at all.
+pub fn diff_tiles(a: &DecodedTile, b: &DecodedTile) -> Option<String> {
+    if a.kind() != b.kind() {
+        return Some(format!("{} vs {}", a.kind(), b.kind()));
+    }
+    let mut count = 0;
+    let mut max_delta = 0;
+    for row in 0..SIDE {
+        for col in 0..SIDE {
+            let delta = a
+                .channels(row, col)
+                .iter()
+                .zip(b.channels(row, col).iter())
+                .map(|(x, y)| (x - y).abs())
+                .max()
+                .unwrap_or(0);
+            if delta > 0 {
+                count += 1;
+                max_delta = max_delta.max(delta);
+            }
+        }
+    }
+    if count == 0 {
+        None
+    } else {
+        Some(format!(
+            "{} samples differ, by at most {}",
+            count, max_delta
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_and_stats() -> Result<()> {
+        let mut heights = vec![0i16; SIDE * SIDE];
+        heights[SIDE + 1] = -20;
+        heights[2 * SIDE + 2] = 100;
+        // Outside of the tile's own samples, so should not count.
+        heights[0] = 1000;
+        let data = heights
+            .iter()
+            .flat_map(|h| h.to_ne_bytes())
+            .collect::<Vec<u8>>();
+        let tile = DecodedTile::from_bytes(&data, None)?;
+        assert_eq!(tile.kind(), "heights");
+        let mut stats = TileStats::default();
+        stats.add(&tile)?;
+        assert_eq!(stats.max_height(), Some(100));
+        assert_eq!(stats.min, vec![-20]);
+
+        let mut other = heights.clone();
+        other[SIDE + 1] = 0;
+        let other_data = other
+            .iter()
+            .flat_map(|h| h.to_ne_bytes())
+            .collect::<Vec<u8>>();
+        let other = DecodedTile::from_bytes(&other_data, Some(TilePixelFormat::R16Sint))?;
+        assert!(diff_tiles(&tile, &tile).is_none());
+        assert_eq!(
+            diff_tiles(&tile, &other).as_deref(),
+            Some("1 samples differ, by at most 20")
+        );
+
+        assert!(DecodedTile::from_bytes(&data[1..], None).is_err());
+        assert_eq!(
+            DecodedTile::from_bytes(&vec![0u8; SIDE * SIDE * 3], None)?.kind(),
+            "colors"
+        );
+        assert_eq!(
+            DecodedTile::from_bytes(
+                &vec![0u8; SIDE * SIDE * 4],
+                Some(TilePixelFormat::Rgba8Unorm)
+            )?
+            .kind(),
+            "colors"
+        );
+        Ok(())
+    }
+}
@@ -12,11 +12,20 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
-use anyhow::Result;
-use memmap::MmapOptions;
-use std::{fs::File, mem, path::PathBuf};
+mod decode;
+
+use crate::decode::{diff_tiles, DecodedTile, TileStats};
+use absolute_unit::{arcseconds, ArcSeconds};
+use anyhow::{bail, Result};
+use geodesy::GeoBox;
+use image::{ImageBuffer, Luma};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
 use structopt::StructOpt;
-use terrain::tile::{ChildIndex, LayerPackHeader, LayerPackIndexItem};
+use terrain::tile::{ChildIndex, LayerPackEntry, LayerPackFile, TerrainLevel};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "dump-layer-pack", about = "Show the contents of layer packs.")]
@@ -29,63 +38,250 @@ struct Opt {
     #[structopt(long)]
     longitude: Option<i32>,
 
+    /// Only look at tiles touching this box, given as south,west,north,east in degrees.
+    #[structopt(long)]
+    region: Option<GeoBox>,
+
     /// Dump detailed tile info for all tiles printed.
     #[structopt(short, long)]
     dump_tile: bool,
 
+    /// With --dump-tile, hex dump the tile as stored, rather than decoding it.
+    #[structopt(long)]
+    raw: bool,
+
+    /// Save each tile printed as a PNG in this directory.
+    #[structopt(long)]
+    export_png: Option<PathBuf>,
+
+    /// Print the range and mean of the samples in all tiles printed.
+    #[structopt(short, long)]
+    stats: bool,
+
+    /// Save an image of the level with a pixel for each tile, lit where the tile exists.
+    #[structopt(long)]
+    coverage: Option<PathBuf>,
+
+    /// Compare the tiles printed against those in another layer pack.
+    #[structopt(long)]
+    diff: Option<PathBuf>,
+
     /// Layer pack file to look at.
     #[structopt(parse(from_os_str))]
     input: PathBuf,
 }
 
-fn main() -> Result<()> {
-    let opt = Opt::from_args();
+// The box of degrees covered by a tile in the pack.
+fn entry_bounds(entry: &LayerPackEntry, extent_as: i32) -> GeoBox {
+    GeoBox::from_corner(
+        arcseconds!(entry.base.0),
+        arcseconds!(entry.base.1),
+        arcseconds!(extent_as),
+    )
+}
 
-    let fp = File::open(&opt.input)?;
-    let mmap = unsafe { MmapOptions::new().map(&fp)? };
-    let header = LayerPackHeader::overlay(&mmap[0..mem::size_of::<LayerPackHeader>()])?;
+fn select_entries(opt: &Opt, pack: &LayerPackFile) -> Vec<LayerPackEntry> {
+    pack.entries()
+        .iter()
+        .filter(|entry| {
+            opt.latitude.is_none_or(|lat| entry.base.0 == lat)
+                && opt.longitude.is_none_or(|lon| entry.base.1 == lon)
+                && opt.region.is_none_or(|region| {
+                    region.intersects(&entry_bounds(entry, pack.angular_extent_as()))
+                })
+        })
+        .cloned()
+        .collect()
+}
 
-    println!("version: {}", header.version());
-    println!("level: {}", header.tile_level());
+fn print_tile(pack: &mut LayerPackFile, entry: &LayerPackEntry, raw: bool) -> Result<()> {
     println!(
-        "extent: {:.02} degrees ({} as)",
-        header.angular_extent_as() as f64 / 3_600f64,
-        header.angular_extent_as(),
+        "    stored: {} bytes at {}",
+        entry.extent.end - entry.extent.start,
+        entry.extent.start
     );
-    println!("tiles: {}", header.tile_count());
+    if let Some(checksum) = entry.checksum {
+        println!("    checksum: {:08X}", checksum);
+    }
+    if raw {
+        for b in pack.read_tile(entry)? {
+            println!("  {:02X}", b);
+        }
+        return Ok(());
+    }
+    let tile = DecodedTile::from_bytes(&pack.decode_tile(entry)?, pack.pixel_format())?;
+    let mut stats = TileStats::default();
+    stats.add(&tile)?;
+    for line in stats.to_string().lines() {
+        println!("    {}", line);
+    }
+    Ok(())
+}
+
+// Each level of the tree covers the same square of tile space, so a level is a square of
+// 2^level tiles on a side. Note that the planet only covers part of the square.
+fn write_coverage(pack: &LayerPackFile, path: &Path) -> Result<()> {
+    let level = pack.terrain_level().offset();
+    let side = 1u32 << level;
+    let base = TerrainLevel::base();
+    let base_lat = base.lat::<ArcSeconds>().round() as i64;
+    let base_lon = base.lon::<ArcSeconds>().round() as i64;
+    let extent = pack.angular_extent_as() as i64;
+    let mut pic: ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::new(side, side);
+    for entry in pack.entries() {
+        let y = (entry.base.0 as i64 - base_lat) / extent;
+        let x = (entry.base.1 as i64 - base_lon) / extent;
+        if x < 0 || y < 0 || x >= side as i64 || y >= side as i64 {
+            println!("tile {:?} is outside of level {}", entry.base, level);
+            continue;
+        }
+        pic.put_pixel(x as u32, side - y as u32 - 1, Luma([255]));
+    }
+    pic.save(path)?;
     println!(
-        "index: {} bytes",
-        header.tile_start() - header.index_start()
+        "coverage: {} of {} tiles in {:?}",
+        pack.entries().len(),
+        side as u64 * side as u64,
+        path
     );
-    let items = LayerPackIndexItem::overlay_slice(
-        &mmap[header.index_start() as usize..header.tile_start() as usize],
-    )?;
-    for item in items {
-        if let Some(latitude) = opt.latitude {
-            if item.base_lat_as() != latitude {
-                continue;
+    Ok(())
+}
+
+fn diff_packs(
+    pack: &mut LayerPackFile,
+    entries: &[LayerPackEntry],
+    other_path: &Path,
+    opt: &Opt,
+) -> Result<()> {
+    let mut other = LayerPackFile::open(other_path)?;
+    let mut difference_count = 0;
+    if pack.terrain_level().offset() != other.terrain_level().offset()
+        || pack.angular_extent_as() != other.angular_extent_as()
+    {
+        println!(
+            "diff: level {} ({} as) vs level {} ({} as)",
+            pack.terrain_level().offset(),
+            pack.angular_extent_as(),
+            other.terrain_level().offset(),
+            other.angular_extent_as()
+        );
+        difference_count += 1;
+    }
+    let other_entries = select_entries(opt, &other)
+        .into_iter()
+        .map(|entry| (entry.base, entry))
+        .collect::<HashMap<_, _>>();
+    for entry in entries {
+        match other_entries.get(&entry.base) {
+            None => {
+                println!("diff: {:?} only in {:?}", entry.base, opt.input);
+                difference_count += 1;
             }
-        }
-        if let Some(longitude) = opt.longitude {
-            if item.base_lon_as() != longitude {
-                continue;
+            Some(other_entry) => {
+                let a = DecodedTile::from_bytes(&pack.decode_tile(entry)?, pack.pixel_format())?;
+                let b = DecodedTile::from_bytes(
+                    &other.decode_tile(other_entry)?,
+                    other.pixel_format(),
+                )?;
+                if let Some(msg) = diff_tiles(&a, &b) {
+                    println!("diff: {:?} {}", entry.base, msg);
+                    difference_count += 1;
+                }
+                if entry.index_in_parent != other_entry.index_in_parent {
+                    println!(
+                        "diff: {:?} is child {} vs {}",
+                        entry.base, entry.index_in_parent, other_entry.index_in_parent
+                    );
+                    difference_count += 1;
+                }
             }
         }
+    }
+    let bases = entries.iter().map(|entry| entry.base).collect::<Vec<_>>();
+    for base in other_entries.keys() {
+        if !bases.contains(base) {
+            println!("diff: {:?} only in {:?}", base, other_path);
+            difference_count += 1;
+        }
+    }
+    if difference_count > 0 {
+        bail!("found {} differences", difference_count);
+    }
+    println!("diff: {} tiles are identical", entries.len());
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+
+    let mut pack = LayerPackFile::open(&opt.input)?;
+
+    println!("version: {}", pack.version());
+    println!("level: {}", pack.terrain_level().offset());
+    println!(
+        "extent: {:.02} degrees ({} as)",
+        pack.angular_extent_as() as f64 / 3_600f64,
+        pack.angular_extent_as(),
+    );
+    println!("compression: {:?}", pack.tile_compression());
+    if let Some(format) = pack.pixel_format() {
+        println!("format: {:?}", format);
+    }
+    if let Some((lo, hi)) = pack.height_range() {
+        println!("heights: {} to {} m", lo, hi);
+    }
+    println!("tiles: {}", pack.entries().len());
+
+    let entries = select_entries(&opt, &pack);
+    for entry in &entries {
         println!(
             "  {:>10}, {:>10}: {:?}",
-            item.base_lat_as(),
-            item.base_lon_as(),
-            ChildIndex::from_index(item.index_in_parent() as usize)
+            entry.base.0,
+            entry.base.1,
+            ChildIndex::from_index(entry.index_in_parent as usize)
         );
         if opt.dump_tile {
-            let st = item.tile_start();
-            let ed = item.tile_end();
-            let data = &mmap[st as usize..ed as usize];
-            for b in data {
-                println!("  {:02X}", b);
-            }
+            print_tile(&mut pack, entry, opt.raw)?;
         }
     }
 
+    // Heights are scaled to the highest point across all tiles, so that exports line up.
+    let mut stats = TileStats::default();
+    if opt.stats || opt.export_png.is_some() {
+        for entry in &entries {
+            stats.add(&DecodedTile::from_bytes(
+                &pack.decode_tile(entry)?,
+                pack.pixel_format(),
+            )?)?;
+        }
+    }
+    if opt.stats {
+        print!("{}", stats);
+    }
+
+    if let Some(directory) = opt.export_png.as_ref() {
+        fs::create_dir_all(directory)?;
+        let max_height = stats.max_height().unwrap_or(i16::MAX);
+        for entry in &entries {
+            let path = directory.join(format!(
+                "L{:02}_{}_{}.png",
+                pack.terrain_level().offset(),
+                entry.base.0,
+                entry.base.1
+            ));
+            DecodedTile::from_bytes(&pack.decode_tile(entry)?, pack.pixel_format())?
+                .save_png(max_height, &path)?;
+        }
+        println!("exported {} tiles to {:?}", entries.len(), directory);
+    }
+
+    if let Some(path) = opt.coverage.as_ref() {
+        write_coverage(&pack, path)?;
+    }
+
+    if let Some(other_path) = opt.diff.as_ref() {
+        diff_packs(&mut pack, &entries, other_path, &opt)?;
+    }
+
     Ok(())
 }
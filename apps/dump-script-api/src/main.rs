@@ -0,0 +1,79 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::Result;
+use nitrous::{script_api_markdown, ScriptApi};
+use std::{fs, path::PathBuf};
+use structopt::StructOpt;
+
+// Scriptable types register themselves when their crate is linked; make sure that
+// every crate with a scripting surface is.
+use animate as _;
+use camera as _;
+use event_mapper as _;
+use global_data as _;
+use gpu as _;
+use input as _;
+use marker as _;
+use measure as _;
+use orrery as _;
+use runtime as _;
+use terrain as _;
+use tracelog as _;
+use vehicle as _;
+use widget as _;
+use window as _;
+use world as _;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "dump-script-api",
+    about = "Write a Markdown reference of every scriptable resource and component."
+)]
+struct Opt {
+    /// Write to this file instead of stdout.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Only list type names and method signatures.
+    #[structopt(short, long)]
+    summary: bool,
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+
+    let out = if opt.summary {
+        let mut out = String::new();
+        for api in ScriptApi::collect() {
+            out += &format!("{:?} {} ({})\n", api.kind, api.type_name, api.module_path);
+            for prop in &api.properties {
+                out += &format!("    {}: {}\n", prop.name, prop.ty);
+            }
+            for method in &api.methods {
+                out += &format!("    {}\n", method.signature());
+            }
+        }
+        out
+    } else {
+        script_api_markdown()
+    };
+
+    if let Some(path) = &opt.output {
+        fs::write(path, out)?;
+    } else {
+        print!("{}", out);
+    }
+    Ok(())
+}
@@ -12,20 +12,27 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
-use crate::mip::{DataSource, Region};
+use crate::mip::{DataSource, Region, SourceInput};
 use absolute_unit::{degrees, ArcSeconds, Degrees};
 use anyhow::Result;
-use geodesy::{GeoSurface, Graticule};
+use geodesy::{GeoBox, GeoSurface, Graticule};
 use image::{open, EncodableLayout, ImageBuffer, Rgb};
 use memmap::{Mmap, MmapOptions};
 use parking_lot::RwLock;
-use std::{fs::File, io::Write, ops::RangeInclusive, path::Path, sync::Arc};
+use std::{
+    fs::File,
+    io::Write,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use terrain::tile::TerrainLevel;
 
 type MmapRgbImage = ImageBuffer<Rgb<u8>, Mmap>;
 
 pub struct Index {
     raw: Vec<Vec<MmapRgbImage>>,
+    raw_paths: Vec<Vec<PathBuf>>,
 }
 
 impl Index {
@@ -33,8 +40,10 @@ impl Index {
 
     pub fn from_directory(month: i32, directory: &Path) -> Result<Arc<RwLock<Self>>> {
         let mut raw = Vec::new();
+        let mut raw_paths = Vec::new();
         for (_lon, lon_name) in "ABCD".chars().enumerate() {
             let mut inner = Vec::new();
+            let mut inner_paths = Vec::new();
             for (_lat, lat_name) in "21".chars().enumerate() {
                 let raw_filename = format!(
                     "world.2004{:02}.3x21600x21600.{}{}.raw",
@@ -58,10 +67,12 @@ impl Index {
                 let buf =
                     ImageBuffer::from_raw(Self::TILE_SIZE, Self::TILE_SIZE, raw_mmap).unwrap();
                 inner.push(buf);
+                inner_paths.push(raw_path);
             }
             raw.push(inner);
+            raw_paths.push(inner_paths);
         }
-        Ok(Arc::new(RwLock::new(Self { raw })))
+        Ok(Arc::new(RwLock::new(Self { raw, raw_paths })))
     }
 }
 
@@ -130,4 +141,20 @@ impl DataSource for Index {
         let lat_off = Self::TILE_SIZE - (lat_px as u32 % Self::TILE_SIZE) - 1;
         *self.raw[lon_img as usize][lat_img as usize].get_pixel(lon_off, lat_off)
     }
+
+    fn inputs(&self) -> Vec<SourceInput> {
+        // Each image covers 90 degrees square; columns west to east, south half first.
+        let mut inputs = Vec::new();
+        for (lon_img, paths) in self.raw_paths.iter().enumerate() {
+            for (lat_img, path) in paths.iter().enumerate() {
+                let south = -90. + 90. * lat_img as f64;
+                let west = -180. + 90. * lon_img as f64;
+                inputs.push(SourceInput {
+                    path: path.to_owned(),
+                    bounds: GeoBox::new(south, west, south + 90., west + 90.),
+                });
+            }
+        }
+        inputs
+    }
 }
@@ -0,0 +1,362 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    geotiff::raster::{Raster, RasterKind, Samples},
+    mip::{DataSource, Region, SourceInput},
+};
+use absolute_unit::{degrees, scalar, Degrees};
+use anyhow::{bail, ensure, Result};
+use geodesy::{GeoBox, GeoSurface, Graticule};
+use image::Rgb;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    ops::RangeInclusive,
+    path::Path,
+    sync::Arc,
+};
+use terrain::tile::TerrainLevel;
+
+// Decoded rasters are large (a one degree, one arcsecond float32 DEM tile is 50MiB), so
+// only keep this many around at once. Tiles are built in roughly geographic order, so
+// this is plenty to avoid thrashing.
+const MAX_LOADED_RASTERS: usize = 32;
+
+// Used to find the spacing of neighboring samples when computing normals.
+const METERS_PER_DEGREE: f64 = 111_319.5;
+
+// Least recently used first.
+#[derive(Default)]
+struct LoadedRasters {
+    samples: HashMap<usize, Arc<Samples>>,
+    order: VecDeque<usize>,
+
+    // Rasters that failed to decode, so that we do not retry on every sample. The first
+    // failure is reported by check_sampling.
+    failed: HashSet<usize>,
+    first_error: Option<String>,
+}
+
+impl LoadedRasters {
+    fn touch(&mut self, raster_id: usize) {
+        if let Some(position) = self.order.iter().position(|&id| id == raster_id) {
+            self.order.remove(position);
+        }
+        self.order.push_back(raster_id);
+    }
+}
+
+/// A directory of georeferenced GeoTIFF rasters in lat/lon coordinates; e.g. ALOS World 3D,
+/// Copernicus DEM, or local survey data. Elevation may be int16 or float32 and imagery
+/// must be 8 bit RGB. Where rasters overlap, the finest one with data at a point wins.
+pub struct Index {
+    kind: RasterKind,
+
+    // Sorted finest first.
+    rasters: Vec<Raster>,
+
+    // Rasters touching each one degree cell, by floor of latitude, then longitude.
+    by_cell: HashMap<(i16, i16), Vec<usize>>,
+
+    root_level: TerrainLevel,
+    intersecting_counts: Vec<usize>,
+    loaded: Mutex<LoadedRasters>,
+}
+
+impl Index {
+    pub fn from_directory(directory: &Path) -> Result<Arc<RwLock<Self>>> {
+        let mut paths = fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| ext.eq_ignore_ascii_case("tif") || ext.eq_ignore_ascii_case("tiff"))
+                    .unwrap_or(false)
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        ensure!(
+            !paths.is_empty(),
+            "no GeoTIFF files found in {:?}",
+            directory
+        );
+
+        let mut rasters = Vec::with_capacity(paths.len());
+        for path in &paths {
+            rasters.push(Raster::open(path)?);
+        }
+        let kind = rasters[0].kind();
+        for raster in &rasters {
+            if raster.kind() != kind {
+                bail!(
+                    "{:?} contains {}, but {:?} contains {}; put them in separate directories",
+                    rasters[0].path(),
+                    kind,
+                    raster.path(),
+                    raster.kind()
+                );
+            }
+        }
+        rasters.sort_by(|a, b| a.pixel_size().partial_cmp(&b.pixel_size()).unwrap());
+
+        let mut by_cell = HashMap::new();
+        for (i, raster) in rasters.iter().enumerate() {
+            let (lat_lo, lat_hi) = raster.lat_bounds();
+            let (lon_lo, lon_hi) = raster.lon_bounds();
+            for lat in lat_lo.floor() as i16..=lat_hi.floor() as i16 {
+                for lon in lon_lo.floor() as i16..=lon_hi.floor() as i16 {
+                    by_cell.entry((lat, lon)).or_insert_with(Vec::new).push(i);
+                }
+            }
+        }
+
+        // Build down to the level that holds every sample of the finest raster; if that is
+        // finer than we have levels for, the deepest level gets a subsampling.
+        let finest_as = rasters[0].pixel_size() * 3600.;
+        let root_level = (0..=TerrainLevel::arcsecond_level())
+            .find(|&level| TerrainLevel::new(level).as_scale().f64() <= finest_as)
+            .unwrap_or_else(TerrainLevel::arcsecond_level);
+        println!(
+            "Mapped {} GeoTIFF {} rasters; building to level {}",
+            rasters.len(),
+            kind,
+            root_level
+        );
+
+        let mut index = Self {
+            kind,
+            rasters,
+            by_cell,
+            root_level: TerrainLevel::new(root_level),
+            intersecting_counts: Vec::new(),
+            loaded: Mutex::new(LoadedRasters::default()),
+        };
+        index.intersecting_counts = (0..=root_level)
+            .map(|level| index.count_intersecting(level))
+            .collect();
+        Ok(Arc::new(RwLock::new(index)))
+    }
+
+    pub fn kind(&self) -> RasterKind {
+        self.kind
+    }
+
+    // Arbitrary rasters do not give us a predictable tile count, so walk the grid once per
+    // level, the same way that the tree builder will.
+    fn count_intersecting(&self, level: usize) -> usize {
+        let extent = TerrainLevel::new(level).angular_extent();
+        let base = TerrainLevel::base();
+        let per_side = 1usize << level;
+        let mut count = 0;
+        for lat_i in 0..per_side {
+            for lon_i in 0..per_side {
+                let region = Region {
+                    base: Graticule::new(
+                        base.latitude + extent * scalar!(lat_i as f64),
+                        base.longitude + extent * scalar!(lon_i as f64),
+                        base.distance,
+                    ),
+                    extent,
+                };
+                if self.contains_region(&region) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    // None if the raster could not be decoded; the error is kept for check_sampling.
+    fn samples(&self, raster_id: usize) -> Option<Arc<Samples>> {
+        {
+            let mut loaded = self.loaded.lock();
+            if loaded.failed.contains(&raster_id) {
+                return None;
+            }
+            if let Some(samples) = loaded.samples.get(&raster_id).cloned() {
+                loaded.touch(raster_id);
+                return Some(samples);
+            }
+        }
+        // Decode outside the lock so that other threads can keep sampling.
+        let result = self.rasters[raster_id].load();
+        let mut loaded = self.loaded.lock();
+        let samples = match result {
+            Ok(samples) => Arc::new(samples),
+            Err(e) => {
+                loaded.failed.insert(raster_id);
+                if loaded.first_error.is_none() {
+                    loaded.first_error = Some(format!(
+                        "failed to decode {:?}: {}",
+                        self.rasters[raster_id].path(),
+                        e
+                    ));
+                }
+                return None;
+            }
+        };
+        loaded.samples.insert(raster_id, samples.clone());
+        loaded.touch(raster_id);
+        while loaded.order.len() > MAX_LOADED_RASTERS {
+            if let Some(oldest) = loaded.order.pop_front() {
+                loaded.samples.remove(&oldest);
+            }
+        }
+        Some(samples)
+    }
+
+    // Rasters that may contain the point, finest first.
+    fn candidates(&self, lat: f64, lon: f64) -> &[usize] {
+        self.by_cell
+            .get(&(lat.floor() as i16, lon.floor() as i16))
+            .map(|v| v.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn height_at(&self, lat: f64, lon: f64) -> Option<f64> {
+        for &raster_id in self.candidates(lat, lon) {
+            let raster = &self.rasters[raster_id];
+            if !raster.overlaps((lat, lat), (lon, lon)) {
+                continue;
+            }
+            let samples = self.samples(raster_id)?;
+            if let Some(height) = raster.sample_height(&samples, lat, lon) {
+                return Some(height);
+            }
+        }
+        None
+    }
+
+    // The spacing of the finest raster at the point, in degrees.
+    fn local_pixel_size(&self, lat: f64, lon: f64) -> f64 {
+        self.candidates(lat, lon)
+            .iter()
+            .map(|&raster_id| &self.rasters[raster_id])
+            .find(|raster| raster.overlaps((lat, lat), (lon, lon)))
+            .map(|raster| raster.pixel_size())
+            .unwrap_or(1. / 3600.)
+    }
+}
+
+impl DataSource for Index {
+    fn contains_region(&self, region: &Region) -> bool {
+        let lat_lo = region.base.lat::<Degrees>().f64();
+        let lon_lo = region.base.lon::<Degrees>().f64();
+        let lat_hi = degrees!(region.base.latitude + region.extent).f64();
+        let lon_hi = degrees!(region.base.longitude + region.extent).f64();
+        if lat_lo > 90. || lat_hi < -90. || lon_lo > 180. || lon_hi < -180. {
+            return false;
+        }
+        for lat in lat_lo.max(-90.).floor() as i16..=lat_hi.min(90.).floor() as i16 {
+            for lon in lon_lo.max(-180.).floor() as i16..=lon_hi.min(180.).floor() as i16 {
+                if let Some(rasters) = self.by_cell.get(&(lat, lon)) {
+                    if rasters.iter().any(|&raster_id| {
+                        self.rasters[raster_id].overlaps((lat_lo, lat_hi), (lon_lo, lon_hi))
+                    }) {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    fn root_level(&self) -> TerrainLevel {
+        self.root_level
+    }
+
+    fn expect_intersecting_tiles(&self, layer: usize) -> usize {
+        self.intersecting_counts.get(layer).copied().unwrap_or(0)
+    }
+
+    fn expect_present_tiles(&self, layer: usize) -> RangeInclusive<usize> {
+        let a = self.expect_intersecting_tiles(layer);
+        a..=a
+    }
+
+    fn sample_nearest_height(&self, grat: &Graticule<GeoSurface>) -> i16 {
+        let lat = grat.lat::<Degrees>().f64();
+        let lon = grat.lon::<Degrees>().f64();
+        self.height_at(lat, lon)
+            .map(|h| h.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16)
+            .unwrap_or(0)
+    }
+
+    // Same encoding as SrtmIndex::compute_local_normal_at, but with the taps spaced by the
+    // resolution of the raster rather than a fixed arcsecond.
+    fn compute_local_normal(&self, grat: &Graticule<GeoSurface>) -> [i16; 2] {
+        let lat = grat.lat::<Degrees>().f64();
+        let lon = grat.lon::<Degrees>().f64();
+        let step = self.local_pixel_size(lat, lon);
+        let h_c = match self.height_at(lat, lon) {
+            Some(h) => h,
+            None => return [0; 2],
+        };
+        let h = |dlat: f64, dlon: f64| self.height_at(lat + dlat, lon + dlon).unwrap_or(h_c);
+        let dx = step * METERS_PER_DEGREE * lat.to_radians().cos().max(0.01);
+        let dz = step * METERS_PER_DEGREE;
+        let slope_x = (h(0., step) - h(0., -step)) / (2. * dx);
+        let slope_z = (h(step, 0.) - h(-step, 0.)) / (2. * dz);
+        let n = nalgebra::Vector3::new(-slope_x, 1., -slope_z).normalize();
+        let s = (n.x * (1 << 15) as f64)
+            .round()
+            .clamp(i16::MIN as f64, i16::MAX as f64);
+        let t = (n.z * (1 << 15) as f64)
+            .round()
+            .clamp(i16::MIN as f64, i16::MAX as f64);
+        [s as i16, t as i16]
+    }
+
+    fn sample_color(&self, grat: &Graticule<GeoSurface>) -> Rgb<u8> {
+        let lat = grat.lat::<Degrees>().f64();
+        let lon = grat.lon::<Degrees>().f64();
+        for &raster_id in self.candidates(lat, lon) {
+            let raster = &self.rasters[raster_id];
+            if !raster.overlaps((lat, lat), (lon, lon)) {
+                continue;
+            }
+            let samples = match self.samples(raster_id) {
+                Some(samples) => samples,
+                None => break,
+            };
+            if let Some(color) = raster.sample_color(&samples, lat, lon) {
+                return color;
+            }
+        }
+        Rgb([0; 3])
+    }
+
+    fn check_sampling(&self) -> Result<()> {
+        match &self.loaded.lock().first_error {
+            Some(e) => bail!("{}", e),
+            None => Ok(()),
+        }
+    }
+
+    fn inputs(&self) -> Vec<SourceInput> {
+        self.rasters
+            .iter()
+            .map(|raster| {
+                let (south, north) = raster.lat_bounds();
+                let (west, east) = raster.lon_bounds();
+                SourceInput {
+                    path: raster.path().to_owned(),
+                    bounds: GeoBox::new(south, west, north, east),
+                }
+            })
+            .collect()
+    }
+}
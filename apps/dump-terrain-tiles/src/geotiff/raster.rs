@@ -0,0 +1,420 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{anyhow, bail, ensure, Result};
+use image::Rgb;
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+use tiff::{
+    decoder::{Decoder, DecodingResult, Limits},
+    tags::Tag,
+    ColorType,
+};
+
+// GeoTIFF stores its georeferencing in a handful of extra TIFF tags.
+// See: http://docs.opengeospatial.org/is/19-008r4/19-008r4.html
+const MODEL_PIXEL_SCALE_TAG: u16 = 33550;
+const MODEL_TIEPOINT_TAG: u16 = 33922;
+const MODEL_TRANSFORMATION_TAG: u16 = 34264;
+const GEO_KEY_DIRECTORY_TAG: u16 = 34735;
+
+// Not part of GeoTIFF proper, but written by GDAL and everything downstream of it.
+const GDAL_NODATA_TAG: u16 = 42113;
+
+// Keys in the GeoKeyDirectory that we care about.
+const GT_MODEL_TYPE_GEO_KEY: u16 = 1024;
+const GT_RASTER_TYPE_GEO_KEY: u16 = 1025;
+const MODEL_TYPE_GEOGRAPHIC: u16 = 2;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+// Values of the TIFF SampleFormat tag; unsigned if the tag is missing.
+const SAMPLE_FORMAT_UINT: u16 = 1;
+const SAMPLE_FORMAT_INT: u16 = 2;
+const SAMPLE_FORMAT_IEEEFP: u16 = 3;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RasterKind {
+    Elevation,
+    Imagery,
+}
+
+impl RasterKind {
+    // Only accept what `Raster::load` can decode, so that a bad file is rejected when the
+    // directory is opened, rather than part way through a build.
+    fn from_format(path: &Path, color: ColorType, sample_format: u16) -> Result<Self> {
+        Ok(match (color, sample_format) {
+            (ColorType::Gray(16), SAMPLE_FORMAT_INT)
+            | (ColorType::Gray(32), SAMPLE_FORMAT_IEEEFP) => Self::Elevation,
+            (ColorType::RGB(8) | ColorType::RGBA(8), SAMPLE_FORMAT_UINT) => Self::Imagery,
+            (color, sample_format) => bail!(
+                "{:?}: unsupported sample type {:?} with sample format {}; expected int16 or \
+                float32 elevation or 8 bit RGB",
+                path,
+                color,
+                sample_format
+            ),
+        })
+    }
+}
+
+impl fmt::Display for RasterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Elevation => write!(f, "elevation"),
+            Self::Imagery => write!(f, "imagery"),
+        }
+    }
+}
+
+/// Maps the center of pixel (row, col) to degrees on the planet:
+///   lon = c[0] + col * c[1] + row * c[2]
+///   lat = c[3] + col * c[4] + row * c[5]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GeoTransform {
+    c: [f64; 6],
+    det: f64,
+}
+
+impl GeoTransform {
+    pub fn new(c: [f64; 6]) -> Result<Self> {
+        let det = c[1] * c[5] - c[2] * c[4];
+        ensure!(
+            det.is_finite() && det != 0.,
+            "raster transform is degenerate: {:?}",
+            c
+        );
+        Ok(Self { c, det })
+    }
+
+    // GeoTIFF gives either a full transformation matrix or a tie point and a pixel scale.
+    // Either way the raster coordinate of a pixel is its corner, unless the file tells us
+    // that the pixels are points; we want to address pixel centers.
+    fn from_tags(
+        pixel_scale: Option<Vec<f64>>,
+        tiepoint: Option<Vec<f64>>,
+        transformation: Option<Vec<f64>>,
+        pixel_is_point: bool,
+    ) -> Result<Self> {
+        let m = if let Some(t) = transformation {
+            ensure!(t.len() == 16, "ModelTransformation must have 16 values");
+            [t[3], t[0], t[1], t[7], t[4], t[5]]
+        } else if let (Some(s), Some(p)) = (pixel_scale, tiepoint) {
+            ensure!(s.len() >= 2, "ModelPixelScale must have at least 2 values");
+            ensure!(p.len() >= 6, "ModelTiepoint must have at least 6 values");
+            [p[3] - p[0] * s[0], s[0], 0., p[4] + p[1] * s[1], 0., -s[1]]
+        } else {
+            bail!("raster is not georeferenced: no transformation or tie point");
+        };
+        let offset = if pixel_is_point { 0. } else { 0.5 };
+        Self::new([
+            m[0] + offset * (m[1] + m[2]),
+            m[1],
+            m[2],
+            m[3] + offset * (m[4] + m[5]),
+            m[4],
+            m[5],
+        ])
+    }
+
+    /// The fractional (row, col) of the given lat/lon in degrees.
+    pub fn to_pixel(&self, lat: f64, lon: f64) -> (f64, f64) {
+        let dlon = lon - self.c[0];
+        let dlat = lat - self.c[3];
+        let col = (dlon * self.c[5] - dlat * self.c[2]) / self.det;
+        let row = (dlat * self.c[1] - dlon * self.c[4]) / self.det;
+        (row, col)
+    }
+
+    /// The (lat, lon) in degrees of the center of the given pixel.
+    pub fn to_graticule(&self, row: f64, col: f64) -> (f64, f64) {
+        (
+            self.c[3] + col * self.c[4] + row * self.c[5],
+            self.c[0] + col * self.c[1] + row * self.c[2],
+        )
+    }
+
+    /// The size of a pixel in degrees, taking the finer axis if they differ.
+    pub fn pixel_size(&self) -> f64 {
+        let lon = (self.c[1] * self.c[1] + self.c[4] * self.c[4]).sqrt();
+        let lat = (self.c[2] * self.c[2] + self.c[5] * self.c[5]).sqrt();
+        lon.min(lat)
+    }
+}
+
+/// Decoded samples of one raster, row major from the top of the image.
+pub enum Samples {
+    Int16(Vec<i16>),
+    Float32(Vec<f32>),
+    Color { data: Vec<u8>, channels: usize },
+}
+
+/// One georeferenced GeoTIFF. We only read the header up front; the samples are decoded
+/// on demand, since a directory of rasters is generally far larger than memory.
+pub struct Raster {
+    path: PathBuf,
+    kind: RasterKind,
+    width: usize,
+    height: usize,
+    transform: GeoTransform,
+    nodata: Option<f64>,
+
+    // Lat and lon extents in degrees, as (low, high), including the edge pixels.
+    lat_bounds: (f64, f64),
+    lon_bounds: (f64, f64),
+}
+
+impl Raster {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+        let (width, height) = decoder.dimensions()?;
+        let sample_format = decoder
+            .find_tag_unsigned_vec::<u16>(Tag::SampleFormat)?
+            .and_then(|formats| formats.first().copied())
+            .unwrap_or(SAMPLE_FORMAT_UINT);
+        let kind = RasterKind::from_format(path, decoder.colortype()?, sample_format)?;
+
+        let mut pixel_is_point = false;
+        if let Ok(keys) = decoder.get_tag_u16_vec(Tag::from_u16_exhaustive(GEO_KEY_DIRECTORY_TAG)) {
+            // A header of 4 shorts, then 4 shorts per key: id, location, count, value.
+            for key in keys.get(4..).unwrap_or_default().chunks_exact(4) {
+                match key[0] {
+                    GT_MODEL_TYPE_GEO_KEY => ensure!(
+                        key[1] == 0 && key[3] == MODEL_TYPE_GEOGRAPHIC,
+                        "{:?}: only rasters in geographic lat/lon coordinates are supported",
+                        path
+                    ),
+                    GT_RASTER_TYPE_GEO_KEY => {
+                        pixel_is_point = key[1] == 0 && key[3] == RASTER_PIXEL_IS_POINT
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut f64_tag = |tag: u16| decoder.get_tag_f64_vec(Tag::from_u16_exhaustive(tag)).ok();
+        let pixel_scale = f64_tag(MODEL_PIXEL_SCALE_TAG);
+        let tiepoint = f64_tag(MODEL_TIEPOINT_TAG);
+        let transformation = f64_tag(MODEL_TRANSFORMATION_TAG);
+        let transform =
+            GeoTransform::from_tags(pixel_scale, tiepoint, transformation, pixel_is_point)
+                .map_err(|e| anyhow!("{:?}: {}", path, e))?;
+
+        let nodata = match decoder.get_tag_ascii_string(Tag::from_u16_exhaustive(GDAL_NODATA_TAG)) {
+            Ok(s) => Some(
+                s.trim_matches(|c: char| c.is_whitespace() || c == '\0')
+                    .parse::<f64>()
+                    .map_err(|e| anyhow!("{:?}: bad nodata value '{}': {}", path, s, e))?,
+            ),
+            Err(_) => None,
+        };
+
+        let mut raster = Self {
+            path: path.to_owned(),
+            kind,
+            width: width as usize,
+            height: height as usize,
+            transform,
+            nodata,
+            lat_bounds: (0., 0.),
+            lon_bounds: (0., 0.),
+        };
+        raster.compute_bounds();
+        Ok(raster)
+    }
+
+    fn compute_bounds(&mut self) {
+        let mut lat_bounds = (f64::MAX, f64::MIN);
+        let mut lon_bounds = (f64::MAX, f64::MIN);
+        for &(row, col) in &[
+            (-0.5, -0.5),
+            (-0.5, self.width as f64 - 0.5),
+            (self.height as f64 - 0.5, -0.5),
+            (self.height as f64 - 0.5, self.width as f64 - 0.5),
+        ] {
+            let (lat, lon) = self.transform.to_graticule(row, col);
+            lat_bounds = (lat_bounds.0.min(lat), lat_bounds.1.max(lat));
+            lon_bounds = (lon_bounds.0.min(lon), lon_bounds.1.max(lon));
+        }
+        self.lat_bounds = lat_bounds;
+        self.lon_bounds = lon_bounds;
+    }
+
+    pub fn load(&self) -> Result<Samples> {
+        let mut decoder =
+            Decoder::new(BufReader::new(File::open(&self.path)?))?.with_limits(Limits::unlimited());
+        let channels = match decoder.colortype()? {
+            ColorType::RGBA(8) => 4,
+            _ => 3,
+        };
+        Ok(match (self.kind, decoder.read_image()?) {
+            (RasterKind::Elevation, DecodingResult::I16(data)) => Samples::Int16(data),
+            (RasterKind::Elevation, DecodingResult::F32(data)) => Samples::Float32(data),
+            (RasterKind::Imagery, DecodingResult::U8(data)) => Samples::Color { data, channels },
+            _ => bail!(
+                "{:?}: unsupported sample format; elevation must be int16 or float32",
+                self.path
+            ),
+        })
+    }
+
+    pub fn kind(&self) -> RasterKind {
+        self.kind
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Pixel size in degrees.
+    pub fn pixel_size(&self) -> f64 {
+        self.transform.pixel_size()
+    }
+
+    pub fn lat_bounds(&self) -> (f64, f64) {
+        self.lat_bounds
+    }
+
+    pub fn lon_bounds(&self) -> (f64, f64) {
+        self.lon_bounds
+    }
+
+    /// True if the lat/lon box overlaps this raster.
+    pub fn overlaps(&self, lat: (f64, f64), lon: (f64, f64)) -> bool {
+        lat.0 <= self.lat_bounds.1
+            && lat.1 >= self.lat_bounds.0
+            && lon.0 <= self.lon_bounds.1
+            && lon.1 >= self.lon_bounds.0
+    }
+
+    // The offset of the nearest sample, if the point is on the raster.
+    fn nearest_offset(&self, lat: f64, lon: f64) -> Option<usize> {
+        let (row, col) = self.transform.to_pixel(lat, lon);
+        let (row, col) = (row.round(), col.round());
+        if row < 0. || col < 0. || row >= self.height as f64 || col >= self.width as f64 {
+            return None;
+        }
+        Some(row as usize * self.width + col as usize)
+    }
+
+    /// The nearest height in meters, or None if off the raster or there is no data there.
+    pub fn sample_height(&self, samples: &Samples, lat: f64, lon: f64) -> Option<f64> {
+        let offset = self.nearest_offset(lat, lon)?;
+        let height = match samples {
+            Samples::Int16(data) => data[offset] as f64,
+            Samples::Float32(data) => data[offset] as f64,
+            Samples::Color { .. } => return None,
+        };
+        if !height.is_finite() || Some(height) == self.nodata {
+            return None;
+        }
+        Some(height)
+    }
+
+    /// The nearest color, or None if off the raster or there is no data there.
+    pub fn sample_color(&self, samples: &Samples, lat: f64, lon: f64) -> Option<Rgb<u8>> {
+        let offset = self.nearest_offset(lat, lon)?;
+        if let Samples::Color { data, channels } = samples {
+            let px = &data[offset * channels..offset * channels + 3];
+            if let Some(nodata) = self.nodata {
+                if px.iter().all(|&v| v as f64 == nodata) {
+                    return None;
+                }
+            }
+            return Some(Rgb([px[0], px[1], px[2]]));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_tiepoint_transform() -> Result<()> {
+        // A 1 degree, 1 arcsecond tile whose top left corner is at 47N 122W.
+        let scale = 1. / 3600.;
+        let xf = GeoTransform::from_tags(
+            Some(vec![scale, scale, 0.]),
+            Some(vec![0., 0., 0., -122., 47., 0.]),
+            None,
+            false,
+        )?;
+        let (lat, lon) = xf.to_graticule(0., 0.);
+        assert_relative_eq!(lat, 47. - scale / 2.);
+        assert_relative_eq!(lon, -122. + scale / 2.);
+        let (row, col) = xf.to_pixel(46.5, -121.5);
+        assert_relative_eq!(row, 1799.5, epsilon = 0.000_001);
+        assert_relative_eq!(col, 1799.5, epsilon = 0.000_001);
+        assert_relative_eq!(xf.pixel_size(), scale);
+
+        // As points, the tie point is the center of the first pixel.
+        let xf = GeoTransform::from_tags(
+            Some(vec![scale, scale, 0.]),
+            Some(vec![0., 0., 0., -122., 47., 0.]),
+            None,
+            true,
+        )?;
+        assert_eq!(xf.to_pixel(47., -122.), (0., 0.));
+        Ok(())
+    }
+
+    #[test]
+    fn test_matrix_transform_round_trip() -> Result<()> {
+        // Slightly rotated, as some survey data is.
+        let xf = GeoTransform::from_tags(
+            None,
+            None,
+            Some(vec![
+                0.001, 0.0001, 0., 10., 0.0001, -0.001, 0., 20., 0., 0., 0., 0., 0., 0., 0., 1.,
+            ]),
+            false,
+        )?;
+        let (lat, lon) = xf.to_graticule(12.25, 37.75);
+        let (row, col) = xf.to_pixel(lat, lon);
+        assert_relative_eq!(row, 12.25, epsilon = 0.000_001);
+        assert_relative_eq!(col, 37.75, epsilon = 0.000_001);
+        assert!(GeoTransform::from_tags(None, None, None, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_format() -> Result<()> {
+        let path = Path::new("dem.tif");
+        let kind = |color, format| RasterKind::from_format(path, color, format);
+        assert_eq!(
+            kind(ColorType::Gray(16), SAMPLE_FORMAT_INT)?,
+            RasterKind::Elevation
+        );
+        assert_eq!(
+            kind(ColorType::Gray(32), SAMPLE_FORMAT_IEEEFP)?,
+            RasterKind::Elevation
+        );
+        assert_eq!(
+            kind(ColorType::RGB(8), SAMPLE_FORMAT_UINT)?,
+            RasterKind::Imagery
+        );
+
+        // Common DEM encodings that we cannot decode yet.
+        assert!(kind(ColorType::Gray(16), SAMPLE_FORMAT_UINT).is_err());
+        assert!(kind(ColorType::Gray(32), SAMPLE_FORMAT_INT).is_err());
+        assert!(kind(ColorType::Gray(8), SAMPLE_FORMAT_UINT).is_err());
+        Ok(())
+    }
+}
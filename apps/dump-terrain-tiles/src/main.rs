@@ -13,24 +13,29 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 mod bmng;
+mod geotiff;
 mod mip;
+mod polar;
 mod srtm;
+mod verify;
 
 use crate::{
     bmng::BmngIndex,
-    mip::{DataSource, MipIndex, MipIndexDataSet, MipTile, NeighborIndex},
+    geotiff::{GeoTiffIndex, RasterKind},
+    mip::{DataSource, MipIndex, MipIndexDataSet, MipTile, NeighborIndex, RebuildPlan},
+    polar::PolarIndex,
     srtm::SrtmIndex,
+    verify::verify_layer_packs,
 };
 use absolute_unit::{arcseconds, degrees, meters, radians, scalar, Angle, Radians};
-use anyhow::Result;
-use bzip2::{read::BzEncoder, Compression};
-use geodesy::{GeoSurface, Graticule};
+use anyhow::{bail, Result};
+use geodesy::{GeoBox, GeoSurface, Graticule};
 use parking_lot::{Mutex, RwLock};
 use rayon::prelude::*;
 use std::{
     cmp::Ordering,
     fs,
-    io::{stdout, Read, Write},
+    io::{stdout, Write},
     path::{Path, PathBuf},
     sync::Arc,
     time::Instant,
@@ -38,7 +43,7 @@ use std::{
 use structopt::StructOpt;
 use terrain::tile::{
     ChildIndex, DataSetCoordinates, DataSetDataKind, LayerPackBuilder, TerrainLevel,
-    TileCompression, TILE_PHYSICAL_SIZE, TILE_SAMPLES,
+    TileCompression, TilePixelFormat, TILE_PHYSICAL_SIZE, TILE_SAMPLES,
 };
 
 #[derive(Debug, StructOpt)]
@@ -55,6 +60,14 @@ struct Opt {
     #[structopt(short, long)]
     bmng_directory: Option<PathBuf>,
 
+    /// Slice a directory of georeferenced GeoTIFF elevation or imagery into tiles
+    #[structopt(long)]
+    geotiff_directory: Option<PathBuf>,
+
+    /// The data set prefix to use for --geotiff-directory
+    #[structopt(long, default_value = "geotiff")]
+    geotiff_prefix: String,
+
     #[structopt(long)]
     serialize: bool,
 
@@ -62,7 +75,7 @@ struct Opt {
     #[structopt(short, long)]
     output_directory: PathBuf,
 
-    /// Compute and print some aggregate statistics.
+    /// Compress tiles in layer packs with none, bz2, zstd, or lz4.
     #[structopt(short, long)]
     compression: Option<String>,
 
@@ -73,6 +86,22 @@ struct Opt {
     /// Overwrite existing files.
     #[structopt(short, long)]
     force: bool,
+
+    /// Also slice each data set onto polar stereographic tiles, for use near the poles.
+    #[structopt(long)]
+    polar: bool,
+
+    /// Only rebuild tiles touching this box, given as south,west,north,east in degrees.
+    #[structopt(long)]
+    region: Option<GeoBox>,
+
+    /// Do not build tiles deeper than this level.
+    #[structopt(long)]
+    max_level: Option<usize>,
+
+    /// Check existing layer packs against the tiles in the work directory, then exit.
+    #[structopt(long)]
+    verify: bool,
 }
 
 #[inline]
@@ -205,13 +234,14 @@ fn nc(
 
 fn build_tree(
     current_level: usize,
+    root_level: usize,
     source: Arc<RwLock<dyn DataSource>>,
     data_set: Arc<RwLock<MipIndexDataSet>>,
     tile_ref: Arc<RwLock<MipTile>>,
     node_count: &mut usize,
     leaf_count: &mut usize,
 ) -> Result<()> {
-    if current_level < source.read().root_level().offset() {
+    if current_level < root_level {
         {
             let src = source.read();
             let mut tile = tile_ref.write();
@@ -228,6 +258,7 @@ fn build_tree(
         for child in tile_ref.read().maybe_children().iter().flatten() {
             build_tree(
                 current_level + 1,
+                root_level,
                 source.clone(),
                 data_set.clone(),
                 child.to_owned(),
@@ -237,7 +268,7 @@ fn build_tree(
         }
         return Ok(());
     }
-    debug_assert_eq!(current_level, source.read().root_level().offset());
+    debug_assert_eq!(current_level, root_level);
     *leaf_count += 1;
     Ok(())
 }
@@ -273,11 +304,7 @@ pub fn generate_mip_tile_from_source(
     node: Arc<RwLock<MipTile>>,
     dump_png: bool,
 ) -> Result<()> {
-    // Assume that tiles we've already created are good.
-    if node.read().file_exists(index.read().work_path()) {
-        return Ok(());
-    }
-
+    // Note: the rebuild plan decides which tiles need building and discards them first.
     node.write().allocate_scratch_data(index.read().kind());
 
     assert_eq!((-1..TILE_SAMPLES + 1).count(), TILE_PHYSICAL_SIZE);
@@ -285,6 +312,7 @@ pub fn generate_mip_tile_from_source(
     let scale = level.as_scale();
     let base = node.read().base_graticule();
     let kind = index.read().kind();
+    let polar = index.read().coordinates() == DataSetCoordinates::CartesianPolar;
 
     let mut sum_height = 0;
     let source = source.read();
@@ -312,11 +340,21 @@ pub fn generate_mip_tile_from_source(
                 lon_srtm -= degrees!(360);
             }
 
-            let srtm_grat = Graticule::<GeoSurface>::new(
-                arcseconds!(lat_srtm),
-                arcseconds!(lon_srtm),
-                meters!(0),
-            );
+            // Polar tiles are on a plane, so there is nothing to clamp or wrap; the source
+            // knows how to get from plane coordinates back to the planet.
+            let srtm_grat = if polar {
+                Graticule::<GeoSurface>::new(
+                    arcseconds!(lat_actual),
+                    arcseconds!(lon_actual),
+                    meters!(0),
+                )
+            } else {
+                Graticule::<GeoSurface>::new(
+                    arcseconds!(lat_srtm),
+                    arcseconds!(lon_srtm),
+                    meters!(0),
+                )
+            };
 
             // FIXME: compute base normals
             match kind {
@@ -341,6 +379,7 @@ pub fn generate_mip_tile_from_source(
             }
         }
     }
+    source.check_sampling()?;
     if dump_png {
         node.read().save_equalized_png(
             index.read().kind(),
@@ -348,6 +387,7 @@ pub fn generate_mip_tile_from_source(
         )?;
     }
     node.write().write(index.read().work_path(), sum_height)?;
+    index.read().rebuild_plan().record_built(&node.read())?;
 
     Ok(())
 }
@@ -357,12 +397,6 @@ pub fn generate_mip_tile_from_mip(
     node: Arc<RwLock<MipTile>>,
     dump_png: bool,
 ) -> Result<()> {
-    // Assume that tiles we've already created are good.
-    if node.read().file_exists(index.read().work_path()) {
-        assert!(node.read().data_state().starts_with("mapped"));
-        return Ok(());
-    }
-
     node.write().allocate_scratch_data(index.read().kind());
 
     let kind = index.read().kind();
@@ -401,6 +435,7 @@ pub fn generate_mip_tile_from_mip(
     // degree slicing so we may have corners that are non-None but still empty. This results in
     // some excess empty tiles up the stack and some extra work, but not a huge amount.
     assert!(node.read().data_state().starts_with("mapped") || node.read().data_state() == "empty");
+    index.read().rebuild_plan().record_built(&node.read())?;
 
     Ok(())
 }
@@ -443,7 +478,7 @@ fn write_layer_pack(
     tiles: &[(Arc<RwLock<MipTile>>, usize)],
     dataset: Arc<RwLock<MipIndexDataSet>>,
     target_level: usize,
-    force: bool,
+    rewrite: bool,
     compression: TileCompression,
 ) -> Result<()> {
     if tiles.is_empty() {
@@ -455,20 +490,25 @@ fn write_layer_pack(
         dataset.read().prefix(),
         target_level
     ));
-    if layer_pack_path.exists() && !force {
+    if layer_pack_path.exists() && !rewrite {
         println!(
-            "  skipping write because have layerpack file at {:?}",
+            "  skipping write because layerpack file at {:?} is up to date",
             layer_pack_path
         );
         return Ok(());
     }
+    let kind = dataset.read().kind();
+    let work_format = TilePixelFormat::legacy(kind);
+    let pack_format = TilePixelFormat::native(kind);
     let layer_pack_builder = Mutex::new(LayerPackBuilder::new(
         &layer_pack_path,
         tiles.len(),
         target_level as u32,
         compression,
+        pack_format,
         TerrainLevel::new(target_level).angular_extent().round() as i32,
     )?);
+    let height_range = Mutex::new((i16::MAX, i16::MIN));
     let progress = Mutex::new(InlinePercentProgress::new(
         "  Writing Pack File:",
         tiles.len(),
@@ -477,16 +517,20 @@ fn write_layer_pack(
     tiles.par_chunks(chunk_size).for_each(|chunk| {
         for (tile, _) in chunk {
             let tile = tile.read();
-            let data = match compression {
-                TileCompression::None => tile.raw_data().to_owned(),
-                TileCompression::Bz2 => {
-                    let mut compressed = Vec::new();
-                    BzEncoder::new(tile.raw_data(), Compression::best())
-                        .read_to_end(&mut compressed)
-                        .expect("compress buffer");
-                    compressed
-                }
-            };
+            if kind == DataSetDataKind::Height {
+                let (lo, hi) = tile
+                    .raw_data()
+                    .chunks_exact(2)
+                    .map(|c| i16::from_ne_bytes([c[0], c[1]]))
+                    .fold((i16::MAX, i16::MIN), |(lo, hi), h| (lo.min(h), hi.max(h)));
+                let mut range = height_range.lock();
+                *range = (range.0.min(lo), range.1.max(hi));
+            }
+            // Store samples the way the atlas wants them, so that loading is just a copy.
+            let data = work_format
+                .convert(tile.raw_data(), pack_format)
+                .and_then(|raw| compression.compress(&raw))
+                .expect("encode tile");
             layer_pack_builder
                 .lock()
                 .push_tile(tile.base(), tile.index_in_parent().to_index() as u32, &data)
@@ -494,18 +538,18 @@ fn write_layer_pack(
         }
         progress.lock().poke_chunk(chunk_size);
     });
+    if kind == DataSetDataKind::Height {
+        let (lo, hi) = *height_range.lock();
+        layer_pack_builder.lock().set_height_range(lo, hi)?;
+    }
     progress.lock().finish();
     Ok(())
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
-    let compression = if let Some(comp) = opt.compression {
-        match comp.as_str() {
-            "none" => TileCompression::None,
-            "bz2" => TileCompression::Bz2,
-            _ => panic!("Unknown tile compression format: expected bz2 or none"),
-        }
+    let compression = if let Some(comp) = opt.compression.as_ref() {
+        TileCompression::from_name(comp)?
     } else {
         TileCompression::None
     };
@@ -524,8 +568,16 @@ fn main() -> Result<()> {
                 Box::leak(format!("bmng-{:02}", month).into_boxed_str()),
                 DataSetDataKind::Color,
                 DataSetCoordinates::Spherical,
-                bmng,
+                bmng.clone(),
             )?;
+            if opt.polar {
+                mip_index.add_data_set(
+                    Box::leak(format!("bmng-{:02}-polar", month).into_boxed_str()),
+                    DataSetDataKind::Color,
+                    DataSetCoordinates::CartesianPolar,
+                    PolarIndex::new(bmng),
+                )?;
+            }
         }
     }
 
@@ -541,17 +593,103 @@ fn main() -> Result<()> {
             "srtmn",
             DataSetDataKind::Normal,
             DataSetCoordinates::Spherical,
-            srtm,
+            srtm.clone(),
         )?;
+        if opt.polar {
+            // Note: srtm stops at 60N, so this will only catch the edge of the cap.
+            mip_index.add_data_set(
+                "srtmh-polar",
+                DataSetDataKind::Height,
+                DataSetCoordinates::CartesianPolar,
+                PolarIndex::new(srtm.clone()),
+            )?;
+            mip_index.add_data_set(
+                "srtmn-polar",
+                DataSetDataKind::Normal,
+                DataSetCoordinates::CartesianPolar,
+                PolarIndex::new(srtm),
+            )?;
+        }
     }
 
-    for dataset in mip_index.data_sets(DataSetCoordinates::Spherical) {
+    if let Some(directory) = opt.geotiff_directory.as_ref() {
+        let geotiff = GeoTiffIndex::from_directory(directory)?;
+        let prefix = opt.geotiff_prefix.as_str();
+        let kind = geotiff.read().kind();
+        let mut add_data_set =
+            |name: String,
+             kind: DataSetDataKind,
+             coordinates: DataSetCoordinates,
+             source: Arc<RwLock<dyn DataSource>>| {
+                mip_index.add_data_set(Box::leak(name.into_boxed_str()), kind, coordinates, source)
+            };
+        match kind {
+            RasterKind::Elevation => {
+                add_data_set(
+                    format!("{}h", prefix),
+                    DataSetDataKind::Height,
+                    DataSetCoordinates::Spherical,
+                    geotiff.clone(),
+                )?;
+                add_data_set(
+                    format!("{}n", prefix),
+                    DataSetDataKind::Normal,
+                    DataSetCoordinates::Spherical,
+                    geotiff.clone(),
+                )?;
+                if opt.polar {
+                    add_data_set(
+                        format!("{}h-polar", prefix),
+                        DataSetDataKind::Height,
+                        DataSetCoordinates::CartesianPolar,
+                        PolarIndex::new(geotiff.clone()),
+                    )?;
+                    add_data_set(
+                        format!("{}n-polar", prefix),
+                        DataSetDataKind::Normal,
+                        DataSetCoordinates::CartesianPolar,
+                        PolarIndex::new(geotiff),
+                    )?;
+                }
+            }
+            RasterKind::Imagery => {
+                add_data_set(
+                    prefix.to_owned(),
+                    DataSetDataKind::Color,
+                    DataSetCoordinates::Spherical,
+                    geotiff.clone(),
+                )?;
+                if opt.polar {
+                    add_data_set(
+                        format!("{}-polar", prefix),
+                        DataSetDataKind::Color,
+                        DataSetCoordinates::CartesianPolar,
+                        PolarIndex::new(geotiff),
+                    )?;
+                }
+            }
+        }
+    }
+
+    for dataset in mip_index.all_data_sets() {
         let start = Instant::now();
+        let plan = RebuildPlan::new(
+            dataset.read().prefix(),
+            dataset.read().base_path(),
+            dataset.read().work_path(),
+            dataset.read().source(),
+            opt.region,
+            opt.max_level,
+            opt.force,
+        )?;
+        let root_level = plan.root_level();
+        dataset.write().set_rebuild_plan(plan);
         let root = dataset.write().get_root_tile();
         let mut node_count = 0usize;
         let mut leaf_count = 0usize;
         build_tree(
             0,
+            root_level,
             dataset.read().source(),
             dataset.clone(),
             root.clone(),
@@ -572,9 +710,27 @@ fn main() -> Result<()> {
         );
     }
 
+    if opt.verify {
+        let mut error_count = 0;
+        for dataset in mip_index.all_data_sets() {
+            println!("Verifying {}:", dataset.read().prefix());
+            let root_level = dataset.read().rebuild_plan().root_level();
+            error_count += verify_layer_packs(dataset, root_level, opt.serialize)?;
+        }
+        if error_count > 0 {
+            bail!("found {} problems in layer packs", error_count);
+        }
+        println!("All layer packs match their tiles");
+        return Ok(());
+    }
+
     // Generate each level from the bottom up, mipmapping as we go.
     for target_level in (0..=SrtmIndex::max_resolution_level()).rev() {
-        for dataset in mip_index.data_sets(DataSetCoordinates::Spherical) {
+        for dataset in mip_index.all_data_sets() {
+            let root_level = dataset.read().rebuild_plan().root_level();
+            if target_level > root_level {
+                continue;
+            }
             println!("{} Level {}:", dataset.read().prefix(), target_level);
             let expect_intersecting = dataset
                 .read()
@@ -605,18 +761,43 @@ fn main() -> Result<()> {
             )?;
             assert!(mmap_count <= expect_intersecting);
             assert!(mmap_count <= *expect_present.end());
-            if !expect_present.contains(&mmap_count) {
+
+            // Find the tiles that are missing or out of date and that we are allowed to build.
+            let assume_complete = expect_present.contains(&mmap_count);
+            let mut stale_tiles = Vec::new();
+            let mut skipped_count = 0;
+            {
+                let ds = dataset.read();
+                let plan = ds.rebuild_plan();
+                for (tile, offset) in &current_tiles {
+                    if plan.is_stale(&tile.read(), assume_complete) {
+                        if plan.in_region(&tile.read()) {
+                            tile.write().discard(ds.work_path())?;
+                            stale_tiles.push((tile.to_owned(), *offset));
+                        } else {
+                            skipped_count += 1;
+                        }
+                    } else {
+                        plan.record_current(&tile.read())?;
+                    }
+                }
+            }
+            if skipped_count > 0 {
+                println!(
+                    "  Skipping {} out of date tiles outside of the region",
+                    skipped_count
+                );
+            }
+
+            if !stale_tiles.is_empty() {
                 let progress = Arc::new(RwLock::new(InlinePercentProgress::new(
                     "  Building tiles:",
-                    current_tiles.len(),
+                    stale_tiles.len(),
                 )));
 
-                match (
-                    opt.serialize,
-                    target_level.cmp(&dataset.read().source().read().root_level().offset()),
-                ) {
+                match (opt.serialize, target_level.cmp(&root_level)) {
                     (true, Ordering::Equal) => {
-                        for (tile, _) in &current_tiles {
+                        for (tile, _) in &stale_tiles {
                             generate_mip_tile_from_source(
                                 dataset.read().source(),
                                 dataset.clone(),
@@ -628,14 +809,14 @@ fn main() -> Result<()> {
                         }
                     }
                     (true, Ordering::Less) => {
-                        for (tile, _) in &current_tiles {
+                        for (tile, _) in &stale_tiles {
                             generate_mip_tile_from_mip(dataset.clone(), tile.to_owned(), dump_png)
                                 .expect("generate_mip_tile_from_mip");
                             progress.write().poke();
                         }
                     }
                     (false, Ordering::Equal) => {
-                        current_tiles.par_chunks(1024).for_each(|chunk| {
+                        stale_tiles.par_chunks(1024).for_each(|chunk| {
                             for (tile, _) in chunk {
                                 generate_mip_tile_from_source(
                                     dataset.read().source(),
@@ -649,7 +830,7 @@ fn main() -> Result<()> {
                         });
                     }
                     (false, Ordering::Less) => {
-                        current_tiles.par_chunks(1024).for_each(|chunk| {
+                        stale_tiles.par_chunks(1024).for_each(|chunk| {
                             for (tile, _) in chunk {
                                 generate_mip_tile_from_mip(
                                     dataset.clone(),
@@ -665,14 +846,17 @@ fn main() -> Result<()> {
                 }
                 progress.write().finish();
             } else {
-                println!("  Found all tiles on disk - demoting absent to empty");
-                current_tiles.par_chunks(4096).for_each(|chunk| {
-                    for (node, _) in chunk {
-                        node.write().promote_absent_to_empty();
-                    }
-                });
+                println!("  Found all tiles on disk");
             }
 
+            // Anything that we did not build or map is known to be empty, or is outside of
+            // the region and will be dealt with on a later run.
+            current_tiles.par_chunks(4096).for_each(|chunk| {
+                for (node, _) in chunk {
+                    node.write().promote_absent_to_empty();
+                }
+            });
+
             // Verify that we do not have any absent tiles. All tiles should be empty or have mmapped data.
             println!("  Verifying tile states");
             for (tile, _) in &current_tiles {
@@ -694,15 +878,16 @@ fn main() -> Result<()> {
                 &current_tiles,
                 dataset.clone(),
                 target_level,
-                opt.force,
+                opt.force || dataset.read().rebuild_plan().rebuilt_level(target_level),
                 compression,
             )?;
         }
     }
 
     // Write out our top level index of the data.
-    for dataset in mip_index.data_sets(DataSetCoordinates::Spherical) {
+    for dataset in mip_index.all_data_sets() {
         dataset.read().write()?;
+        dataset.read().rebuild_plan().finish()?;
     }
 
     Ok(())
@@ -12,7 +12,7 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
-use crate::mip::{tile::Tile, DataSource};
+use crate::mip::{tile::Tile, DataSource, RebuildPlan};
 use absolute_unit::ArcSeconds;
 use anyhow::Result;
 use json::JsonValue;
@@ -79,6 +79,13 @@ impl Index {
         dss.sort_by_key(|ds| ds.read().prefix().to_owned());
         dss
     }
+
+    // Spherical data sets first, then polar.
+    pub fn all_data_sets(&self) -> Vec<Arc<RwLock<IndexDataSet>>> {
+        let mut dss = self.data_sets(DataSetCoordinates::Spherical);
+        dss.extend(self.data_sets(DataSetCoordinates::CartesianPolar));
+        dss
+    }
 }
 
 pub struct IndexDataSet {
@@ -89,6 +96,7 @@ pub struct IndexDataSet {
     coordinates: DataSetCoordinates,
     root: Arc<RwLock<Tile>>,
     source: Arc<RwLock<dyn DataSource>>,
+    rebuild_plan: Option<RebuildPlan>,
 }
 
 impl IndexDataSet {
@@ -121,6 +129,7 @@ impl IndexDataSet {
                 TerrainLevel::base_angular_extent().round() as i32,
             ))),
             source,
+            rebuild_plan: None,
         })
     }
 
@@ -148,6 +157,16 @@ impl IndexDataSet {
         &self.work_path
     }
 
+    pub fn set_rebuild_plan(&mut self, plan: RebuildPlan) {
+        self.rebuild_plan = Some(plan);
+    }
+
+    pub fn rebuild_plan(&self) -> &RebuildPlan {
+        self.rebuild_plan
+            .as_ref()
+            .expect("rebuild plan must be set before building")
+    }
+
     pub fn get_root_tile(&mut self) -> Arc<RwLock<Tile>> {
         self.root.clone()
     }
@@ -13,15 +13,18 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 mod index;
+mod rebuild;
 mod tile;
 
 pub use index::{Index as MipIndex, IndexDataSet as MipIndexDataSet};
+pub use rebuild::RebuildPlan;
 pub use tile::{NeighborIndex, Tile as MipTile};
 
 use absolute_unit::{Angle, ArcSeconds};
-use geodesy::{GeoCenter, GeoSurface, Graticule};
+use anyhow::Result;
+use geodesy::{GeoBox, GeoCenter, GeoSurface, Graticule};
 use image::Rgb;
-use std::ops::RangeInclusive;
+use std::{ops::RangeInclusive, path::PathBuf};
 use terrain::tile::TerrainLevel;
 
 #[derive(Copy, Clone, Debug)]
@@ -30,6 +33,24 @@ pub struct Region {
     pub extent: Angle<ArcSeconds>,
 }
 
+impl Region {
+    /// The region as a box of degrees.
+    pub fn bounds(&self) -> GeoBox {
+        GeoBox::from_corner(
+            self.base.lat::<ArcSeconds>(),
+            self.base.lon::<ArcSeconds>(),
+            self.extent,
+        )
+    }
+}
+
+/// A file that a data source reads, and the lat/lon box that it covers.
+#[derive(Clone, Debug)]
+pub struct SourceInput {
+    pub path: PathBuf,
+    pub bounds: GeoBox,
+}
+
 pub trait DataSource: Send + Sync {
     // Return true if the dataset has interesting data in the given region. Mip tiles fill a grid
     // substantially larger than the typical planet, so it is expected that a significant fraction
@@ -53,4 +74,48 @@ pub trait DataSource: Send + Sync {
     fn sample_nearest_height(&self, grat: &Graticule<GeoSurface>) -> i16;
     fn compute_local_normal(&self, grat: &Graticule<GeoSurface>) -> [i16; 2];
     fn sample_color(&self, grat: &Graticule<GeoSurface>) -> Rgb<u8>;
+
+    // Datasets that load their data lazily may fail part way through sampling a tile. They
+    // should sample as if there were no data and report the failure here, which is checked
+    // after every tile.
+    fn check_sampling(&self) -> Result<()> {
+        Ok(())
+    }
+
+    // The files that this dataset is built from. We track these between runs so that we
+    // only need to rebuild the tiles that touch files that have changed. Datasets that do
+    // not list their inputs are assumed never to change.
+    fn inputs(&self) -> Vec<SourceInput> {
+        Vec::new()
+    }
+
+    // Map a lat/lon box onto the boxes of tile space that it touches. Datasets that are not
+    // laid out in lat/lon must override this.
+    fn tile_boxes(&self, bounds: &GeoBox) -> Vec<GeoBox> {
+        vec![*bounds]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use absolute_unit::{arcseconds, degrees, meters};
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_region_bounds() {
+        let region = Region {
+            base: Graticule::<GeoCenter>::new(
+                arcseconds!(degrees!(11.5)),
+                arcseconds!(degrees!(-20)),
+                meters!(0),
+            ),
+            extent: arcseconds!(degrees!(1)),
+        };
+        let bounds = region.bounds();
+        assert_abs_diff_eq!(bounds.south, 11.5, epsilon = 1e-9);
+        assert_abs_diff_eq!(bounds.west, -20., epsilon = 1e-9);
+        assert_abs_diff_eq!(bounds.north, 12.5, epsilon = 1e-9);
+        assert_abs_diff_eq!(bounds.east, -19., epsilon = 1e-9);
+    }
 }
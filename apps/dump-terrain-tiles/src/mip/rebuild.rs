@@ -0,0 +1,656 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::mip::{tile::Tile, DataSource, SourceInput};
+use absolute_unit::{arcseconds, scalar};
+use anyhow::{bail, Result};
+use geodesy::GeoBox;
+use json::JsonValue;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+use terrain::tile::TerrainLevel;
+
+// Tiles are keyed by level and base, in arcseconds.
+type TileKey = (usize, i32, i32);
+
+fn tile_key(tile: &Tile) -> TileKey {
+    (tile.level().offset(), tile.base().0, tile.base().1)
+}
+
+fn tile_bounds(tile: &Tile) -> GeoBox {
+    GeoBox::from_corner(
+        tile.base_latitude(),
+        tile.base_longitude(),
+        arcseconds!(tile.extent()),
+    )
+}
+
+/// How a tile was made: sampled directly from the data source, or mipmapped from its children.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Origin {
+    Source,
+    Mip,
+}
+
+impl Origin {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Source => "source",
+            Self::Mip => "mip",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "source" => Self::Source,
+            "mip" => Self::Mip,
+            _ => bail!("unknown tile origin in journal: {}", name),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct JournalEntry {
+    has_data: bool,
+    origin: Origin,
+}
+
+// What we knew about an input file the last time that it was fully built.
+#[derive(Clone, Debug, PartialEq)]
+struct InputRecord {
+    bounds: GeoBox,
+    size: u64,
+    modified: (u64, u32),
+}
+
+impl InputRecord {
+    fn stat(input: &SourceInput) -> Result<Self> {
+        let meta = fs::metadata(&input.path)?;
+        let modified = meta.modified()?.duration_since(UNIX_EPOCH)?;
+        Ok(Self {
+            bounds: input.bounds,
+            size: meta.len(),
+            modified: (modified.as_secs(), modified.subsec_nanos()),
+        })
+    }
+
+    fn from_json(value: &JsonValue) -> Option<(PathBuf, Self)> {
+        Some((
+            PathBuf::from(value["path"].as_str()?),
+            Self {
+                bounds: GeoBox::new(
+                    value["south"].as_f64()?,
+                    value["west"].as_f64()?,
+                    value["north"].as_f64()?,
+                    value["east"].as_f64()?,
+                ),
+                size: value["size"].as_u64()?,
+                modified: (
+                    value["modified_secs"].as_u64()?,
+                    value["modified_nanos"].as_u32()?,
+                ),
+            },
+        ))
+    }
+
+    fn as_json(&self, path: &Path) -> Result<JsonValue> {
+        let mut obj = JsonValue::new_object();
+        obj.insert("path", path.to_string_lossy().as_ref())?;
+        obj.insert("south", self.bounds.south)?;
+        obj.insert("west", self.bounds.west)?;
+        obj.insert("north", self.bounds.north)?;
+        obj.insert("east", self.bounds.east)?;
+        obj.insert("size", self.size)?;
+        obj.insert("modified_secs", self.modified.0)?;
+        obj.insert("modified_nanos", self.modified.1)?;
+        Ok(obj)
+    }
+}
+
+/// Decides which tiles of a data set need to be built on this run.
+///
+/// Finished tiles are appended to a journal in the data set directory, so that an
+/// interrupted build can pick up where it left off without trusting half written files.
+/// The size and modification time of each source file are recorded when a build finishes,
+/// so that the next run only rebuilds tiles touching files that changed, and the mips above
+/// them. A region and max level can further restrict a run to part of the planet.
+///
+/// The source manifest is only written by `finish`. If a build is interrupted, the next run
+/// still sees the changed files and rebuilds every tile that touches them, including those
+/// that made it into the journal the first time around.
+pub struct RebuildPlan {
+    journal_path: PathBuf,
+    manifest_path: PathBuf,
+    work_path: PathBuf,
+
+    // If there was no journal, this data set was built before we kept one, so anything
+    // on disk is taken as complete.
+    legacy: bool,
+    journal: Mutex<HashMap<TileKey, JournalEntry>>,
+    journal_file: Mutex<File>,
+
+    force: bool,
+    root_level: usize,
+    truncated: bool,
+    region: Option<GeoBox>,
+    region_boxes: Option<Vec<GeoBox>>,
+
+    // Tile space boxes touching source files that changed since the last build.
+    changed_boxes: Vec<GeoBox>,
+    previous_inputs: HashMap<PathBuf, InputRecord>,
+    current_inputs: HashMap<PathBuf, InputRecord>,
+
+    rebuilt: Mutex<HashSet<TileKey>>,
+}
+
+impl RebuildPlan {
+    pub fn new(
+        prefix: &str,
+        base_path: &Path,
+        work_path: &Path,
+        source: Arc<RwLock<dyn DataSource>>,
+        region: Option<GeoBox>,
+        max_level: Option<usize>,
+        force: bool,
+    ) -> Result<Self> {
+        let source = source.read();
+        let source_root = source.root_level().offset();
+        let root_level = max_level.map_or(source_root, |max| max.min(source_root));
+
+        let journal_path = base_path.join(format!("{}-journal.txt", prefix));
+        let legacy = !journal_path.exists();
+        let mut journal = HashMap::new();
+        if !legacy {
+            for (i, line) in fs::read_to_string(&journal_path)?.lines().enumerate() {
+                let parts = line.split_whitespace().collect::<Vec<_>>();
+                if parts.len() != 5 {
+                    // The last line may be cut short if we were interrupted.
+                    println!("  ignoring malformed journal line {}: '{}'", i + 1, line);
+                    continue;
+                }
+                journal.insert(
+                    (parts[0].parse()?, parts[1].parse()?, parts[2].parse()?),
+                    JournalEntry {
+                        has_data: parts[3] == "data",
+                        origin: Origin::from_name(parts[4])?,
+                    },
+                );
+            }
+        }
+        let journal_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_path)?;
+
+        let manifest_path = base_path.join(format!("{}-sources.json", prefix));
+        let mut previous_inputs = HashMap::new();
+        let have_manifest = manifest_path.exists();
+        if have_manifest {
+            let manifest = json::parse(&fs::read_to_string(&manifest_path)?)?;
+            for value in manifest["inputs"].members() {
+                if let Some((path, record)) = InputRecord::from_json(value) {
+                    previous_inputs.insert(path, record);
+                }
+            }
+        }
+        let mut current_inputs = HashMap::new();
+        for input in source.inputs() {
+            current_inputs.insert(input.path.clone(), InputRecord::stat(&input)?);
+        }
+
+        // Without a manifest, we have no idea what changed, so leave it to the journal.
+        let mut changed = Vec::new();
+        if have_manifest {
+            for (path, record) in &current_inputs {
+                if previous_inputs.get(path) != Some(record) {
+                    changed.push(record.bounds);
+                }
+            }
+            for (path, record) in &previous_inputs {
+                if !current_inputs.contains_key(path) {
+                    changed.push(record.bounds);
+                }
+            }
+        }
+        if !changed.is_empty() {
+            println!(
+                "  {} source files changed since the last build",
+                changed.len()
+            );
+        }
+        let changed_boxes = changed
+            .iter()
+            .flat_map(|bounds| source.tile_boxes(bounds))
+            .collect();
+        let region_boxes = region.map(|bounds| source.tile_boxes(&bounds));
+
+        Ok(Self {
+            journal_path,
+            manifest_path,
+            work_path: work_path.to_owned(),
+            legacy,
+            journal: Mutex::new(journal),
+            journal_file: Mutex::new(journal_file),
+            force,
+            root_level,
+            truncated: root_level < source_root,
+            region,
+            region_boxes,
+            changed_boxes,
+            previous_inputs,
+            current_inputs,
+            rebuilt: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// The deepest level that we build on this run.
+    pub fn root_level(&self) -> usize {
+        self.root_level
+    }
+
+    fn expected_origin(&self, tile: &Tile) -> Origin {
+        if tile.level().offset() == self.root_level {
+            Origin::Source
+        } else {
+            Origin::Mip
+        }
+    }
+
+    /// True if the tile is within the region being built on this run.
+    pub fn in_region(&self, tile: &Tile) -> bool {
+        match &self.region_boxes {
+            Some(boxes) => {
+                let tile_bounds = tile_bounds(tile);
+                boxes.iter().any(|bounds| bounds.intersects(&tile_bounds))
+            }
+            None => true,
+        }
+    }
+
+    /// True if the tile is missing, out of date, or was built differently than it should be.
+    /// If assume_complete is set, tiles from before we kept a journal that have no file on
+    /// disk are taken to be empty.
+    pub fn is_stale(&self, tile: &Tile, assume_complete: bool) -> bool {
+        if self.force {
+            return true;
+        }
+        let expected = self.expected_origin(tile);
+        match self.journal.lock().get(&tile_key(tile)) {
+            Some(entry) => {
+                if entry.origin != expected
+                    || (entry.has_data && !tile.file_exists(&self.work_path))
+                {
+                    return true;
+                }
+            }
+            None => {
+                if !self.legacy || !(assume_complete || tile.file_exists(&self.work_path)) {
+                    return true;
+                }
+            }
+        }
+        if expected == Origin::Source {
+            // Sampling reaches a pixel or so outside of the tile.
+            let pad = TerrainLevel::new(self.root_level).as_scale() * scalar!(2);
+            let tile_bounds = tile_bounds(tile).grow(pad);
+            self.changed_boxes
+                .iter()
+                .any(|bounds| bounds.intersects(&tile_bounds))
+        } else {
+            let rebuilt = self.rebuilt.lock();
+            tile.maybe_children()
+                .iter()
+                .flatten()
+                .any(|child| rebuilt.contains(&tile_key(&child.read())))
+        }
+    }
+
+    fn append(&self, tile: &Tile, entry: JournalEntry) -> Result<()> {
+        let (level, lat, lon) = tile_key(tile);
+        writeln!(
+            self.journal_file.lock(),
+            "{} {} {} {} {}",
+            level,
+            lat,
+            lon,
+            if entry.has_data { "data" } else { "empty" },
+            entry.origin.name()
+        )?;
+        self.journal.lock().insert(tile_key(tile), entry);
+        Ok(())
+    }
+
+    /// Note that the tile has been built and written.
+    pub fn record_built(&self, tile: &Tile) -> Result<()> {
+        self.append(
+            tile,
+            JournalEntry {
+                has_data: tile.data().is_mapped(),
+                origin: self.expected_origin(tile),
+            },
+        )?;
+        self.rebuilt.lock().insert(tile_key(tile));
+        Ok(())
+    }
+
+    /// Note that a tile that we did not need to build is complete as it is.
+    pub fn record_current(&self, tile: &Tile) -> Result<()> {
+        if self.journal.lock().contains_key(&tile_key(tile)) {
+            return Ok(());
+        }
+        self.append(
+            tile,
+            JournalEntry {
+                has_data: tile.data().is_mapped(),
+                origin: self.expected_origin(tile),
+            },
+        )
+    }
+
+    /// True if any tiles at the given level were built on this run.
+    pub fn rebuilt_level(&self, level: usize) -> bool {
+        self.rebuilt.lock().iter().any(|key| key.0 == level)
+    }
+
+    /// Called once every level has been built: compact the journal and remember the source
+    /// files that we have fully incorporated.
+    pub fn finish(&self) -> Result<()> {
+        {
+            let journal = self.journal.lock();
+            let mut keys = journal.keys().collect::<Vec<_>>();
+            keys.sort();
+            let mut content = String::new();
+            for key in keys {
+                let entry = &journal[key];
+                content += &format!(
+                    "{} {} {} {} {}\n",
+                    key.0,
+                    key.1,
+                    key.2,
+                    if entry.has_data { "data" } else { "empty" },
+                    entry.origin.name()
+                );
+            }
+            let tmp_path = self.journal_path.with_extension("txt.tmp");
+            fs::write(&tmp_path, content)?;
+            fs::rename(&tmp_path, &self.journal_path)?;
+        }
+
+        // Changes that we did not build everywhere need to be seen again next time.
+        let covered = |bounds: &GeoBox| {
+            !self.truncated && self.region.is_none_or(|region| region.covers(bounds))
+        };
+        let mut inputs = Vec::new();
+        for (path, record) in &self.current_inputs {
+            match self.previous_inputs.get(path) {
+                Some(previous) if previous != record && !covered(&record.bounds) => {
+                    inputs.push(previous.as_json(path)?)
+                }
+                None if !covered(&record.bounds) => {}
+                _ => inputs.push(record.as_json(path)?),
+            }
+        }
+        for (path, previous) in &self.previous_inputs {
+            if !self.current_inputs.contains_key(path) && !covered(&previous.bounds) {
+                inputs.push(previous.as_json(path)?);
+            }
+        }
+        let mut manifest = JsonValue::new_object();
+        manifest.insert("inputs", JsonValue::Array(inputs))?;
+        fs::write(&self.manifest_path, manifest.pretty(2))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mip::Region;
+    use absolute_unit::ArcSeconds;
+    use geodesy::{GeoSurface, Graticule};
+    use image::Rgb;
+    use std::ops::RangeInclusive;
+    use terrain::tile::ChildIndex;
+
+    struct TestSource {
+        inputs: Vec<SourceInput>,
+    }
+
+    impl DataSource for TestSource {
+        fn contains_region(&self, _region: &Region) -> bool {
+            true
+        }
+
+        fn root_level(&self) -> TerrainLevel {
+            TerrainLevel::new(1)
+        }
+
+        fn expect_intersecting_tiles(&self, _layer: usize) -> usize {
+            0
+        }
+
+        fn expect_present_tiles(&self, _layer: usize) -> RangeInclusive<usize> {
+            0..=0
+        }
+
+        fn sample_nearest_height(&self, _grat: &Graticule<GeoSurface>) -> i16 {
+            0
+        }
+
+        fn compute_local_normal(&self, _grat: &Graticule<GeoSurface>) -> [i16; 2] {
+            [0, 0]
+        }
+
+        fn sample_color(&self, _grat: &Graticule<GeoSurface>) -> Rgb<u8> {
+            Rgb([0, 0, 0])
+        }
+
+        fn inputs(&self) -> Vec<SourceInput> {
+            self.inputs.clone()
+        }
+    }
+
+    fn test_dir(name: &str) -> Result<PathBuf> {
+        let path =
+            std::env::temp_dir().join(format!("nitrogen-rebuild-{}-{}", name, std::process::id()));
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        Ok(path)
+    }
+
+    fn plan(
+        path: &Path,
+        inputs: &[SourceInput],
+        region: Option<GeoBox>,
+        max_level: Option<usize>,
+        force: bool,
+    ) -> Result<RebuildPlan> {
+        let source: Arc<RwLock<dyn DataSource>> = Arc::new(RwLock::new(TestSource {
+            inputs: inputs.to_vec(),
+        }));
+        RebuildPlan::new(
+            "test",
+            path,
+            &path.join("work"),
+            source,
+            region,
+            max_level,
+            force,
+        )
+    }
+
+    // A root tile and its four children, in ChildIndex order.
+    fn tiles() -> (Tile, Vec<Arc<RwLock<Tile>>>) {
+        let mut root = Tile::new_uninitialized(
+            "test",
+            TerrainLevel::new(0),
+            ChildIndex::SouthWest,
+            (
+                TerrainLevel::base().lat::<ArcSeconds>().round() as i32,
+                TerrainLevel::base().lon::<ArcSeconds>().round() as i32,
+            ),
+            TerrainLevel::base_angular_extent().round() as i32,
+        );
+        let children = ChildIndex::all()
+            .iter()
+            .map(|&index| root.add_child(TerrainLevel::new(1), index))
+            .collect();
+        (root, children)
+    }
+
+    fn input(path: &Path, name: &str, content: &str, bounds: GeoBox) -> Result<SourceInput> {
+        let path = path.join(name);
+        fs::write(&path, content)?;
+        Ok(SourceInput { path, bounds })
+    }
+
+    fn build_all(plan: &RebuildPlan, root: &Tile, children: &[Arc<RwLock<Tile>>]) -> Result<()> {
+        for child in children {
+            plan.record_built(&child.read())?;
+        }
+        plan.record_built(root)
+    }
+
+    #[test]
+    fn test_is_stale() -> Result<()> {
+        let path = test_dir("stale")?;
+        let inputs = vec![input(
+            &path,
+            "ne.dat",
+            "one",
+            GeoBox::new(10., 10., 11., 11.),
+        )?];
+        let (root, children) = tiles();
+        let (sw, ne) = (&children[0], &children[3]);
+
+        // Nothing has been built yet.
+        let first = plan(&path, &inputs, None, None, false)?;
+        assert!(first.is_stale(&sw.read(), false));
+        build_all(&first, &root, &children)?;
+        first.finish()?;
+
+        // Nothing has changed.
+        let second = plan(&path, &inputs, None, None, false)?;
+        assert!(!second.is_stale(&sw.read(), false));
+        assert!(!second.is_stale(&ne.read(), false));
+        assert!(!second.is_stale(&root, false));
+        let forced = plan(&path, &inputs, None, None, true)?;
+        assert!(forced.is_stale(&sw.read(), false));
+
+        // Only the tile under the changed file and the mip above it need to be rebuilt, and
+        // the mip only once the child has been.
+        fs::write(&inputs[0].path, "changed")?;
+        let third = plan(&path, &inputs, None, None, false)?;
+        assert!(!third.is_stale(&sw.read(), false));
+        assert!(third.is_stale(&ne.read(), false));
+        assert!(!third.is_stale(&root, false));
+        third.record_built(&ne.read())?;
+        assert!(third.is_stale(&root, false));
+
+        // Tiles built from the source when they should now be mips are stale.
+        let truncated = plan(&path, &inputs, None, Some(0), false)?;
+        assert!(truncated.is_stale(&sw.read(), false));
+
+        fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupted_journal() -> Result<()> {
+        let path = test_dir("journal")?;
+        let (root, children) = tiles();
+
+        let first = plan(&path, &[], None, None, false)?;
+        first.record_built(&children[0].read())?;
+        first.record_built(&children[3].read())?;
+        drop(first);
+        // Interrupted part way through writing the next entry.
+        let journal_path = path.join("test-journal.txt");
+        let mut journal = OpenOptions::new().append(true).open(&journal_path)?;
+        write!(journal, "0 {}", root.base().0)?;
+        drop(journal);
+
+        let second = plan(&path, &[], None, None, false)?;
+        assert!(!second.is_stale(&children[0].read(), false));
+        assert!(second.is_stale(&children[1].read(), false));
+        assert!(second.is_stale(&children[2].read(), false));
+        assert!(!second.is_stale(&children[3].read(), false));
+        assert!(second.is_stale(&root, false));
+
+        // Once finished, the journal is compacted and the partial line is gone.
+        build_all(&second, &root, &children)?;
+        second.finish()?;
+        let content = fs::read_to_string(&journal_path)?;
+        assert_eq!(content.lines().count(), 5);
+        assert!(content
+            .lines()
+            .all(|line| line.split_whitespace().count() == 5));
+
+        fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_finish_writes_manifest() -> Result<()> {
+        let path = test_dir("manifest")?;
+        let inside = input(&path, "inside.dat", "in", GeoBox::new(0., 0., 1., 1.))?;
+        let outside = input(&path, "outside.dat", "out", GeoBox::new(10., 10., 11., 11.))?;
+        let inputs = vec![inside.clone(), outside.clone()];
+        let (root, children) = tiles();
+
+        let first = plan(&path, &inputs, None, None, false)?;
+        build_all(&first, &root, &children)?;
+        first.finish()?;
+        let manifest = json::parse(&fs::read_to_string(path.join("test-sources.json"))?)?;
+        let mut records = manifest["inputs"]
+            .members()
+            .filter_map(InputRecord::from_json)
+            .collect::<Vec<_>>();
+        records.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].0, inside.path);
+        assert_eq!(records[0].1, InputRecord::stat(&inside)?);
+        assert_eq!(records[1].0, outside.path);
+        assert_eq!(records[1].1.bounds, outside.bounds);
+
+        // A change outside of the region we rebuilt must still be seen on the next run.
+        let previous = InputRecord::stat(&outside)?;
+        fs::write(&outside.path, "changed")?;
+        let region = Some(GeoBox::new(-1., -1., 2., 2.));
+        let second = plan(&path, &inputs, region, None, false)?;
+        build_all(&second, &root, &children)?;
+        second.finish()?;
+        let manifest = json::parse(&fs::read_to_string(path.join("test-sources.json"))?)?;
+        let outside_record = manifest["inputs"]
+            .members()
+            .filter_map(InputRecord::from_json)
+            .find(|(path, _)| path == &outside.path)
+            .map(|(_, record)| record);
+        assert_eq!(outside_record, Some(previous));
+        let third = plan(&path, &inputs, None, None, false)?;
+        assert!(third.is_stale(&children[3].read(), false));
+
+        fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+}
@@ -679,6 +679,16 @@ impl Tile {
         }
     }
 
+    // Drop whatever we have for this tile, on disk and in memory, so that it can be rebuilt.
+    pub fn discard(&mut self, directory: &Path) -> Result<()> {
+        self.data = TileData::Absent;
+        let mip_filename = self.mip_filename(directory);
+        if mip_filename.exists() {
+            fs::remove_file(&mip_filename)?;
+        }
+        Ok(())
+    }
+
     pub fn data_state(&self) -> &'static str {
         self.data.state()
     }
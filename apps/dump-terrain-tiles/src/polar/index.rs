@@ -0,0 +1,141 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::mip::{DataSource, Region, SourceInput};
+use absolute_unit::{arcseconds, ArcSeconds};
+use anyhow::Result;
+use geodesy::{GeoBox, GeoSurface, Graticule};
+use image::Rgb;
+use parking_lot::RwLock;
+use std::{ops::RangeInclusive, sync::Arc};
+use terrain::tile::{PolarProjection, Pole, TerrainLevel};
+
+// Re-slices another data source onto the polar stereographic plane used by CartesianPolar
+// tiles. The mip tree and tile sampling see plane (y, x) arcseconds where they would
+// normally see latitude and longitude, so we unproject each sample and ask the underlying
+// source for whatever is at that point on the planet.
+pub struct Index {
+    inner: Arc<RwLock<dyn DataSource>>,
+    intersecting_counts: Vec<usize>,
+}
+
+impl Index {
+    pub fn new(inner: Arc<RwLock<dyn DataSource>>) -> Arc<RwLock<Self>> {
+        let root_level = inner.read().root_level().offset();
+        let intersecting_counts = (0..=root_level).map(Self::count_intersecting).collect();
+        Arc::new(RwLock::new(Self {
+            inner,
+            intersecting_counts,
+        }))
+    }
+
+    // Unlike the spherical sources, the number of tiles that touch the polar caps is easy
+    // to compute, so there is no need to keep a table of measured counts.
+    fn count_intersecting(level: usize) -> usize {
+        let extent = TerrainLevel::new(level).angular_extent().f64();
+        let base = TerrainLevel::base().lat::<ArcSeconds>().f64();
+        let per_side = 1usize << level;
+        let mut count = 0;
+        for lat_i in 0..per_side {
+            let lat = base + lat_i as f64 * extent;
+            if lat > PolarProjection::radius_as() || lat + extent < -PolarProjection::radius_as() {
+                continue;
+            }
+            for lon_i in 0..per_side {
+                let lon = base + lon_i as f64 * extent;
+                if PolarProjection::intersects_region((lat, lon), extent) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    fn unproject(grat: &Graticule<GeoSurface>) -> Option<Graticule<GeoSurface>> {
+        let (y_as, x_as) = PolarProjection::plane_coordinates(grat);
+        PolarProjection::unproject(y_as, x_as)
+    }
+}
+
+impl DataSource for Index {
+    fn contains_region(&self, region: &Region) -> bool {
+        PolarProjection::intersects_region(
+            (
+                region.base.lat::<ArcSeconds>().f64(),
+                region.base.lon::<ArcSeconds>().f64(),
+            ),
+            arcseconds!(region.extent).f64(),
+        )
+    }
+
+    fn root_level(&self) -> TerrainLevel {
+        self.inner.read().root_level()
+    }
+
+    fn expect_intersecting_tiles(&self, layer: usize) -> usize {
+        // Nothing is built below the root level of the underlying source.
+        self.intersecting_counts.get(layer).copied().unwrap_or(0)
+    }
+
+    fn expect_present_tiles(&self, layer: usize) -> RangeInclusive<usize> {
+        let a = self.expect_intersecting_tiles(layer);
+        a..=a
+    }
+
+    fn sample_nearest_height(&self, grat: &Graticule<GeoSurface>) -> i16 {
+        Self::unproject(grat)
+            .map(|g| self.inner.read().sample_nearest_height(&g))
+            .unwrap_or(0)
+    }
+
+    fn compute_local_normal(&self, grat: &Graticule<GeoSurface>) -> [i16; 2] {
+        Self::unproject(grat)
+            .map(|g| self.inner.read().compute_local_normal(&g))
+            .unwrap_or([0; 2])
+    }
+
+    fn sample_color(&self, grat: &Graticule<GeoSurface>) -> Rgb<u8> {
+        Self::unproject(grat)
+            .map(|g| self.inner.read().sample_color(&g))
+            .unwrap_or(Rgb([0, 0, 0]))
+    }
+
+    fn check_sampling(&self) -> Result<()> {
+        self.inner.read().check_sampling()
+    }
+
+    fn inputs(&self) -> Vec<SourceInput> {
+        self.inner.read().inputs()
+    }
+
+    // Anything that reaches into a polar cap may touch any part of it, so be conservative
+    // and take the whole cap.
+    fn tile_boxes(&self, bounds: &GeoBox) -> Vec<GeoBox> {
+        let limit = PolarProjection::limit_latitude();
+        let radius = PolarProjection::radius_as() / 3600.;
+        let mut boxes = Vec::new();
+        for pole in Pole::all() {
+            let touches = match pole {
+                Pole::North => bounds.north >= limit,
+                Pole::South => bounds.south <= -limit,
+            };
+            if touches {
+                let (y, x) = PolarProjection::pole_plane_coordinates(pole);
+                let (y, x) = (y / 3600., x / 3600.);
+                boxes.push(GeoBox::new(y - radius, x - radius, y + radius, x + radius));
+            }
+        }
+        boxes
+    }
+}
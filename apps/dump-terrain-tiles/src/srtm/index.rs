@@ -13,13 +13,13 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 use crate::{
-    mip::{DataSource, Region},
+    mip::{DataSource, Region, SourceInput},
     srtm::tile::Tile,
 };
 use absolute_unit::{arcseconds, degrees, meters, ArcSeconds, Degrees, Meters};
 use anyhow::Result;
 use approx::assert_relative_eq;
-use geodesy::{Cartesian, GeoCenter, GeoSurface, Graticule};
+use geodesy::{Cartesian, GeoBox, GeoCenter, GeoSurface, Graticule};
 use image::Rgb;
 use nalgebra::{Vector2, Vector3};
 use parking_lot::RwLock;
@@ -100,6 +100,19 @@ impl DataSource for Index {
     fn sample_color(&self, _grat: &Graticule<GeoSurface>) -> Rgb<u8> {
         Rgb([0; 3])
     }
+
+    fn inputs(&self) -> Vec<SourceInput> {
+        self.tiles
+            .iter()
+            .map(|tile| {
+                let (lat, lon) = (tile.latitude() as f64, tile.longitude() as f64);
+                SourceInput {
+                    path: tile.path().to_owned(),
+                    bounds: GeoBox::new(lat, lon, lat + 1., lon + 1.),
+                }
+            })
+            .collect()
+    }
 }
 
 impl Index {
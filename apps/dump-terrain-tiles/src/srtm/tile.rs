@@ -217,7 +217,6 @@ impl Tile {
         self.longitude
     }
 
-    #[allow(unused)]
     pub fn path(&self) -> &Path {
         &self.path
     }
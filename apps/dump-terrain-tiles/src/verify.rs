@@ -0,0 +1,171 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{collect_tiles_at_level, map_all_available_tile, mip::MipIndexDataSet};
+use anyhow::Result;
+use parking_lot::RwLock;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use terrain::tile::{LayerPackFile, TerrainLevel, TilePixelFormat};
+
+/// Check that the layer pack for each level indexes exactly the non-empty tiles in the
+/// work directory, and that the data it stores for each matches the tile on disk. Returns
+/// the number of problems found, after printing each of them.
+pub fn verify_layer_packs(
+    dataset: Arc<RwLock<MipIndexDataSet>>,
+    root_level: usize,
+    serialize: bool,
+) -> Result<usize> {
+    let mut error_count = 0;
+    for target_level in 0..=root_level {
+        let mut report = |msg: String| {
+            println!("  L{:02}: {}", target_level, msg);
+            error_count += 1;
+        };
+
+        let mut current_tiles = Vec::new();
+        let mut offset = 0;
+        let root_tile = dataset.write().get_root_tile();
+        collect_tiles_at_level(target_level, 0, root_tile, &mut offset, &mut current_tiles)?;
+        map_all_available_tile(
+            dataset.read().kind(),
+            &mut current_tiles,
+            dataset.read().work_path(),
+            serialize,
+        )?;
+        let tiles = current_tiles
+            .iter()
+            .filter(|(tile, _)| tile.read().data().is_mapped())
+            .map(|(tile, _)| (tile.read().base(), tile.clone()))
+            .collect::<HashMap<_, _>>();
+
+        let layer_pack_path = dataset.read().base_path().join(format!(
+            "{}-L{:02}.mip",
+            dataset.read().prefix(),
+            target_level
+        ));
+        if !layer_pack_path.exists() {
+            if !tiles.is_empty() {
+                report(format!(
+                    "missing layer pack {:?} for {} tiles",
+                    layer_pack_path,
+                    tiles.len()
+                ));
+            }
+            continue;
+        }
+        let mut pack = match LayerPackFile::open(&layer_pack_path) {
+            Ok(pack) => pack,
+            Err(e) => {
+                report(format!("{}", e));
+                continue;
+            }
+        };
+        if pack.terrain_level().offset() != target_level {
+            report(format!(
+                "pack is for level {}",
+                pack.terrain_level().offset()
+            ));
+        }
+        let expect_extent = TerrainLevel::new(target_level).angular_extent().round() as i32;
+        if pack.angular_extent_as() != expect_extent {
+            report(format!(
+                "pack has angular extent {}, expected {}",
+                pack.angular_extent_as(),
+                expect_extent
+            ));
+        }
+
+        // The index must not repeat tiles or overlap their data.
+        let mut seen = HashSet::new();
+        let mut extents = Vec::new();
+        let data_extent = pack.tile_data_extent();
+        for entry in pack.entries() {
+            if !seen.insert(entry.base) {
+                report(format!("tile {:?} is in the index twice", entry.base));
+            }
+            if entry.extent.start > entry.extent.end
+                || entry.extent.start < data_extent.start
+                || entry.extent.end > data_extent.end
+            {
+                report(format!(
+                    "tile {:?} data at {:?} is outside of {:?}",
+                    entry.base, entry.extent, data_extent
+                ));
+            } else {
+                extents.push(entry.extent.clone());
+            }
+        }
+        extents.sort_by_key(|extent| extent.start);
+        for pair in extents.windows(2) {
+            if pair[0].end > pair[1].start {
+                report(format!(
+                    "tile data at {:?} and {:?} overlap",
+                    pair[0], pair[1]
+                ));
+            }
+        }
+
+        // And must match the tiles that we built.
+        for entry in pack.entries().to_owned() {
+            let tile = match tiles.get(&entry.base) {
+                Some(tile) => tile.read(),
+                None => {
+                    report(format!("tile {:?} has no matching work tile", entry.base));
+                    continue;
+                }
+            };
+            if tile.index_in_parent().to_index() as u32 != entry.index_in_parent {
+                report(format!(
+                    "tile {:?} is child {} in the pack, but child {} in the tree",
+                    entry.base,
+                    entry.index_in_parent,
+                    tile.index_in_parent().to_index()
+                ));
+            }
+            let data = match pack.decode_tile(&entry) {
+                Ok(data) => data,
+                Err(e) => {
+                    report(format!("tile {:?} cannot be read: {}", entry.base, e));
+                    continue;
+                }
+            };
+            // Packs from before version 2 store samples exactly as the work tiles do.
+            let work_format = TilePixelFormat::legacy(dataset.read().kind());
+            let expect =
+                work_format.convert(tile.raw_data(), pack.pixel_format().unwrap_or(work_format))?;
+            if data != expect {
+                report(format!(
+                    "tile {:?} data does not match {:?}",
+                    entry.base,
+                    tile.mip_filename(dataset.read().work_path())
+                ));
+            }
+        }
+        for base in tiles.keys() {
+            if !seen.contains(base) {
+                report(format!("work tile {:?} is not in the pack", base));
+            }
+        }
+        println!(
+            "  L{:02}: checked {} tiles in {:?}",
+            target_level,
+            pack.entries().len(),
+            layer_pack_path
+        );
+    }
+    Ok(error_count)
+}
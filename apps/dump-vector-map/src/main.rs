@@ -0,0 +1,70 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+use vector_map::{FeatureKind, VectorMap, VectorMapBuilder};
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "dump-vector-map",
+    about = "Build vector map packs from GeoJSON, or show what is in them."
+)]
+struct Opt {
+    /// Build a pack at this path from the inputs, which are given as kind=path to a GeoJSON
+    /// FeatureCollection, e.g. airport=airports.geojson. Without this, the inputs are packs
+    /// to summarize.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// GeoJSON files to pack, or packs to summarize.
+    inputs: Vec<String>,
+}
+
+fn build(output: &Path, inputs: &[String]) -> Result<()> {
+    let mut builder = VectorMapBuilder::default();
+    for input in inputs {
+        let (kind, path) = input
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected an input as kind=path, not '{}'", input))?;
+        let kind = FeatureKind::from_name(kind)?;
+        let count = builder.push_geojson(kind, &fs::read_to_string(path)?)?;
+        println!("{}: {} {} features", path, count, kind.name());
+    }
+    builder.write(output)?;
+    println!(
+        "wrote {} features to {}",
+        builder.feature_count(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    if let Some(output) = &opt.output {
+        return build(output, &opt.inputs);
+    }
+    for input in &opt.inputs {
+        let mut map = VectorMap::default();
+        map.add_pack(&fs::read(input)?)?;
+        println!("{}:", input);
+        print!("{}", map.report());
+    }
+    Ok(())
+}
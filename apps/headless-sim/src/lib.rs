@@ -0,0 +1,138 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use animate::{TimeStep, Timeline};
+use anyhow::Result;
+use event_mapper::EventMapper;
+use input::{HeadlessInput, InputTarget};
+use orrery::Orrery;
+use runtime::{Extension, Runtime, ScriptCompletions};
+use vehicle::{
+    AirbrakeEffector, BayEffector, FlapsEffector, GearEffector, HookEffector, PitchInceptor,
+    PowerSystem, RollInceptor, YawInceptor,
+};
+
+/// The sim-only subset of the engine: time, orrery, animation, bindings, vehicle systems
+/// and the script herder. Nothing loaded here needs a window or a GPU, so this can run
+/// flight-model and scenario tests in CI containers with no display.
+pub struct HeadlessSim;
+
+impl Extension for HeadlessSim {
+    fn init(runtime: &mut Runtime) -> Result<()> {
+        runtime
+            .load_extension::<HeadlessInput>()?
+            .load_extension::<InputTarget>()?
+            .load_extension::<EventMapper>()?
+            .load_extension::<Orrery>()?
+            .load_extension::<Timeline>()?
+            .load_extension::<TimeStep>()?
+            .load_extension::<PitchInceptor>()?
+            .load_extension::<RollInceptor>()?
+            .load_extension::<YawInceptor>()?
+            .load_extension::<PowerSystem>()?
+            .load_extension::<AirbrakeEffector>()?
+            .load_extension::<BayEffector>()?
+            .load_extension::<FlapsEffector>()?
+            .load_extension::<GearEffector>()?
+            .load_extension::<HookEffector>()?;
+        Ok(())
+    }
+}
+
+/// Summary of a headless run.
+#[derive(Debug, Default)]
+pub struct HeadlessReport {
+    pub ticks_run: usize,
+    pub script_errors: Vec<String>,
+}
+
+impl HeadlessReport {
+    pub fn is_success(&self) -> bool {
+        self.script_errors.is_empty()
+    }
+}
+
+impl HeadlessSim {
+    /// Run up to `ticks` fixed sim steps, collecting any script failures along the way.
+    /// The frame schedule is run after every tick so that script completions are cleared
+    /// the same way they would be with a window.
+    pub fn run_ticks(runtime: &mut Runtime, ticks: usize) -> HeadlessReport {
+        let mut report = HeadlessReport::default();
+        for _ in 0..ticks {
+            if TimeStep::run_sim_ticks(runtime, 1) == 0 {
+                break;
+            }
+            report.ticks_run += 1;
+            for completion in runtime.resource::<ScriptCompletions>() {
+                if let Some(err) = completion.result.error() {
+                    report.script_errors.push(err.to_owned());
+                }
+            }
+            runtime.run_frame_once();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use input::{ElementState, InputEvent, ModifiersState, VirtualKeyCode};
+
+    #[test]
+    fn test_headless_bindings() -> Result<()> {
+        let mut runtime = Runtime::default();
+        runtime.load_extension::<HeadlessSim>()?;
+        let player = runtime
+            .spawn_named("player")?
+            .insert_named(PitchInceptor::default())?
+            .id();
+        runtime.run_string(
+            r#"bindings.bind("+Up", "@player.stick_pitch.key_move_back(pressed)");"#,
+        )?;
+        runtime.run_startup();
+
+        runtime
+            .resource_mut::<HeadlessInput>()
+            .push_event(InputEvent::KeyboardKey {
+                scancode: 0,
+                virtual_keycode: VirtualKeyCode::Up,
+                press_state: ElementState::Pressed,
+                modifiers_state: ModifiersState::empty(),
+                window_focused: true,
+            });
+        let report = HeadlessSim::run_ticks(&mut runtime, 30);
+        assert!(report.is_success());
+        assert_eq!(report.ticks_run, 30);
+        assert!(runtime.get::<PitchInceptor>(player).position() > 0.);
+        Ok(())
+    }
+
+    #[test]
+    fn test_headless_exit_and_errors() -> Result<()> {
+        let mut runtime = Runtime::default();
+        runtime.load_extension::<HeadlessSim>()?;
+        runtime.run_startup();
+
+        runtime.run_string("time.not_a_method()")?;
+        let report = HeadlessSim::run_ticks(&mut runtime, 2);
+        assert_eq!(report.ticks_run, 2);
+        assert_eq!(report.script_errors.len(), 1);
+
+        runtime.run_string("exit()")?;
+        let report = HeadlessSim::run_ticks(&mut runtime, 10);
+        assert_eq!(report.ticks_run, 1);
+        Ok(())
+    }
+}
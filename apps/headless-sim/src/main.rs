@@ -0,0 +1,83 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{bail, Result};
+use headless_sim::HeadlessSim;
+use runtime::{Runtime, StartupOpts};
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+
+/// Run the simulation without a window or GPU
+#[derive(Clone, Debug, StructOpt)]
+#[structopt(name = "headless-sim")]
+struct Opt {
+    /// Number of sim ticks to run before exiting
+    #[structopt(short, long, default_value = "600")]
+    ticks: usize,
+
+    /// Exit successfully, even if a script fails
+    #[structopt(long)]
+    allow_errors: bool,
+
+    #[structopt(flatten)]
+    startup_opts: StartupOpts,
+
+    /// Scripts to run after startup, in order; use - to read from stdin
+    #[structopt(parse(from_os_str))]
+    scripts: Vec<PathBuf>,
+}
+
+fn read_script(path: &Path) -> Result<String> {
+    Ok(if path.as_os_str() == "-" {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        buffer
+    } else {
+        fs::read_to_string(path)?
+    })
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    env_logger::init();
+
+    let mut runtime = Runtime::default();
+    runtime
+        .insert_resource(opt.startup_opts.clone())
+        .load_extension::<HeadlessSim>()?
+        .load_extension::<StartupOpts>()?;
+    runtime.run_startup();
+
+    // Queue scripts after startup so that they run in the sim phase, where we can see the
+    // completions and report failures.
+    for path in &opt.scripts {
+        runtime.run_string(&read_script(path)?)?;
+    }
+
+    let report = HeadlessSim::run_ticks(&mut runtime, opt.ticks);
+    runtime.run_shutdown();
+
+    println!("ran {} of {} ticks", report.ticks_run, opt.ticks);
+    for err in &report.script_errors {
+        println!("script error: {}", err);
+    }
+    if !report.is_success() && !opt.allow_errors {
+        bail!("{} script(s) failed", report.script_errors.len());
+    }
+    Ok(())
+}
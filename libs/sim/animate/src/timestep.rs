@@ -15,7 +15,7 @@
 use anyhow::Result;
 use bevy_ecs::prelude::*;
 use nitrous::{inject_nitrous_resource, method, NitrousResource};
-use runtime::{Extension, Runtime};
+use runtime::{ExitRequest, Extension, Runtime, ScriptTimers};
 use std::time::{Duration, Instant};
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash, SystemLabel)]
@@ -102,13 +102,33 @@ impl TimeStep {
         }
     }
 
+    /// Step the sim forward by exactly `ticks` steps, without reference to wall time. This is
+    /// what we want when running headless, where results should not depend on host speed.
+    /// Stops early if a script requests exit. Returns the number of ticks that were run.
+    pub fn run_sim_ticks(runtime: &mut Runtime, ticks: usize) -> usize {
+        for tick in 0..ticks {
+            if !runtime.resource::<ExitRequest>().still_running() {
+                return tick;
+            }
+            {
+                let mut ts = runtime.resource_mut::<TimeStep>();
+                let dt = ts.sim_step;
+                ts.next_sim_time += dt;
+                ts.real_time = Instant::now();
+            }
+            runtime.run_sim_once();
+        }
+        ticks
+    }
+
     pub fn need_step(&self) -> bool {
         self.sim_time + self.sim_step < self.next_sim_time
     }
 
-    pub fn sys_tick_time(mut timestep: ResMut<TimeStep>) {
+    pub fn sys_tick_time(mut timestep: ResMut<TimeStep>, mut timers: ResMut<ScriptTimers>) {
         let dt = timestep.sim_step;
         timestep.sim_time += dt;
+        timers.advance(dt);
     }
 
     pub fn sim_start_time(&self) -> &Instant {
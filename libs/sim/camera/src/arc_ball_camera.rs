@@ -12,16 +12,19 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
-use crate::camera_impl::CameraStep;
+use crate::{
+    camera_impl::CameraStep,
+    locations::{CameraView, Locations},
+};
 use absolute_unit::{
     degrees, meters, radians, scalar, Degrees, Length, LengthUnit, Meters, Radians,
 };
-use anyhow::{bail, ensure, Result};
+use anyhow::{anyhow, ensure, Result};
 use bevy_ecs::prelude::*;
 use geodesy::{Cartesian, GeoCenter, GeoSurface, Graticule, Target};
 use measure::WorldSpaceFrame;
 use nalgebra::{Unit as NUnit, UnitQuaternion, Vector3};
-use nitrous::{inject_nitrous_component, method, NitrousComponent};
+use nitrous::{inject_nitrous_component, method, HeapRef, NitrousComponent};
 use runtime::{Extension, Runtime};
 use std::{f64::consts::PI, fmt::Write};
 
@@ -88,33 +91,32 @@ impl ArcBallController {
         WorldSpaceFrame::new(eye, forward)
     }
 
+    /// Look up the target of a named location or bookmark.
     #[method]
-    pub fn notable_location(&self, name: &str) -> Result<Graticule<GeoSurface>> {
-        Ok(match name {
-            "ISS" => Graticule::<GeoSurface>::new(
-                degrees!(27.9880704),
-                degrees!(-86.9245623),
-                meters!(408_000.),
-            ),
-            "Everest" => Graticule::<GeoSurface>::new(
-                degrees!(27.9880704),
-                degrees!(-86.9245623),
-                meters!(8000.),
-            ),
-            "London" => Graticule::<GeoSurface>::new(degrees!(51.5), degrees!(-0.1), meters!(100.)),
-            _ => bail!("unknown notable location: {}", name),
-        })
+    pub fn notable_location(&self, name: &str, heap: HeapRef) -> Result<Graticule<GeoSurface>> {
+        let locations = heap
+            .maybe_resource::<Locations>()
+            .ok_or_else(|| anyhow!("the Locations extension is not loaded"))?;
+        Ok(locations.view(name)?.target)
     }
 
+    /// Look up a good eye position for viewing a named location or bookmark.
     #[method]
-    pub fn eye_for(&self, kind: &str) -> Graticule<Target> {
-        match kind {
-            "ISS" => Graticule::<Target>::new(degrees!(58), degrees!(308.0), meters!(1_308.)),
-            "Everest" => Graticule::<Target>::new(degrees!(9), degrees!(130), meters!(12_000.)),
-            _ => Graticule::<Target>::new(degrees!(11.5), degrees!(149.5), meters!(67_668.)),
+    pub fn eye_for(&self, name: &str, heap: HeapRef) -> Graticule<Target> {
+        match heap
+            .maybe_resource::<Locations>()
+            .and_then(|l| l.lookup(name))
+        {
+            Some(view) => view.eye,
+            None => Graticule::<Target>::new(degrees!(11.5), degrees!(149.5), meters!(67_668.)),
         }
     }
 
+    pub fn set_view(&mut self, view: &CameraView) -> Result<()> {
+        self.set_target(view.target);
+        self.set_eye(view.eye)
+    }
+
     #[method]
     pub fn target(&self) -> Graticule<GeoSurface> {
         self.target
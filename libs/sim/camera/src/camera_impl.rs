@@ -12,18 +12,24 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    arc_ball_camera::ArcBallController,
+    locations::{CameraView, Locations},
+};
 use absolute_unit::{
     degrees, meters, radians, Angle, AngleUnit, Degrees, Kilometers, Length, LengthUnit, Meters,
     Radians,
 };
-use anyhow::Result;
+use anyhow::{anyhow, ensure, Result};
 use bevy_ecs::prelude::*;
 use geodesy::{Cartesian, GeoCenter};
 use geometry::Plane;
+use log::warn;
 use measure::WorldSpaceFrame;
 use nalgebra::{Isometry3, Matrix4, Perspective3, Point3, UnitQuaternion, Vector3};
 use nitrous::{
-    inject_nitrous_component, inject_nitrous_resource, method, NitrousComponent, NitrousResource,
+    inject_nitrous_component, inject_nitrous_resource, method, EntityName, HeapMut,
+    NitrousComponent, NitrousResource,
 };
 use runtime::{Extension, Runtime};
 use std::f64::consts::PI;
@@ -77,6 +83,8 @@ impl HudCamera {
     // FIXME: passthrough, maybe? Implement Hud Camera support.
 }
 
+/// Marks the entity whose WorldSpaceFrame the screen camera looks out of. There should
+/// only be one; use `camera.attach(name)` to move it.
 #[derive(Clone, Debug, Default, Component)]
 pub struct ScreenCameraController;
 
@@ -97,6 +105,9 @@ pub struct ScreenCamera {
     forward: Vector3<f64>,
     up: Vector3<f64>,
     right: Vector3<f64>,
+
+    // The number of controllers we last saw, so that we only warn when it changes.
+    controller_count: Option<usize>,
 }
 
 #[inject_nitrous_resource]
@@ -124,6 +135,8 @@ impl ScreenCamera {
             forward: Vector3::new(0f64, 0f64, -1f64),
             up: Vector3::new(0f64, 1f64, 0f64),
             right: Vector3::new(1f64, 0f64, 0f64),
+
+            controller_count: None,
         }
     }
 
@@ -151,10 +164,96 @@ impl ScreenCamera {
         self.exposure /= 1.1;
     }
 
+    /// Look through the named entity, which must have a WorldSpaceFrame. This is usually
+    /// an entity with a camera controller, such as arcball, cockpit, chase, flyby or free.
+    #[method]
+    pub fn attach(&mut self, name: &str, mut heap: HeapMut) -> Result<()> {
+        let entity = heap
+            .maybe_entity_by_name(name)
+            .ok_or_else(|| anyhow!("no entity named {} to attach the camera to", name))?;
+        ensure!(
+            heap.maybe_get::<WorldSpaceFrame>(entity).is_some(),
+            "cannot attach the camera to {}: it has no frame",
+            name
+        );
+        let current = heap
+            .query::<(Entity, &ScreenCameraController)>()
+            .iter(heap.world())
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        for prior in current {
+            heap.entity_mut(prior).remove::<ScreenCameraController>();
+        }
+        heap.entity_mut(entity).insert(ScreenCameraController);
+        Ok(())
+    }
+
+    pub(crate) fn attached_entity(heap: &mut HeapMut) -> Result<Entity> {
+        let entity = heap
+            .query::<(Entity, &ScreenCameraController)>()
+            .iter(heap.world())
+            .map(|(entity, _)| entity)
+            .next();
+        entity.ok_or_else(|| anyhow!("the camera is not attached to anything"))
+    }
+
+    /// Save the current arcball target and eye, and the fov, as a bookmark, then write
+    /// out the bookmarks file. Return to it later with `camera.go_to(name)`.
+    #[method]
+    pub fn bookmark(&mut self, name: &str, mut heap: HeapMut) -> Result<()> {
+        let entity = Self::attached_entity(&mut heap)?;
+        let arcball = heap
+            .maybe_get::<ArcBallController>(entity)
+            .ok_or_else(|| anyhow!("bookmarks are only supported from an arcball camera"))?;
+        let view =
+            CameraView::new(arcball.target(), arcball.eye()).with_fov_y(degrees!(self.fov_y));
+        heap.maybe_resource_mut::<Locations>()
+            .ok_or_else(|| anyhow!("the Locations extension is not loaded"))?
+            .add_bookmark(name, view)
+    }
+
+    /// Point the arcball camera at a named location or bookmark.
+    #[method]
+    pub fn go_to(&mut self, name: &str, mut heap: HeapMut) -> Result<()> {
+        let view = heap
+            .maybe_resource::<Locations>()
+            .ok_or_else(|| anyhow!("the Locations extension is not loaded"))?
+            .view(name)?
+            .to_owned();
+        let entity = Self::attached_entity(&mut heap)?;
+        heap.maybe_get_mut::<ArcBallController>(entity)
+            .ok_or_else(|| {
+                anyhow!(
+                    "the camera must be attached to an arcball to go to {}",
+                    name
+                )
+            })?
+            .set_view(&view)?;
+        if let Some(fov_y) = view.fov_y {
+            self.set_fov_y(fov_y);
+        }
+        Ok(())
+    }
+
+    /// The name of the entity the camera is looking through.
+    #[method]
+    pub fn attached(&self, mut heap: HeapMut) -> String {
+        let name = heap
+            .query::<(&EntityName, &ScreenCameraController)>()
+            .iter(heap.world())
+            .map(|(name, _)| name.name().to_owned())
+            .next();
+        name.unwrap_or_default()
+    }
+
     pub fn exposure(&self) -> f32 {
         self.exposure as f32
     }
 
+    pub fn set_exposure(&mut self, exposure: f64) {
+        self.exposure = exposure;
+    }
+
     pub fn fov_y(&self) -> Angle<Radians> {
         self.fov_y
     }
@@ -300,11 +399,20 @@ impl ScreenCamera {
     // Apply interpreted inputs from prior stage; apply new world position.
     fn sys_apply_input(
         mut camera: ResMut<ScreenCamera>,
-        query: Query<(&WorldSpaceFrame, &ScreenCameraController)>,
+        query: Query<&WorldSpaceFrame, With<ScreenCameraController>>,
     ) {
-        let (frame, _) = query.single();
         camera.apply_input_state();
-        camera.update_frame(frame);
+        let count = query.iter().count();
+        if count != 1 && camera.controller_count != Some(count) {
+            warn!(
+                "screen camera needs exactly one controller, but found {}",
+                count
+            );
+        }
+        camera.controller_count = Some(count);
+        if let Ok(frame) = query.get_single() {
+            camera.update_frame(frame);
+        }
     }
 
     // Apply updated system config, e.g. aspect
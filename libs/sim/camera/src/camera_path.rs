@@ -0,0 +1,697 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    arc_ball_camera::{ArcBallController, ArcBallStep},
+    camera_impl::{ScreenCamera, ScreenCameraController},
+    locations::CameraView,
+};
+use absolute_unit::{degrees, meters, radians, Angle, Degrees};
+use animate::TimeStep;
+use anyhow::{anyhow, bail, ensure, Result};
+use bevy_ecs::prelude::*;
+use futures::future::{ready, FutureExt};
+use geodesy::{GeoSurface, Graticule, Target};
+use json::{object, JsonValue};
+use log::error;
+use nitrous::{inject_nitrous_resource, method, HeapMut, NitrousResource, Value};
+use parking_lot::RwLock;
+use runtime::{Extension, Runtime};
+use std::{f64::consts::PI, fmt::Write, fs, str::FromStr, sync::Arc, time::Duration};
+use triggered::{trigger, Trigger};
+
+// Camera paths are saved as json, so that flythroughs can be checked in next to the
+// scripts that use them:
+//
+// {
+//   "interpolation": "catmull_rom",
+//   "keys": [
+//     {
+//       "time": 0,
+//       "target": { "latitude": 27.988, "longitude": 86.925, "height": 8849 },
+//       "eye": { "latitude": 9, "longitude": 130, "distance": 12000 },
+//       "fov": 90,
+//       "exposure": 0.0001
+//     }
+//   ]
+// }
+//
+// Times are in seconds from the start of the path; other units are as for locations.
+
+/// How to get from one key to the next.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PathInterpolation {
+    Linear,
+
+    // A curve through every key, with velocity carried smoothly through each.
+    CatmullRom,
+
+    // Each span is a cubic Bezier with its inner control points set from the neighbouring
+    // keys, so the camera keeps moving through each key. Unlike CatmullRom, the control
+    // points ignore key times, so unevenly spaced keys change speed at each key.
+    Bezier,
+}
+
+impl FromStr for PathInterpolation {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "linear" => Self::Linear,
+            "catmull_rom" => Self::CatmullRom,
+            "bezier" => Self::Bezier,
+            _ => bail!(
+                "unknown path interpolation '{}'; expected linear, catmull_rom, or bezier",
+                s
+            ),
+        })
+    }
+}
+
+impl PathInterpolation {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Linear => "linear",
+            Self::CatmullRom => "catmull_rom",
+            Self::Bezier => "bezier",
+        }
+    }
+}
+
+/// The full state of the camera at one point on a path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraKey {
+    pub time: f64,
+    pub target: Graticule<GeoSurface>,
+    pub eye: Graticule<Target>,
+    pub fov_y: Angle<Degrees>,
+    pub exposure: f64,
+}
+
+// The number of values we interpolate for each key.
+const CHANNELS: usize = 8;
+
+impl CameraKey {
+    // Distances and exposure are interpolated on a log scale, so that moving from 1km out
+    // to 1000km out does not cover almost all of the distance in the first moment.
+    fn channels(&self) -> [f64; CHANNELS] {
+        [
+            self.target.latitude.f64(),
+            self.target.longitude.f64(),
+            meters!(self.target.distance).f64(),
+            self.eye.latitude.f64(),
+            self.eye.longitude.f64(),
+            meters!(self.eye.distance).f64().max(0.01).ln(),
+            self.fov_y.f64(),
+            self.exposure.max(f64::MIN_POSITIVE).ln(),
+        ]
+    }
+
+    fn from_channels(time: f64, c: &[f64; CHANNELS]) -> Self {
+        Self {
+            time,
+            target: Graticule::<GeoSurface>::new(radians!(c[0]), radians!(c[1]), meters!(c[2])),
+            eye: Graticule::<Target>::new(radians!(c[3]), radians!(c[4]), meters!(c[5].exp())),
+            fov_y: degrees!(c[6]),
+            exposure: c[7].exp(),
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut value = CameraView::new(self.target, self.eye)
+            .with_fov_y(self.fov_y)
+            .to_json();
+        value["time"] = self.time.into();
+        value["exposure"] = self.exposure.into();
+        value
+    }
+
+    fn from_json(index: usize, value: &JsonValue) -> Result<Self> {
+        let name = format!("key {}", index);
+        let view = CameraView::from_json(&name, value)?;
+        Ok(Self {
+            time: value["time"]
+                .as_f64()
+                .ok_or_else(|| anyhow!("camera path {} is missing a time", name))?,
+            target: view.target,
+            eye: view.eye,
+            fov_y: view
+                .fov_y
+                .ok_or_else(|| anyhow!("camera path {} is missing a fov", name))?,
+            exposure: value["exposure"]
+                .as_f64()
+                .ok_or_else(|| anyhow!("camera path {} is missing an exposure", name))?,
+        })
+    }
+}
+
+fn wrap_angle(a: f64) -> f64 {
+    let a = (a + PI).rem_euclid(2. * PI) - PI;
+    if a == -PI {
+        PI
+    } else {
+        a
+    }
+}
+
+#[derive(Debug)]
+struct Playback {
+    time: f64,
+    // Seconds of path per sim step, or None to play along with sim time.
+    fixed_step: Option<f64>,
+    trigger: Trigger,
+}
+
+#[derive(Debug)]
+struct Recording {
+    interval: Duration,
+    last: Option<Duration>,
+}
+
+/// Record the screen camera's arcball view into a path of timed keys, then fly it back
+/// along a smooth spline through them, e.g. for trailers or repeatable flythroughs.
+#[derive(Debug, NitrousResource)]
+pub struct CameraPath {
+    keys: Vec<CameraKey>,
+    interpolation: PathInterpolation,
+
+    // The sim time that corresponds to the start of the path, when recording.
+    origin: Option<Duration>,
+
+    recording: Option<Recording>,
+    playback: Option<Playback>,
+
+    #[property]
+    looping: bool,
+}
+
+impl Default for CameraPath {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            interpolation: PathInterpolation::CatmullRom,
+            origin: None,
+            recording: None,
+            playback: None,
+            looping: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, SystemLabel)]
+pub enum CameraPathStep {
+    Apply,
+}
+
+impl Extension for CameraPath {
+    fn init(runtime: &mut Runtime) -> Result<()> {
+        runtime.insert_named_resource("camera_path", CameraPath::default());
+        runtime.add_sim_system(
+            Self::sys_apply_path
+                .label(CameraPathStep::Apply)
+                .before(ArcBallStep::ApplyInput),
+        );
+        Ok(())
+    }
+}
+
+#[inject_nitrous_resource]
+impl CameraPath {
+    pub fn keys(&self) -> &[CameraKey] {
+        &self.keys
+    }
+
+    /// Add a key, keeping the keys in time order. A key at the same time as an existing
+    /// key replaces it.
+    pub fn insert_key(&mut self, key: CameraKey) -> Result<()> {
+        ensure!(
+            key.time.is_finite() && key.time >= 0.,
+            "camera path keys need a non-negative time, not {}",
+            key.time
+        );
+        match self
+            .keys
+            .binary_search_by(|k| k.time.partial_cmp(&key.time).unwrap())
+        {
+            Ok(i) => self.keys[i] = key,
+            Err(i) => self.keys.insert(i, key),
+        }
+        Ok(())
+    }
+
+    /// The length of the path, in seconds.
+    #[method]
+    pub fn duration(&self) -> f64 {
+        self.keys.last().map(|k| k.time).unwrap_or(0.)
+    }
+
+    #[method]
+    pub fn key_count(&self) -> i64 {
+        self.keys.len() as i64
+    }
+
+    #[method]
+    pub fn interpolation(&self) -> String {
+        self.interpolation.name().to_owned()
+    }
+
+    /// One of linear, catmull_rom, or bezier.
+    #[method]
+    pub fn set_interpolation(&mut self, interpolation: &str) -> Result<()> {
+        self.interpolation = PathInterpolation::from_str(interpolation)?;
+        Ok(())
+    }
+
+    /// Remove all keys and stop any recording or playback.
+    #[method]
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.origin = None;
+        self.recording = None;
+        self.stop();
+    }
+
+    #[method]
+    pub fn remove_key(&mut self, index: i64) -> Result<()> {
+        ensure!(
+            index >= 0 && (index as usize) < self.keys.len(),
+            "no camera path key {}",
+            index
+        );
+        self.keys.remove(index as usize);
+        Ok(())
+    }
+
+    #[method]
+    pub fn list(&self) -> Result<String> {
+        let mut out = String::new();
+        for (i, key) in self.keys.iter().enumerate() {
+            writeln!(
+                out,
+                "{}: {:0.2}s target {} eye {} fov {:0.1} exposure {:e}",
+                i,
+                key.time,
+                key.target,
+                key.eye,
+                key.fov_y.f64(),
+                key.exposure
+            )?;
+        }
+        Ok(out)
+    }
+
+    fn capture(heap: &mut HeapMut, time: f64) -> Result<CameraKey> {
+        let entity = ScreenCamera::attached_entity(heap)?;
+        let arcball = heap
+            .maybe_get::<ArcBallController>(entity)
+            .ok_or_else(|| anyhow!("camera paths can only be recorded from an arcball camera"))?;
+        let camera = heap.resource::<ScreenCamera>();
+        Ok(CameraKey {
+            time,
+            target: arcball.target(),
+            eye: arcball.eye(),
+            fov_y: degrees!(camera.fov_y()),
+            exposure: f64::from(camera.exposure()),
+        })
+    }
+
+    fn sim_now(heap: &HeapMut) -> Duration {
+        heap.resource::<TimeStep>().sim_duration()
+    }
+
+    /// Add a key with the current view. The first key is at time zero and later keys are
+    /// placed by how much sim time has passed since.
+    #[method]
+    pub fn key(&mut self, mut heap: HeapMut) -> Result<()> {
+        let now = Self::sim_now(&heap);
+        let origin = *self.origin.get_or_insert(now);
+        let time = now.saturating_sub(origin).as_secs_f64();
+        let key = Self::capture(&mut heap, time)?;
+        self.insert_key(key)
+    }
+
+    /// Add a key with the current view at the given number of seconds into the path.
+    #[method]
+    pub fn key_at(&mut self, time: f64, mut heap: HeapMut) -> Result<()> {
+        let key = Self::capture(&mut heap, time)?;
+        self.insert_key(key)
+    }
+
+    /// Add a key every `interval` seconds of sim time, while the camera is flown by hand,
+    /// until stop_recording is called.
+    #[method]
+    pub fn record(&mut self, interval: f64) -> Result<()> {
+        ensure!(
+            interval.is_finite() && interval > 0.,
+            "recording interval must be a positive number of seconds, not {}",
+            interval
+        );
+        self.stop();
+        self.recording = Some(Recording {
+            interval: Duration::from_secs_f64(interval),
+            last: None,
+        });
+        Ok(())
+    }
+
+    #[method]
+    pub fn stop_recording(&mut self) {
+        self.recording = None;
+    }
+
+    #[method]
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    fn start(&mut self, fixed_step: Option<f64>) -> Result<Value> {
+        ensure!(!self.keys.is_empty(), "the camera path has no keys to play");
+        self.recording = None;
+        self.stop();
+        let (trigger, listener) = trigger();
+        self.playback = Some(Playback {
+            time: 0.,
+            fixed_step,
+            trigger,
+        });
+        Ok(Value::Future(Arc::new(RwLock::new(Box::pin(
+            listener.then(|_| ready(Value::True())),
+        )))))
+    }
+
+    /// Fly the path in sim time. Returns a future that completes at the end of the path.
+    #[method]
+    pub fn play(&mut self) -> Result<Value> {
+        self.start(None)
+    }
+
+    /// Fly the path, moving exactly `step` seconds along it on every sim step, no matter
+    /// how fast the sim is running. Use this for repeatable captures.
+    #[method]
+    pub fn play_fixed(&mut self, step: f64) -> Result<Value> {
+        ensure!(
+            step.is_finite() && step > 0.,
+            "fixed playback step must be a positive number of seconds, not {}",
+            step
+        );
+        self.start(Some(step))
+    }
+
+    #[method]
+    pub fn stop(&mut self) {
+        if let Some(playback) = self.playback.take() {
+            playback.trigger.trigger();
+        }
+    }
+
+    #[method]
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    #[method]
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let root = object! {
+            interpolation: self.interpolation.name(),
+            keys: JsonValue::Array(self.keys.iter().map(|k| k.to_json()).collect()),
+        };
+        fs::write(filename, root.pretty(2))?;
+        Ok(())
+    }
+
+    /// Replace the path with one saved to a file.
+    #[method]
+    pub fn load(&mut self, filename: &str) -> Result<()> {
+        let root = json::parse(&fs::read_to_string(filename)?)?;
+        let mut path = CameraPath::default();
+        if let Some(interpolation) = root["interpolation"].as_str() {
+            path.set_interpolation(interpolation)?;
+        }
+        for (i, key) in root["keys"].members().enumerate() {
+            path.insert_key(CameraKey::from_json(i, key)?)?;
+        }
+        path.looping = self.looping;
+        self.clear();
+        *self = path;
+        Ok(())
+    }
+
+    /// The camera state at `time` seconds into the path.
+    pub fn sample(&self, time: f64) -> Option<CameraKey> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if self.keys.len() == 1 || time <= first.time {
+            return Some(CameraKey {
+                time,
+                ..first.clone()
+            });
+        }
+        if time >= last.time {
+            return Some(CameraKey {
+                time,
+                ..last.clone()
+            });
+        }
+
+        // Unwrap longitudes so that we always take the short way around.
+        let mut points = self.keys.iter().map(|k| k.channels()).collect::<Vec<_>>();
+        for i in 1..points.len() {
+            for c in [1, 4] {
+                points[i][c] = points[i - 1][c] + wrap_angle(points[i][c] - points[i - 1][c]);
+            }
+        }
+        let times = self.keys.iter().map(|k| k.time).collect::<Vec<_>>();
+
+        let i = times.partition_point(|&t| t <= time) - 1;
+        let h = times[i + 1] - times[i];
+        let u = (time - times[i]) / h;
+        let mut out = [0f64; CHANNELS];
+        match self.interpolation {
+            PathInterpolation::Linear => {
+                for (c, v) in out.iter_mut().enumerate() {
+                    *v = points[i][c] + (points[i + 1][c] - points[i][c]) * u;
+                }
+            }
+            PathInterpolation::Bezier => {
+                // Control points at p1 ± (p2 - p0) / 6, as for a uniform Catmull-Rom.
+                let lead = |k: usize, c: usize| {
+                    let a = k.saturating_sub(1);
+                    let b = (k + 1).min(points.len() - 1);
+                    (points[b][c] - points[a][c]) / 6.
+                };
+                let w = 1. - u;
+                let (b0, b1, b2, b3) = (w * w * w, 3. * w * w * u, 3. * w * u * u, u * u * u);
+                for (c, v) in out.iter_mut().enumerate() {
+                    *v = b0 * points[i][c]
+                        + b1 * (points[i][c] + lead(i, c))
+                        + b2 * (points[i + 1][c] - lead(i + 1, c))
+                        + b3 * points[i + 1][c];
+                }
+            }
+            PathInterpolation::CatmullRom => {
+                // Cubic Hermite with Catmull-Rom tangents, scaled for uneven key spacing.
+                let tangent = |k: usize, c: usize| {
+                    let a = k.saturating_sub(1);
+                    let b = (k + 1).min(points.len() - 1);
+                    (points[b][c] - points[a][c]) / (times[b] - times[a])
+                };
+                let (u2, u3) = (u * u, u * u * u);
+                let h00 = 2. * u3 - 3. * u2 + 1.;
+                let h10 = u3 - 2. * u2 + u;
+                let h01 = -2. * u3 + 3. * u2;
+                let h11 = u3 - u2;
+                for (c, v) in out.iter_mut().enumerate() {
+                    *v = h00 * points[i][c]
+                        + h10 * h * tangent(i, c)
+                        + h01 * points[i + 1][c]
+                        + h11 * h * tangent(i + 1, c);
+                }
+            }
+        }
+        let mut key = CameraKey::from_channels(time, &out);
+        key.target.latitude = key
+            .target
+            .latitude
+            .clamp(radians!(-PI / 2.), radians!(PI / 2.));
+        key.target.longitude = radians!(wrap_angle(key.target.longitude.f64()));
+        key.eye.latitude = key
+            .eye
+            .latitude
+            .clamp(radians!(-PI / 2. + 0.001), radians!(PI / 2. - 0.001));
+        key.eye.longitude = radians!(wrap_angle(key.eye.longitude.f64()));
+        Some(key)
+    }
+
+    /// Move playback forward by `dt` seconds of sim time and return the camera state to
+    /// show now, if we are playing.
+    pub fn advance(&mut self, dt: f64) -> Option<CameraKey> {
+        let duration = self.duration();
+        let playback = self.playback.as_mut()?;
+        let time = playback.time.min(duration);
+        playback.time += playback.fixed_step.unwrap_or(dt);
+        let finished = time >= duration;
+        if finished && self.looping {
+            playback.time = 0.;
+        }
+        let key = self.sample(time);
+        if finished && !self.looping {
+            self.stop();
+        }
+        key
+    }
+
+    fn record_tick(&mut self, now: Duration) -> bool {
+        if let Some(recording) = &mut self.recording {
+            if recording
+                .last
+                .map(|last| now.saturating_sub(last) >= recording.interval)
+                .unwrap_or(true)
+            {
+                recording.last = Some(now);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn sys_apply_path(
+        timestep: Res<TimeStep>,
+        mut path: ResMut<CameraPath>,
+        mut camera: ResMut<ScreenCamera>,
+        mut arcballs: Query<&mut ArcBallController, With<ScreenCameraController>>,
+    ) {
+        if path.is_playing() {
+            if let Some(key) = path.advance(timestep.step().as_secs_f64()) {
+                for mut arcball in arcballs.iter_mut() {
+                    arcball.set_target(key.target);
+                    if let Err(e) = arcball.set_eye(key.eye) {
+                        error!("camera path: {}", e);
+                    }
+                }
+                camera.set_fov_y(key.fov_y);
+                camera.set_exposure(key.exposure);
+            }
+            return;
+        }
+
+        let now = timestep.sim_duration();
+        if let Some(arcball) = arcballs.iter().next() {
+            if path.record_tick(now) {
+                let origin = *path.origin.get_or_insert(now);
+                let key = CameraKey {
+                    time: now.saturating_sub(origin).as_secs_f64(),
+                    target: arcball.target(),
+                    eye: arcball.eye(),
+                    fov_y: degrees!(camera.fov_y()),
+                    exposure: f64::from(camera.exposure()),
+                };
+                if let Err(e) = path.insert_key(key) {
+                    error!("camera path: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn key(time: f64, lon: f64, distance: f64) -> CameraKey {
+        CameraKey {
+            time,
+            target: Graticule::<GeoSurface>::new(degrees!(10), degrees!(lon), meters!(100)),
+            eye: Graticule::<Target>::new(degrees!(20), degrees!(0), meters!(distance)),
+            fov_y: degrees!(60),
+            exposure: 1e-4,
+        }
+    }
+
+    #[test]
+    fn test_path_sampling() -> Result<()> {
+        let mut path = CameraPath::default();
+        path.insert_key(key(2., 20., 10_000.))?;
+        path.insert_key(key(0., 0., 100.))?;
+        path.insert_key(key(4., 40., 100.))?;
+        assert_eq!(path.duration(), 4.);
+
+        for interpolation in ["linear", "catmull_rom", "bezier"] {
+            path.set_interpolation(interpolation)?;
+            for k in path.keys().to_vec() {
+                let s = path.sample(k.time).unwrap();
+                assert_relative_eq!(
+                    s.target.lon::<Degrees>().f64(),
+                    k.target.lon::<Degrees>().f64(),
+                    epsilon = 1e-9
+                );
+                assert_relative_eq!(s.eye.distance.f64(), k.eye.distance.f64(), epsilon = 1e-6);
+            }
+        }
+
+        // Evenly spaced keys in a line stay on the line with Catmull-Rom.
+        path.set_interpolation("catmull_rom")?;
+        let s = path.sample(1.).unwrap();
+        assert_relative_eq!(s.target.lon::<Degrees>().f64(), 10., epsilon = 1e-9);
+        // Distance is interpolated on a log scale: 100m to 10km passes 1km halfway.
+        path.set_interpolation("linear")?;
+        assert_relative_eq!(
+            path.sample(1.).unwrap().eye.distance.f64(),
+            1000.,
+            epsilon = 1e-6
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bezier_keeps_moving_through_keys() -> Result<()> {
+        let mut path = CameraPath::default();
+        path.set_interpolation("bezier")?;
+        path.insert_key(key(0., 0., 100.))?;
+        path.insert_key(key(2., 20., 100.))?;
+        path.insert_key(key(4., 40., 100.))?;
+        let lon = |t: f64| path.sample(t).unwrap().target.lon::<Degrees>().f64();
+        let dt = 1e-4;
+        let before = (lon(2.) - lon(2. - dt)) / dt;
+        let after = (lon(2. + dt) - lon(2.)) / dt;
+        assert!(before > 1.);
+        assert_relative_eq!(before, after, epsilon = 1e-2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_longitude_wraps() -> Result<()> {
+        let mut path = CameraPath::default();
+        path.set_interpolation("linear")?;
+        path.insert_key(key(0., 170., 100.))?;
+        path.insert_key(key(1., -170., 100.))?;
+        let lon = path.sample(0.5).unwrap().target.lon::<Degrees>().f64();
+        assert_relative_eq!(lon.abs(), 180., epsilon = 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_playback() -> Result<()> {
+        let mut path = CameraPath::default();
+        path.insert_key(key(0., 0., 100.))?;
+        path.insert_key(key(1., 10., 100.))?;
+        path.play_fixed(0.25)?;
+        let mut times = Vec::new();
+        while let Some(key) = path.advance(1. / 60.) {
+            times.push(key.time);
+        }
+        assert_eq!(times, vec![0., 0.25, 0.5, 0.75, 1.]);
+        assert!(!path.is_playing());
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::vehicle_camera::{followable_entity, frame_axes, look_at};
+use animate::TimeStep;
+use anyhow::{ensure, Result};
+use bevy_ecs::prelude::*;
+use measure::WorldSpaceFrame;
+use nalgebra::Vector3;
+use nitrous::{inject_nitrous_component, method, HeapRef, NitrousComponent};
+
+/// Follow behind and above a vehicle. The camera is pulled towards its place behind the
+/// vehicle, rather than fixed to it, so that turns and rolls swing the view smoothly.
+#[derive(Debug, Component, NitrousComponent)]
+#[Name = "chase"]
+pub struct ChaseCamera {
+    vehicle: Option<Entity>,
+
+    // Where we are now; None until we have seen the vehicle, so that we start in place.
+    position: Option<Vector3<f64>>,
+
+    #[property]
+    distance: f64,
+
+    #[property]
+    height: f64,
+
+    // How quickly the camera closes on its place, per second. Larger is stiffer.
+    #[property]
+    stiffness: f64,
+}
+
+impl Default for ChaseCamera {
+    fn default() -> Self {
+        Self {
+            vehicle: None,
+            position: None,
+            distance: 30.,
+            height: 8.,
+            stiffness: 4.,
+        }
+    }
+}
+
+#[inject_nitrous_component]
+impl ChaseCamera {
+    /// Chase the named entity.
+    #[method]
+    pub fn follow(&mut self, name: &str, heap: HeapRef) -> Result<()> {
+        self.follow_entity(followable_entity(name, heap)?);
+        Ok(())
+    }
+
+    pub fn follow_entity(&mut self, vehicle: Entity) {
+        self.vehicle = Some(vehicle);
+        self.position = None;
+    }
+
+    /// Set how far behind and above the vehicle to sit, in meters.
+    #[method]
+    pub fn set_offset(&mut self, distance: f64, height: f64) -> Result<()> {
+        ensure!(
+            distance > 0.,
+            "chase distance must be positive, not {}",
+            distance
+        );
+        self.distance = distance;
+        self.height = height;
+        Ok(())
+    }
+
+    /// Jump straight to the chase position, rather than smoothly moving there.
+    #[method]
+    pub fn snap(&mut self) {
+        self.position = None;
+    }
+
+    fn desired_position(&self, vehicle: &WorldSpaceFrame) -> Vector3<f64> {
+        let (forward, up, _) = frame_axes(vehicle);
+        vehicle.position().vec64() - forward * self.distance + up * self.height
+    }
+
+    pub fn step(&mut self, vehicle: &WorldSpaceFrame, dt: f64) -> Option<WorldSpaceFrame> {
+        let desired = self.desired_position(vehicle);
+        let position = match self.position {
+            Some(current) => {
+                // Exponential approach, so that the lag does not depend on the step size.
+                let blend = 1. - (-self.stiffness.max(0.) * dt).exp();
+                current + (desired - current) * blend
+            }
+            None => desired,
+        };
+        self.position = Some(position);
+        look_at(position, vehicle.position().vec64())
+    }
+
+    pub(crate) fn sys_apply_input(
+        timestep: Res<TimeStep>,
+        mut cameras: Query<(&mut ChaseCamera, &mut WorldSpaceFrame)>,
+        vehicles: Query<&WorldSpaceFrame, Without<ChaseCamera>>,
+    ) {
+        let dt = timestep.step().as_secs_f64();
+        for (mut chase, mut frame) in cameras.iter_mut() {
+            if let Some(vehicle) = chase.vehicle.and_then(|v| vehicles.get(v).ok()) {
+                if let Some(next) = chase.step(vehicle, dt) {
+                    *frame = next;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vehicle_camera::north_facing_frame;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_chase_smoothing() {
+        let mut chase = ChaseCamera::default();
+        let vehicle = north_facing_frame();
+
+        // The first step puts us in place, behind the vehicle and looking at it.
+        let frame = chase.step(&vehicle, 0.1).unwrap();
+        let offset = frame.position().vec64() - vehicle.position().vec64();
+        assert_relative_eq!(
+            offset.norm(),
+            (30f64 * 30. + 8. * 8.).sqrt(),
+            epsilon = 0.001
+        );
+        assert!(offset.dot(&vehicle.forward()) < 0.);
+        assert!(frame.forward().dot(&-offset.normalize()) > 0.999);
+
+        // When the vehicle jumps, we only cover part of the distance in one step.
+        let mut moved = vehicle.clone();
+        *moved.position_mut() = (vehicle.position().vec64() + vehicle.forward() * 100.).into();
+        let before = chase.position.unwrap();
+        let frame = chase.step(&moved, 0.1).unwrap();
+        let covered = (frame.position().vec64() - before).norm();
+        assert!(covered > 10. && covered < 90., "covered {}", covered);
+
+        // And end up in place in the long run.
+        for _ in 0..100 {
+            chase.step(&moved, 0.1);
+        }
+        assert_relative_eq!(
+            (chase.position.unwrap() - chase.desired_position(&moved)).norm(),
+            0.,
+            epsilon = 0.001
+        );
+    }
+}
@@ -0,0 +1,189 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::vehicle_camera::{followable_entity, frame_axes};
+use absolute_unit::{degrees, radians, Angle, Degrees, Meters, Radians};
+use anyhow::Result;
+use bevy_ecs::prelude::*;
+use geodesy::{Cartesian, GeoCenter};
+use measure::WorldSpaceFrame;
+use nalgebra::{Unit as NUnit, UnitQuaternion, Vector3};
+use nitrous::{inject_nitrous_component, method, HeapRef, NitrousComponent};
+
+/// Look out from inside a vehicle. The head turns with the mouse, or is pointed directly
+/// by a pair of absolute axes, such as a hat or head tracker.
+#[derive(Debug, Component, NitrousComponent)]
+#[Name = "cockpit"]
+pub struct CockpitCamera {
+    vehicle: Option<Entity>,
+
+    // Eye position relative to the vehicle's origin, along its forward, up, and right axes.
+    eye_offset: Vector3<f64>,
+
+    head_yaw: Angle<Radians>,
+    head_pitch: Angle<Radians>,
+
+    #[property]
+    mouse_sensitivity: f64,
+}
+
+impl Default for CockpitCamera {
+    fn default() -> Self {
+        Self {
+            vehicle: None,
+            eye_offset: Vector3::new(0., 1., 0.),
+            head_yaw: radians!(0),
+            head_pitch: radians!(0),
+            mouse_sensitivity: 0.25,
+        }
+    }
+}
+
+#[inject_nitrous_component]
+impl CockpitCamera {
+    const MAX_YAW: f64 = 170.;
+    const MIN_PITCH: f64 = -45.;
+    const MAX_PITCH: f64 = 89.;
+
+    /// Ride along in the named entity.
+    #[method]
+    pub fn follow(&mut self, name: &str, heap: HeapRef) -> Result<()> {
+        self.vehicle = Some(followable_entity(name, heap)?);
+        Ok(())
+    }
+
+    pub fn follow_entity(&mut self, vehicle: Entity) {
+        self.vehicle = Some(vehicle);
+    }
+
+    /// Place the eye relative to the vehicle's origin, in meters along its axes.
+    #[method]
+    pub fn set_eye_offset(&mut self, forward: f64, up: f64, right: f64) {
+        self.eye_offset = Vector3::new(forward, up, right);
+    }
+
+    #[method]
+    pub fn head_yaw_degrees(&self) -> f64 {
+        degrees!(self.head_yaw).f64()
+    }
+
+    #[method]
+    pub fn head_pitch_degrees(&self) -> f64 {
+        degrees!(self.head_pitch).f64()
+    }
+
+    /// Turn the head to the given angles; positive yaw is to the right and positive pitch
+    /// is up. Angles are clamped to what a pilot could see over their shoulder.
+    #[method]
+    pub fn set_head_degrees(&mut self, yaw: f64, pitch: f64) {
+        self.head_yaw = radians!(degrees!(yaw.clamp(-Self::MAX_YAW, Self::MAX_YAW)));
+        self.head_pitch = radians!(degrees!(pitch.clamp(Self::MIN_PITCH, Self::MAX_PITCH)));
+    }
+
+    #[method]
+    pub fn center_view(&mut self) {
+        self.set_head_degrees(0., 0.);
+    }
+
+    #[method]
+    pub fn handle_mousemotion(&mut self, x: f64, y: f64) {
+        self.set_head_degrees(
+            self.head_yaw_degrees() + x * self.mouse_sensitivity,
+            self.head_pitch_degrees() - y * self.mouse_sensitivity,
+        );
+    }
+
+    /// Point the head with an axis in [-1, 1], where the ends are full left and right.
+    #[method]
+    pub fn head_yaw_axis(&mut self, value: f64) {
+        self.head_yaw = radians!(degrees!(value.clamp(-1., 1.) * Self::MAX_YAW));
+    }
+
+    /// Point the head with an axis in [-1, 1], where the ends are full down and up.
+    #[method]
+    pub fn head_pitch_axis(&mut self, value: f64) {
+        let value = value.clamp(-1., 1.);
+        let pitch = if value < 0. {
+            -value * Self::MIN_PITCH
+        } else {
+            value * Self::MAX_PITCH
+        };
+        self.head_pitch = radians!(degrees!(pitch));
+    }
+
+    pub fn head_angles(&self) -> (Angle<Degrees>, Angle<Degrees>) {
+        (degrees!(self.head_yaw), degrees!(self.head_pitch))
+    }
+
+    pub fn world_space_frame(&self, vehicle: &WorldSpaceFrame) -> WorldSpaceFrame {
+        let (forward, up, right) = frame_axes(vehicle);
+        let position = vehicle.position().vec64()
+            + forward * self.eye_offset.x
+            + up * self.eye_offset.y
+            + right * self.eye_offset.z;
+        // Yaw around the vehicle's up axis, then pitch around the turned right axis.
+        let yaw = UnitQuaternion::from_axis_angle(&NUnit::new_normalize(up), -self.head_yaw.f64());
+        let pitch =
+            UnitQuaternion::from_axis_angle(&NUnit::new_normalize(right), self.head_pitch.f64());
+        WorldSpaceFrame::from_quaternion(
+            Cartesian::<GeoCenter, Meters>::from(position),
+            yaw * pitch * vehicle.facing(),
+        )
+    }
+
+    pub(crate) fn sys_apply_input(
+        mut cameras: Query<(&CockpitCamera, &mut WorldSpaceFrame)>,
+        vehicles: Query<&WorldSpaceFrame, Without<CockpitCamera>>,
+    ) {
+        for (cockpit, mut frame) in cameras.iter_mut() {
+            if let Some(vehicle) = cockpit.vehicle.and_then(|v| vehicles.get(v).ok()) {
+                *frame = cockpit.world_space_frame(vehicle);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vehicle_camera::north_facing_frame;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_head_turns_within_limits() {
+        let vehicle = north_facing_frame();
+        let (forward, up, right) = frame_axes(&vehicle);
+
+        // Looking ahead from the eye point.
+        let mut cockpit = CockpitCamera::default();
+        cockpit.set_eye_offset(2., 1., 0.);
+        let frame = cockpit.world_space_frame(&vehicle);
+        let eye = vehicle.position().vec64() + forward * 2. + up;
+        assert_relative_eq!((frame.position().vec64() - eye).norm(), 0., epsilon = 0.001);
+        assert!(frame.forward().dot(&forward) > 0.999);
+
+        // Turning the head right looks along the vehicle's right wing.
+        cockpit.set_head_degrees(90., 0.);
+        assert!(cockpit.world_space_frame(&vehicle).forward().dot(&right) > 0.999);
+
+        // The head stops at what a pilot could see over their shoulder.
+        cockpit.set_head_degrees(200., 100.);
+        assert_relative_eq!(cockpit.head_yaw_degrees(), 170., epsilon = 0.001);
+        assert_relative_eq!(cockpit.head_pitch_degrees(), 89., epsilon = 0.001);
+        cockpit.head_pitch_axis(-1.);
+        assert_relative_eq!(cockpit.head_pitch_degrees(), -45., epsilon = 0.001);
+        cockpit.center_view();
+        assert!(cockpit.world_space_frame(&vehicle).forward().dot(&forward) > 0.999);
+    }
+}
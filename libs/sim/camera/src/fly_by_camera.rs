@@ -0,0 +1,160 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::vehicle_camera::{followable_entity, frame_axes, look_at};
+use anyhow::{ensure, Result};
+use bevy_ecs::prelude::*;
+use measure::WorldSpaceFrame;
+use nalgebra::Vector3;
+use nitrous::{inject_nitrous_component, method, HeapRef, NitrousComponent};
+
+/// Watch a vehicle go by from a fixed spot ahead of it and off to one side. Once the
+/// vehicle has passed and is far enough away, the camera moves ahead of it again.
+#[derive(Debug, Component, NitrousComponent)]
+#[Name = "flyby"]
+pub struct FlyByCamera {
+    vehicle: Option<Entity>,
+    position: Option<Vector3<f64>>,
+
+    #[property]
+    lead_distance: f64,
+
+    #[property]
+    side_offset: f64,
+
+    #[property]
+    height_offset: f64,
+}
+
+impl Default for FlyByCamera {
+    fn default() -> Self {
+        Self {
+            vehicle: None,
+            position: None,
+            lead_distance: 300.,
+            side_offset: 30.,
+            height_offset: 10.,
+        }
+    }
+}
+
+#[inject_nitrous_component]
+impl FlyByCamera {
+    /// Watch the named entity.
+    #[method]
+    pub fn follow(&mut self, name: &str, heap: HeapRef) -> Result<()> {
+        self.follow_entity(followable_entity(name, heap)?);
+        Ok(())
+    }
+
+    pub fn follow_entity(&mut self, vehicle: Entity) {
+        self.vehicle = Some(vehicle);
+        self.position = None;
+    }
+
+    /// Set where the camera is placed, in meters ahead of, to the right of, and above the
+    /// vehicle's current position.
+    #[method]
+    pub fn set_offset(&mut self, lead: f64, side: f64, height: f64) -> Result<()> {
+        ensure!(lead > 0., "fly-by lead must be positive, not {}", lead);
+        self.lead_distance = lead;
+        self.side_offset = side;
+        self.height_offset = height;
+        Ok(())
+    }
+
+    /// Move ahead of the vehicle now, rather than waiting for it to pass.
+    #[method]
+    pub fn reposition(&mut self) {
+        self.position = None;
+    }
+
+    fn place_ahead(&self, vehicle: &WorldSpaceFrame) -> Vector3<f64> {
+        let (forward, up, right) = frame_axes(vehicle);
+        vehicle.position().vec64()
+            + forward * self.lead_distance
+            + right * self.side_offset
+            + up * self.height_offset
+    }
+
+    fn has_passed(&self, position: &Vector3<f64>, vehicle: &WorldSpaceFrame) -> bool {
+        let to_vehicle = vehicle.position().vec64() - position;
+        to_vehicle.dot(&vehicle.forward()) > 0. && to_vehicle.norm() > self.lead_distance
+    }
+
+    pub fn step(&mut self, vehicle: &WorldSpaceFrame) -> Option<WorldSpaceFrame> {
+        let position = match self.position {
+            Some(position) if !self.has_passed(&position, vehicle) => position,
+            _ => self.place_ahead(vehicle),
+        };
+        self.position = Some(position);
+        look_at(position, vehicle.position().vec64())
+    }
+
+    pub(crate) fn sys_apply_input(
+        mut cameras: Query<(&mut FlyByCamera, &mut WorldSpaceFrame)>,
+        vehicles: Query<&WorldSpaceFrame, Without<FlyByCamera>>,
+    ) {
+        for (mut flyby, mut frame) in cameras.iter_mut() {
+            if let Some(vehicle) = flyby.vehicle.and_then(|v| vehicles.get(v).ok()) {
+                if let Some(next) = flyby.step(vehicle) {
+                    *frame = next;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vehicle_camera::north_facing_frame;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_fly_by_repositions_after_pass() {
+        let vehicle = north_facing_frame();
+        let mut flyby = FlyByCamera::default();
+
+        // We start ahead of the vehicle, looking back at it.
+        let frame = flyby.step(&vehicle).unwrap();
+        let start = flyby.position.unwrap();
+        assert_relative_eq!(
+            (start - flyby.place_ahead(&vehicle)).norm(),
+            0.,
+            epsilon = 0.001
+        );
+        assert!(frame.forward().dot(&vehicle.forward()) < 0.);
+
+        // The camera stays put while the vehicle approaches.
+        let mut moved = vehicle.clone();
+        *moved.position_mut() = (vehicle.position().vec64() + vehicle.forward() * 200.).into();
+        flyby.step(&moved);
+        assert_relative_eq!(
+            (flyby.position.unwrap() - start).norm(),
+            0.,
+            epsilon = 0.001
+        );
+
+        // Once the vehicle is well past, the camera moves ahead of it again.
+        *moved.position_mut() = (vehicle.position().vec64() + vehicle.forward() * 700.).into();
+        let frame = flyby.step(&moved).unwrap();
+        assert_relative_eq!(
+            (flyby.position.unwrap() - flyby.place_ahead(&moved)).norm(),
+            0.,
+            epsilon = 0.001
+        );
+        assert!(frame.forward().dot(&moved.forward()) < 0.);
+    }
+}
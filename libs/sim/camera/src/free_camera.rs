@@ -0,0 +1,275 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::vehicle_camera::{followable_entity, frame_axes};
+use absolute_unit::Meters;
+use animate::TimeStep;
+use anyhow::Result;
+use bevy_ecs::prelude::*;
+use geodesy::{Cartesian, GeoCenter};
+use measure::WorldSpaceFrame;
+use nalgebra::{Unit as NUnit, UnitQuaternion, Vector3};
+use nitrous::{inject_nitrous_component, method, HeapRef, NitrousComponent};
+
+// The held state of the two keys that drive one axis, so that pressing and releasing one
+// does not cancel the other while it is still held.
+#[derive(Clone, Copy, Debug, Default)]
+struct KeyPair {
+    positive: bool,
+    negative: bool,
+}
+
+impl KeyPair {
+    fn value(&self) -> f64 {
+        let mut value = 0.;
+        if self.positive {
+            value += 1.;
+        }
+        if self.negative {
+            value -= 1.;
+        }
+        value
+    }
+}
+
+#[derive(Debug, Default)]
+struct InputState {
+    // Movement along forward, up, and right.
+    translate: [KeyPair; 3],
+
+    // Pitch up, yaw right, and roll right.
+    rotate: [KeyPair; 3],
+
+    // Accumulated mouse motion, in degrees, applied on the next step.
+    look_yaw: f64,
+    look_pitch: f64,
+    in_look: bool,
+}
+
+impl InputState {
+    fn translate(&self) -> Vector3<f64> {
+        Vector3::new(
+            self.translate[0].value(),
+            self.translate[1].value(),
+            self.translate[2].value(),
+        )
+    }
+
+    fn rotate(&self) -> Vector3<f64> {
+        Vector3::new(
+            self.rotate[0].value(),
+            self.rotate[1].value(),
+            self.rotate[2].value(),
+        )
+    }
+}
+
+/// A debug camera that flies anywhere with 6-DOF keyboard movement and mouse look.
+#[derive(Debug, Component, NitrousComponent)]
+#[Name = "free"]
+pub struct FreeCamera {
+    input: InputState,
+
+    // Copy this entity's frame on the next step.
+    jump_to: Option<Entity>,
+
+    // Meters per second.
+    #[property]
+    speed: f64,
+
+    // Degrees per second.
+    #[property]
+    turn_rate: f64,
+
+    #[property]
+    mouse_sensitivity: f64,
+}
+
+impl Default for FreeCamera {
+    fn default() -> Self {
+        Self {
+            input: InputState::default(),
+            jump_to: None,
+            speed: 100.,
+            turn_rate: 45.,
+            mouse_sensitivity: 0.25,
+        }
+    }
+}
+
+#[inject_nitrous_component]
+impl FreeCamera {
+    /// Move the camera to the named entity's position and facing, then fly on from there.
+    #[method]
+    pub fn jump_to(&mut self, name: &str, heap: HeapRef) -> Result<()> {
+        self.jump_to = Some(followable_entity(name, heap)?);
+        Ok(())
+    }
+
+    #[method]
+    pub fn move_forward(&mut self, pressed: bool) {
+        self.input.translate[0].positive = pressed;
+    }
+
+    #[method]
+    pub fn move_backward(&mut self, pressed: bool) {
+        self.input.translate[0].negative = pressed;
+    }
+
+    #[method]
+    pub fn move_up(&mut self, pressed: bool) {
+        self.input.translate[1].positive = pressed;
+    }
+
+    #[method]
+    pub fn move_down(&mut self, pressed: bool) {
+        self.input.translate[1].negative = pressed;
+    }
+
+    #[method]
+    pub fn move_right(&mut self, pressed: bool) {
+        self.input.translate[2].positive = pressed;
+    }
+
+    #[method]
+    pub fn move_left(&mut self, pressed: bool) {
+        self.input.translate[2].negative = pressed;
+    }
+
+    #[method]
+    pub fn pitch_up(&mut self, pressed: bool) {
+        self.input.rotate[0].positive = pressed;
+    }
+
+    #[method]
+    pub fn pitch_down(&mut self, pressed: bool) {
+        self.input.rotate[0].negative = pressed;
+    }
+
+    #[method]
+    pub fn yaw_right(&mut self, pressed: bool) {
+        self.input.rotate[1].positive = pressed;
+    }
+
+    #[method]
+    pub fn yaw_left(&mut self, pressed: bool) {
+        self.input.rotate[1].negative = pressed;
+    }
+
+    #[method]
+    pub fn roll_right(&mut self, pressed: bool) {
+        self.input.rotate[2].positive = pressed;
+    }
+
+    #[method]
+    pub fn roll_left(&mut self, pressed: bool) {
+        self.input.rotate[2].negative = pressed;
+    }
+
+    #[method]
+    pub fn faster(&mut self) {
+        self.speed *= 2.;
+    }
+
+    #[method]
+    pub fn slower(&mut self) {
+        self.speed /= 2.;
+    }
+
+    #[method]
+    pub fn look(&mut self, pressed: bool) {
+        self.input.in_look = pressed;
+    }
+
+    #[method]
+    pub fn handle_mousemotion(&mut self, x: f64, y: f64) {
+        if self.input.in_look {
+            self.input.look_yaw += x * self.mouse_sensitivity;
+            self.input.look_pitch -= y * self.mouse_sensitivity;
+        }
+    }
+
+    pub fn step(&mut self, frame: &mut WorldSpaceFrame, dt: f64) {
+        let (forward, up, right) = frame_axes(frame);
+        let rotation = |axis: Vector3<f64>, angle: f64| {
+            UnitQuaternion::from_axis_angle(&NUnit::new_normalize(axis), angle.to_radians())
+        };
+        let turn = self.turn_rate * dt;
+        let rotate = self.input.rotate();
+        let pitch = rotation(right, rotate.x * turn + self.input.look_pitch);
+        let yaw = rotation(up, -(rotate.y * turn + self.input.look_yaw));
+        let roll = rotation(forward, rotate.z * turn);
+        self.input.look_yaw = 0.;
+        self.input.look_pitch = 0.;
+        *frame.facing_mut() = yaw * pitch * roll * frame.facing();
+
+        let translate = self.input.translate();
+        let motion =
+            (forward * translate.x + up * translate.y + right * translate.z) * self.speed * dt;
+        let position = frame.position().vec64() + motion;
+        *frame.position_mut() = Cartesian::<GeoCenter, Meters>::from(position);
+    }
+
+    pub(crate) fn sys_apply_input(
+        timestep: Res<TimeStep>,
+        mut cameras: Query<(&mut FreeCamera, &mut WorldSpaceFrame)>,
+        targets: Query<&WorldSpaceFrame, Without<FreeCamera>>,
+    ) {
+        let dt = timestep.step().as_secs_f64();
+        for (mut free, mut frame) in cameras.iter_mut() {
+            if let Some(target) = free.jump_to.take().and_then(|t| targets.get(t).ok()) {
+                *frame = target.clone();
+            }
+            free.step(&mut frame, dt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vehicle_camera::north_facing_frame;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_opposite_keys() {
+        let mut free = FreeCamera::default();
+        let start = north_facing_frame();
+        let mut frame = start.clone();
+
+        // Holding both keys holds still; releasing one leaves the other in effect.
+        free.move_forward(true);
+        free.move_backward(true);
+        free.step(&mut frame, 1.);
+        assert_relative_eq!(
+            (frame.position().vec64() - start.position().vec64()).norm(),
+            0.,
+            epsilon = 0.001
+        );
+        free.move_backward(false);
+        free.step(&mut frame, 1.);
+        let moved = frame.position().vec64() - start.position().vec64();
+        assert_relative_eq!(moved.norm(), free.speed, epsilon = 0.001);
+        assert!(moved.dot(&start.forward()) > 0.);
+
+        free.move_forward(false);
+        let before = frame.position().vec64();
+        free.step(&mut frame, 1.);
+        assert_relative_eq!(
+            (frame.position().vec64() - before).norm(),
+            0.,
+            epsilon = 0.001
+        );
+    }
+}
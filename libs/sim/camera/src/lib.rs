@@ -14,6 +14,20 @@
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 mod arc_ball_camera;
 mod camera_impl;
+mod camera_path;
+mod chase_camera;
+mod cockpit_camera;
+mod fly_by_camera;
+mod free_camera;
+mod locations;
+mod vehicle_camera;
 
 pub use arc_ball_camera::{ArcBallController, ArcBallStep, ArcBallSystem};
 pub use camera_impl::{CameraStep, CameraSystem, HudCamera, ScreenCamera, ScreenCameraController};
+pub use camera_path::{CameraKey, CameraPath, CameraPathStep, PathInterpolation};
+pub use chase_camera::ChaseCamera;
+pub use cockpit_camera::CockpitCamera;
+pub use fly_by_camera::FlyByCamera;
+pub use free_camera::FreeCamera;
+pub use locations::{CameraView, Locations};
+pub use vehicle_camera::{VehicleCameraStep, VehicleCameraSystem};
@@ -0,0 +1,312 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use absolute_unit::{degrees, meters, Angle, Degrees};
+use anyhow::{anyhow, bail, Result};
+use catalog::{from_utf8_string, Catalog};
+use geodesy::{GeoSurface, Graticule, Target};
+use json::{object, JsonValue};
+use log::{info, warn};
+use nitrous::{inject_nitrous_resource, method, NitrousResource};
+use platform_dirs::AppDirs;
+use runtime::{Extension, Runtime};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Named locations come from `locations.json` in the catalog, if there is one, and
+// bookmarks from `bookmarks.json` in the user's data directory. Both use the same format,
+// so that a bookmark can be shared by pasting it into either file:
+//
+// {
+//   "Everest": {
+//     "target": { "latitude": 27.988, "longitude": 86.925, "height": 8849 },
+//     "eye": { "latitude": 9, "longitude": 130, "distance": 12000 },
+//     "fov": 90
+//   }
+// }
+//
+// Angles are in degrees and lengths in meters. The eye is relative to the target, as in
+// the arcball camera. The fov is optional. The defaults in assets/locations.json are built
+// in, and are replaced by a locations.json in the catalog.
+const CATALOG_LOCATIONS: &str = "locations.json";
+const DEFAULT_LOCATIONS: &str = include_str!("../assets/locations.json");
+const BOOKMARKS_FILE: &str = "bookmarks.json";
+
+/// Where a camera was looking: an arcball target and eye, and maybe a field of view.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraView {
+    pub target: Graticule<GeoSurface>,
+    pub eye: Graticule<Target>,
+    pub fov_y: Option<Angle<Degrees>>,
+}
+
+impl CameraView {
+    pub fn new(target: Graticule<GeoSurface>, eye: Graticule<Target>) -> Self {
+        Self {
+            target,
+            eye,
+            fov_y: None,
+        }
+    }
+
+    pub fn with_fov_y(mut self, fov_y: Angle<Degrees>) -> Self {
+        self.fov_y = Some(fov_y);
+        self
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        let mut value = object! {
+            target: object! {
+                latitude: self.target.lat::<Degrees>().f64(),
+                longitude: self.target.lon::<Degrees>().f64(),
+                height: meters!(self.target.distance).f64(),
+            },
+            eye: object! {
+                latitude: self.eye.lat::<Degrees>().f64(),
+                longitude: self.eye.lon::<Degrees>().f64(),
+                distance: meters!(self.eye.distance).f64(),
+            },
+        };
+        if let Some(fov_y) = self.fov_y {
+            value["fov"] = fov_y.f64().into();
+        }
+        value
+    }
+
+    pub(crate) fn from_json(name: &str, value: &JsonValue) -> Result<Self> {
+        let number = |part: &str, field: &str| {
+            value[part][field]
+                .as_f64()
+                .ok_or_else(|| anyhow!("location {} is missing {}.{}", name, part, field))
+        };
+        let target = Graticule::<GeoSurface>::new(
+            degrees!(number("target", "latitude")?),
+            degrees!(number("target", "longitude")?),
+            meters!(number("target", "height")?),
+        );
+        let eye = Graticule::<Target>::new(
+            degrees!(number("eye", "latitude")?),
+            degrees!(number("eye", "longitude")?),
+            meters!(number("eye", "distance")?),
+        );
+        let mut view = Self::new(target, eye);
+        if value.has_key("fov") {
+            match value["fov"].as_f64() {
+                Some(fov) => view = view.with_fov_y(degrees!(fov)),
+                None => bail!("location {} has a fov that is not a number", name),
+            }
+        }
+        Ok(view)
+    }
+}
+
+fn views_to_json(views: &BTreeMap<String, CameraView>) -> String {
+    let mut root = JsonValue::new_object();
+    for (name, view) in views {
+        root[name.as_str()] = view.to_json();
+    }
+    root.pretty(2)
+}
+
+fn views_from_json(data: &str) -> Result<BTreeMap<String, CameraView>> {
+    let root = json::parse(data)?;
+    if !root.is_object() {
+        bail!("expected an object of named locations");
+    }
+    root.entries()
+        .map(|(name, value)| Ok((name.to_owned(), CameraView::from_json(name, value)?)))
+        .collect()
+}
+
+// For when there is no locations.json in the catalog.
+fn builtin_locations() -> BTreeMap<String, CameraView> {
+    views_from_json(DEFAULT_LOCATIONS).expect("valid built in locations.json")
+}
+
+/// Named places to point the camera at, and the user's bookmarked views.
+#[derive(Debug, NitrousResource)]
+pub struct Locations {
+    locations: BTreeMap<String, CameraView>,
+    bookmarks: BTreeMap<String, CameraView>,
+    bookmarks_path: Option<PathBuf>,
+}
+
+impl Extension for Locations {
+    fn init(runtime: &mut Runtime) -> Result<()> {
+        let mut locations = match runtime.maybe_resource::<Catalog>() {
+            Some(catalog) => Locations::from_catalog(catalog)?,
+            None => Locations::default(),
+        };
+        // A broken bookmarks file or directory should not stop the game from starting.
+        if let Some(app_dirs) = runtime.maybe_resource::<AppDirs>() {
+            if let Err(e) = fs::create_dir_all(&app_dirs.data_dir) {
+                warn!(
+                    "failed to create {:?}; bookmarks will not be saved: {}",
+                    app_dirs.data_dir, e
+                );
+            } else {
+                let path = app_dirs.data_dir.join(BOOKMARKS_FILE);
+                if let Err(e) = locations.use_bookmarks_file(&path) {
+                    warn!("failed to load bookmarks from {:?}: {}", path, e);
+                }
+            }
+        }
+        runtime.insert_named_resource("locations", locations);
+        Ok(())
+    }
+}
+
+impl Default for Locations {
+    fn default() -> Self {
+        Self {
+            locations: builtin_locations(),
+            bookmarks: BTreeMap::new(),
+            bookmarks_path: None,
+        }
+    }
+}
+
+#[inject_nitrous_resource]
+impl Locations {
+    pub fn from_catalog(catalog: &Catalog) -> Result<Self> {
+        let mut locations = Self::default();
+        if catalog.exists(CATALOG_LOCATIONS) {
+            let data = catalog.read_name(CATALOG_LOCATIONS)?;
+            locations.locations = views_from_json(&from_utf8_string(data)?)?;
+            info!(
+                "loaded {} locations from the catalog",
+                locations.locations.len()
+            );
+        }
+        Ok(locations)
+    }
+
+    pub fn use_bookmarks_file(&mut self, path: &Path) -> Result<()> {
+        self.bookmarks_path = Some(path.to_owned());
+        if path.exists() {
+            self.bookmarks = views_from_json(&fs::read_to_string(path)?)?;
+        }
+        Ok(())
+    }
+
+    /// Find a view by name. Bookmarks take precedence over named locations.
+    pub fn lookup(&self, name: &str) -> Option<&CameraView> {
+        self.bookmarks
+            .get(name)
+            .or_else(|| self.locations.get(name))
+    }
+
+    pub fn view(&self, name: &str) -> Result<&CameraView> {
+        self.lookup(name)
+            .ok_or_else(|| anyhow!("unknown location or bookmark: {}", name))
+    }
+
+    /// Add or replace a bookmark and write out the bookmarks file, if we have one.
+    pub fn add_bookmark(&mut self, name: &str, view: CameraView) -> Result<()> {
+        self.bookmarks.insert(name.to_owned(), view);
+        self.save_if_configured()
+    }
+
+    fn save_if_configured(&self) -> Result<()> {
+        if let Some(path) = &self.bookmarks_path {
+            fs::write(path, views_to_json(&self.bookmarks))?;
+        }
+        Ok(())
+    }
+
+    #[method]
+    pub fn remove_bookmark(&mut self, name: &str) -> Result<()> {
+        if self.bookmarks.remove(name).is_none() {
+            bail!("no bookmark named {}", name);
+        }
+        self.save_if_configured()
+    }
+
+    /// Re-read the bookmarks file, e.g. after pasting in a view from someone else.
+    #[method]
+    pub fn reload_bookmarks(&mut self) -> Result<()> {
+        match self.bookmarks_path.clone() {
+            Some(path) => self.use_bookmarks_file(&path),
+            None => bail!("no bookmarks file has been configured"),
+        }
+    }
+
+    /// Show the json for a view, to share it or add it to locations.json.
+    #[method]
+    pub fn describe(&self, name: &str) -> Result<String> {
+        let mut root = JsonValue::new_object();
+        root[name] = self.view(name)?.to_json();
+        Ok(root.pretty(2))
+    }
+
+    #[method]
+    pub fn list(&self) -> Result<String> {
+        let mut out = String::new();
+        for name in self.locations.keys() {
+            writeln!(out, "{}", name)?;
+        }
+        for name in self.bookmarks.keys() {
+            writeln!(out, "{} (bookmark)", name)?;
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bookmark_round_trip() -> Result<()> {
+        let mut locations = Locations::default();
+        let view = CameraView::new(
+            Graticule::<GeoSurface>::new(degrees!(46.5), degrees!(-7.9), meters!(4000.)),
+            Graticule::<Target>::new(degrees!(15), degrees!(200), meters!(3000.)),
+        )
+        .with_fov_y(degrees!(60));
+        locations.add_bookmark("Eiger", view.clone())?;
+        locations.add_bookmark("London", view.clone())?;
+        assert_eq!(locations.view("London")?, &view);
+        assert!(locations.view("Atlantis").is_err());
+
+        let parsed = views_from_json(&views_to_json(&locations.bookmarks))?;
+        assert_eq!(parsed.len(), 2);
+        let eiger = &parsed["Eiger"];
+        assert!((eiger.target.lat::<Degrees>().f64() - 46.5).abs() < 1e-9);
+        assert!((eiger.eye.distance.f64() - 3000.).abs() < 1e-9);
+        assert!((eiger.fov_y.unwrap().f64() - 60.).abs() < 1e-9);
+
+        locations.remove_bookmark("London")?;
+        assert_eq!(locations.view("London")?.fov_y, None);
+        assert!(views_from_json("[]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_builtin_locations() -> Result<()> {
+        let locations = Locations::default();
+        let everest = &locations.view("Everest")?.target;
+        assert!((everest.lat::<Degrees>().f64() - 27.988).abs() < 0.01);
+        assert!((everest.lon::<Degrees>().f64() - 86.925).abs() < 0.01);
+        let iss = &locations.view("ISS")?.target;
+        assert_ne!(iss.latitude, everest.latitude);
+        assert_ne!(iss.longitude, everest.longitude);
+        assert!(locations.view("London").is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{camera_impl::CameraStep, ChaseCamera, CockpitCamera, FlyByCamera, FreeCamera};
+use absolute_unit::Meters;
+use anyhow::{anyhow, ensure, Result};
+use bevy_ecs::prelude::*;
+use geodesy::{Cartesian, GeoCenter};
+use measure::WorldSpaceFrame;
+use nalgebra::Vector3;
+use nitrous::HeapRef;
+use runtime::{Extension, Runtime};
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, SystemLabel)]
+pub enum VehicleCameraStep {
+    ApplyInput,
+}
+
+/// Cameras that ride in, chase, or watch the WorldSpaceFrame of some other entity,
+/// usually a vehicle, plus a free flying debug camera. Each is a component on its own
+/// camera entity; use `camera.attach(name)` to look through one of them.
+pub struct VehicleCameraSystem;
+impl Extension for VehicleCameraSystem {
+    fn init(runtime: &mut Runtime) -> Result<()> {
+        runtime.add_sim_system(
+            CockpitCamera::sys_apply_input
+                .label(VehicleCameraStep::ApplyInput)
+                .before(CameraStep::ApplyInput),
+        );
+        runtime.add_sim_system(
+            ChaseCamera::sys_apply_input
+                .label(VehicleCameraStep::ApplyInput)
+                .before(CameraStep::ApplyInput),
+        );
+        runtime.add_sim_system(
+            FlyByCamera::sys_apply_input
+                .label(VehicleCameraStep::ApplyInput)
+                .before(CameraStep::ApplyInput),
+        );
+        runtime.add_sim_system(
+            FreeCamera::sys_apply_input
+                .label(VehicleCameraStep::ApplyInput)
+                .before(CameraStep::ApplyInput),
+        );
+        Ok(())
+    }
+}
+
+/// Find an entity that a camera can follow.
+pub(crate) fn followable_entity(name: &str, heap: HeapRef) -> Result<Entity> {
+    let entity = heap
+        .maybe_entity_by_name(name)
+        .ok_or_else(|| anyhow!("no entity named {} to follow", name))?;
+    ensure!(
+        heap.maybe_get::<WorldSpaceFrame>(entity).is_some(),
+        "{} does not have a frame for a camera to follow",
+        name
+    );
+    Ok(entity)
+}
+
+/// The forward, up, and right vectors of a frame, in world space.
+///
+/// Note: we compute right here, rather than using the basis, so that positive rotations
+/// around each axis are always right-hand rotations.
+pub(crate) fn frame_axes(frame: &WorldSpaceFrame) -> (Vector3<f64>, Vector3<f64>, Vector3<f64>) {
+    let basis = frame.basis();
+    let right = basis.forward.cross(&basis.up);
+    (basis.forward, basis.up, right)
+}
+
+/// A frame at `position` looking at `target`, with the planet's surface as down.
+pub(crate) fn look_at(position: Vector3<f64>, target: Vector3<f64>) -> Option<WorldSpaceFrame> {
+    let forward = target - position;
+    // Looking straight up or down leaves us without a horizon to orient against.
+    if forward.norm() < 0.001 || forward.normalize().cross(&position.normalize()).norm() < 1e-6 {
+        return None;
+    }
+    Some(WorldSpaceFrame::new(
+        Cartesian::<GeoCenter, Meters>::from(position),
+        forward,
+    ))
+}
+
+/// A vehicle 1km above the ground at 10N 20E, facing north, for tests.
+#[cfg(test)]
+pub(crate) fn north_facing_frame() -> WorldSpaceFrame {
+    use absolute_unit::{degrees, meters};
+    use geodesy::{GeoSurface, Graticule};
+    let position = Graticule::<GeoSurface>::new(degrees!(10), degrees!(20), meters!(1000))
+        .cartesian::<Meters>();
+    let up = position.vec64().normalize();
+    let north = (Vector3::y() - up * up.y).normalize();
+    WorldSpaceFrame::new(position, north)
+}
@@ -0,0 +1,281 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{bail, ensure, Result};
+use std::{fmt, str::FromStr};
+
+/// The shape applied to the magnitude of an axis after the deadzone and saturation have been
+/// taken out. Curves map [0, 1] onto [0, 1]; the sign is re-applied afterwards.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResponseCurve {
+    Linear,
+
+    // Classic RC style expo: (1 - k) * x + k * x^3, for k in [0, 1]. Softens the
+    // response around center while still reaching full deflection.
+    Expo(f64),
+
+    // Piecewise linear through the given (input, output) points, sorted by input.
+    // The curve is pinned at (0, 0) and (1, 1) if those points are not given.
+    Custom(Vec<(f64, f64)>),
+}
+
+impl ResponseCurve {
+    fn apply(&self, x: f64) -> f64 {
+        match self {
+            Self::Linear => x,
+            Self::Expo(k) => (1. - k) * x + k * x * x * x,
+            Self::Custom(points) => {
+                let mut prior = (0., 0.);
+                for &(px, py) in points.iter().chain([(1., 1.)].iter()) {
+                    if x <= px {
+                        let span = px - prior.0;
+                        if span <= 0. {
+                            return py;
+                        }
+                        return prior.1 + (py - prior.1) * (x - prior.0) / span;
+                    }
+                    prior = (px, py);
+                }
+                prior.1
+            }
+        }
+    }
+}
+
+/// Processing applied to a joystick axis before handing the value to a binding.
+///
+/// Responses are written as space separated options, any of which may be omitted:
+///   deadzone=0.05 saturation=0.95 expo=0.3 trim=0.02 invert
+/// or with a custom curve in place of expo:
+///   deadzone=0.05 curve=0.25:0.1,0.5:0.3,0.75:0.6
+#[derive(Clone, Debug, PartialEq)]
+pub struct AxisResponse {
+    deadzone: f64,
+    saturation: f64,
+    curve: ResponseCurve,
+    inverted: bool,
+    trim: f64,
+}
+
+impl Default for AxisResponse {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.,
+            saturation: 1.,
+            curve: ResponseCurve::Linear,
+            inverted: false,
+            trim: 0.,
+        }
+    }
+}
+
+impl AxisResponse {
+    pub fn deadzone(&self) -> f64 {
+        self.deadzone
+    }
+
+    pub fn saturation(&self) -> f64 {
+        self.saturation
+    }
+
+    pub fn curve(&self) -> &ResponseCurve {
+        &self.curve
+    }
+
+    pub fn inverted(&self) -> bool {
+        self.inverted
+    }
+
+    pub fn trim(&self) -> f64 {
+        self.trim
+    }
+
+    /// Map a raw axis value in [-1, 1] to the value seen by bindings, also in [-1, 1].
+    pub fn apply(&self, raw: f64) -> f64 {
+        let v = if self.inverted { -raw } else { raw };
+        let magnitude = v.abs();
+        let scaled = if magnitude <= self.deadzone {
+            0.
+        } else if magnitude >= self.saturation {
+            1.
+        } else {
+            (magnitude - self.deadzone) / (self.saturation - self.deadzone)
+        };
+        let shaped = self.curve.apply(scaled).copysign(v);
+        (shaped + self.trim).clamp(-1., 1.)
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            (0. ..1.).contains(&self.deadzone),
+            "axis deadzone must be in [0, 1)"
+        );
+        ensure!(
+            self.saturation > self.deadzone && self.saturation <= 1.,
+            "axis saturation must be in (deadzone, 1]"
+        );
+        ensure!(
+            (-1. ..=1.).contains(&self.trim),
+            "axis trim must be in [-1, 1]"
+        );
+        match &self.curve {
+            ResponseCurve::Linear => {}
+            ResponseCurve::Expo(k) => {
+                ensure!((0. ..=1.).contains(k), "axis expo must be in [0, 1]")
+            }
+            ResponseCurve::Custom(points) => {
+                let mut prior = 0.;
+                for (x, y) in points {
+                    ensure!(
+                        *x >= prior && *x <= 1.,
+                        "axis curve points must be in order and in [0, 1]"
+                    );
+                    ensure!(
+                        (0. ..=1.).contains(y),
+                        "axis curve outputs must be in [0, 1]"
+                    );
+                    prior = *x;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for AxisResponse {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        fn number(key: &str, value: Option<&str>) -> Result<f64> {
+            match value.map(|v| v.trim().parse::<f64>()) {
+                Some(Ok(v)) => Ok(v),
+                _ => bail!(
+                    "axis option {} needs a numeric value, e.g. {}=0.1",
+                    key,
+                    key
+                ),
+            }
+        }
+
+        let mut response = Self::default();
+        for option in s.split_whitespace() {
+            let mut parts = option.splitn(2, '=');
+            let key = parts.next().unwrap_or_default();
+            let value = parts.next();
+            match key {
+                "deadzone" => response.deadzone = number(key, value)?,
+                "saturation" => response.saturation = number(key, value)?,
+                "trim" => response.trim = number(key, value)?,
+                "expo" => response.curve = ResponseCurve::Expo(number(key, value)?),
+                "linear" => response.curve = ResponseCurve::Linear,
+                "invert" | "inverted" => response.inverted = true,
+                "curve" => {
+                    let mut points = Vec::new();
+                    for point in value.unwrap_or_default().split(',') {
+                        let mut xy = point.splitn(2, ':');
+                        let x = number("curve", xy.next())?;
+                        let y = number("curve", xy.next())?;
+                        points.push((x, y));
+                    }
+                    response.curve = ResponseCurve::Custom(points);
+                }
+                _ => bail!(
+                    "unknown axis option '{}'; expected deadzone, saturation, expo, curve, trim, or invert",
+                    option
+                ),
+            }
+        }
+        response.validate()?;
+        Ok(response)
+    }
+}
+
+impl fmt::Display for AxisResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "deadzone={} saturation={}",
+            self.deadzone, self.saturation
+        )?;
+        match &self.curve {
+            ResponseCurve::Linear => {}
+            ResponseCurve::Expo(k) => write!(f, " expo={}", k)?,
+            ResponseCurve::Custom(points) => {
+                let points = points
+                    .iter()
+                    .map(|(x, y)| format!("{}:{}", x, y))
+                    .collect::<Vec<_>>();
+                write!(f, " curve={}", points.join(","))?;
+            }
+        }
+        if self.trim != 0. {
+            write!(f, " trim={}", self.trim)?;
+        }
+        if self.inverted {
+            write!(f, " invert")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_default_is_identity() {
+        let response = AxisResponse::default();
+        for v in [-1., -0.5, 0., 0.25, 1.] {
+            assert_relative_eq!(response.apply(v), v);
+        }
+    }
+
+    #[test]
+    fn test_deadzone_and_saturation() -> Result<()> {
+        let response = "deadzone=0.1 saturation=0.9".parse::<AxisResponse>()?;
+        assert_relative_eq!(response.apply(0.05), 0.);
+        assert_relative_eq!(response.apply(-0.1), 0.);
+        assert_relative_eq!(response.apply(0.5), 0.5);
+        assert_relative_eq!(response.apply(-0.95), -1.);
+        Ok(())
+    }
+
+    #[test]
+    fn test_curves_invert_and_trim() -> Result<()> {
+        let expo = "expo=1".parse::<AxisResponse>()?;
+        assert_relative_eq!(expo.apply(0.5), 0.125);
+        assert_relative_eq!(expo.apply(-0.5), -0.125);
+
+        let custom = "curve=0.5:0.2 invert trim=0.1".parse::<AxisResponse>()?;
+        assert_relative_eq!(custom.apply(-0.5), 0.3);
+        assert_relative_eq!(custom.apply(-0.75), 0.7);
+        assert_relative_eq!(custom.apply(-1.), 1.);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        for spec in [
+            "deadzone=0.05 saturation=0.95 expo=0.3",
+            "deadzone=0 saturation=1 curve=0.25:0.1,0.75:0.6 trim=-0.02 invert",
+        ] {
+            let response = spec.parse::<AxisResponse>()?;
+            assert_eq!(response.to_string(), spec);
+        }
+        assert!("deadzone=1.5".parse::<AxisResponse>().is_err());
+        assert!("wobble=3".parse::<AxisResponse>().is_err());
+        Ok(())
+    }
+}
@@ -13,23 +13,30 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 use crate::{
-    input::{Input, InputSet},
+    axis::AxisResponse,
+    input::{AxisInput, Input, InputSet},
+    timing::ChordTiming,
     State,
 };
-use anyhow::Result;
+use anyhow::{bail, Result};
 use input::ElementState;
 use log::{debug, trace};
 use nitrous::{LocalNamespace, NitrousScript, Value};
+use ordered_float::OrderedFloat;
 use runtime::ScriptHerder;
 use smallvec::{smallvec, SmallVec};
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap, time::Duration};
 
 // Map from key, buttons, and axes to commands.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Bindings {
     pub name: String,
     press_chords: HashMap<Input, Vec<InputSet>>,
     script_map: HashMap<InputSet, Vec<NitrousScript>>,
+    axis_responses: HashMap<Input, AxisResponse>,
+
+    // The text of each binding, in the order bound, for listing and saving.
+    sources: Vec<(String, String)>,
 }
 
 impl Bindings {
@@ -38,6 +45,8 @@ impl Bindings {
             name: name.to_owned(),
             press_chords: HashMap::new(),
             script_map: HashMap::new(),
+            axis_responses: HashMap::new(),
+            sources: Vec::new(),
         }
     }
 
@@ -63,9 +72,111 @@ impl Bindings {
                 sets.sort_by_key(|ks| usize::max_value() - ks.keys.len());
             }
         }
+        self.sources
+            .push((event_name.to_owned(), script_raw.to_owned()));
+        Ok(())
+    }
+
+    /// Remove every script bound to the given keys, regardless of whether it was bound to
+    /// trigger on press or release. Axis responses are left in place. Returns true if anything
+    /// was bound.
+    pub fn unbind(&mut self, event_name: &str) -> Result<bool> {
+        let targets = InputSet::from_binding(event_name)?;
+        let is_target = |ks: &InputSet| targets.iter().any(|t| t.same_keys(ks));
+        let before = self.script_map.len();
+        self.script_map.retain(|ks, _| !is_target(ks));
+        for sets in self.press_chords.values_mut() {
+            sets.retain(|ks| !is_target(ks));
+        }
+        self.press_chords.retain(|_, sets| !sets.is_empty());
+        self.sources.retain(|(name, _)| {
+            InputSet::from_binding(name)
+                .map(|sets| !sets.iter().any(is_target))
+                .unwrap_or(true)
+        });
+        Ok(self.script_map.len() != before)
+    }
+
+    /// The (input, script) pairs already bound to the same keys as the given binding.
+    pub fn conflicts(&self, event_name: &str) -> Result<Vec<(&str, &str)>> {
+        let targets = InputSet::from_binding(event_name)?;
+        let is_bound = targets.iter().any(|t| {
+            t.keys.iter().any(|key| {
+                self.press_chords
+                    .get(key)
+                    .map(|sets| sets.iter().any(|ks| t.same_keys(ks)))
+                    .unwrap_or(false)
+            })
+        });
+        if !is_bound {
+            return Ok(vec![]);
+        }
+        Ok(self
+            .sources()
+            .filter(|(name, _)| {
+                InputSet::from_binding(name)
+                    .map(|sets| {
+                        sets.iter()
+                            .any(|ks| targets.iter().any(|t| t.same_keys(ks)))
+                    })
+                    .unwrap_or(false)
+            })
+            .collect())
+    }
+
+    /// The (input, script) pairs bound here, in the order they were bound.
+    pub fn sources(&self) -> impl Iterator<Item = (&str, &str)> + '_ {
+        self.sources
+            .iter()
+            .map(|(name, script)| (name.as_str(), script.as_str()))
+    }
+
+    /// The responses set on joystick axes here, by binding name; e.g. axis1.
+    pub fn axis_responses(&self) -> impl Iterator<Item = (String, &AxisResponse)> + '_ {
+        self.axis_responses
+            .iter()
+            .filter_map(|(input, response)| match input {
+                Input::Axis(AxisInput::JoystickAxis(i)) => Some((format!("axis{}", i), response)),
+                _ => None,
+            })
+    }
+
+    /// Set the deadzone, curve, etc, applied to the `value` passed to scripts bound to the
+    /// given joystick axis. The raw value is always available as `raw_value`.
+    pub fn set_axis_response(&mut self, axis_name: &str, response: AxisResponse) -> Result<()> {
+        let input = Input::from_binding(axis_name)?;
+        if !matches!(input, Input::Axis(AxisInput::JoystickAxis(_))) {
+            bail!("{} is not a joystick axis, e.g. axis0", axis_name);
+        }
+        self.axis_responses.insert(input, response);
         Ok(())
     }
 
+    pub fn axis_response(&self, axis_name: &str) -> Result<Option<&AxisResponse>> {
+        Ok(self.axis_responses.get(&Input::from_binding(axis_name)?))
+    }
+
+    // Axis events carry the raw value in both `raw_value` and `value`; replace the latter
+    // with the shaped value if we have a response for this axis.
+    fn shape_axis<'a>(
+        &self,
+        input: Input,
+        locals: &'a LocalNamespace,
+    ) -> Result<Cow<'a, LocalNamespace>> {
+        if let (Some(response), Some(raw)) =
+            (self.axis_responses.get(&input), locals.get("raw_value"))
+        {
+            let mut locals = locals.to_owned();
+            locals.put(
+                "value",
+                Value::Float(OrderedFloat(response.apply(raw.to_float()?))),
+            );
+            return Ok(Cow::Owned(locals));
+        }
+        Ok(Cow::Borrowed(locals))
+    }
+
+    /// Returns true if anything here is bound to the input.
     pub fn match_input(
         &self,
         input: Input,
@@ -73,13 +184,12 @@ impl Bindings {
         state: &mut State,
         locals: &LocalNamespace,
         herder: &mut ScriptHerder,
-    ) -> Result<()> {
-        match press_state {
+    ) -> Result<bool> {
+        Ok(match press_state {
             Some(ElementState::Pressed) => self.handle_press(input, state, locals, herder)?,
             Some(ElementState::Released) => self.handle_release(input, state, locals, herder)?,
             None => self.handle_edge(input, state, locals, herder)?,
-        }
-        Ok(())
+        })
     }
 
     fn handle_edge(
@@ -88,18 +198,21 @@ impl Bindings {
         state: &mut State,
         locals: &LocalNamespace,
         herder: &mut ScriptHerder,
-    ) -> Result<()> {
+    ) -> Result<bool> {
+        let mut matched = false;
         if let Some(possible_chord_list) = self.press_chords.get(&input) {
+            let locals = self.shape_axis(input, locals)?;
             for chord in possible_chord_list {
                 if chord.is_pressed(Some(input), state) {
+                    matched = true;
                     // Note: chord is in possible chord list, so must be present.
                     for script in &self.script_map[chord] {
-                        herder.run_binding(locals.to_owned(), script.to_owned());
+                        herder.run_binding(locals.as_ref().to_owned(), script.to_owned());
                     }
                 }
             }
         }
-        Ok(())
+        Ok(matched)
     }
 
     fn handle_press(
@@ -108,17 +221,19 @@ impl Bindings {
         state: &mut State,
         locals: &LocalNamespace,
         herder: &mut ScriptHerder,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         // The press chords gives us a quick map from a key press to all chords which could become
         // active in the case that it is pressed so that we don't have to look at everything.
+        let mut matched = false;
         if let Some(possible_chord_list) = self.press_chords.get(&input) {
             for chord in possible_chord_list {
                 if chord.is_pressed(None, state) {
+                    matched = true;
                     self.maybe_activate_chord(chord, state, locals, herder)?;
                 }
             }
         }
-        Ok(())
+        Ok(matched)
     }
 
     fn chord_is_masked(chord: &InputSet, state: &State) -> bool {
@@ -164,13 +279,13 @@ impl Bindings {
         for masked in &masked_chords {
             state.active_chords.remove(masked);
             if let Some(scripts) = self.script_map.get(masked) {
-                self.deactiveate_chord(masked, locals, scripts, herder);
+                self.deactiveate_chord(masked, state, locals, scripts, herder);
             }
         }
 
         // Activate the chord and run the command.
         state.active_chords.insert(chord.to_owned());
-        self.activate_chord(chord, locals, &self.script_map[chord], herder);
+        self.activate_chord(chord, state, locals, &self.script_map[chord], herder);
 
         Ok(())
     }
@@ -181,20 +296,18 @@ impl Bindings {
         state: &mut State,
         locals: &LocalNamespace,
         herder: &mut ScriptHerder,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         // Remove any chords that have been released.
-        let mut released_chords: SmallVec<[InputSet; 4]> = smallvec![];
-        for active_chord in &state.active_chords {
-            if active_chord.contains_key(&key) {
-                // Note: unlike with press, we do not implicitly filter out keys we don't care about.
-                if let Some(scripts) = self.script_map.get(active_chord) {
-                    released_chords.push(active_chord.to_owned());
-                    self.deactiveate_chord(active_chord, locals, scripts, herder);
-                }
-            }
-        }
+        // Note: unlike with press, we do not implicitly filter out keys we don't care about.
+        let released_chords = state
+            .active_chords
+            .iter()
+            .filter(|chord| chord.contains_key(&key) && self.script_map.contains_key(chord))
+            .cloned()
+            .collect::<SmallVec<[InputSet; 4]>>();
         for chord in &released_chords {
             state.active_chords.remove(chord);
+            self.deactiveate_chord(chord, state, locals, &self.script_map[chord], herder);
         }
 
         // If we removed a chord, then it may have been masking an active command. Re-enable any
@@ -203,44 +316,226 @@ impl Bindings {
             for (chord, scripts) in &self.script_map {
                 if chord.is_subset_of(released_chord) && chord.is_pressed(None, state) {
                     state.active_chords.insert(chord.to_owned());
-                    self.activate_chord(chord, locals, scripts, herder);
+                    self.activate_chord(chord, state, locals, scripts, herder);
                 }
             }
         }
 
-        Ok(())
+        Ok(!released_chords.is_empty())
+    }
+
+    // Timed bindings on the same keys as a plain binding turn the plain binding into a tap.
+    fn defers_tap(&self, chord: &InputSet) -> bool {
+        self.timed_siblings(chord).any(|ks| ks.timing.defers_tap())
+    }
+
+    // How long to wait after a tap to see if it is the first half of a double tap.
+    fn tap_window(&self, chord: &InputSet) -> Option<Duration> {
+        self.timed_siblings(chord)
+            .filter_map(|ks| match ks.timing {
+                ChordTiming::DoubleTap(window) => Some(window),
+                _ => None,
+            })
+            .max()
+    }
+
+    fn timed_siblings<'a>(&'a self, chord: &'a InputSet) -> impl Iterator<Item = &'a InputSet> {
+        chord
+            .keys
+            .first()
+            .and_then(|key| self.press_chords.get(key))
+            .into_iter()
+            .flatten()
+            .filter(move |ks| ks.same_hold(chord) && !ks.timing.is_immediate())
     }
 
     fn activate_chord(
         &self,
         chord: &InputSet,
+        state: &mut State,
         locals: &LocalNamespace,
         scripts: &[NitrousScript],
         herder: &mut ScriptHerder,
     ) {
-        if !chord.trigger_on_down {
-            return;
+        let now = state.now;
+        let hold = state.holds.entry(chord.hold_key()).or_default();
+        if !hold.held {
+            hold.held = true;
+            hold.consumed = false;
+            hold.previous_press = if hold.tap_pending {
+                Some(hold.pressed_at)
+            } else {
+                None
+            };
+            hold.pressed_at = now;
         }
-        for script in scripts {
-            let mut locals = locals.to_owned();
-            locals.put("pressed", Value::True());
-            herder.run_binding(locals, script);
+        match chord.timing {
+            ChordTiming::Immediate => {
+                if !self.defers_tap(chord) {
+                    Self::run_scripts(chord.trigger_on_down, true, locals, scripts, herder);
+                }
+            }
+            // Fired from handle_time once held long enough.
+            ChordTiming::LongPress(_) => {}
+            ChordTiming::DoubleTap(window) => {
+                let is_second_tap = hold.tap_pending
+                    && hold
+                        .previous_press
+                        .map(|t| now - t <= window)
+                        .unwrap_or(false);
+                if is_second_tap {
+                    hold.tap_pending = false;
+                    hold.consumed = true;
+                    state.fired.insert(chord.to_owned());
+                    Self::run_scripts(chord.trigger_on_down, true, locals, scripts, herder);
+                }
+            }
+            ChordTiming::Repeat { delay, .. } => {
+                state.fired.insert(chord.to_owned());
+                state.repeats.insert(chord.to_owned(), now + delay);
+                Self::run_scripts(chord.trigger_on_down, true, locals, scripts, herder);
+            }
         }
     }
 
     fn deactiveate_chord(
         &self,
         chord: &InputSet,
+        state: &mut State,
+        locals: &LocalNamespace,
+        scripts: &[NitrousScript],
+        herder: &mut ScriptHerder,
+    ) {
+        let fired = state.fired.remove(chord);
+        state.repeats.remove(chord);
+        let hold = state.holds.entry(chord.hold_key()).or_default();
+        hold.held = false;
+        match chord.timing {
+            ChordTiming::Immediate => {
+                if !self.defers_tap(chord) {
+                    Self::run_scripts(chord.trigger_on_up, false, locals, scripts, herder);
+                } else if !hold.consumed {
+                    if self.tap_window(chord).is_some() {
+                        // Wait and see if this is the first half of a double tap.
+                        hold.tap_pending = true;
+                    } else {
+                        Self::run_tap(chord, locals, scripts, herder);
+                    }
+                }
+            }
+            _ => {
+                if matches!(chord.timing, ChordTiming::DoubleTap(_)) && !hold.consumed {
+                    hold.tap_pending = true;
+                }
+                if fired {
+                    Self::run_scripts(chord.trigger_on_up, false, locals, scripts, herder);
+                }
+            }
+        }
+    }
+
+    /// Run any timed bindings that have come due: long presses that have been held long
+    /// enough, repeats, and taps that did not turn into a double tap.
+    pub fn handle_time(&self, state: &mut State, herder: &mut ScriptHerder) {
+        let now = state.now;
+        let locals = LocalNamespace::empty();
+        let mut expired_taps: SmallVec<[InputSet; 2]> = smallvec![];
+        for (chord, scripts) in &self.script_map {
+            match chord.timing {
+                ChordTiming::Immediate | ChordTiming::DoubleTap(_) => {
+                    let window = match self.tap_window(chord) {
+                        Some(window) => window,
+                        None => continue,
+                    };
+                    let expired = state
+                        .holds
+                        .get(&chord.hold_key())
+                        .map(|hold| {
+                            hold.tap_pending && !hold.held && now - hold.pressed_at > window
+                        })
+                        .unwrap_or(false);
+                    if expired {
+                        if chord.timing.is_immediate() {
+                            Self::run_tap(chord, &locals, scripts, herder);
+                        }
+                        expired_taps.push(chord.hold_key());
+                    }
+                }
+                ChordTiming::LongPress(threshold) => {
+                    if !state.active_chords.contains(chord) || state.fired.contains(chord) {
+                        continue;
+                    }
+                    if let Some(hold) = state.holds.get_mut(&chord.hold_key()) {
+                        if hold.held && now - hold.pressed_at >= threshold {
+                            hold.consumed = true;
+                            state.fired.insert(chord.to_owned());
+                            Self::run_scripts(
+                                chord.trigger_on_down,
+                                true,
+                                &locals,
+                                scripts,
+                                herder,
+                            );
+                        }
+                    }
+                }
+                ChordTiming::Repeat { interval, .. } => {
+                    if let Some(next) = state.repeats.get_mut(chord) {
+                        if *next <= now {
+                            // Like timers, fire once if we have fallen behind.
+                            while *next <= now {
+                                *next += interval;
+                            }
+                            Self::run_scripts(
+                                chord.trigger_on_down,
+                                true,
+                                &locals,
+                                scripts,
+                                herder,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        for key in &expired_taps {
+            if let Some(hold) = state.holds.get_mut(key) {
+                hold.tap_pending = false;
+            }
+        }
+        state.holds.retain(|_, hold| hold.held || hold.tap_pending);
+    }
+
+    fn run_tap(
+        chord: &InputSet,
+        locals: &LocalNamespace,
+        scripts: &[NitrousScript],
+        herder: &mut ScriptHerder,
+    ) {
+        Self::run_scripts(chord.trigger_on_down, true, locals, scripts, herder);
+        Self::run_scripts(chord.trigger_on_up, false, locals, scripts, herder);
+    }
+
+    fn run_scripts(
+        enabled: bool,
+        pressed: bool,
         locals: &LocalNamespace,
         scripts: &[NitrousScript],
         herder: &mut ScriptHerder,
     ) {
-        if !chord.trigger_on_up {
+        if !enabled {
             return;
         }
         for script in scripts {
             let mut locals = locals.to_owned();
-            locals.put("pressed", Value::False());
+            locals.put(
+                "pressed",
+                if pressed {
+                    Value::True()
+                } else {
+                    Value::False()
+                },
+            );
             herder.run_binding(locals, script);
         }
     }
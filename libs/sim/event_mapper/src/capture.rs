@@ -0,0 +1,114 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::input::{AxisInput, Input};
+use input::{ElementState, InputEvent};
+use nitrous::NitrousScript;
+use std::collections::HashMap;
+
+// How far an axis has to move from where it was when we first saw it, to count as the user
+// picking that axis. Large, so that noise and accidental bumps of a second axis are ignored.
+const AXIS_CAPTURE_THRESHOLD: f64 = 0.5;
+
+/// Watches for the next chord or axis motion, so that a controls menu can ask the user to
+/// press the thing they want to bind.
+#[derive(Default)]
+pub(crate) struct InputCapture {
+    armed: bool,
+    script: Option<NitrousScript>,
+    axis_baseline: HashMap<(u32, u32), f64>,
+    result: Option<String>,
+}
+
+impl std::fmt::Debug for InputCapture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InputCapture")
+            .field("armed", &self.armed)
+            .field("result", &self.result)
+            .finish()
+    }
+}
+
+impl InputCapture {
+    pub(crate) fn arm(&mut self, script: Option<NitrousScript>) {
+        self.armed = true;
+        self.script = script;
+        self.axis_baseline.clear();
+        self.result = None;
+    }
+
+    pub(crate) fn cancel(&mut self) {
+        self.armed = false;
+        self.script = None;
+    }
+
+    pub(crate) fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    pub(crate) fn result(&self) -> Option<&str> {
+        self.result.as_deref()
+    }
+
+    pub(crate) fn take_result(&mut self) -> Option<String> {
+        self.result.take()
+    }
+
+    /// Returns true if the event was used by the capture and should not be passed on to
+    /// bindings. Returns the captured chord and the script to notify once we have one.
+    pub(crate) fn observe(
+        &mut self,
+        event: &InputEvent,
+        input: Input,
+    ) -> (bool, Option<(String, Option<NitrousScript>)>) {
+        if !self.armed {
+            return (false, None);
+        }
+        let captured = match event {
+            // Modifiers on their own are part of a chord, not a binding.
+            InputEvent::KeyboardKey { .. }
+            | InputEvent::MouseButton { .. }
+            | InputEvent::JoystickButton { .. } => {
+                if event.press_state() != Some(ElementState::Pressed) {
+                    // Let releases through so that chords active when we armed can finish.
+                    return (false, None);
+                }
+                if input.is_modifier() {
+                    return (true, None);
+                }
+                input.chord_name(event.modifiers_state().unwrap_or_default())
+            }
+            InputEvent::JoystickAxis {
+                device, id, value, ..
+            } => {
+                let baseline = *self.axis_baseline.entry((*device, *id)).or_insert(*value);
+                if (value - baseline).abs() < AXIS_CAPTURE_THRESHOLD {
+                    return (true, None);
+                }
+                Input::Axis(AxisInput::JoystickAxis(*id))
+                    .binding_name()
+                    .map(|s| s.to_owned())
+            }
+            _ => return (false, None),
+        };
+        match captured {
+            Some(chord) => {
+                self.armed = false;
+                self.result = Some(chord.clone());
+                (true, Some((chord, self.script.take())))
+            }
+            None => (true, None),
+        }
+    }
+}
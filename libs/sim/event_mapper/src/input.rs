@@ -12,9 +12,12 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
-use crate::State;
+use crate::{timing::ChordTiming, State};
 use anyhow::{bail, ensure, Result};
-use input::{ButtonId, ElementState, InputEvent, ModifiersState, VirtualKeyCode};
+use input::{
+    ButtonId, ElementState, InputEvent, ModifiersState, VirtualKeyCode, MAX_JOYSTICK_AXES,
+    MAX_JOYSTICK_BUTTONS,
+};
 use log::warn;
 use once_cell::sync::Lazy;
 use smallvec::SmallVec;
@@ -48,7 +51,7 @@ impl Input {
             InputEvent::MouseButton { button, .. } => Input::MouseButton(*button),
             InputEvent::MouseMotion { .. } => Input::Axis(AxisInput::MouseMotion),
             InputEvent::MouseWheel { .. } => Input::Axis(AxisInput::MouseWheel),
-            InputEvent::JoystickButton { dummy, .. } => Input::JoystickButton(*dummy),
+            InputEvent::JoystickButton { button, .. } => Input::JoystickButton(*button),
             InputEvent::JoystickAxis { id, .. } => Input::Axis(AxisInput::JoystickAxis(*id)),
             InputEvent::CursorMove { .. } => return None,
             InputEvent::DeviceAdded { .. } => Self::DeviceAdded,
@@ -91,10 +94,10 @@ static BIND_MAP: Lazy<HashMap<Ascii<Cow<'static, str>>, Input>> = Lazy::new(|| {
         m.insert(Ascii::new(Cow::from(format!("mouse{}", i))), Input::MouseButton(i));
     }
     // Joystick
-    for i in 0..99 {
+    for i in 0..MAX_JOYSTICK_AXES {
         m.insert(Ascii::new(Cow::from(format!("axis{}", i))), Input::Axis(AxisInput::JoystickAxis(i)));
     }
-    for i in 0..99 {
+    for i in 0..MAX_JOYSTICK_BUTTONS {
         m.insert(Ascii::new(Cow::from(format!("joy{}", i))), Input::JoystickButton(i));
     }
     // Key
@@ -262,7 +265,50 @@ static BIND_MAP: Lazy<HashMap<Ascii<Cow<'static, str>>, Input>> = Lazy::new(|| {
     m
 });
 
+// The canonical binding name for each input, for going the other direction.
+static NAME_MAP: Lazy<HashMap<Input, String>> = Lazy::new(|| {
+    let mut m: HashMap<Input, String> = HashMap::new();
+    for (name, input) in BIND_MAP.iter() {
+        let name = name.to_string();
+        match m.get(input) {
+            Some(prior) if *prior <= name => {}
+            _ => {
+                m.insert(*input, name);
+            }
+        }
+    }
+    m
+});
+
 impl Input {
+    /// The name to use to bind this input, e.g. joy3 or PageUp.
+    pub fn binding_name(&self) -> Option<&'static str> {
+        NAME_MAP.get(self).map(|s| s.as_str())
+    }
+
+    pub fn is_modifier(&self) -> bool {
+        !self.modifier().is_empty()
+    }
+
+    /// A binding for this input with the given modifiers held, e.g. Shift+joy3.
+    pub fn chord_name(&self, modifiers: ModifiersState) -> Option<String> {
+        let mut parts = Vec::new();
+        if modifiers.ctrl() {
+            parts.push("Control");
+        }
+        if modifiers.alt() {
+            parts.push("Alt");
+        }
+        if modifiers.shift() {
+            parts.push("Shift");
+        }
+        if modifiers.logo() {
+            parts.push("Win");
+        }
+        parts.push(self.binding_name()?);
+        Some(parts.join("+"))
+    }
+
     pub fn from_binding(s: &str) -> Result<Self> {
         Ok(if let Some(key) = BIND_MAP.get(&Ascii::new(Cow::from(s))) {
             *key
@@ -304,6 +350,7 @@ pub struct InputSet {
     pub(crate) modifiers: ModifiersState,
     pub(crate) trigger_on_down: bool,
     pub(crate) trigger_on_up: bool,
+    pub(crate) timing: ChordTiming,
 }
 
 impl InputSet {
@@ -314,6 +361,9 @@ impl InputSet {
     //
     // Prefixing with a + will cause the binding to fire on both keyup and keydown events.
     //
+    // Suffixing with a :long, :double, or :repeat will delay or repeat the binding; see
+    // timing.rs for details.
+    //
     // Note that there is a special case for the 4 modifiers in which we
     // expect to be able to refer to "Control" and not care what key it is.
     // In this case we emit all possible keysets, combinatorially.
@@ -334,6 +384,10 @@ impl InputSet {
         } else {
             keyset
         };
+        let (keyset, timing) = match keyset.split_once(':') {
+            Some((keyset, timing)) => (keyset, timing.parse::<ChordTiming>()?),
+            None => (keyset, ChordTiming::Immediate),
+        };
         for keyname in keyset.split('+') {
             if let Ok(key) = Input::from_binding(keyname) {
                 for tmp in &mut out {
@@ -364,10 +418,34 @@ impl InputSet {
                 keys: v,
                 trigger_on_down,
                 trigger_on_up,
+                timing,
             })
             .collect::<Vec<_>>())
     }
 
+    /// True if both sets are made of the same keys, ignoring press and release triggers.
+    /// Differently timed bindings on the same keys are not the same, so that a long press
+    /// can be bound or unbound separately from a tap.
+    pub fn same_keys(&self, other: &InputSet) -> bool {
+        self.timing == other.timing && self.same_hold(other)
+    }
+
+    /// True if both sets are made of the same keys, regardless of timing.
+    pub(crate) fn same_hold(&self, other: &InputSet) -> bool {
+        self.keys.len() == other.keys.len() && self.keys.iter().all(|k| other.keys.contains(k))
+    }
+
+    /// The set that all differently timed bindings on these keys share their state under.
+    pub(crate) fn hold_key(&self) -> InputSet {
+        InputSet {
+            keys: self.keys.clone(),
+            modifiers: self.modifiers,
+            trigger_on_down: true,
+            trigger_on_up: false,
+            timing: ChordTiming::Immediate,
+        }
+    }
+
     pub fn contains_key(&self, key: &Input) -> bool {
         for own_key in &self.keys {
             if key == own_key {
@@ -434,7 +512,7 @@ impl fmt::Display for InputSet {
             }
             write!(f, "{:?}", key)?;
         }
-        write!(f, "}}")
+        write!(f, "}}{}", self.timing)
     }
 }
 
@@ -473,6 +551,23 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_chord_names() -> Result<()> {
+        assert_eq!(Input::JoystickButton(3).binding_name(), Some("joy3"));
+        assert_eq!(
+            Input::KeyboardKey(VirtualKeyCode::PageUp)
+                .chord_name(ModifiersState::SHIFT | ModifiersState::CTRL),
+            Some("Control+Shift+PageUp".to_owned())
+        );
+        for name in ["Shift+joy3", "axis2", "Control+Alt+F"] {
+            let sets = InputSet::from_binding(name)?;
+            let first = &sets[0];
+            let last = first.keys.last().unwrap();
+            assert_eq!(last.chord_name(first.modifiers), Some(name.to_owned()));
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_can_create_keysets() -> Result<()> {
         assert_eq!(InputSet::from_binding("a+b")?.len(), 1);
@@ -12,12 +12,19 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+mod axis;
 mod bindings;
+mod capture;
 mod input;
 mod mapper;
+mod profile;
+mod timing;
 
 pub(crate) use crate::mapper::State;
 pub use crate::{
+    axis::{AxisResponse, ResponseCurve},
     bindings::Bindings,
     mapper::{EventMapper, EventMapperStep},
+    profile::{BindingOp, BindingProfile},
+    timing::ChordTiming,
 };
@@ -13,21 +13,30 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 use crate::{
+    axis::AxisResponse,
     bindings::Bindings,
+    capture::InputCapture,
     input::{Input, InputSet},
+    profile::{profiles_from_json, profiles_to_json, BindingOp, BindingProfile},
+    timing::Hold,
 };
 use anyhow::{bail, Result};
 use bevy_ecs::prelude::*;
 use input::{
     ElementState, InputEvent, InputEventVec, InputFocus, InputStep, InputTarget, ModifiersState,
 };
-use nitrous::{inject_nitrous_resource, method, NitrousResource, Value};
+use log::warn;
+use nitrous::{inject_nitrous_resource, method, NitrousResource, NitrousScript, Value};
 use ordered_float::OrderedFloat;
-use runtime::{Extension, Runtime, ScriptHerder};
+use runtime::{Extension, Runtime, ScriptHerder, ScriptTimers};
+use smallvec::SmallVec;
 use std::{
     collections::{HashMap, HashSet},
     fmt::Debug,
+    fs,
+    path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 
 #[derive(Debug, Default)]
@@ -35,6 +44,12 @@ pub struct State {
     pub modifiers_state: ModifiersState,
     pub input_states: HashMap<Input, ElementState>,
     pub active_chords: HashSet<InputSet>,
+
+    // Sim time, for timed bindings.
+    pub(crate) now: Duration,
+    pub(crate) holds: HashMap<InputSet, Hold>,
+    pub(crate) fired: HashSet<InputSet>,
+    pub(crate) repeats: HashMap<InputSet, Duration>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash, SystemLabel)]
@@ -44,8 +59,24 @@ pub enum EventMapperStep {
 
 #[derive(Default, Debug, NitrousResource)]
 pub struct EventMapper {
+    // Bindings made by extensions and the prelude at startup.
+    defaults: HashMap<InputFocus, Bindings>,
+
+    // User changes, layered on top of the defaults; see profile.rs.
+    profiles: Vec<BindingProfile>,
+    editing: Option<String>,
+    profile_path: Option<PathBuf>,
+    connected_devices: HashMap<u32, String>,
+
+    // The defaults with all active profiles applied.
     bindings: HashMap<InputFocus, Bindings>,
-    state: State,
+
+    // Chord state for each focus context, so that a release only reaches the bindings that
+    // saw the press, even if the focus stack has changed in between.
+    states: HashMap<InputFocus, State>,
+
+    // Set while a controls menu is waiting for the user to press something.
+    capture: InputCapture,
 }
 
 impl Extension for EventMapper {
@@ -63,10 +94,51 @@ impl Extension for EventMapper {
 #[inject_nitrous_resource]
 impl EventMapper {
     pub fn new() -> Self {
-        Self {
-            bindings: HashMap::new(),
-            state: State::default(),
+        Self::default()
+    }
+
+    fn parse_focus(focus_name: &str) -> Result<InputFocus> {
+        match InputFocus::from_str(focus_name) {
+            Ok(focus) => Ok(focus),
+            Err(e) => bail!("{:?}", e),
+        }
+    }
+
+    fn editing_profile(&mut self) -> Option<&mut BindingProfile> {
+        let name = self.editing.as_ref()?;
+        self.profiles.iter_mut().find(|p| p.name() == name.as_str())
+    }
+
+    fn profile_is_active(&self, profile: &BindingProfile) -> bool {
+        match profile.device() {
+            Some(guid) => self.connected_devices.values().any(|g| g == guid),
+            None => true,
+        }
+    }
+
+    // Recompute the live bindings from the defaults and whichever profiles are active:
+    // profiles without a device first, then device profiles, each in the order created.
+    fn rebuild(&mut self) -> Result<()> {
+        let mut bindings = self.defaults.clone();
+        for for_device in [false, true] {
+            for profile in &self.profiles {
+                if profile.device().is_some() == for_device && self.profile_is_active(profile) {
+                    profile.apply(&mut bindings)?;
+                }
+            }
+        }
+        self.bindings = bindings;
+        Ok(())
+    }
+
+    // Record a change in the profile being edited, or in the defaults if none is.
+    fn record(&mut self, op: BindingOp) -> Result<()> {
+        if let Some(profile) = self.editing_profile() {
+            profile.push(op)?;
+        } else {
+            op.apply(&mut self.defaults)?;
         }
+        self.rebuild()
     }
 
     pub fn bind_in_focus(
@@ -75,21 +147,18 @@ impl EventMapper {
         event_name: &str,
         script_raw: &str,
     ) -> Result<()> {
-        let bindings = self
-            .bindings
-            .entry(focus)
-            .or_insert_with(|| Bindings::new(focus.name()));
-        bindings.bind(event_name, script_raw)?;
-        Ok(())
+        self.record(BindingOp::Bind {
+            focus,
+            input: event_name.to_owned(),
+            script: script_raw.to_owned(),
+        })
     }
 
+    /// Bind in a named focus context. These bindings only see input while the context is
+    /// open on the input_target stack and not hidden by a context above it.
     #[method]
     pub fn bind_in(&mut self, focus_name: &str, event_name: &str, script_raw: &str) -> Result<()> {
-        let focus = match InputFocus::from_str(focus_name) {
-            Ok(focus) => focus,
-            Err(e) => bail!("{:?}", e),
-        };
-        self.bind_in_focus(focus, event_name, script_raw)
+        self.bind_in_focus(Self::parse_focus(focus_name)?, event_name, script_raw)
     }
 
     #[method]
@@ -97,14 +166,291 @@ impl EventMapper {
         self.bind_in_focus(InputFocus::default(), event_name, script_raw)
     }
 
+    pub fn unbind_in_focus(&mut self, focus: InputFocus, event_name: &str) -> Result<()> {
+        self.record(BindingOp::Unbind {
+            focus,
+            input: event_name.to_owned(),
+        })
+    }
+
+    /// Remove everything bound to the given keys in the given focus.
+    #[method]
+    pub fn unbind_in(&mut self, focus_name: &str, event_name: &str) -> Result<()> {
+        self.unbind_in_focus(Self::parse_focus(focus_name)?, event_name)
+    }
+
+    /// Remove everything bound to the given keys, e.g. unbind("Shift+F").
+    #[method]
+    pub fn unbind(&mut self, event_name: &str) -> Result<()> {
+        self.unbind_in_focus(InputFocus::default(), event_name)
+    }
+
+    /// Show everything that is currently bound, after applying profiles.
+    #[method]
+    pub fn list(&self) -> String {
+        let mut foci = self.bindings.keys().collect::<Vec<_>>();
+        foci.sort();
+        let mut out = Vec::new();
+        for focus in foci {
+            let bindings = &self.bindings[focus];
+            for (input, script) in bindings.sources() {
+                out.push(format!("{}: {} => {}", focus.name(), input, script));
+            }
+            let mut responses = bindings.axis_responses().collect::<Vec<_>>();
+            responses.sort_by(|a, b| a.0.cmp(&b.0));
+            for (axis, response) in responses {
+                out.push(format!("{}: {} ~ {}", focus.name(), axis, response));
+            }
+        }
+        out.join("\n")
+    }
+
+    pub fn set_axis_response_in_focus(
+        &mut self,
+        focus: InputFocus,
+        axis_name: &str,
+        response: &str,
+    ) -> Result<()> {
+        self.record(BindingOp::Axis {
+            focus,
+            axis: axis_name.to_owned(),
+            response: response.parse::<AxisResponse>()?,
+        })
+    }
+
+    /// Shape the value passed to scripts bound to a joystick axis in the given focus.
+    /// e.g. set_axis_response_in("game", "axis1", "deadzone=0.05 expo=0.3 invert")
+    #[method]
+    pub fn set_axis_response_in(
+        &mut self,
+        focus_name: &str,
+        axis_name: &str,
+        response: &str,
+    ) -> Result<()> {
+        self.set_axis_response_in_focus(Self::parse_focus(focus_name)?, axis_name, response)
+    }
+
+    /// Shape the value passed to scripts bound to a joystick axis. Options are deadzone,
+    /// saturation, expo or curve, trim, and invert; e.g. "deadzone=0.05 expo=0.3".
+    #[method]
+    pub fn set_axis_response(&mut self, axis_name: &str, response: &str) -> Result<()> {
+        self.set_axis_response_in_focus(InputFocus::default(), axis_name, response)
+    }
+
+    /// Show the response applied to a joystick axis in the default focus.
+    #[method]
+    pub fn axis_response(&self, axis_name: &str) -> Result<String> {
+        Ok(self
+            .bindings
+            .get(&InputFocus::default())
+            .map(|bindings| bindings.axis_response(axis_name))
+            .transpose()?
+            .flatten()
+            .cloned()
+            .unwrap_or_default()
+            .to_string())
+    }
+
+    /// Send subsequent bind, unbind, and set_axis_response calls to the named profile,
+    /// creating it if needed.
+    #[method]
+    pub fn edit_profile(&mut self, name: &str) {
+        if !self.profiles.iter().any(|p| p.name() == name) {
+            self.profiles.push(BindingProfile::new(name));
+        }
+        self.editing = Some(name.to_owned());
+    }
+
+    /// Send subsequent bind, unbind, and set_axis_response calls to the defaults.
+    #[method]
+    pub fn edit_defaults(&mut self) {
+        self.editing = None;
+    }
+
+    /// Only apply the named profile while the device with the given guid is connected. Pass an
+    /// empty guid to apply the profile all the time. See joysticks.list() for guids.
+    #[method]
+    pub fn set_profile_device(&mut self, name: &str, guid: &str) -> Result<()> {
+        let profile = match self.profiles.iter_mut().find(|p| p.name() == name) {
+            Some(profile) => profile,
+            None => bail!("no binding profile named {}", name),
+        };
+        profile.set_device(if guid.is_empty() { None } else { Some(guid) });
+        self.rebuild()
+    }
+
+    #[method]
+    pub fn remove_profile(&mut self, name: &str) -> Result<()> {
+        self.profiles.retain(|p| p.name() != name);
+        if self.editing.as_deref() == Some(name) {
+            self.editing = None;
+        }
+        self.rebuild()
+    }
+
+    /// Show all binding profiles, the device they are for, and whether they are active.
+    #[method]
+    pub fn profiles(&self) -> String {
+        self.profiles
+            .iter()
+            .map(|profile| {
+                format!(
+                    "{}{}{}{}",
+                    profile.name(),
+                    profile
+                        .device()
+                        .map(|guid| format!(" [{}]", guid))
+                        .unwrap_or_default(),
+                    if self.profile_is_active(profile) {
+                        " (active)"
+                    } else {
+                        ""
+                    },
+                    if self.editing.as_deref() == Some(profile.name()) {
+                        " (editing)"
+                    } else {
+                        ""
+                    },
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Record the next chord or axis movement instead of running bindings for it. When
+    /// something is captured, the given script is run with `captured` set to the binding
+    /// name, e.g. Shift+joy3 or axis2, and `conflicts` set to anything already bound to it.
+    /// Pass an empty script to poll with captured() instead.
+    #[method]
+    pub fn capture(&mut self, script: &str) -> Result<()> {
+        let script = if script.is_empty() {
+            None
+        } else {
+            Some(NitrousScript::compile(script)?)
+        };
+        self.capture.arm(script);
+        Ok(())
+    }
+
+    #[method]
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_armed()
+    }
+
+    #[method]
+    pub fn cancel_capture(&mut self) {
+        self.capture.cancel();
+    }
+
+    /// The last input captured, or an empty string if still waiting.
+    #[method]
+    pub fn captured(&self) -> String {
+        self.capture.result().unwrap_or_default().to_owned()
+    }
+
+    /// Take the last input captured, if any, so that it is only handled once.
+    pub fn take_captured(&mut self) -> Option<String> {
+        self.capture.take_result()
+    }
+
+    /// Show everything already bound to the same keys as the given binding, in any focus.
+    #[method]
+    pub fn conflicts(&self, event_name: &str) -> Result<String> {
+        let mut foci = self.bindings.keys().collect::<Vec<_>>();
+        foci.sort();
+        let mut out = Vec::new();
+        for focus in foci {
+            for (input, script) in self.bindings[focus].conflicts(event_name)? {
+                out.push(format!("{}: {} => {}", focus.name(), input, script));
+            }
+        }
+        Ok(out.join("\n"))
+    }
+
+    /// Use the given file for save_profiles and load_profiles, loading it now if it exists.
+    /// A file that cannot be loaded is reported and the default bindings are used instead,
+    /// so that a bad edit does not keep the game from starting.
+    pub fn use_profile_file(&mut self, path: &Path) -> Result<()> {
+        self.profile_path = Some(path.to_owned());
+        if path.exists() {
+            if let Err(e) = self.load_profiles_from(path) {
+                warn!(
+                    "failed to load binding profiles from {}, using default bindings: {}",
+                    path.display(),
+                    e
+                );
+                self.profiles.clear();
+                self.editing = None;
+                self.rebuild()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save_profiles_to(&self, path: &Path) -> Result<()> {
+        fs::write(path, profiles_to_json(&self.profiles))?;
+        Ok(())
+    }
+
+    pub fn load_profiles_from(&mut self, path: &Path) -> Result<()> {
+        self.profiles = profiles_from_json(&fs::read_to_string(path)?)?;
+        if let Some(name) = &self.editing {
+            if !self.profiles.iter().any(|p| p.name() == name) {
+                self.editing = None;
+            }
+        }
+        self.rebuild()
+    }
+
+    fn profile_path(&self) -> Result<&Path> {
+        match &self.profile_path {
+            Some(path) => Ok(path),
+            None => bail!("no binding profile file has been configured"),
+        }
+    }
+
+    /// Write all binding profiles to the profile file.
+    #[method]
+    pub fn save_profiles(&self) -> Result<()> {
+        self.save_profiles_to(self.profile_path()?)
+    }
+
+    /// Replace all binding profiles with the ones in the profile file.
+    #[method]
+    pub fn load_profiles(&mut self) -> Result<()> {
+        let path = self.profile_path()?.to_owned();
+        self.load_profiles_from(&path)
+    }
+
+    // Device profiles come and go with their devices.
+    fn track_device(&mut self, event: &InputEvent) -> Result<()> {
+        let guid = match event {
+            InputEvent::DeviceAdded { device, guid, .. } => {
+                self.connected_devices.insert(*device, guid.to_owned());
+                Some(guid.to_owned())
+            }
+            InputEvent::DeviceRemoved { device } => self.connected_devices.remove(device),
+            _ => return Ok(()),
+        };
+        if self
+            .profiles
+            .iter()
+            .any(|p| p.device().is_some() && p.device() == guid.as_deref())
+        {
+            self.rebuild()?;
+        }
+        Ok(())
+    }
+
     pub fn sys_handle_input_events(
         events: Res<InputEventVec>,
         input_target: Res<InputTarget>,
+        timers: Res<ScriptTimers>,
         mut herder: ResMut<ScriptHerder>,
         mut mapper: ResMut<EventMapper>,
     ) {
         mapper
-            .handle_events(&events, &input_target, &mut herder)
+            .handle_events(&events, &input_target, timers.now(), &mut herder)
             .expect("EventMapper::handle_events");
     }
 
@@ -112,8 +458,28 @@ impl EventMapper {
         &mut self,
         events: &[InputEvent],
         target: &InputTarget,
+        now: Duration,
         herder: &mut ScriptHerder,
     ) -> Result<()> {
+        // Inputs released in this batch; their repeats must not fire one last time below.
+        let released = events
+            .iter()
+            .filter(|event| event.press_state() == Some(ElementState::Released))
+            .filter_map(Input::from_event)
+            .collect::<SmallVec<[Input; 2]>>();
+
+        // Flush timed bindings first, so that a tap that has run out of time to become a
+        // double tap is not confused with a new press.
+        for (focus, bindings) in &self.bindings {
+            let state = self.states.entry(focus.to_owned()).or_default();
+            state.now = now;
+            state
+                .repeats
+                .retain(|chord, _| !chord.keys.iter().any(|key| released.contains(key)));
+            if !target.terminal_active() {
+                bindings.handle_time(state, herder);
+            }
+        }
         for event in events {
             self.handle_event(event, target, herder)?;
         }
@@ -126,6 +492,8 @@ impl EventMapper {
         target: &InputTarget,
         herder: &mut ScriptHerder,
     ) -> Result<()> {
+        self.track_device(event)?;
+
         let input = Input::from_event(event);
         if input.is_none() {
             return Ok(());
@@ -135,13 +503,18 @@ impl EventMapper {
         let mut variables = HashMap::with_capacity(8);
         variables.insert("window_focused", Value::Boolean(event.is_window_focused()));
 
-        if let Some(press_state) = event.press_state() {
-            self.state.input_states.insert(input, press_state);
-            // Note: pressed variable is set later, since we need to disable masked input sets.
+        // Note: pressed variable is set later, since we need to disable masked input sets.
+        for focus in self.bindings.keys() {
+            let state = self.states.entry(focus.to_owned()).or_default();
+            if let Some(press_state) = event.press_state() {
+                state.input_states.insert(input, press_state);
+            }
+            if let Some(modifiers_state) = event.modifiers_state() {
+                state.modifiers_state = modifiers_state;
+            }
         }
 
         if let Some(modifiers_state) = event.modifiers_state() {
-            self.state.modifiers_state = modifiers_state;
             variables.insert("shift_pressed", Value::Boolean(modifiers_state.shift()));
             variables.insert("alt_pressed", Value::Boolean(modifiers_state.alt()));
             variables.insert("ctrl_pressed", Value::Boolean(modifiers_state.ctrl()));
@@ -153,6 +526,19 @@ impl EventMapper {
             return Ok(());
         }
 
+        // Don't run bindings for input meant for the capture.
+        let (consumed, captured) = self.capture.observe(event, input);
+        if let Some((chord, Some(script))) = captured {
+            let conflicts = self.conflicts(&chord)?;
+            let mut locals = HashMap::with_capacity(2);
+            locals.insert("captured", Value::String(chord));
+            locals.insert("conflicts", Value::String(conflicts));
+            herder.run_binding(locals.into(), script);
+        }
+        if consumed {
+            return Ok(());
+        }
+
         // Collect variables to inject.
         match event {
             InputEvent::MouseMotion {
@@ -178,19 +564,49 @@ impl EventMapper {
                 );
                 variables.insert("in_window", Value::Boolean(*in_window));
             }
-            InputEvent::DeviceAdded { dummy } => {
-                variables.insert("device_id", Value::Integer(*dummy as i64));
+            InputEvent::DeviceAdded { device, name, guid } => {
+                variables.insert("device_id", Value::Integer(*device as i64));
+                variables.insert("device_name", Value::String(name.to_owned()));
+                variables.insert("device_guid", Value::String(guid.to_owned()));
+            }
+            InputEvent::DeviceRemoved { device } => {
+                variables.insert("device_id", Value::Integer(*device as i64));
             }
-            InputEvent::DeviceRemoved { dummy } => {
-                variables.insert("device_id", Value::Integer(*dummy as i64));
+            InputEvent::JoystickButton { device, .. } => {
+                variables.insert("device_id", Value::Integer(*device as i64));
             }
-            // FIXME: set variables for button state, key state, joy state, etc
+            InputEvent::JoystickAxis { device, value, .. } => {
+                variables.insert("device_id", Value::Integer(*device as i64));
+                // Bindings with an axis response will replace `value` with the shaped value.
+                variables.insert("raw_value", Value::Float(OrderedFloat(*value)));
+                variables.insert("value", Value::Float(OrderedFloat(*value)));
+            }
+            // FIXME: set variables for button state, key state, etc
             _ => {}
         }
 
         let locals = variables.into();
-        for bindings in self.bindings.values() {
-            bindings.match_input(input, event.press_state(), &mut self.state, &locals, herder)?
+        if event.press_state() == Some(ElementState::Released) {
+            // Releases go to every context, so that nothing stays held if the focus changes
+            // while a key is down.
+            for (focus, bindings) in &self.bindings {
+                if let Some(state) = self.states.get_mut(focus) {
+                    bindings.match_input(input, event.press_state(), state, &locals, herder)?;
+                }
+            }
+        } else {
+            // Offer everything else to the focus stack from the top down, stopping at the
+            // first context that is bound to it or that does not pass input through.
+            for context in target.active_contexts() {
+                let focus = context.focus();
+                if let (Some(bindings), Some(state)) =
+                    (self.bindings.get(focus), self.states.get_mut(focus))
+                {
+                    if bindings.match_input(input, event.press_state(), state, &locals, herder)? {
+                        break;
+                    }
+                }
+            }
         }
 
         Ok(())
@@ -211,6 +627,8 @@ mod test {
     struct Player {
         walking: bool,
         running: bool,
+        pitch: f64,
+        count: i64,
     }
 
     #[inject_nitrous_resource]
@@ -224,6 +642,49 @@ mod test {
         fn run(&mut self, pressed: bool) {
             self.running = pressed;
         }
+
+        #[method]
+        fn set_pitch(&mut self, value: f64) {
+            self.pitch = value;
+        }
+
+        #[method]
+        fn add(&mut self, n: i64) {
+            self.count += n;
+        }
+    }
+
+    fn button(button: u32, pressed: bool) -> InputEvent {
+        InputEvent::JoystickButton {
+            device: 0,
+            button,
+            press_state: if pressed {
+                ElementState::Pressed
+            } else {
+                ElementState::Released
+            },
+            modifiers_state: ModifiersState::empty(),
+            window_focused: true,
+        }
+    }
+
+    // Advance sim time by 100ms and handle the given events.
+    fn step(runtime: &mut Runtime, events: Vec<InputEvent>) {
+        runtime
+            .resource_mut::<ScriptTimers>()
+            .advance(Duration::from_millis(100));
+        runtime.insert_resource(mkinp(events));
+        runtime.run_sim_once();
+    }
+
+    fn axis(id: u32, value: f64) -> InputEvent {
+        InputEvent::JoystickAxis {
+            device: 0,
+            id,
+            value,
+            modifiers_state: ModifiersState::empty(),
+            window_focused: true,
+        }
     }
 
     fn press(key: VirtualKeyCode, ms: &mut ModifiersState) -> InputEvent {
@@ -329,6 +790,300 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_axis_response() -> Result<()> {
+        let mut runtime = prepare()?;
+        runtime.resource_mut::<ScriptHerder>().run_string(
+            r#"
+                bindings.bind("axis1", "player.set_pitch(value)");
+                bindings.set_axis_response("axis1", "deadzone=0.1 invert");
+            "#,
+        )?;
+        runtime.insert_resource(mkinp(vec![]));
+        runtime.run_sim_once();
+
+        runtime.insert_resource(mkinp(vec![axis(1, 0.55)]));
+        runtime.run_sim_once();
+        assert!((runtime.resource::<Player>().pitch + 0.5).abs() < 0.000_001);
+        runtime.insert_resource(mkinp(vec![axis(1, 0.05)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().pitch, 0.);
+
+        // Unshaped axes pass the raw value through.
+        runtime
+            .resource_mut::<ScriptHerder>()
+            .run_string(r#"bindings.bind("axis2", "player.set_pitch(value)");"#)?;
+        runtime.insert_resource(mkinp(vec![]));
+        runtime.run_sim_once();
+        runtime.insert_resource(mkinp(vec![axis(2, 0.05)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().pitch, 0.05);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn test_profiles_override_defaults() -> Result<()> {
+        let mut runtime = prepare()?;
+        let mut state = ModifiersState::empty();
+        let ms = &mut state;
+        runtime.resource_mut::<ScriptHerder>().run_string(
+            r#"
+                bindings.edit_profile("stick");
+                bindings.bind("+w", "player.run(pressed)");
+                bindings.set_profile_device("stick", "0300beef");
+                bindings.edit_defaults();
+            "#,
+        )?;
+        runtime.insert_resource(mkinp(vec![]));
+        runtime.run_sim_once();
+
+        // The device is not connected, so the defaults apply.
+        runtime.insert_resource(mkinp(vec![press(VKC::W, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().walking, true);
+        assert_eq!(runtime.resource::<Player>().running, false);
+        runtime.insert_resource(mkinp(vec![release(VKC::W, ms)]));
+        runtime.run_sim_once();
+
+        // Plugging in the device swaps in its profile.
+        runtime.insert_resource(mkinp(vec![InputEvent::DeviceAdded {
+            device: 1,
+            name: "Stick".to_owned(),
+            guid: "0300beef".to_owned(),
+        }]));
+        runtime.run_sim_once();
+        runtime.insert_resource(mkinp(vec![press(VKC::W, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().walking, false);
+        assert_eq!(runtime.resource::<Player>().running, true);
+        assert!(runtime
+            .resource::<EventMapper>()
+            .list()
+            .contains("game: +w => player.run(pressed)"));
+        runtime.insert_resource(mkinp(vec![release(VKC::W, ms)]));
+        runtime.run_sim_once();
+
+        // Save and reload.
+        let path = std::env::temp_dir().join("nitrogen-test-bindings.json");
+        runtime.resource::<EventMapper>().save_profiles_to(&path)?;
+        runtime
+            .resource_mut::<EventMapper>()
+            .remove_profile("stick")?;
+        assert!(!runtime
+            .resource::<EventMapper>()
+            .list()
+            .contains("game: +w => player.run"));
+        runtime
+            .resource_mut::<EventMapper>()
+            .load_profiles_from(&path)?;
+        std::fs::remove_file(&path)?;
+        assert!(runtime
+            .resource::<EventMapper>()
+            .list()
+            .contains("game: +w => player.run"));
+
+        // Unplugging it restores the defaults.
+        runtime.insert_resource(mkinp(vec![InputEvent::DeviceRemoved { device: 1 }]));
+        runtime.run_sim_once();
+        assert!(!runtime
+            .resource::<EventMapper>()
+            .list()
+            .contains("game: +w => player.run"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_profile_file_uses_defaults() -> Result<()> {
+        let mut runtime = prepare()?;
+        let path = std::env::temp_dir().join("nitrogen-test-bad-bindings.json");
+        std::fs::write(&path, "{ not json")?;
+        let result = runtime
+            .resource_mut::<EventMapper>()
+            .use_profile_file(&path);
+        std::fs::remove_file(&path)?;
+        result?;
+        assert!(runtime
+            .resource::<EventMapper>()
+            .list()
+            .contains("game: +w => player.walk(pressed)"));
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn test_focus_stack() -> Result<()> {
+        let mut runtime = prepare()?;
+        let mut state = ModifiersState::empty();
+        let ms = &mut state;
+        runtime
+            .resource_mut::<ScriptHerder>()
+            .run_string(r#"bindings.bind_in("map", "+w", "player.run(pressed)");"#)?;
+        runtime.insert_resource(mkinp(vec![]));
+        runtime.run_sim_once();
+
+        // The map is not open, so its bindings do not see input.
+        runtime.insert_resource(mkinp(vec![press(VKC::W, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().walking, true);
+        assert_eq!(runtime.resource::<Player>().running, false);
+        runtime.insert_resource(mkinp(vec![release(VKC::W, ms)]));
+        runtime.run_sim_once();
+
+        // A modal map takes W, and hides everything beneath it.
+        runtime.resource_mut::<InputTarget>().push("map", false)?;
+        runtime.insert_resource(mkinp(vec![press(VKC::W, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().walking, false);
+        assert_eq!(runtime.resource::<Player>().running, true);
+        runtime.insert_resource(mkinp(vec![release(VKC::W, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().running, false);
+        runtime.insert_resource(mkinp(vec![press(VKC::LShift, ms), press(VKC::W, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().running, false);
+        runtime.insert_resource(mkinp(vec![release(VKC::W, ms), release(VKC::LShift, ms)]));
+        runtime.run_sim_once();
+
+        // Passing input through lets the game see what the map does not use.
+        runtime.resource_mut::<InputTarget>().push("map", true)?;
+        runtime.insert_resource(mkinp(vec![press(VKC::LShift, ms), press(VKC::W, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().running, true);
+        runtime.insert_resource(mkinp(vec![release(VKC::W, ms), release(VKC::LShift, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().running, false);
+
+        // Keys held when the map closes are still released by the map.
+        runtime.insert_resource(mkinp(vec![press(VKC::W, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().running, true);
+        assert_eq!(runtime.resource_mut::<InputTarget>().pop()?, "map");
+        runtime.insert_resource(mkinp(vec![release(VKC::W, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().running, false);
+        assert_eq!(runtime.resource::<Player>().walking, false);
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn test_capture() -> Result<()> {
+        let mut runtime = prepare()?;
+        let mut state = ModifiersState::empty();
+        let ms = &mut state;
+
+        // The captured chord is held back from the bindings and handed to the script.
+        runtime
+            .resource_mut::<EventMapper>()
+            .capture(r#"bindings.bind(captured, "player.walk(pressed)")"#)?;
+        runtime.insert_resource(mkinp(vec![press(VKC::LShift, ms), press(VKC::E, ms)]));
+        runtime.run_sim_once();
+        assert!(!runtime.resource::<EventMapper>().is_capturing());
+        assert_eq!(runtime.resource::<EventMapper>().captured(), "Shift+E");
+        assert_eq!(runtime.resource::<Player>().walking, false);
+        runtime.insert_resource(mkinp(vec![release(VKC::E, ms), release(VKC::LShift, ms)]));
+        runtime.run_sim_once();
+
+        runtime.insert_resource(mkinp(vec![press(VKC::LShift, ms), press(VKC::E, ms)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Player>().walking, true);
+        runtime.insert_resource(mkinp(vec![release(VKC::E, ms), release(VKC::LShift, ms)]));
+        runtime.run_sim_once();
+
+        // Capturing something already bound reports what it is bound to.
+        runtime.resource_mut::<EventMapper>().capture("")?;
+        runtime.insert_resource(mkinp(vec![press(VKC::W, ms)]));
+        runtime.run_sim_once();
+        let captured = runtime.resource_mut::<EventMapper>().take_captured();
+        assert_eq!(captured.as_deref(), Some("W"));
+        assert_eq!(
+            runtime.resource::<EventMapper>().conflicts("W")?,
+            "game: +w => player.walk(pressed)"
+        );
+        assert_eq!(runtime.resource::<EventMapper>().conflicts("Alt+W")?, "");
+
+        // Axes need to move well away from where they started.
+        runtime.resource_mut::<EventMapper>().capture("")?;
+        runtime.insert_resource(mkinp(vec![axis(2, -0.9), axis(2, -0.6), axis(3, 0.2)]));
+        runtime.run_sim_once();
+        assert!(runtime.resource::<EventMapper>().is_capturing());
+        runtime.insert_resource(mkinp(vec![axis(3, 0.8)]));
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<EventMapper>().captured(), "axis3");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timed_bindings() -> Result<()> {
+        let mut runtime = prepare()?;
+        runtime.resource_mut::<ScriptHerder>().run_string(
+            r#"
+                bindings.bind("joy3", "player.add(1)");
+                bindings.bind("joy3:long", "player.add(10)");
+                bindings.bind("joy4", "player.add(100)");
+                bindings.bind("joy4:double", "player.add(1000)");
+                bindings.bind("joy5:repeat=0.5,0.1", "player.add(10000)");
+            "#,
+        )?;
+        step(&mut runtime, vec![]);
+        let count = |runtime: &Runtime| runtime.resource::<Player>().count;
+
+        // A short press runs the plain binding on release; holding runs the long press instead.
+        step(&mut runtime, vec![button(3, true)]);
+        assert_eq!(count(&runtime), 0);
+        step(&mut runtime, vec![button(3, false)]);
+        assert_eq!(count(&runtime), 1);
+        step(&mut runtime, vec![button(3, true)]);
+        for _ in 0..4 {
+            step(&mut runtime, vec![]);
+        }
+        assert_eq!(count(&runtime), 1);
+        step(&mut runtime, vec![]);
+        assert_eq!(count(&runtime), 11);
+        step(&mut runtime, vec![button(3, false)]);
+        step(&mut runtime, vec![]);
+        assert_eq!(count(&runtime), 11);
+
+        // A second press inside the window is a double tap, and swallows the single tap.
+        step(&mut runtime, vec![button(4, true)]);
+        step(&mut runtime, vec![button(4, false)]);
+        step(&mut runtime, vec![button(4, true)]);
+        assert_eq!(count(&runtime), 1011);
+        step(&mut runtime, vec![button(4, false)]);
+        for _ in 0..5 {
+            step(&mut runtime, vec![]);
+        }
+        assert_eq!(count(&runtime), 1011);
+
+        // Without a second press, the single tap runs once the window has passed.
+        step(&mut runtime, vec![button(4, true)]);
+        step(&mut runtime, vec![button(4, false)]);
+        step(&mut runtime, vec![]);
+        step(&mut runtime, vec![]);
+        assert_eq!(count(&runtime), 1011);
+        step(&mut runtime, vec![]);
+        assert_eq!(count(&runtime), 1111);
+
+        // Repeats run on press, then again after the delay, then at every interval.
+        step(&mut runtime, vec![button(5, true)]);
+        assert_eq!(count(&runtime), 11111);
+        for _ in 0..5 {
+            step(&mut runtime, vec![]);
+        }
+        assert_eq!(count(&runtime), 21111);
+        step(&mut runtime, vec![]);
+        assert_eq!(count(&runtime), 31111);
+        step(&mut runtime, vec![button(5, false)]);
+        step(&mut runtime, vec![]);
+        assert_eq!(count(&runtime), 31111);
+
+        Ok(())
+    }
+
     #[test]
     #[ignore]
     #[allow(clippy::bool_assert_comparison)]
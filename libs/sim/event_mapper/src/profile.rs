@@ -0,0 +1,329 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{axis::AxisResponse, bindings::Bindings, input::InputSet};
+use anyhow::{anyhow, bail, Result};
+use input::InputFocus;
+use json::{object, JsonValue};
+use nitrous::NitrousScript;
+use std::{collections::HashMap, str::FromStr};
+
+// Binding profiles hold the user's changes to the default bindings that extensions and the
+// prelude set up at startup. Profiles are layered on top of the defaults in order: first
+// profiles that are not tied to a device, then the profiles for each connected device. A
+// binding in a profile replaces whatever was bound to the same keys beneath it.
+//
+// Profiles are saved as json so that they can be edited by hand:
+//
+// {
+//   "profiles": [
+//     {
+//       "name": "warthog",
+//       "device": "030000004f0400000204000011010000",
+//       "bindings": [
+//         { "focus": "game", "bind": "+joy0", "script": "@player.stick_pitch.adjust_trim(0.01)" },
+//         { "focus": "game", "unbind": "F" },
+//         { "focus": "game", "axis": "axis1", "response": "deadzone=0.05 expo=0.3" }
+//       ]
+//     }
+//   ]
+// }
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BindingOp {
+    Bind {
+        focus: InputFocus,
+        input: String,
+        script: String,
+    },
+    Unbind {
+        focus: InputFocus,
+        input: String,
+    },
+    Axis {
+        focus: InputFocus,
+        axis: String,
+        response: AxisResponse,
+    },
+}
+
+impl BindingOp {
+    fn focus(&self) -> &InputFocus {
+        match self {
+            Self::Bind { focus, .. } => focus,
+            Self::Unbind { focus, .. } => focus,
+            Self::Axis { focus, .. } => focus,
+        }
+    }
+
+    fn input(&self) -> &str {
+        match self {
+            Self::Bind { input, .. } => input,
+            Self::Unbind { input, .. } => input,
+            Self::Axis { axis, .. } => axis,
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        match self {
+            Self::Bind {
+                focus,
+                input,
+                script,
+            } => object! { focus: focus.name(), bind: input.as_str(), script: script.as_str() },
+            Self::Unbind { focus, input } => {
+                object! { focus: focus.name(), unbind: input.as_str() }
+            }
+            Self::Axis {
+                focus,
+                axis,
+                response,
+            } => object! {
+                focus: focus.name(),
+                axis: axis.as_str(),
+                response: response.to_string(),
+            },
+        }
+    }
+
+    fn from_json(value: &JsonValue) -> Result<Self> {
+        let focus = match value["focus"].as_str() {
+            Some(name) => InputFocus::from_str(name)?,
+            None => InputFocus::default(),
+        };
+        let field = |name: &str| {
+            value[name]
+                .as_str()
+                .map(|s| s.to_owned())
+                .ok_or_else(|| anyhow!("binding is missing '{}': {}", name, value.dump()))
+        };
+        let op = if value.has_key("bind") {
+            Self::Bind {
+                focus,
+                input: field("bind")?,
+                script: field("script")?,
+            }
+        } else if value.has_key("unbind") {
+            Self::Unbind {
+                focus,
+                input: field("unbind")?,
+            }
+        } else if value.has_key("axis") {
+            Self::Axis {
+                focus,
+                axis: field("axis")?,
+                response: field("response")?.parse()?,
+            }
+        } else {
+            bail!(
+                "expected one of 'bind', 'unbind', or 'axis' in binding: {}",
+                value.dump()
+            );
+        };
+        op.validate()?;
+        Ok(op)
+    }
+
+    pub(crate) fn apply(&self, bindings: &mut HashMap<InputFocus, Bindings>) -> Result<()> {
+        let layer = bindings
+            .entry(self.focus().to_owned())
+            .or_insert_with(|| Bindings::new(self.focus().name()));
+        match self {
+            Self::Bind { input, script, .. } => layer.bind(input, script)?,
+            Self::Unbind { input, .. } => {
+                layer.unbind(input)?;
+            }
+            Self::Axis { axis, response, .. } => {
+                layer.set_axis_response(axis, response.to_owned())?
+            }
+        }
+        Ok(())
+    }
+
+    // Catch mistakes when the op is recorded, rather than when the profile is applied.
+    pub(crate) fn validate(&self) -> Result<()> {
+        InputSet::from_binding(self.input())?;
+        if let Self::Bind { script, .. } = self {
+            NitrousScript::compile(script)?;
+        }
+        Ok(())
+    }
+}
+
+fn same_input(a: &str, b: &str) -> bool {
+    match (InputSet::from_binding(a), InputSet::from_binding(b)) {
+        (Ok(a), Ok(b)) => a.iter().any(|x| b.iter().any(|y| x.same_keys(y))),
+        _ => a == b,
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BindingProfile {
+    name: String,
+    device: Option<String>,
+    ops: Vec<BindingOp>,
+}
+
+impl BindingProfile {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            device: None,
+            ops: Vec::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The guid of the device this profile applies to, if any.
+    pub fn device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    pub fn set_device(&mut self, device: Option<&str>) {
+        self.device = device.map(|s| s.to_owned());
+    }
+
+    pub fn ops(&self) -> &[BindingOp] {
+        &self.ops
+    }
+
+    pub fn push(&mut self, op: BindingOp) -> Result<()> {
+        op.validate()?;
+        let (focus, input) = (op.focus().to_owned(), op.input().to_owned());
+        match &op {
+            // An unbind wipes out anything this profile bound to the same keys.
+            BindingOp::Unbind { .. } => self.ops.retain(|prior| {
+                !(prior.focus() == &focus
+                    && !matches!(prior, BindingOp::Axis { .. })
+                    && same_input(prior.input(), &input))
+            }),
+            // A bind supersedes an earlier unbind of the same keys.
+            BindingOp::Bind { .. } => self.ops.retain(|prior| {
+                !(prior.focus() == &focus
+                    && matches!(prior, BindingOp::Unbind { .. })
+                    && same_input(prior.input(), &input))
+            }),
+            // Only the last response for an axis matters.
+            BindingOp::Axis { .. } => self.ops.retain(|prior| {
+                !(prior.focus() == &focus
+                    && matches!(prior, BindingOp::Axis { .. })
+                    && same_input(prior.input(), &input))
+            }),
+        }
+        self.ops.push(op);
+        Ok(())
+    }
+
+    pub(crate) fn apply(&self, bindings: &mut HashMap<InputFocus, Bindings>) -> Result<()> {
+        // Anything bound in this profile replaces what was bound to those keys beneath it.
+        for op in &self.ops {
+            if let BindingOp::Bind { focus, input, .. } = op {
+                if let Some(layer) = bindings.get_mut(focus) {
+                    layer.unbind(input)?;
+                }
+            }
+        }
+        for op in &self.ops {
+            op.apply(bindings)?;
+        }
+        Ok(())
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut profile = object! {
+            name: self.name.as_str(),
+            bindings: JsonValue::Array(self.ops.iter().map(|op| op.to_json()).collect()),
+        };
+        if let Some(device) = &self.device {
+            profile["device"] = device.as_str().into();
+        }
+        profile
+    }
+
+    fn from_json(value: &JsonValue) -> Result<Self> {
+        let name = value["name"]
+            .as_str()
+            .ok_or_else(|| anyhow!("binding profile is missing a name"))?;
+        let mut profile = Self::new(name);
+        profile.device = value["device"].as_str().map(|s| s.to_owned());
+        for op in value["bindings"].members() {
+            profile.ops.push(BindingOp::from_json(op)?);
+        }
+        Ok(profile)
+    }
+}
+
+pub(crate) fn profiles_to_json(profiles: &[BindingProfile]) -> String {
+    object! {
+        profiles: JsonValue::Array(profiles.iter().map(|p| p.to_json()).collect()),
+    }
+    .pretty(2)
+}
+
+pub(crate) fn profiles_from_json(data: &str) -> Result<Vec<BindingProfile>> {
+    let root = json::parse(data)?;
+    root["profiles"]
+        .members()
+        .map(BindingProfile::from_json)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() -> Result<()> {
+        let mut profile = BindingProfile::new("warthog");
+        profile.set_device(Some("0300abcd"));
+        profile.push(BindingOp::Bind {
+            focus: InputFocus::default(),
+            input: "+joy0".to_owned(),
+            script: r#"bindings.bind("a", "exit()")"#.to_owned(),
+        })?;
+        profile.push(BindingOp::Unbind {
+            focus: InputFocus::default(),
+            input: "F".to_owned(),
+        })?;
+        profile.push(BindingOp::Axis {
+            focus: InputFocus::default(),
+            axis: "axis1".to_owned(),
+            response: "deadzone=0.05 expo=0.3".parse()?,
+        })?;
+        let profiles = vec![profile, BindingProfile::new("pilot")];
+        let text = profiles_to_json(&profiles);
+        assert_eq!(profiles_from_json(&text)?, profiles);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbind_replaces_bind() -> Result<()> {
+        let mut profile = BindingProfile::new("pilot");
+        profile.push(BindingOp::Bind {
+            focus: InputFocus::default(),
+            input: "+w".to_owned(),
+            script: "exit()".to_owned(),
+        })?;
+        profile.push(BindingOp::Unbind {
+            focus: InputFocus::default(),
+            input: "w".to_owned(),
+        })?;
+        assert_eq!(profile.ops().len(), 1);
+        assert!(matches!(profile.ops()[0], BindingOp::Unbind { .. }));
+        Ok(())
+    }
+}
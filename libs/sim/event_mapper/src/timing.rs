@@ -0,0 +1,166 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{bail, ensure, Result};
+use std::{fmt, str::FromStr, time::Duration};
+
+const DEFAULT_LONG_PRESS: Duration = Duration::from_millis(500);
+const DEFAULT_DOUBLE_TAP: Duration = Duration::from_millis(300);
+const DEFAULT_REPEAT_DELAY: Duration = Duration::from_millis(500);
+const DEFAULT_REPEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// When a chord's scripts run, relative to when it was pressed, measured in sim time.
+///
+/// Timing is given after a colon at the end of a binding, with optional times in seconds:
+///   joy3:long        -- once, after holding for 0.5s; or joy3:long=1.2
+///   joy3:double      -- on the second of two presses within 0.3s; or joy3:double=0.25
+///   joy3:repeat      -- on press, then every 0.1s after holding for 0.5s; or joy3:repeat=0.4,0.05
+///
+/// If the keys of a plain binding also have a long or double binding, the plain binding
+/// becomes a tap: it runs when the keys are released, if neither of the others fired.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub enum ChordTiming {
+    #[default]
+    Immediate,
+    LongPress(Duration),
+    DoubleTap(Duration),
+    Repeat {
+        delay: Duration,
+        interval: Duration,
+    },
+}
+
+impl ChordTiming {
+    pub fn is_immediate(&self) -> bool {
+        matches!(self, Self::Immediate)
+    }
+
+    // Long and double bindings need to wait to see what a press turns into.
+    pub(crate) fn defers_tap(&self) -> bool {
+        matches!(self, Self::LongPress(_) | Self::DoubleTap(_))
+    }
+}
+
+fn seconds(option: &str, value: &str) -> Result<Duration> {
+    match value.trim().parse::<f64>() {
+        Ok(v) if v.is_finite() && v > 0. => Ok(Duration::from_secs_f64(v)),
+        _ => bail!(
+            "binding timing {} needs a positive number of seconds, not '{}'",
+            option,
+            value
+        ),
+    }
+}
+
+impl FromStr for ChordTiming {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.trim().splitn(2, '=');
+        let option = parts.next().unwrap_or_default();
+        let value = parts.next();
+        Ok(match option {
+            "long" => Self::LongPress(match value {
+                Some(v) => seconds(option, v)?,
+                None => DEFAULT_LONG_PRESS,
+            }),
+            "double" => Self::DoubleTap(match value {
+                Some(v) => seconds(option, v)?,
+                None => DEFAULT_DOUBLE_TAP,
+            }),
+            "repeat" => {
+                let (delay, interval) = match value {
+                    Some(v) => {
+                        let mut times = v.splitn(2, ',');
+                        let delay = seconds(option, times.next().unwrap_or_default())?;
+                        let interval = match times.next() {
+                            Some(t) => seconds(option, t)?,
+                            None => DEFAULT_REPEAT_INTERVAL,
+                        };
+                        (delay, interval)
+                    }
+                    None => (DEFAULT_REPEAT_DELAY, DEFAULT_REPEAT_INTERVAL),
+                };
+                ensure!(!interval.is_zero(), "binding repeat interval must not be 0");
+                Self::Repeat { delay, interval }
+            }
+            _ => bail!(
+                "unknown binding timing '{}'; expected long, double, or repeat",
+                s
+            ),
+        })
+    }
+}
+
+impl fmt::Display for ChordTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Immediate => Ok(()),
+            Self::LongPress(t) => write!(f, ":long={}", t.as_secs_f64()),
+            Self::DoubleTap(t) => write!(f, ":double={}", t.as_secs_f64()),
+            Self::Repeat { delay, interval } => write!(
+                f,
+                ":repeat={},{}",
+                delay.as_secs_f64(),
+                interval.as_secs_f64()
+            ),
+        }
+    }
+}
+
+/// What we know about the current and prior press of one set of keys, shared by all of the
+/// differently timed bindings on those keys.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Hold {
+    pub(crate) pressed_at: Duration,
+    pub(crate) previous_press: Option<Duration>,
+    pub(crate) held: bool,
+
+    // A long or double binding fired during this press, so the tap should not.
+    pub(crate) consumed: bool,
+
+    // The keys were tapped and released, but we are waiting to see if a second tap follows.
+    pub(crate) tap_pending: bool,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_timing() -> Result<()> {
+        assert_eq!(
+            "long".parse::<ChordTiming>()?,
+            ChordTiming::LongPress(DEFAULT_LONG_PRESS)
+        );
+        assert_eq!(
+            "double=0.25".parse::<ChordTiming>()?,
+            ChordTiming::DoubleTap(Duration::from_millis(250))
+        );
+        assert_eq!(
+            "repeat=0.4,0.05".parse::<ChordTiming>()?,
+            ChordTiming::Repeat {
+                delay: Duration::from_millis(400),
+                interval: Duration::from_millis(50)
+            }
+        );
+        assert_eq!(
+            "repeat=0.25".parse::<ChordTiming>()?.to_string(),
+            ":repeat=0.25,0.1"
+        );
+        assert!("long=-1".parse::<ChordTiming>().is_err());
+        assert!("triple".parse::<ChordTiming>().is_err());
+        Ok(())
+    }
+}
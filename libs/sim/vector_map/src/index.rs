@@ -0,0 +1,112 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use fxhash::FxHashMap;
+use geodesy::GeoBox;
+use physical_constants::EARTH_RADIUS;
+
+// Features are bucketed into cells of one degree of latitude and longitude. Long features
+// are recorded in every cell that one of their segments passes over, so a coastline does
+// not drag every query along its length into the candidate set.
+const CELL_DEGREES: f64 = 1.;
+const LON_CELLS: i32 = (360. / CELL_DEGREES) as i32;
+const LAT_CELLS: i32 = (180. / CELL_DEGREES) as i32;
+
+fn lat_cell(lat: f64) -> i32 {
+    ((lat + 90.) / CELL_DEGREES)
+        .floor()
+        .clamp(0., (LAT_CELLS - 1) as f64) as i32
+}
+
+fn lon_cell(lon: f64) -> i32 {
+    ((lon + 180.) / CELL_DEGREES).floor() as i32
+}
+
+/// Spatial index from grid cells to the features that touch them.
+#[derive(Debug, Default)]
+pub(crate) struct GridIndex {
+    cells: FxHashMap<(i32, i32), Vec<u32>>,
+}
+
+impl GridIndex {
+    pub(crate) fn insert(&mut self, id: u32, points: &[[f64; 2]], closed: bool) {
+        if points.len() == 1 {
+            self.mark(id, points[0], points[0]);
+            return;
+        }
+        for pair in points.windows(2) {
+            self.insert_segment(id, pair[0], pair[1]);
+        }
+        if closed && points.len() > 2 {
+            self.insert_segment(id, points[points.len() - 1], points[0]);
+        }
+    }
+
+    fn insert_segment(&mut self, id: u32, a: [f64; 2], b: [f64; 2]) {
+        // Segments that jump more than half way around the world take the short way, across
+        // the antimeridian.
+        if (a[1] - b[1]).abs() > 180. {
+            // Mark both halves over the full latitude range of the segment.
+            let (west, east) = if a[1] < b[1] { (a, b) } else { (b, a) };
+            self.mark(id, [west[0], east[1]], [east[0], 180.]);
+            self.mark(id, [west[0], -180.], [east[0], west[1]]);
+        } else {
+            self.mark(id, a, b);
+        }
+    }
+
+    fn mark(&mut self, id: u32, a: [f64; 2], b: [f64; 2]) {
+        let (lat0, lat1) = (lat_cell(a[0].min(b[0])), lat_cell(a[0].max(b[0])));
+        let (lon0, lon1) = (lon_cell(a[1].min(b[1])), lon_cell(a[1].max(b[1])));
+        for lat in lat0..=lat1 {
+            for lon in lon0..=lon1 {
+                let cell = self
+                    .cells
+                    .entry((lat, lon.rem_euclid(LON_CELLS)))
+                    .or_default();
+                // Features are inserted one at a time, so repeats are always at the end.
+                if cell.last() != Some(&id) {
+                    cell.push(id);
+                }
+            }
+        }
+    }
+
+    /// Every feature that touches a cell overlapping `region`, sorted and without repeats.
+    pub(crate) fn candidates(&self, region: &GeoBox) -> Vec<u32> {
+        let mut out = Vec::new();
+        let (lat0, lat1) = (lat_cell(region.south), lat_cell(region.north));
+        let lon0 = lon_cell(region.west);
+        let lon1 = lon_cell(region.east).min(lon0 + LON_CELLS - 1);
+        for lat in lat0..=lat1 {
+            for lon in lon0..=lon1 {
+                if let Some(ids) = self.cells.get(&(lat, lon.rem_euclid(LON_CELLS))) {
+                    out.extend_from_slice(ids);
+                }
+            }
+        }
+        out.sort_unstable();
+        out.dedup();
+        out
+    }
+}
+
+/// Great circle distance in meters between two points in degrees.
+pub fn haversine_distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    let (lat0, lat1) = (a[0].to_radians(), b[0].to_radians());
+    let dlat = lat1 - lat0;
+    let dlon = (b[1] - a[1]).to_radians();
+    let h = (dlat / 2.).sin().powi(2) + lat0.cos() * lat1.cos() * (dlon / 2.).sin().powi(2);
+    2. * EARTH_RADIUS.f64() * h.sqrt().min(1.).asin()
+}
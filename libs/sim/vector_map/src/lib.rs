@@ -0,0 +1,523 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+mod index;
+mod pack;
+
+pub use crate::{
+    index::haversine_distance,
+    pack::{read_pack, FeatureRecord, PackFeature, PackedPoint, VectorMapBuilder, VectorMapHeader},
+};
+
+use crate::index::GridIndex;
+use absolute_unit::{degrees, meters, nautical_miles, Degrees, Length, Meters, NauticalMiles};
+use anyhow::{bail, Result};
+use catalog::Catalog;
+use geodesy::{Cartesian, GeoBox, GeoCenter, GeoSurface, Graticule, GraticuleOrigin};
+use geometry::planar::{local_offset, polygon_distance, segment_distance};
+use log::{info, warn};
+use nitrous::{inject_nitrous_resource, method, NitrousResource};
+use physical_constants::EARTH_RADIUS;
+use runtime::{Extension, Runtime};
+use std::{cmp::Ordering, fmt::Write, ops::Range};
+
+/// What a map feature represents.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum FeatureKind {
+    Coastline = 1,
+    Border = 2,
+    River = 3,
+    Road = 4,
+    Runway = 5,
+    Airport = 6,
+}
+
+impl FeatureKind {
+    pub const ALL: [FeatureKind; 6] = [
+        Self::Coastline,
+        Self::Border,
+        Self::River,
+        Self::Road,
+        Self::Runway,
+        Self::Airport,
+    ];
+
+    pub fn from_u8(kind: u8) -> Result<Self> {
+        Ok(match kind {
+            1 => Self::Coastline,
+            2 => Self::Border,
+            3 => Self::River,
+            4 => Self::Road,
+            5 => Self::Runway,
+            6 => Self::Airport,
+            _ => bail!("unknown map feature kind {}", kind),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Coastline => "coastline",
+            Self::Border => "border",
+            Self::River => "river",
+            Self::Road => "road",
+            Self::Runway => "runway",
+            Self::Airport => "airport",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        for kind in Self::ALL {
+            if kind.name() == name {
+                return Ok(kind);
+            }
+        }
+        bail!(
+            "unknown map feature kind {}; expected one of: coastline, border, river, road, \
+            runway or airport",
+            name
+        )
+    }
+}
+
+/// How a map feature's points are joined.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FeatureGeometry {
+    Point = 1,
+    Polyline = 2,
+    /// A closed ring; the last point joins back to the first.
+    Polygon = 3,
+}
+
+impl FeatureGeometry {
+    pub fn from_u8(geometry: u8) -> Result<Self> {
+        Ok(match geometry {
+            1 => Self::Point,
+            2 => Self::Polyline,
+            3 => Self::Polygon,
+            _ => bail!("unknown map feature geometry {}", geometry),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Point => "point",
+            Self::Polyline => "polyline",
+            Self::Polygon => "polygon",
+        }
+    }
+
+    /// The fewest points that a feature with this geometry can have.
+    pub fn min_points(&self) -> usize {
+        match self {
+            Self::Point => 1,
+            Self::Polyline => 2,
+            Self::Polygon => 3,
+        }
+    }
+}
+
+/// Identifies a feature within the VectorMap.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FeatureId(u32);
+
+#[derive(Debug)]
+struct Feature {
+    kind: FeatureKind,
+    geometry: FeatureGeometry,
+    rank: u16,
+    name: String,
+    points: Range<usize>,
+}
+
+/// A borrowed view of one feature, for drawing or inspection.
+#[derive(Copy, Clone, Debug)]
+pub struct FeatureRef<'a> {
+    id: FeatureId,
+    feature: &'a Feature,
+    points: &'a [[f64; 2]],
+}
+
+impl<'a> FeatureRef<'a> {
+    pub fn id(&self) -> FeatureId {
+        self.id
+    }
+
+    pub fn kind(&self) -> FeatureKind {
+        self.feature.kind
+    }
+
+    pub fn geometry(&self) -> FeatureGeometry {
+        self.feature.geometry
+    }
+
+    pub fn rank(&self) -> u16 {
+        self.feature.rank
+    }
+
+    pub fn name(&self) -> &'a str {
+        &self.feature.name
+    }
+
+    /// Points as [lat, lon] in degrees.
+    pub fn points_degrees(&self) -> &'a [[f64; 2]] {
+        self.points
+    }
+
+    pub fn graticules(&self) -> impl Iterator<Item = Graticule<GeoSurface>> + 'a {
+        self.points
+            .iter()
+            .map(|p| Graticule::new(degrees!(p[0]), degrees!(p[1]), meters!(0)))
+    }
+
+    /// Points on the sea level surface in world coordinates, ready to turn into line
+    /// vertices. Polygons repeat their first point at the end, so that they can be drawn
+    /// as a line strip.
+    pub fn cartesian_points(&self) -> Vec<Cartesian<GeoCenter, Meters>> {
+        let mut out = self
+            .graticules()
+            .map(|g| g.cartesian::<Meters>())
+            .collect::<Vec<_>>();
+        if self.geometry() == FeatureGeometry::Polygon {
+            out.push(out[0]);
+        }
+        out
+    }
+
+    /// Distance in meters from a point in degrees to the nearest part of this feature; zero
+    /// inside of a polygon. Lines are measured on a plane tangent at `from`, so distances
+    /// to lines are only accurate out to a few hundred miles.
+    pub fn distance_from(&self, from: [f64; 2]) -> f64 {
+        if self.geometry() == FeatureGeometry::Point {
+            return haversine_distance(from, self.points[0]);
+        }
+        let origin = radians_of(from);
+        let local = self
+            .points
+            .iter()
+            .map(|p| local_offset(origin, radians_of(*p), EARTH_RADIUS.f64()))
+            .collect::<Vec<_>>();
+        if self.geometry() == FeatureGeometry::Polygon {
+            return polygon_distance(&local, [0., 0.]).max(0.);
+        }
+        local
+            .windows(2)
+            .map(|pair| segment_distance(pair[0], pair[1], [0., 0.]))
+            .fold(f64::MAX, f64::min)
+    }
+}
+
+/// Vector map data: coastlines, borders, rivers, roads, runways and airports.
+///
+/// Features are loaded from every `*.vmp` pack in the catalog when the extension starts,
+/// and indexed on a one degree grid. Queries from Rust take a `Graticule` and return
+/// `FeatureRef`s, which the map and marker views can draw directly. Scripts can query by
+/// latitude and longitude in degrees and distances in nautical miles.
+#[derive(Debug, Default, NitrousResource)]
+pub struct VectorMap {
+    features: Vec<Feature>,
+    points: Vec<[f64; 2]>,
+    index: GridIndex,
+}
+
+impl Extension for VectorMap {
+    fn init(runtime: &mut Runtime) -> Result<()> {
+        let map = VectorMap::from_catalog(runtime.resource::<Catalog>())?;
+        runtime.insert_named_resource("vector_map", map);
+        Ok(())
+    }
+}
+
+fn degrees_of<Origin: GraticuleOrigin>(grat: &Graticule<Origin>) -> [f64; 2] {
+    [grat.lat::<Degrees>().f64(), grat.lon::<Degrees>().f64()]
+}
+
+fn radians_of(p: [f64; 2]) -> [f64; 2] {
+    [p[0].to_radians(), p[1].to_radians()]
+}
+
+fn nautical_miles_to_meters(nm: f64) -> f64 {
+    meters!(nautical_miles!(nm)).f64()
+}
+
+#[inject_nitrous_resource]
+impl VectorMap {
+    pub fn from_catalog(catalog: &Catalog) -> Result<Self> {
+        let mut map = Self::default();
+        for fid in catalog.find_glob_with_extension("*.vmp", Some("vmp"))? {
+            let name = catalog.stat(fid)?.name().to_owned();
+            if let Err(e) = map.add_pack(&catalog.read(fid)?) {
+                warn!("skipping vector map {}: {}", name, e);
+            }
+        }
+        info!(
+            "loaded {} vector map features with {} points",
+            map.features.len(),
+            map.points.len()
+        );
+        Ok(map)
+    }
+
+    /// Add all features in a packed vector map.
+    pub fn add_pack(&mut self, data: &[u8]) -> Result<()> {
+        read_pack(data, |feature| {
+            self.add_feature(feature);
+            Ok(())
+        })
+    }
+
+    pub fn add_feature(&mut self, feature: PackFeature) -> FeatureId {
+        let id = self.features.len() as u32;
+        let start = self.points.len();
+        self.points.extend_from_slice(&feature.points);
+        self.index.insert(
+            id,
+            &feature.points,
+            feature.geometry == FeatureGeometry::Polygon,
+        );
+        self.features.push(Feature {
+            kind: feature.kind,
+            geometry: feature.geometry,
+            rank: feature.rank,
+            name: feature.name,
+            points: start..self.points.len(),
+        });
+        FeatureId(id)
+    }
+
+    pub fn feature_count(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn get(&self, id: FeatureId) -> FeatureRef<'_> {
+        let feature = &self.features[id.0 as usize];
+        FeatureRef {
+            id,
+            feature,
+            points: &self.points[feature.points.clone()],
+        }
+    }
+
+    /// Features of the given kinds (or all kinds, if empty) that may be in `region`, for
+    /// drawing a map. This can include features a little way outside of the region.
+    pub fn in_region(&self, region: &GeoBox, kinds: &[FeatureKind]) -> Vec<FeatureRef<'_>> {
+        self.index
+            .candidates(region)
+            .into_iter()
+            .map(|id| self.get(FeatureId(id)))
+            .filter(|feature| kinds.is_empty() || kinds.contains(&feature.kind()))
+            .collect()
+    }
+
+    /// Features of the given kinds (or all kinds, if empty) within `radius` of `center`,
+    /// nearest first.
+    pub fn within<Origin: GraticuleOrigin>(
+        &self,
+        center: &Graticule<Origin>,
+        radius: Length<Meters>,
+        kinds: &[FeatureKind],
+    ) -> Vec<(FeatureRef<'_>, Length<Meters>)> {
+        let from = degrees_of(center);
+        let region = GeoBox::around(center, radius);
+        let mut out = self
+            .in_region(&region, kinds)
+            .into_iter()
+            .map(|feature| (feature, feature.distance_from(from)))
+            .filter(|(_, distance)| *distance <= radius.f64())
+            .collect::<Vec<_>>();
+        out.sort_by(|a, b| {
+            a.1.partial_cmp(&b.1)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.0.rank().cmp(&b.0.rank()))
+        });
+        out.into_iter()
+            .map(|(feature, distance)| (feature, meters!(distance)))
+            .collect()
+    }
+
+    /// The nearest feature of the given kind to `center`, if there is one within `max_radius`.
+    pub fn nearest<Origin: GraticuleOrigin>(
+        &self,
+        center: &Graticule<Origin>,
+        kind: FeatureKind,
+        max_radius: Length<Meters>,
+    ) -> Option<(FeatureRef<'_>, Length<Meters>)> {
+        // Widen the search until we find something, so that nearby queries stay cheap.
+        let mut radius = max_radius.f64().min(nautical_miles_to_meters(50.));
+        loop {
+            if let Some(found) = self
+                .within(center, meters!(radius), &[kind])
+                .into_iter()
+                .next()
+            {
+                return Some(found);
+            }
+            if radius >= max_radius.f64() {
+                return None;
+            }
+            radius = (radius * 4.).min(max_radius.f64());
+        }
+    }
+
+    fn format_within(&self, kinds: &[FeatureKind], lat: f64, lon: f64, nm: f64) -> String {
+        let center = Graticule::<GeoSurface>::new(degrees!(lat), degrees!(lon), meters!(0));
+        let mut out = String::new();
+        for (feature, distance) in
+            self.within(&center, meters!(nautical_miles_to_meters(nm)), kinds)
+        {
+            writeln!(
+                out,
+                "{} {} {:0.1}nm",
+                feature.kind().name(),
+                feature.name(),
+                Length::<NauticalMiles>::from(&distance).f64()
+            )
+            .ok();
+        }
+        out
+    }
+
+    /// Airports within `nm` nautical miles of a point in degrees, nearest first.
+    #[method]
+    pub fn airports_within(&self, lat: f64, lon: f64, nm: f64) -> String {
+        self.format_within(&[FeatureKind::Airport], lat, lon, nm)
+    }
+
+    /// Features of `kind` within `nm` nautical miles of a point in degrees, nearest first.
+    #[method]
+    pub fn features_within(&self, kind: &str, lat: f64, lon: f64, nm: f64) -> Result<String> {
+        Ok(self.format_within(&[FeatureKind::from_name(kind)?], lat, lon, nm))
+    }
+
+    /// The name of the nearest airport within `nm` nautical miles, or an empty string.
+    #[method]
+    pub fn nearest_airport(&self, lat: f64, lon: f64, nm: f64) -> String {
+        let center = Graticule::<GeoSurface>::new(degrees!(lat), degrees!(lon), meters!(0));
+        self.nearest(
+            &center,
+            FeatureKind::Airport,
+            meters!(nautical_miles_to_meters(nm)),
+        )
+        .map(|(feature, _)| feature.name().to_owned())
+        .unwrap_or_default()
+    }
+
+    #[method]
+    pub fn count(&self, kind: &str) -> Result<i64> {
+        let kind = FeatureKind::from_name(kind)?;
+        Ok(self.features.iter().filter(|f| f.kind == kind).count() as i64)
+    }
+
+    #[method]
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for kind in FeatureKind::ALL {
+            let (count, points) = self
+                .features
+                .iter()
+                .filter(|f| f.kind == kind)
+                .fold((0, 0), |(count, points), f| {
+                    (count + 1, points + f.points.len())
+                });
+            writeln!(
+                out,
+                "{:<10} {:>8} features {:>10} points",
+                kind.name(),
+                count,
+                points
+            )
+            .ok();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn test_map() -> Result<VectorMap> {
+        let mut builder = VectorMapBuilder::default();
+        for (name, lat, lon) in [
+            ("KSEA", 47.4502, -122.3088),
+            ("KBFI", 47.5300, -122.3020),
+            ("KPDX", 45.5887, -122.5975),
+            ("NFFN", -17.7554, 177.4434),
+        ] {
+            builder.push(
+                FeatureKind::Airport,
+                FeatureGeometry::Point,
+                0,
+                name,
+                &[[lat, lon]],
+            )?;
+        }
+        // A road across the antimeridian, near Fiji.
+        builder.push(
+            FeatureKind::Road,
+            FeatureGeometry::Polyline,
+            0,
+            "dateline",
+            &[[-17., 179.9], [-17., -179.9]],
+        )?;
+        builder.push(
+            FeatureKind::Border,
+            FeatureGeometry::Polygon,
+            0,
+            "square",
+            &[[10., 10.], [10., 12.], [12., 12.], [12., 10.]],
+        )?;
+        let mut map = VectorMap::default();
+        map.add_pack(&builder.to_bytes())?;
+        Ok(map)
+    }
+
+    #[test]
+    fn test_airports_within() -> Result<()> {
+        let map = test_map()?;
+        let seattle = Graticule::<GeoSurface>::new(degrees!(47.6), degrees!(-122.33), meters!(0));
+        let near = map.within(
+            &seattle,
+            meters!(nautical_miles_to_meters(50.)),
+            &[FeatureKind::Airport],
+        );
+        let names = near.iter().map(|(f, _)| f.name()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["KBFI", "KSEA"]);
+        assert_relative_eq!(near[0].1.f64(), 7_900., epsilon = 200.);
+        assert_eq!(map.nearest_airport(45., -122., 500.), "KPDX");
+        assert_eq!(map.nearest_airport(0., 0., 500.), "");
+        assert_eq!(map.count("airport")?, 4);
+        assert!(map.count("runways").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lines_and_polygons() -> Result<()> {
+        let map = test_map()?;
+        // Just east of the antimeridian, the road is a couple of miles to the north.
+        let fiji = Graticule::<GeoSurface>::new(degrees!(-17.03), degrees!(-179.95), meters!(0));
+        let roads = map.within(&fiji, meters!(10_000), &[FeatureKind::Road]);
+        assert_eq!(roads.len(), 1);
+        assert_relative_eq!(roads[0].1.f64(), 3_335., epsilon = 20.);
+
+        // Inside the square is no distance at all.
+        let inside = Graticule::<GeoSurface>::new(degrees!(11), degrees!(11), meters!(0));
+        let borders = map.within(&inside, meters!(1), &[]);
+        assert_eq!(borders.len(), 1);
+        assert_eq!(borders[0].0.cartesian_points().len(), 5);
+        let region = GeoBox::new(11.5, 11.5, 13., 13.);
+        assert_eq!(map.in_region(&region, &[FeatureKind::Border]).len(), 1);
+        Ok(())
+    }
+}
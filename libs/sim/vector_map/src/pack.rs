@@ -0,0 +1,419 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{FeatureGeometry, FeatureKind};
+use anyhow::{anyhow, bail, ensure, Result};
+use packed_struct::packed_struct;
+use std::{fmt, fs, mem, ops::Range, path::Path};
+use zerocopy::AsBytes;
+
+// Vector maps can hold millions of points of coastline, so we keep them in a flat binary
+// format in the catalog, rather than parsing GeoJSON at startup. A pack is a header, a
+// table of features, a table of points, and a blob of UTF-8 names. Points are stored as
+// latitude and longitude in units of 1e-7 degrees, which is about a centimeter.
+
+#[packed_struct]
+pub struct VectorMapHeader {
+    magic: [u8; 3],
+    version: u8,
+    feature_count: u32,
+    point_count: u32,
+    feature_start: u32,
+    point_start: u32,
+    name_start: u32,
+    name_size: u32,
+}
+
+#[packed_struct]
+pub struct FeatureRecord {
+    kind: u8,
+    geometry: u8,
+    // Relative importance within the kind: road class, airport size, etc. Lower is more
+    // important.
+    rank: u16,
+    // Offsets into the point table and name blob.
+    point_start: u32,
+    point_count: u32,
+    name_start: u32,
+    name_size: u32,
+}
+
+#[packed_struct]
+pub struct PackedPoint {
+    lat_e7: i32,
+    lon_e7: i32,
+}
+
+const HEADER_MAGIC: [u8; 3] = [b'V', b'M', b'P'];
+const HEADER_VERSION: u8 = 1;
+
+const DEGREES_TO_E7: f64 = 10_000_000.;
+
+/// One feature as read from a pack, with points in degrees as [lat, lon].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackFeature {
+    pub kind: FeatureKind,
+    pub geometry: FeatureGeometry,
+    pub rank: u16,
+    pub name: String,
+    pub points: Vec<[f64; 2]>,
+}
+
+fn section(data: &[u8], start: u32, count: u32, item_size: usize) -> Result<&[u8]> {
+    let start = start as usize;
+    let end = start + count as usize * item_size;
+    ensure!(end <= data.len(), "vector map section is truncated");
+    Ok(&data[start..end])
+}
+
+/// Read every feature out of a packed vector map, calling `visit` with each.
+pub fn read_pack(data: &[u8], mut visit: impl FnMut(PackFeature) -> Result<()>) -> Result<()> {
+    ensure!(
+        data.len() >= mem::size_of::<VectorMapHeader>(),
+        "vector map is too small to hold a header"
+    );
+    let header = VectorMapHeader::overlay_prefix(data)?;
+    ensure!(header.magic() == HEADER_MAGIC, "not a vector map");
+    ensure!(
+        header.version() == HEADER_VERSION,
+        "unsupported vector map version {}",
+        header.version()
+    );
+    let features = FeatureRecord::overlay_slice(section(
+        data,
+        header.feature_start(),
+        header.feature_count(),
+        mem::size_of::<FeatureRecord>(),
+    )?)?;
+    let points = PackedPoint::overlay_slice(section(
+        data,
+        header.point_start(),
+        header.point_count(),
+        mem::size_of::<PackedPoint>(),
+    )?)?;
+    let names = section(data, header.name_start(), header.name_size(), 1)?;
+
+    for record in features {
+        let point_range = record.point_start() as usize
+            ..record.point_start() as usize + record.point_count() as usize;
+        let name_range = record.name_start() as usize
+            ..record.name_start() as usize + record.name_size() as usize;
+        ensure!(
+            point_range.end <= points.len() && name_range.end <= names.len(),
+            "vector map feature points outside of its tables"
+        );
+        let geometry = FeatureGeometry::from_u8(record.geometry())?;
+        ensure!(
+            point_range.len() >= geometry.min_points(),
+            "vector map {} has {} points, but needs at least {}",
+            geometry.name(),
+            point_range.len(),
+            geometry.min_points()
+        );
+        visit(PackFeature {
+            kind: FeatureKind::from_u8(record.kind())?,
+            geometry,
+            rank: record.rank(),
+            name: std::str::from_utf8(&names[name_range])?.to_owned(),
+            points: points[point_range]
+                .iter()
+                .map(|p| {
+                    [
+                        p.lat_e7() as f64 / DEGREES_TO_E7,
+                        p.lon_e7() as f64 / DEGREES_TO_E7,
+                    ]
+                })
+                .collect(),
+        })?;
+    }
+    Ok(())
+}
+
+/// Collects features, then writes them out as a packed vector map.
+#[derive(Default)]
+pub struct VectorMapBuilder {
+    features: Vec<FeatureRecord>,
+    points: Vec<PackedPoint>,
+    names: Vec<u8>,
+}
+
+impl fmt::Debug for VectorMapBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VectorMapBuilder")
+            .field("features", &self.features.len())
+            .field("points", &self.points.len())
+            .finish()
+    }
+}
+
+impl VectorMapBuilder {
+    pub fn feature_count(&self) -> usize {
+        self.features.len()
+    }
+
+    /// Add a feature with points given in degrees as [lat, lon]. Polygons are implicitly
+    /// closed, so do not need to repeat their first point.
+    pub fn push(
+        &mut self,
+        kind: FeatureKind,
+        geometry: FeatureGeometry,
+        rank: u16,
+        name: &str,
+        points: &[[f64; 2]],
+    ) -> Result<()> {
+        ensure!(
+            points.len() >= geometry.min_points(),
+            "a {} needs at least {} points",
+            geometry.name(),
+            geometry.min_points()
+        );
+        for p in points {
+            ensure!(
+                (-90. ..=90.).contains(&p[0]) && (-180. ..=180.).contains(&p[1]),
+                "point {:?} is not a latitude and longitude in degrees",
+                p
+            );
+        }
+        let point_start = self.points.len() as u32;
+        let name_start = self.names.len() as u32;
+        self.points.extend(points.iter().map(|p| PackedPoint {
+            lat_e7: (p[0] * DEGREES_TO_E7).round() as i32,
+            lon_e7: (p[1] * DEGREES_TO_E7).round() as i32,
+        }));
+        self.names.extend_from_slice(name.as_bytes());
+        self.features.push(FeatureRecord {
+            kind: kind as u8,
+            geometry: geometry as u8,
+            rank,
+            point_start,
+            point_count: points.len() as u32,
+            name_start,
+            name_size: name.len() as u32,
+        });
+        Ok(())
+    }
+
+    /// Add every feature in a GeoJSON FeatureCollection as `kind`. Names come from the
+    /// `name` property and ranks from `rank`, if present. Polygon holes are dropped. Returns
+    /// the number of features added.
+    pub fn push_geojson(&mut self, kind: FeatureKind, geojson: &str) -> Result<usize> {
+        let doc = json::parse(geojson)?;
+        ensure!(
+            doc["type"] == "FeatureCollection",
+            "expected a GeoJSON FeatureCollection"
+        );
+        let start_count = self.features.len();
+        for feature in doc["features"].members() {
+            let name = feature["properties"]["name"].as_str().unwrap_or("");
+            let rank = feature["properties"]["rank"].as_u16().unwrap_or(0);
+            let geom = &feature["geometry"];
+            let coords = &geom["coordinates"];
+            match geom["type"].as_str() {
+                Some("Point") => self.push(
+                    kind,
+                    FeatureGeometry::Point,
+                    rank,
+                    name,
+                    &[geojson_point(coords)?],
+                )?,
+                Some("MultiPoint") => {
+                    for point in coords.members() {
+                        self.push(
+                            kind,
+                            FeatureGeometry::Point,
+                            rank,
+                            name,
+                            &[geojson_point(point)?],
+                        )?;
+                    }
+                }
+                Some("LineString") => self.push(
+                    kind,
+                    FeatureGeometry::Polyline,
+                    rank,
+                    name,
+                    &geojson_line(coords)?,
+                )?,
+                Some("MultiLineString") => {
+                    for line in coords.members() {
+                        self.push(
+                            kind,
+                            FeatureGeometry::Polyline,
+                            rank,
+                            name,
+                            &geojson_line(line)?,
+                        )?;
+                    }
+                }
+                Some("Polygon") => self.push(
+                    kind,
+                    FeatureGeometry::Polygon,
+                    rank,
+                    name,
+                    &geojson_ring(&coords[0])?,
+                )?,
+                Some("MultiPolygon") => {
+                    for polygon in coords.members() {
+                        self.push(
+                            kind,
+                            FeatureGeometry::Polygon,
+                            rank,
+                            name,
+                            &geojson_ring(&polygon[0])?,
+                        )?;
+                    }
+                }
+                other => bail!("unsupported GeoJSON geometry: {:?}", other),
+            }
+        }
+        Ok(self.features.len() - start_count)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn extent(start: usize, size: usize) -> Range<usize> {
+            start..start + size
+        }
+        let features = extent(
+            mem::size_of::<VectorMapHeader>(),
+            self.features.len() * mem::size_of::<FeatureRecord>(),
+        );
+        let points = extent(
+            features.end,
+            self.points.len() * mem::size_of::<PackedPoint>(),
+        );
+        let names = extent(points.end, self.names.len());
+        let header = VectorMapHeader {
+            magic: HEADER_MAGIC,
+            version: HEADER_VERSION,
+            feature_count: self.features.len() as u32,
+            point_count: self.points.len() as u32,
+            feature_start: features.start as u32,
+            point_start: points.start as u32,
+            name_start: names.start as u32,
+            name_size: self.names.len() as u32,
+        };
+        let mut out = Vec::with_capacity(names.end);
+        out.extend_from_slice(header.as_bytes());
+        out.extend_from_slice(self.features.as_bytes());
+        out.extend_from_slice(self.points.as_bytes());
+        out.extend_from_slice(&self.names);
+        out
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+}
+
+// GeoJSON positions are [lon, lat]; we want [lat, lon].
+fn geojson_point(position: &json::JsonValue) -> Result<[f64; 2]> {
+    let lon = position[0].as_f64();
+    let lat = position[1].as_f64();
+    match (lat, lon) {
+        (Some(lat), Some(lon)) => Ok([lat, lon]),
+        _ => Err(anyhow!("invalid GeoJSON position: {}", position)),
+    }
+}
+
+fn geojson_line(positions: &json::JsonValue) -> Result<Vec<[f64; 2]>> {
+    positions.members().map(geojson_point).collect()
+}
+
+fn geojson_ring(positions: &json::JsonValue) -> Result<Vec<[f64; 2]>> {
+    let mut ring = geojson_line(positions)?;
+    if ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+    Ok(ring)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_geojson_round_trip() -> Result<()> {
+        let geojson = r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "properties": { "name": "KSEA", "rank": 1 },
+                    "geometry": { "type": "Point", "coordinates": [-122.3088, 47.4502] }
+                },
+                {
+                    "type": "Feature",
+                    "properties": { "name": "Apron" },
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]]
+                    }
+                }
+            ]
+        }"#;
+        let mut builder = VectorMapBuilder::default();
+        assert_eq!(builder.push_geojson(FeatureKind::Airport, geojson)?, 2);
+        builder.push(
+            FeatureKind::Road,
+            FeatureGeometry::Polyline,
+            3,
+            "",
+            &[[10., 179.5], [10.5, -179.5]],
+        )?;
+        assert!(builder
+            .push(
+                FeatureKind::Road,
+                FeatureGeometry::Polyline,
+                0,
+                "",
+                &[[0., 0.]]
+            )
+            .is_err());
+
+        let mut features = Vec::new();
+        read_pack(&builder.to_bytes(), |feature| {
+            features.push(feature);
+            Ok(())
+        })?;
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[0].name, "KSEA");
+        assert_eq!(features[0].rank, 1);
+        assert_eq!(features[0].points, vec![[47.4502, -122.3088]]);
+        assert_eq!(features[1].geometry, FeatureGeometry::Polygon);
+        assert_eq!(features[1].points.len(), 3);
+        assert_eq!(features[2].kind, FeatureKind::Road);
+        assert_eq!(features[2].points[1], [10.5, -179.5]);
+
+        assert!(read_pack(&builder.to_bytes()[..20], |_| Ok(())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_too_few_points() -> Result<()> {
+        let mut builder = VectorMapBuilder::default();
+        builder.push(
+            FeatureKind::Border,
+            FeatureGeometry::Polygon,
+            0,
+            "",
+            &[[0., 0.], [0., 1.], [1., 1.]],
+        )?;
+        // Only the builder checks this, so corrupt the record behind its back.
+        builder.features[0].point_count = 2;
+        let err = read_pack(&builder.to_bytes(), |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("needs at least 3"));
+        Ok(())
+    }
+}
@@ -20,15 +20,28 @@ use nitrous::{inject_nitrous_component, method, NitrousComponent};
 use runtime::{Extension, Runtime};
 
 // Self-centering, 0 centered, symmetrical controls.
+//
+// The position is the sum of up to three sources, clamped to [-1, 1]:
+//   * An absolute position from an analog device, e.g. a joystick axis bound to set_position.
+//   * A keyboard position, which ramps towards full deflection at key_sensitivity while a key
+//     is held and back towards center at centering_rate once released. A centering_rate of 0
+//     leaves the keyboard position where it was let go.
+//   * Trim, which offsets both of the above.
 macro_rules! make_sym {
     ($cls:ident, $name:expr, $up:ident, $down:ident) => {
         #[derive(Component, NitrousComponent, Debug, Copy, Clone)]
         #[Name = $name]
         pub struct $cls {
-            position: f64,        // [-1, 1]
-            key_move_target: f64, // target of move, depending on what key is held
+            position: f64,              // [-1, 1]
+            key_move_target: f64,       // target of move, depending on what key is held
+            key_position: f64,          // keyboard contribution to position
+            axis_position: Option<f64>, // analog contribution, if an axis is driving us
             #[property]
             key_sensitivity: f64,
+            #[property]
+            centering_rate: f64,
+            #[property]
+            trim: f64,
         }
 
         impl Extension for $cls {
@@ -43,7 +56,11 @@ macro_rules! make_sym {
                 Self {
                     position: 0_f64,
                     key_move_target: 0_f64,
+                    key_position: 0_f64,
+                    axis_position: None,
                     key_sensitivity: 2_f64,
+                    centering_rate: 2_f64,
+                    trim: 0_f64,
                 }
             }
         }
@@ -65,13 +82,49 @@ macro_rules! make_sym {
                 self.position as f64
             }
 
+            // Absolute input, e.g. from a joystick axis.
+            #[method]
+            pub fn set_position(&mut self, v: f64) {
+                self.axis_position = Some(v.clamp(-1., 1.));
+                self.position = self.combined_position();
+            }
+
+            // Stop listening to the analog source, e.g. when the device is unplugged.
+            #[method]
+            pub fn release_axis(&mut self) {
+                self.axis_position = None;
+                self.position = self.combined_position();
+            }
+
+            #[method]
+            pub fn adjust_trim(&mut self, delta: f64) {
+                self.trim = (self.trim + delta).clamp(-1., 1.);
+            }
+
+            #[method]
+            pub fn reset_trim(&mut self) {
+                self.trim = 0.;
+            }
+
+            fn combined_position(&self) -> f64 {
+                (self.axis_position.unwrap_or_default() + self.key_position + self.trim)
+                    .clamp(-1., 1.)
+            }
+
+            fn tick(&mut self, dt: f64) {
+                let rate = if self.key_move_target == 0. {
+                    self.centering_rate
+                } else {
+                    self.key_sensitivity
+                };
+                self.key_position =
+                    inceptor_position_tick(self.key_move_target, rate * dt, self.key_position);
+                self.position = self.combined_position();
+            }
+
             fn sys_tick(timestep: Res<TimeStep>, mut query: Query<&mut $cls>) {
                 for mut inceptor in query.iter_mut() {
-                    inceptor.position = inceptor_position_tick(
-                        inceptor.key_move_target,
-                        inceptor.key_sensitivity * timestep.step().as_secs_f64(),
-                        inceptor.position,
-                    );
+                    inceptor.tick(timestep.step().as_secs_f64());
                 }
             }
         }
@@ -81,3 +134,41 @@ macro_rules! make_sym {
 make_sym!(PitchInceptor, "stick_pitch", key_move_back, key_move_front);
 make_sym!(RollInceptor, "stick_roll", key_move_right, key_move_left);
 make_sym!(YawInceptor, "pedals_yaw", key_move_right, key_move_left);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_keyboard_and_axis_blend() {
+        let mut pitch = PitchInceptor::default();
+        pitch.key_move_back(true);
+        pitch.tick(0.25);
+        assert_relative_eq!(pitch.position(), 0.5);
+
+        // The stick adds to the keyboard, and the keys fall back to center when released.
+        pitch.set_position(-0.25);
+        assert_relative_eq!(pitch.position(), 0.25);
+        pitch.key_move_back(false);
+        pitch.tick(1.);
+        assert_relative_eq!(pitch.position(), -0.25);
+
+        pitch.adjust_trim(0.1);
+        pitch.release_axis();
+        assert_relative_eq!(pitch.position(), 0.1);
+    }
+
+    #[test]
+    fn test_no_centering() {
+        let mut roll = RollInceptor {
+            centering_rate: 0.,
+            ..Default::default()
+        };
+        roll.key_move_left(true);
+        roll.tick(0.1);
+        roll.key_move_left(false);
+        roll.tick(1.);
+        assert_relative_eq!(roll.position(), -0.2);
+    }
+}
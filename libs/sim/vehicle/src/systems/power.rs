@@ -17,9 +17,9 @@ use absolute_unit::{kilograms, newtons, Force, Meters, Newtons, Seconds, Velocit
 use animate::TimeStep;
 use anyhow::Result;
 use bevy_ecs::prelude::*;
-use nitrous::{inject_nitrous_component, method, NitrousComponent};
+use nitrous::{inject_nitrous_component, method, LocalNamespace, NitrousComponent, Value};
 use physical_constants::StandardAtmosphere;
-use runtime::{Extension, Runtime};
+use runtime::{EventBus, Extension, Runtime};
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash, SystemLabel)]
 pub enum PowerSystemStep {
@@ -76,9 +76,11 @@ impl PowerSystem {
 
     fn sys_consume_fuel(
         timestep: Res<TimeStep>,
-        mut query: Query<(&mut PowerSystem, &mut FuelSystem)>,
+        mut events: ResMut<EventBus>,
+        mut query: Query<(Entity, &mut PowerSystem, &mut FuelSystem)>,
     ) {
-        for (mut power, mut fuel) in query.iter_mut() {
+        for (entity, mut power, mut fuel) in query.iter_mut() {
+            let had_fuel = fuel.fuel_mass() > kilograms!(0f64);
             // Compute fuel use up front so that we can flame out all engines,
             // rather than staggering them out.
             let mut required_fuel = kilograms!(0f64);
@@ -90,6 +92,11 @@ impl PowerSystem {
                 for engine in &mut power.engines {
                     engine.set_out_of_fuel();
                 }
+                if had_fuel {
+                    let mut args = LocalNamespace::empty();
+                    args.put("entity", Value::Entity(entity));
+                    events.emit("out_of_fuel", args);
+                }
             }
         }
     }
@@ -13,8 +13,12 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 mod detail;
+mod timestamps;
 
-pub use crate::detail::{CpuDetailLevel, DetailLevelOpts, GpuDetailLevel};
+pub use crate::{
+    detail::{CpuDetailLevel, DetailLevelOpts, GpuDetailLevel},
+    timestamps::GpuTimestamps,
+};
 pub use window::DisplayConfig;
 
 // Note: re-export for use by FrameGraph when it is instantiated in other crates.
@@ -27,7 +31,7 @@ use futures::executor::block_on;
 use log::{info, trace};
 use nitrous::{inject_nitrous_resource, method, NitrousResource};
 use parking_lot::Mutex;
-use runtime::{Extension, Runtime};
+use runtime::{Extension, Profiler, Runtime};
 use std::{borrow::Cow, fmt::Debug, fs, mem, num::NonZeroU32, path::PathBuf, ptr, sync::Arc};
 use wgpu::util::DeviceExt;
 use window::{Window, WindowStep};
@@ -92,7 +96,13 @@ pub struct Gpu {
 impl Extension for Gpu {
     fn init(runtime: &mut Runtime) -> Result<()> {
         let gpu = Self::new(runtime.resource::<Window>(), Default::default())?;
+        let timestamps = GpuTimestamps::new(
+            &gpu.device,
+            &gpu.queue,
+            runtime.resource::<Profiler>().sink(),
+        );
         runtime.insert_named_resource("gpu", gpu);
+        runtime.insert_resource(timestamps);
         runtime.add_frame_system(
             Self::sys_handle_display_config_change
                 .label(GpuStep::HandleDisplayChange)
@@ -402,26 +412,34 @@ impl Gpu {
         gpu: Res<Gpu>,
         maybe_surface: Res<Option<wgpu::SurfaceTexture>>,
         mut maybe_encoder: ResMut<Option<wgpu::CommandEncoder>>,
+        mut timestamps: ResMut<GpuTimestamps>,
     ) {
         // Only create the encoder if we have a surface to write to.
         assert!(maybe_encoder.is_none());
         if maybe_surface.is_some() {
-            *maybe_encoder = Some(gpu.device().create_command_encoder(
-                &wgpu::CommandEncoderDescriptor {
-                    label: Some("frame-encoder"),
-                },
-            ));
+            let mut encoder =
+                gpu.device()
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("frame-encoder"),
+                    });
+            timestamps.begin_frame(gpu.device(), &mut encoder);
+            *maybe_encoder = Some(encoder);
         }
     }
 
     fn sys_submit_frame_commands(
         mut gpu: ResMut<Gpu>,
         mut maybe_encoder: ResMut<Option<wgpu::CommandEncoder>>,
+        mut timestamps: ResMut<GpuTimestamps>,
     ) {
         let mut empty_encoder = None as Option<wgpu::CommandEncoder>;
         mem::swap(&mut empty_encoder, &mut maybe_encoder);
-        if let Some(encoder) = empty_encoder {
+        if let Some(mut encoder) = empty_encoder {
+            let resolved = timestamps.resolve(&mut encoder);
             gpu.queue_mut().submit(vec![encoder.finish()]);
+            if resolved {
+                timestamps.map_results();
+            }
         }
     }
 
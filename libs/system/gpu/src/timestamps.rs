@@ -0,0 +1,165 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use parking_lot::Mutex;
+use runtime::ProfileSink;
+use std::{mem, sync::Arc, time::Duration};
+
+/// The maximum number of timestamps that we can write in a single frame.
+const MAX_TIMESTAMPS: u32 = 64;
+
+/// Record GPU execution time of render passes, when the adapter supports timestamp queries.
+///
+/// Passes call `write` with the encoder after recording their work; the time between each
+/// pair of timestamps is attributed to the later label. The first timestamp is written when
+/// the frame encoder is created. Results come back asynchronously a frame or more later and
+/// are fed to the Profiler under `gpu/<label>`. If timestamps are not supported, all of
+/// this does nothing.
+pub struct GpuTimestamps {
+    queries: Option<TimestampQueries>,
+    labels: Vec<&'static str>,
+    sink: ProfileSink,
+}
+
+struct TimestampQueries {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    period_ns: f64,
+    in_flight: Option<Vec<&'static str>>,
+    // Set by the map callback: Some(true) when the results are readable.
+    mapped: Arc<Mutex<Option<bool>>>,
+}
+
+impl GpuTimestamps {
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue, sink: ProfileSink) -> Self {
+        let queries = if device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            let size = (MAX_TIMESTAMPS as usize * mem::size_of::<u64>()) as wgpu::BufferAddress;
+            Some(TimestampQueries {
+                query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                    label: Some("gpu-timestamps-query-set"),
+                    ty: wgpu::QueryType::Timestamp,
+                    count: MAX_TIMESTAMPS,
+                }),
+                resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("gpu-timestamps-resolve-buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("gpu-timestamps-readback-buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                period_ns: queue.get_timestamp_period() as f64,
+                in_flight: None,
+                mapped: Arc::new(Mutex::new(None)),
+            })
+        } else {
+            None
+        };
+        Self {
+            queries,
+            labels: Vec::new(),
+            sink,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.queries.is_some()
+    }
+
+    /// Mark the end of the GPU work labeled with `label`.
+    pub fn write(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) {
+        if let Some(queries) = self.queries.as_ref() {
+            // Skip frames where the last readback has not finished: we can only reuse the
+            // query set once the previous results have been copied out.
+            if queries.in_flight.is_some() || self.labels.len() >= MAX_TIMESTAMPS as usize {
+                return;
+            }
+            encoder.write_timestamp(&queries.query_set, self.labels.len() as u32);
+            self.labels.push(label);
+        }
+    }
+
+    /// Called as the frame encoder is created to read back any finished results
+    /// and to write the starting timestamp for this frame.
+    pub(crate) fn begin_frame(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if let Some(queries) = self.queries.as_mut() {
+            device.poll(wgpu::Maintain::Poll);
+            let maybe_mapped = queries.mapped.lock().take();
+            if let Some(mapped_ok) = maybe_mapped {
+                let labels = queries.in_flight.take().unwrap_or_default();
+                if mapped_ok {
+                    {
+                        let view = queries.readback_buffer.slice(..).get_mapped_range();
+                        let ticks = view
+                            .chunks_exact(mem::size_of::<u64>())
+                            .take(labels.len())
+                            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                            .collect::<Vec<_>>();
+                        for (i, label) in labels.iter().enumerate().skip(1) {
+                            let delta = ticks[i].saturating_sub(ticks[i - 1]);
+                            let nanos = (delta as f64 * queries.period_ns) as u64;
+                            self.sink.record("gpu", label, Duration::from_nanos(nanos));
+                        }
+                    }
+                    queries.readback_buffer.unmap();
+                }
+            }
+        }
+        self.labels.clear();
+        self.write(encoder, "begin");
+    }
+
+    /// Called before the frame encoder is finished to copy out this frame's timestamps.
+    pub(crate) fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) -> bool {
+        if let Some(queries) = self.queries.as_mut() {
+            if queries.in_flight.is_none() && self.labels.len() > 1 {
+                let count = self.labels.len() as u32;
+                let size = (count as usize * mem::size_of::<u64>()) as wgpu::BufferAddress;
+                encoder.resolve_query_set(&queries.query_set, 0..count, &queries.resolve_buffer, 0);
+                encoder.copy_buffer_to_buffer(
+                    &queries.resolve_buffer,
+                    0,
+                    &queries.readback_buffer,
+                    0,
+                    size,
+                );
+                queries.in_flight = Some(mem::take(&mut self.labels));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Called after submit, if `resolve` returned true, to start the readback.
+    pub(crate) fn map_results(&mut self) {
+        if let Some(queries) = self.queries.as_ref() {
+            let mapped = queries.mapped.clone();
+            queries
+                .readback_buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    *mapped.lock() = Some(result.is_ok());
+                });
+        }
+    }
+}
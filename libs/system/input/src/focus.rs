@@ -0,0 +1,299 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{InputEvent, InputEventVec};
+use anyhow::{bail, ensure, Result};
+use bevy_ecs::prelude::*;
+use nitrous::{inject_nitrous_resource, method, NitrousResource};
+use runtime::{Extension, Runtime};
+use std::{fmt, str::FromStr};
+use winit::event::{ElementState, ModifiersState, VirtualKeyCode};
+
+const GAME_FOCUS: &str = "game";
+const TERMINAL_FOCUS: &str = "terminal";
+
+/// The name of a context that bindings can be made in; e.g. game, menu, map, or cockpit.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct InputFocus(String);
+
+impl InputFocus {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.0 == TERMINAL_FOCUS
+    }
+}
+
+impl FromStr for InputFocus {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        ensure!(
+            !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'),
+            "input focus names must be lower_case, not '{}'",
+            s
+        );
+        Ok(Self(s.to_owned()))
+    }
+}
+
+impl fmt::Display for InputFocus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Default for InputFocus {
+    fn default() -> Self {
+        Self(GAME_FOCUS.to_owned())
+    }
+}
+
+/// An entry in the focus stack.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FocusContext {
+    focus: InputFocus,
+    passthrough: bool,
+}
+
+impl FocusContext {
+    pub fn new(focus: InputFocus, passthrough: bool) -> Self {
+        Self { focus, passthrough }
+    }
+
+    pub fn focus(&self) -> &InputFocus {
+        &self.focus
+    }
+
+    /// Whether input that is not bound in this context goes on to the context beneath it.
+    pub fn passes_through(&self) -> bool {
+        self.passthrough
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, SystemLabel)]
+pub enum InputTargetSimStep {
+    ToggleTerminal,
+}
+
+/// Decides which bindings see input, as a stack of focus contexts. Input goes to the top
+/// context first. If nothing there is bound to it, it continues down the stack for as long
+/// as the contexts pass input through. The bottom of the stack is always the game context.
+///
+/// The terminal is a context that swallows everything, so that typing in it does not fly
+/// the plane. Should be installed before Terminal and anything that processes input, like
+/// EventMapper and WidgetBuffer.
+#[derive(NitrousResource, Clone, Debug, Eq, PartialEq)]
+pub struct InputTarget {
+    stack: Vec<FocusContext>,
+}
+
+impl Extension for InputTarget {
+    fn init(runtime: &mut Runtime) -> Result<()> {
+        runtime.insert_named_resource("input_target", InputTarget::default());
+        runtime.add_input_system(
+            Self::sys_handle_toggle_terminal.label(InputTargetSimStep::ToggleTerminal),
+        );
+        Ok(())
+    }
+}
+
+impl Default for InputTarget {
+    fn default() -> Self {
+        Self {
+            stack: vec![FocusContext::new(InputFocus::default(), false)],
+        }
+    }
+}
+
+#[inject_nitrous_resource]
+impl InputTarget {
+    // Handle terminal separately from bindings in case things get messed up
+    pub fn sys_handle_toggle_terminal(events: Res<InputEventVec>, mut target: ResMut<InputTarget>) {
+        if events
+            .iter()
+            .any(|event| target.is_toggle_terminal_event(event))
+        {
+            target.toggle_terminal();
+        }
+    }
+
+    fn is_toggle_terminal_event(&self, event: &InputEvent) -> bool {
+        if let InputEvent::KeyboardKey {
+            virtual_keycode,
+            press_state,
+            modifiers_state,
+            ..
+        } = event
+        {
+            if self.terminal_active() && *virtual_keycode == VirtualKeyCode::Escape
+                || *virtual_keycode == VirtualKeyCode::Grave
+                    && *modifiers_state == ModifiersState::CTRL
+                    && *press_state == ElementState::Pressed
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The contexts that input is offered to, from the top of the stack down.
+    pub fn active_contexts(&self) -> impl Iterator<Item = &FocusContext> + '_ {
+        let depth = self
+            .stack
+            .iter()
+            .rev()
+            .position(|context| !context.passthrough)
+            .map(|i| i + 1)
+            .unwrap_or(self.stack.len());
+        self.stack.iter().rev().take(depth)
+    }
+
+    pub fn top_focus(&self) -> &InputFocus {
+        // Note: the base context is never removed.
+        self.stack.last().unwrap().focus()
+    }
+
+    pub fn push_context(&mut self, context: FocusContext) {
+        // Pushing a context that is already open brings it to the top.
+        let base = self.stack[0].focus.clone();
+        self.stack
+            .retain(|c| c.focus == base || c.focus != context.focus);
+        self.stack.push(context);
+    }
+
+    /// Open a named context on top of the stack. If passthrough is set, input that is not
+    /// bound in the context is passed to the contexts beneath it; otherwise it is dropped.
+    #[method]
+    pub fn push(&mut self, name: &str, passthrough: bool) -> Result<()> {
+        ensure!(
+            name != self.stack[0].focus.name(),
+            "{} is the base input context",
+            name
+        );
+        self.push_context(FocusContext::new(InputFocus::from_str(name)?, passthrough));
+        Ok(())
+    }
+
+    /// Close the top context, returning its name.
+    #[method]
+    pub fn pop(&mut self) -> Result<String> {
+        if self.stack.len() <= 1 {
+            bail!("cannot pop the base input context");
+        }
+        Ok(self.stack.pop().unwrap().focus.0)
+    }
+
+    /// Close the named context wherever it is in the stack. Returns false if it was not open.
+    #[method]
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.stack.len();
+        let base = self.stack[0].clone();
+        self.stack.retain(|c| c == &base || c.focus.name() != name);
+        self.stack.len() != before
+    }
+
+    /// The name of the context on top of the stack.
+    #[method]
+    pub fn top(&self) -> String {
+        self.top_focus().to_string()
+    }
+
+    /// True if the named context is open and not hidden by a context above it.
+    #[method]
+    pub fn is_active(&self, name: &str) -> bool {
+        self.active_contexts().any(|c| c.focus.name() == name)
+    }
+
+    /// Show the stack, from the top down.
+    #[method]
+    pub fn stack(&self) -> String {
+        self.stack
+            .iter()
+            .rev()
+            .map(|c| {
+                if c.passthrough {
+                    format!("{} (passthrough)", c.focus)
+                } else {
+                    c.focus.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[method]
+    pub fn terminal_active(&self) -> bool {
+        self.top_focus().is_terminal()
+    }
+
+    #[method]
+    pub fn set_terminal_active(&mut self, active: bool) {
+        if active {
+            self.push_context(FocusContext::new(
+                InputFocus(TERMINAL_FOCUS.to_owned()),
+                false,
+            ));
+        } else {
+            self.remove(TERMINAL_FOCUS);
+        }
+    }
+
+    #[method]
+    pub fn toggle_terminal(&mut self) {
+        self.set_terminal_active(!self.terminal_active());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_focus_stack() -> Result<()> {
+        let mut target = InputTarget::default();
+        assert_eq!(target.top(), "game");
+        target.push("map", true)?;
+        target.push("pause", false)?;
+        let active = target
+            .active_contexts()
+            .map(|c| c.focus().name())
+            .collect::<Vec<_>>();
+        assert_eq!(active, vec!["pause"]);
+
+        // Re-pushing moves a context to the top.
+        target.push("map", true)?;
+        let active = target
+            .active_contexts()
+            .map(|c| c.focus().name())
+            .collect::<Vec<_>>();
+        assert_eq!(active, vec!["map", "pause"]);
+
+        target.toggle_terminal();
+        assert!(target.terminal_active());
+        assert!(!target.is_active("map"));
+        target.toggle_terminal();
+        assert!(target.is_active("map"));
+
+        assert!(target.remove("pause"));
+        assert_eq!(target.pop()?, "map");
+        assert!(target.pop().is_err());
+        assert!(target.push("game", true).is_err());
+        assert!(target.push("Bad Name", true).is_err());
+        Ok(())
+    }
+}
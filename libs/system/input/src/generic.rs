@@ -61,7 +61,8 @@ pub enum InputEvent {
     },
 
     JoystickButton {
-        dummy: u32,
+        device: u32,
+        button: u32,
         press_state: ElementState,
         modifiers_state: ModifiersState,
         window_focused: bool,
@@ -90,7 +91,9 @@ pub enum InputEvent {
         window_focused: bool,
     },
 
+    // Values are normalized to [-1, 1].
     JoystickAxis {
+        device: u32,
         id: u32,
         value: f64,
         modifiers_state: ModifiersState,
@@ -98,13 +101,16 @@ pub enum InputEvent {
     },
 
     // We do not generally care about individual mice or keyboards: the events will still come
-    // through automatically. This will be very important for Joystick management, however, as we
-    // expect those to cycle relatively frequently during gameplay.
+    // through automatically. This is very important for Joystick management, however, as we
+    // expect those to cycle relatively frequently during gameplay. The device id matches the
+    // one on JoystickButton and JoystickAxis events; the guid is stable across runs.
     DeviceAdded {
-        dummy: u32,
+        device: u32,
+        name: String,
+        guid: String,
     },
     DeviceRemoved {
-        dummy: u32,
+        device: u32,
     },
 }
 
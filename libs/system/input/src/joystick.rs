@@ -0,0 +1,439 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{GlobalInputState, InputEvent, InputEventVec, InputStep};
+use anyhow::{anyhow, Result};
+use bevy_ecs::prelude::*;
+use nitrous::{inject_nitrous_resource, method, NitrousResource};
+use runtime::Runtime;
+use std::collections::BTreeMap;
+use winit::event::ElementState;
+
+// Joysticks and gamepads do not come through winit, which only reports them as anonymous
+// DeviceEvent::Added and Motion events with no way to tell which device is which. Instead we
+// poll them with gilrs on a separate thread and forward the events to the main event loop as
+// a MetaEvent, where they get stamped with the current modifier and focus state, exactly like
+// winit events. The ids we hand out are gilrs' gamepad ids, which are reused if the same device
+// is unplugged and plugged back in. The guid is stable across runs and is what should be used
+// to persist anything device specific.
+//
+// Buttons and axes are numbered as follows, so that bindings like `joy0` and `axis1` have a
+// consistent meaning across devices:
+//   * Buttons that gilrs knows a gamepad mapping for use a fixed layout: 0 is South (A on an
+//     xbox pad), through 18 for DPadRight; see `button_index`.
+//   * Unmapped buttons, e.g. every button on a HOTAS, start at 32, in the order the device
+//     reports them.
+//   * Mapped axes use 0-7 (left stick x/y, left z, right stick x/y, right z, dpad x/y), and
+//     unmapped axes start at 16, in the order the device reports them.
+pub const UNMAPPED_BUTTON_BASE: u32 = 32;
+pub const UNMAPPED_AXIS_BASE: u32 = 16;
+pub const MAX_JOYSTICK_BUTTONS: u32 = 128;
+pub const MAX_JOYSTICK_AXES: u32 = 128;
+
+/// Raw events from the joystick driver thread, before being stamped with window state.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JoystickEvent {
+    Connected {
+        device: u32,
+        name: String,
+        guid: String,
+    },
+    Disconnected {
+        device: u32,
+    },
+    Button {
+        device: u32,
+        button: u32,
+        pressed: bool,
+    },
+    Axis {
+        device: u32,
+        axis: u32,
+        value: f64,
+    },
+}
+
+impl JoystickEvent {
+    pub(crate) fn wrap(&self, input_state: &GlobalInputState) -> InputEvent {
+        match self {
+            Self::Connected { device, name, guid } => InputEvent::DeviceAdded {
+                device: *device,
+                name: name.to_owned(),
+                guid: guid.to_owned(),
+            },
+            Self::Disconnected { device } => InputEvent::DeviceRemoved { device: *device },
+            Self::Button {
+                device,
+                button,
+                pressed,
+            } => InputEvent::JoystickButton {
+                device: *device,
+                button: *button,
+                press_state: if *pressed {
+                    ElementState::Pressed
+                } else {
+                    ElementState::Released
+                },
+                modifiers_state: input_state.modifiers_state,
+                window_focused: input_state.window_focused,
+            },
+            Self::Axis {
+                device,
+                axis,
+                value,
+            } => InputEvent::JoystickAxis {
+                device: *device,
+                id: *axis,
+                value: *value,
+                modifiers_state: input_state.modifiers_state,
+                window_focused: input_state.window_focused,
+            },
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod driver {
+    use super::{JoystickEvent, UNMAPPED_AXIS_BASE, UNMAPPED_BUTTON_BASE};
+    use crate::MetaEvent;
+    use gilrs::{ev::Code, Axis, Button, Event, EventType, GamepadId, Gilrs, GilrsBuilder};
+    use log::{info, warn};
+    use std::{thread, time::Duration};
+    use winit::event_loop::EventLoopProxy;
+
+    // Gilrs does not offer a blocking wait, so poll at a rate well above the sim tick.
+    const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+    /// Poll gilrs and forward joystick events to the event loop until the loop shuts down.
+    pub(crate) fn run(proxy: EventLoopProxy<MetaEvent>) {
+        // Deadzones and response curves are applied per-binding, so take raw values.
+        let mut gilrs = match GilrsBuilder::new()
+            .with_default_filters(false)
+            .add_included_mappings(true)
+            .add_env_mappings(true)
+            .build()
+        {
+            Ok(gilrs) => gilrs,
+            Err(e) => {
+                warn!("joystick support is unavailable: {}", e);
+                return;
+            }
+        };
+
+        let present = gilrs
+            .gamepads()
+            .map(|(id, _)| connected(&gilrs, id))
+            .collect::<Vec<_>>();
+        for evt in present {
+            if proxy.send_event(MetaEvent::Joystick(evt)).is_err() {
+                return;
+            }
+        }
+
+        loop {
+            while let Some(Event { id, event, .. }) = gilrs.next_event() {
+                if let Some(evt) = translate(&gilrs, id, event) {
+                    if proxy.send_event(MetaEvent::Joystick(evt)).is_err() {
+                        // The event loop has exited.
+                        return;
+                    }
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn device_id(id: GamepadId) -> u32 {
+        usize::from(id) as u32
+    }
+
+    fn connected(gilrs: &Gilrs, id: GamepadId) -> JoystickEvent {
+        let gamepad = gilrs.gamepad(id);
+        let guid = gamepad
+            .uuid()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        info!(
+            "joystick {} connected: {} [{}]",
+            device_id(id),
+            gamepad.name(),
+            guid
+        );
+        JoystickEvent::Connected {
+            device: device_id(id),
+            name: gamepad.name().to_owned(),
+            guid,
+        }
+    }
+
+    fn translate(gilrs: &Gilrs, id: GamepadId, event: EventType) -> Option<JoystickEvent> {
+        let device = device_id(id);
+        Some(match event {
+            EventType::Connected => connected(gilrs, id),
+            EventType::Disconnected => {
+                info!("joystick {} disconnected", device);
+                JoystickEvent::Disconnected { device }
+            }
+            EventType::ButtonPressed(button, code) => JoystickEvent::Button {
+                device,
+                button: button_index(button, code)?,
+                pressed: true,
+            },
+            EventType::ButtonReleased(button, code) => JoystickEvent::Button {
+                device,
+                button: button_index(button, code)?,
+                pressed: false,
+            },
+            EventType::AxisChanged(axis, value, code) => JoystickEvent::Axis {
+                device,
+                axis: axis_index(axis, code)?,
+                value: (value as f64).clamp(-1., 1.),
+            },
+            // Analog button values and key repeat are not interesting to the sim.
+            EventType::ButtonChanged(..) | EventType::ButtonRepeated(..) => return None,
+            EventType::Dropped => return None,
+        })
+    }
+
+    fn button_index(button: Button, code: Code) -> Option<u32> {
+        Some(match button {
+            Button::South => 0,
+            Button::East => 1,
+            Button::North => 2,
+            Button::West => 3,
+            Button::C => 4,
+            Button::Z => 5,
+            Button::LeftTrigger => 6,
+            Button::LeftTrigger2 => 7,
+            Button::RightTrigger => 8,
+            Button::RightTrigger2 => 9,
+            Button::Select => 10,
+            Button::Start => 11,
+            Button::Mode => 12,
+            Button::LeftThumb => 13,
+            Button::RightThumb => 14,
+            Button::DPadUp => 15,
+            Button::DPadDown => 16,
+            Button::DPadLeft => 17,
+            Button::DPadRight => 18,
+            Button::Unknown => UNMAPPED_BUTTON_BASE + raw_button_offset(code)?,
+        })
+    }
+
+    fn axis_index(axis: Axis, code: Code) -> Option<u32> {
+        Some(match axis {
+            Axis::LeftStickX => 0,
+            Axis::LeftStickY => 1,
+            Axis::LeftZ => 2,
+            Axis::RightStickX => 3,
+            Axis::RightStickY => 4,
+            Axis::RightZ => 5,
+            Axis::DPadX => 6,
+            Axis::DPadY => 7,
+            Axis::Unknown => UNMAPPED_AXIS_BASE + raw_axis_offset(code)?,
+        })
+    }
+
+    // On Linux, the code is the evdev (type << 16 | code). Joystick buttons live in
+    // BTN_JOYSTICK (0x120-0x12f), BTN_GAMEPAD (0x130-0x13f) and BTN_TRIGGER_HAPPY (0x2c0+).
+    #[cfg(target_os = "linux")]
+    fn raw_button_offset(code: Code) -> Option<u32> {
+        let raw = code.into_u32() & 0xFFFF;
+        match raw {
+            0x120..=0x13f => Some(raw - 0x120),
+            0x2c0..=0x2ff => Some(0x20 + raw - 0x2c0),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn raw_button_offset(code: Code) -> Option<u32> {
+        let raw = code.into_u32() & 0xFFFF;
+        (raw < super::MAX_JOYSTICK_BUTTONS - UNMAPPED_BUTTON_BASE).then_some(raw)
+    }
+
+    // Absolute axes are numbered from ABS_X (0) up through ABS_MISC (0x28) on Linux.
+    fn raw_axis_offset(code: Code) -> Option<u32> {
+        let raw = code.into_u32() & 0xFFFF;
+        (raw < super::MAX_JOYSTICK_AXES - UNMAPPED_AXIS_BASE).then_some(raw)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JoystickInfo {
+    name: String,
+    guid: String,
+}
+
+impl JoystickInfo {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn guid(&self) -> &str {
+        &self.guid
+    }
+}
+
+/// The joysticks and gamepads that are currently plugged in, keyed by device id.
+#[derive(Debug, Default, NitrousResource)]
+pub struct Joysticks {
+    devices: BTreeMap<u32, JoystickInfo>,
+}
+
+#[inject_nitrous_resource]
+impl Joysticks {
+    pub(crate) fn install(runtime: &mut Runtime) {
+        runtime.insert_named_resource("joysticks", Joysticks::default());
+        runtime.add_input_system(
+            Self::sys_track_devices
+                .label(InputStep::TrackDevices)
+                .after(InputStep::ReadInput),
+        );
+    }
+
+    pub fn sys_track_devices(events: Res<InputEventVec>, mut joysticks: ResMut<Joysticks>) {
+        for event in events.iter() {
+            joysticks.handle_event(event);
+        }
+    }
+
+    fn handle_event(&mut self, event: &InputEvent) {
+        match event {
+            InputEvent::DeviceAdded { device, name, guid } => {
+                self.devices.insert(
+                    *device,
+                    JoystickInfo {
+                        name: name.to_owned(),
+                        guid: guid.to_owned(),
+                    },
+                );
+            }
+            InputEvent::DeviceRemoved { device } => {
+                self.devices.remove(device);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn device(&self, device: u32) -> Option<&JoystickInfo> {
+        self.devices.get(&device)
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = (u32, &JoystickInfo)> {
+        self.devices.iter().map(|(id, info)| (*id, info))
+    }
+
+    fn lookup(&self, device: i64) -> Result<&JoystickInfo> {
+        u32::try_from(device)
+            .ok()
+            .and_then(|id| self.device(id))
+            .ok_or_else(|| anyhow!("no joystick with id {}", device))
+    }
+
+    /// The number of joysticks and gamepads currently attached.
+    #[method]
+    pub fn count(&self) -> i64 {
+        self.devices.len() as i64
+    }
+
+    /// The name the device reports for itself.
+    #[method]
+    pub fn name(&self, device: i64) -> Result<String> {
+        Ok(self.lookup(device)?.name.clone())
+    }
+
+    /// A hardware identifier for the device that is stable across runs.
+    #[method]
+    pub fn guid(&self, device: i64) -> Result<String> {
+        Ok(self.lookup(device)?.guid.clone())
+    }
+
+    /// One line per attached device: id, name and guid.
+    #[method]
+    pub fn list(&self) -> String {
+        self.devices
+            .iter()
+            .map(|(id, info)| format!("{}: {} [{}]", id, info.name, info.guid))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wrap_joystick_events() {
+        let input_state = GlobalInputState {
+            window_focused: true,
+            ..Default::default()
+        };
+        let evt = JoystickEvent::Button {
+            device: 2,
+            button: 33,
+            pressed: true,
+        }
+        .wrap(&input_state);
+        assert!(matches!(
+            evt,
+            InputEvent::JoystickButton {
+                device: 2,
+                button: 33,
+                press_state: ElementState::Pressed,
+                window_focused: true,
+                ..
+            }
+        ));
+        assert_eq!(evt.press_state(), Some(ElementState::Pressed));
+
+        let evt = JoystickEvent::Axis {
+            device: 2,
+            axis: 1,
+            value: -0.5,
+        }
+        .wrap(&input_state);
+        assert!(matches!(
+            evt,
+            InputEvent::JoystickAxis { device: 2, id: 1, value, .. } if value == -0.5
+        ));
+    }
+
+    #[test]
+    fn test_track_devices() -> Result<()> {
+        let mut runtime = Runtime::default();
+        runtime.insert_resource(InputEventVec::new());
+        Joysticks::install(&mut runtime);
+
+        let added = JoystickEvent::Connected {
+            device: 3,
+            name: "Throttle".to_owned(),
+            guid: "0300".to_owned(),
+        };
+        *runtime.resource_mut::<InputEventVec>() =
+            crate::test_make_input_events(vec![added.wrap(&GlobalInputState::default())]);
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Joysticks>().count(), 1);
+        assert_eq!(runtime.resource::<Joysticks>().name(3)?, "Throttle");
+        assert!(runtime.resource::<Joysticks>().guid(4).is_err());
+
+        *runtime.resource_mut::<InputEventVec>() =
+            crate::test_make_input_events(vec![InputEvent::DeviceRemoved { device: 3 }]);
+        runtime.run_sim_once();
+        assert_eq!(runtime.resource::<Joysticks>().count(), 0);
+        Ok(())
+    }
+}
@@ -12,16 +12,21 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+mod focus;
 mod generic;
+mod joystick;
 
+pub use focus::{FocusContext, InputFocus, InputTarget, InputTargetSimStep};
 pub use generic::{InputEvent, MouseAxis, SystemEvent};
+pub use joystick::{
+    JoystickEvent, JoystickInfo, Joysticks, MAX_JOYSTICK_AXES, MAX_JOYSTICK_BUTTONS,
+    UNMAPPED_AXIS_BASE, UNMAPPED_BUTTON_BASE,
+};
 pub use winit::event::{ButtonId, ElementState, ModifiersState, VirtualKeyCode};
 
 use anyhow::{bail, Result};
 use bevy_ecs::prelude::*;
-use gilrs::{Button as GilButton, Event as GilEvent, GilrsBuilder};
 use log::warn;
-use nitrous::{inject_nitrous_resource, method, NitrousResource};
 use parking_lot::Mutex;
 use runtime::{Extension, Runtime};
 use smallvec::SmallVec;
@@ -29,7 +34,6 @@ use std::{
     collections::HashMap,
     fmt::Debug,
     hash::Hash,
-    str::FromStr,
     sync::{
         mpsc::{channel, Receiver, TryRecvError},
         Arc,
@@ -55,140 +59,10 @@ pub fn test_make_input_events(mut events: Vec<InputEvent>) -> InputEventVec {
     out
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
-pub enum InputFocus {
-    Menu,
-    GameMenu,
-    Game,
-    Edit,
-}
-
-impl InputFocus {
-    pub fn name(&self) -> &'static str {
-        match self {
-            Self::Menu => "menu",
-            Self::GameMenu => "game_menu",
-            Self::Game => "game",
-            Self::Edit => "edit",
-        }
-    }
-}
-
-impl FromStr for InputFocus {
-    type Err = anyhow::Error;
-    fn from_str(s: &str) -> Result<Self> {
-        Ok(match s {
-            "menu" => Self::Menu,
-            "game_menu" => Self::GameMenu,
-            "game" => Self::Game,
-            "edit" => Self::Edit,
-            _ => bail!("not an input focus"),
-        })
-    }
-}
-
-impl ToString for InputFocus {
-    fn to_string(&self) -> String {
-        self.name().to_owned()
-    }
-}
-
-impl Default for InputFocus {
-    fn default() -> Self {
-        Self::Game
-    }
-}
-
-#[derive(Clone, Debug, Eq, PartialEq, Hash, SystemLabel)]
-pub enum InputTargetSimStep {
-    ToggleTerminal,
-}
-
-/// Controls terminal visibility and key access. Should be installed before Terminal and anything
-/// that processes input, like EventMapper and WidgetBuffer.
-#[derive(NitrousResource, Copy, Clone, Debug, Eq, PartialEq, Hash)]
-pub struct InputTarget {
-    terminal_active: bool,
-    input_focus: InputFocus,
-}
-
-impl Extension for InputTarget {
-    fn init(runtime: &mut Runtime) -> Result<()> {
-        runtime.insert_named_resource("input_target", InputTarget::default());
-        runtime.add_input_system(
-            Self::sys_handle_toggle_terminal.label(InputTargetSimStep::ToggleTerminal),
-        );
-        Ok(())
-    }
-}
-
-impl Default for InputTarget {
-    fn default() -> Self {
-        Self {
-            terminal_active: false,
-            input_focus: InputFocus::Edit,
-        }
-    }
-}
-
-#[inject_nitrous_resource]
-impl InputTarget {
-    // Handle terminal separately from bindings in case things get messed up
-    pub fn sys_handle_toggle_terminal(events: Res<InputEventVec>, mut target: ResMut<InputTarget>) {
-        if events
-            .iter()
-            .any(|event| target.is_toggle_terminal_event(event))
-        {
-            target.toggle_terminal();
-        }
-    }
-
-    fn is_toggle_terminal_event(&self, event: &InputEvent) -> bool {
-        if let InputEvent::KeyboardKey {
-            virtual_keycode,
-            press_state,
-            modifiers_state,
-            ..
-        } = event
-        {
-            if self.terminal_active && *virtual_keycode == VirtualKeyCode::Escape
-                || *virtual_keycode == VirtualKeyCode::Grave
-                    && *modifiers_state == ModifiersState::CTRL
-                    && *press_state == ElementState::Pressed
-            {
-                return true;
-            }
-        }
-        false
-    }
-
-    #[method]
-    pub fn terminal_active(&self) -> bool {
-        self.terminal_active
-    }
-
-    #[method]
-    pub fn set_terminal_active(&mut self, active: bool) {
-        self.terminal_active = active;
-    }
-
-    #[method]
-    pub fn toggle_terminal(&mut self) {
-        self.terminal_active = !self.terminal_active;
-    }
-
-    pub fn focus(&self) -> String {
-        self.input_focus.to_string()
-    }
-
-    pub fn input_focus(&self) -> InputFocus {
-        self.input_focus
-    }
-}
-
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum MetaEvent {
     Stop,
+    Joystick(JoystickEvent),
 }
 
 #[derive(Debug, Default)]
@@ -202,6 +76,7 @@ pub struct GlobalInputState {
 pub enum InputStep {
     ReadInput,
     ReadSystem,
+    TrackDevices,
 }
 
 pub struct InputController {
@@ -231,6 +106,7 @@ impl InputController {
         runtime.insert_resource(input_controller.clone());
         runtime.insert_resource(InputEventVec::new());
         runtime.insert_resource(SystemEventVec::new());
+        Joysticks::install(runtime);
 
         runtime.add_input_system(Self::sys_read_input_events.label(InputStep::ReadInput));
         runtime.add_frame_system(Self::sys_read_system_events.label(InputStep::ReadSystem));
@@ -358,6 +234,39 @@ impl InputController {
     }
 }
 
+/// Stands in for InputController when there is no window or event loop, e.g. when running
+/// the sim headless in CI. Provides the same resources and ReadInput step so that consumers
+/// like EventMapper do not need to know the difference. Events queued here are delivered
+/// on the next sim tick, which lets tests drive bindings without a display.
+#[derive(Debug, Default)]
+pub struct HeadlessInput {
+    pending: InputEventVec,
+}
+
+impl Extension for HeadlessInput {
+    fn init(runtime: &mut Runtime) -> Result<()> {
+        runtime.insert_resource(HeadlessInput::default());
+        runtime.insert_resource(InputEventVec::new());
+        runtime.insert_resource(SystemEventVec::new());
+        Joysticks::install(runtime);
+        runtime.add_input_system(Self::sys_deliver_input_events.label(InputStep::ReadInput));
+        Ok(())
+    }
+}
+
+impl HeadlessInput {
+    pub fn push_event(&mut self, event: InputEvent) {
+        self.pending.push(event);
+    }
+
+    pub fn sys_deliver_input_events(
+        mut headless: ResMut<HeadlessInput>,
+        mut input_events: ResMut<InputEventVec>,
+    ) {
+        *input_events = std::mem::take(&mut headless.pending);
+    }
+}
+
 #[derive(Debug)]
 pub struct InputSystem;
 
@@ -453,6 +362,7 @@ impl InputSystem {
         let (tx_input_event, rx_input_event) = channel();
         let (tx_system_event, rx_system_event) = channel();
         let event_loop_proxy = event_loop.create_proxy();
+        let joystick_proxy = event_loop.create_proxy();
 
         // Spawn the game thread.
         std::thread::spawn(move || {
@@ -473,25 +383,9 @@ impl InputSystem {
             input_controller.lock().quit().ok();
         });
 
-        // Spawn a thread to play with joysticks
-        std::thread::spawn(move || {
-            let mut gilrs = GilrsBuilder::new()
-                .add_included_mappings(true)
-                .add_env_mappings(true)
-                .build()
-                .expect("Gilrs load");
-            let mut _active_gamepad = None;
-            for (id, gamepad) in gilrs.gamepads() {
-                println!("{}: {} is {:?}", id, gamepad.name(), gamepad.power_info());
-                println!("MAPPING: {:?}", gamepad.mapping_source());
-                println!("STATE: {:?}", gamepad.state());
-                println!("BUTTON: {:?}", gamepad.button_code(GilButton::LeftTrigger));
-            }
-            while let Some(GilEvent { id, event, time }) = gilrs.next_event() {
-                println!("{:?} New event from {}: {:?}", time, id, event);
-                _active_gamepad = Some(id);
-            }
-        });
+        // Spawn a thread to poll joysticks and gamepads. Events get routed through the event
+        // loop so that they pick up the same modifier and focus state as everything else.
+        std::thread::spawn(move || joystick::driver::run(joystick_proxy));
 
         // Hijack the main thread.
         let mut input_events = Vec::new();
@@ -540,6 +434,9 @@ impl InputSystem {
             Event::DeviceEvent { device_id, event } => {
                 Self::wrap_device_event(device_id, event, input_state, input_events)
             }
+            Event::UserEvent(MetaEvent::Joystick(joystick_event)) => {
+                input_events.push(joystick_event.wrap(input_state));
+            }
             Event::MainEventsCleared => {}
             Event::RedrawRequested(_window_id) => {}
             Event::RedrawEventsCleared => {}
@@ -663,13 +560,10 @@ impl InputSystem {
         out: &mut Vec<InputEvent>,
     ) {
         match event {
-            // Device change events
-            DeviceEvent::Added => {
-                out.push(InputEvent::DeviceAdded { dummy: 0 });
-            }
-            DeviceEvent::Removed => {
-                out.push(InputEvent::DeviceRemoved { dummy: 0 });
-            }
+            // Winit reports every device here, with no way to tell a mouse from a joystick.
+            // Joysticks and gamepads come through the joystick driver instead.
+            DeviceEvent::Added => {}
+            DeviceEvent::Removed => {}
 
             // Mouse Motion: unfiltered, arbitrary units
             DeviceEvent::MouseMotion { delta: (dx, dy) } => {
@@ -763,15 +657,9 @@ impl InputSystem {
                 }
             }
 
-            // Includes both joystick and mouse axis motion.
-            DeviceEvent::Motion { axis, value } => {
-                out.push(InputEvent::JoystickAxis {
-                    id: *axis,
-                    value: *value,
-                    modifiers_state: input_state.modifiers_state,
-                    window_focused: input_state.window_focused,
-                });
-            }
+            // Includes both joystick and mouse axis motion, in device units. Mouse motion is
+            // handled above and joystick axes come through the joystick driver.
+            DeviceEvent::Motion { .. } => {}
 
             // I'm not sure what this does?
             DeviceEvent::Text { .. } => {}
@@ -1088,7 +976,7 @@ mod test {
             &mut evts,
             &mut syss,
         );
-        assert!(matches!(evts[0], InputEvent::DeviceAdded { .. }));
+        assert!(evts.is_empty());
 
         let mut evts = Vec::new();
         let mut syss = Vec::new();
@@ -1098,7 +986,21 @@ mod test {
             &mut evts,
             &mut syss,
         );
-        assert!(matches!(evts[0], InputEvent::DeviceRemoved { .. }));
+        assert!(evts.is_empty());
+
+        let mut evts = Vec::new();
+        let mut syss = Vec::new();
+        InputSystem::wrap_event(
+            &Event::UserEvent(MetaEvent::Joystick(JoystickEvent::Connected {
+                device: 1,
+                name: "Stick".to_owned(),
+                guid: "0300".to_owned(),
+            })),
+            &mut input_state,
+            &mut evts,
+            &mut syss,
+        );
+        assert!(matches!(evts[0], InputEvent::DeviceAdded { device: 1, .. }));
 
         let mut evts = Vec::new();
         let mut syss = Vec::new();
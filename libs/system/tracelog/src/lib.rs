@@ -12,9 +12,14 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+mod profile_layer;
+
+pub use crate::profile_layer::ProfileLayer;
+
 use anyhow::Result;
+use log::warn;
 use nitrous::{inject_nitrous_resource, NitrousResource};
-use runtime::{Extension, Runtime};
+use runtime::{Extension, Profiler, Runtime};
 use structopt::StructOpt;
 use tracing_subscriber::{
     fmt::{format::DefaultFields, FormattedFields},
@@ -40,37 +45,46 @@ impl TraceLog {}
 
 impl Extension for TraceLog {
     fn init(runtime: &mut Runtime) -> Result<()> {
-        if let Some(opts) = runtime.maybe_resource::<TraceLogOpts>() {
-            if !opts.trace {
-                return Ok(());
-            }
+        let trace = runtime
+            .maybe_resource::<TraceLogOpts>()
+            .map(|opts| opts.trace)
+            .unwrap_or(false);
+
+        // The profile layer is always installed so that per-system timings are available
+        // to the profiler, even when we are not capturing a trace.
+        let profile_layer = ProfileLayer::new(runtime.resource::<Profiler>().sink());
 
-            let subscriber = Registry::default();
-            let subscriber = subscriber.with(tracing_error::ErrorLayer::default());
-            let chrome_layer = {
-                let (chrome_layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
-                    .name_fn(Box::new(|event_or_span| match event_or_span {
-                        tracing_chrome::EventOrSpan::Event(event) => event.metadata().name().into(),
-                        tracing_chrome::EventOrSpan::Span(span) => {
-                            if let Some(fields) =
-                                span.extensions().get::<FormattedFields<DefaultFields>>()
-                            {
-                                format!("{}: {}", span.metadata().name(), fields.fields.as_str())
-                            } else {
-                                span.metadata().name().into()
-                            }
+        let (fmt_layer, chrome_layer) = if trace {
+            let (chrome_layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+                .name_fn(Box::new(|event_or_span| match event_or_span {
+                    tracing_chrome::EventOrSpan::Event(event) => event.metadata().name().into(),
+                    tracing_chrome::EventOrSpan::Span(span) => {
+                        if let Some(fields) =
+                            span.extensions().get::<FormattedFields<DefaultFields>>()
+                        {
+                            format!("{}: {}", span.metadata().name(), fields.fields.as_str())
+                        } else {
+                            span.metadata().name().into()
                         }
-                    }))
-                    .build();
-                runtime.insert_non_send_resource(guard);
-                chrome_layer
-            };
+                    }
+                }))
+                .build();
+            runtime.insert_non_send_resource(guard);
+            (
+                Some(tracing_subscriber::fmt::Layer::default()),
+                Some(chrome_layer),
+            )
+        } else {
+            (None, None)
+        };
 
-            let fmt_layer = tracing_subscriber::fmt::Layer::default();
-            let subscriber = subscriber.with(fmt_layer);
-            let subscriber = subscriber.with(chrome_layer);
-            tracing::subscriber::set_global_default(subscriber)
-                .expect("Could not set global default tracing subscriber. If you've already set up a tracing subscriber, please disable LogPlugin from Bevy's DefaultPlugins");
+        let subscriber = Registry::default()
+            .with(tracing_error::ErrorLayer::default())
+            .with(profile_layer)
+            .with(fmt_layer)
+            .with(chrome_layer);
+        if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+            warn!("Could not set global default tracing subscriber: {}", e);
         }
 
         Ok(())
@@ -0,0 +1,158 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use runtime::ProfileSink;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Feeds per-system wall times from bevy's trace spans into the runtime Profiler. Systems
+/// are grouped by the `schedule` span that Runtime opens around each schedule run.
+pub struct ProfileLayer {
+    sink: ProfileSink,
+}
+
+impl ProfileLayer {
+    pub fn new(sink: ProfileSink) -> Self {
+        Self { sink }
+    }
+}
+
+struct ScheduleName(String);
+
+// Parallel systems are instrumented futures, so may be entered and exited more than once.
+struct SpanTiming {
+    key: String,
+    busy: Duration,
+    entered: Option<Instant>,
+}
+
+#[derive(Default)]
+struct NameVisitor(Option<String>);
+
+impl Visit for NameVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "name" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "name" && self.0.is_none() {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+// System names are full type paths; the type and function are enough to be readable.
+fn short_system_name(name: &str) -> &str {
+    let base = name.split('<').next().unwrap_or(name);
+    let offset = base
+        .rmatch_indices("::")
+        .nth(1)
+        .map(|(i, _)| i + 2)
+        .unwrap_or(0);
+    &name[offset..]
+}
+
+impl<S> Layer<S> for ProfileLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = if let Some(span) = ctx.span(id) {
+            span
+        } else {
+            return;
+        };
+        match attrs.metadata().name() {
+            "schedule" => {
+                let mut visitor = NameVisitor::default();
+                attrs.record(&mut visitor);
+                if let Some(name) = visitor.0 {
+                    span.extensions_mut().insert(ScheduleName(name));
+                }
+            }
+            "system" | "exclusive_system" => {
+                let mut visitor = NameVisitor::default();
+                attrs.record(&mut visitor);
+                let schedule = span.scope().skip(1).find_map(|parent| {
+                    parent
+                        .extensions()
+                        .get::<ScheduleName>()
+                        .map(|schedule| schedule.0.clone())
+                });
+                if let (Some(schedule), Some(name)) = (schedule, visitor.0) {
+                    span.extensions_mut().insert(SpanTiming {
+                        key: format!("{}/{}", schedule, short_system_name(&name)),
+                        busy: Duration::ZERO,
+                        entered: None,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<SpanTiming>() {
+                timing.entered = Some(Instant::now());
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<SpanTiming>() {
+                if let Some(entered) = timing.entered.take() {
+                    timing.busy += entered.elapsed();
+                }
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            if let Some(timing) = span.extensions_mut().remove::<SpanTiming>() {
+                self.sink.record_key(&timing.key, timing.busy);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_short_system_name() {
+        assert_eq!(
+            short_system_name("camera::arc_ball_camera::ArcBallController::sys_apply_input"),
+            "ArcBallController::sys_apply_input"
+        );
+        assert_eq!(short_system_name("sys_tick"), "sys_tick");
+        assert_eq!(
+            short_system_name("vehicle::Foo<vehicle::Bar>::sys_tick"),
+            "vehicle::Foo<vehicle::Bar>::sys_tick"
+        );
+    }
+}
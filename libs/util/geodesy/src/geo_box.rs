@@ -0,0 +1,174 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{Graticule, GraticuleOrigin};
+use absolute_unit::{Angle, AngleUnit, Degrees, Length, Meters};
+use anyhow::{ensure, Result};
+use physical_constants::EARTH_RADIUS;
+use std::str::FromStr;
+
+/// A box of latitude and longitude, in degrees. Tools that work with tiles in another
+/// coordinate system may use it for whatever stands in for latitude and longitude there.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GeoBox {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+}
+
+impl GeoBox {
+    pub fn new(south: f64, west: f64, north: f64, east: f64) -> Self {
+        Self {
+            south,
+            west,
+            north,
+            east,
+        }
+    }
+
+    /// A square box with its south west corner at the given point.
+    pub fn from_corner<Unit: AngleUnit>(
+        south: Angle<Unit>,
+        west: Angle<Unit>,
+        extent: Angle<Unit>,
+    ) -> Self {
+        let south = Angle::<Degrees>::from(&south).f64();
+        let west = Angle::<Degrees>::from(&west).f64();
+        let extent = Angle::<Degrees>::from(&extent).f64();
+        Self::new(south, west, south + extent, west + extent)
+    }
+
+    /// A box that holds every point on the surface within `radius` of `center`. Near the
+    /// antimeridian, the longitudes run past +/-180 rather than wrapping.
+    pub fn around<Origin: GraticuleOrigin>(
+        center: &Graticule<Origin>,
+        radius: Length<Meters>,
+    ) -> Self {
+        let lat = center.lat::<Degrees>().f64();
+        let lon = center.lon::<Degrees>().f64();
+        let dlat = (radius.f64() / EARTH_RADIUS.f64()).to_degrees();
+        let south = (lat - dlat).max(-90.);
+        let north = (lat + dlat).min(90.);
+        // Near the poles the box covers all longitudes.
+        let widest = south.abs().max(north.abs());
+        if widest >= 89.9 || dlat >= 90. {
+            return Self::new(south, -180., north, 180.);
+        }
+        let dlon = (dlat / widest.to_radians().cos()).min(180.);
+        Self::new(south, lon - dlon, north, lon + dlon)
+    }
+
+    /// This box, grown by pad on all sides.
+    pub fn grow<Unit: AngleUnit>(&self, pad: Angle<Unit>) -> Self {
+        let pad = Angle::<Degrees>::from(&pad).f64();
+        Self::new(
+            self.south - pad,
+            self.west - pad,
+            self.north + pad,
+            self.east + pad,
+        )
+    }
+
+    /// True if the boxes overlap. Boxes that only share an edge count as overlapping.
+    pub fn intersects(&self, other: &GeoBox) -> bool {
+        self.south <= other.north
+            && self.north >= other.south
+            && self.west <= other.east
+            && self.east >= other.west
+    }
+
+    /// True if other lies entirely within this box.
+    pub fn covers(&self, other: &GeoBox) -> bool {
+        self.south <= other.south
+            && self.west <= other.west
+            && self.north >= other.north
+            && self.east >= other.east
+    }
+}
+
+// Given on the command line as south,west,north,east.
+impl FromStr for GeoBox {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()?;
+        ensure!(
+            parts.len() == 4,
+            "expected a box as south,west,north,east in degrees, not '{}'",
+            s
+        );
+        let bounds = Self::new(parts[0], parts[1], parts[2], parts[3]);
+        ensure!(
+            bounds.south <= bounds.north && bounds.west <= bounds.east,
+            "box {} must have south <= north and west <= east",
+            s
+        );
+        Ok(bounds)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeoSurface;
+    use absolute_unit::{arcseconds, degrees, kilometers, meters};
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let bounds = "10,-20.5,11,-19.5".parse::<GeoBox>()?;
+        assert_eq!(bounds, GeoBox::new(10., -20.5, 11., -19.5));
+        assert_eq!(" 10, -20.5 ,11,-19.5".parse::<GeoBox>()?, bounds);
+        assert!("10,-20,11".parse::<GeoBox>().is_err());
+        assert!("10,-20,11,x".parse::<GeoBox>().is_err());
+        assert!("11,-20,10,-19".parse::<GeoBox>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_intersects() {
+        let bounds = GeoBox::new(10., -20.5, 11., -19.5);
+        let tile = GeoBox::from_corner(degrees!(11.5), degrees!(-20), degrees!(1));
+        assert_eq!(tile, GeoBox::new(11.5, -20., 12.5, -19.));
+        assert!(!bounds.intersects(&tile));
+        assert!(bounds.intersects(&tile.grow(arcseconds!(degrees!(1)))));
+        assert!(bounds.intersects(&GeoBox::new(11., -19.5, 12., -18.5)));
+    }
+
+    #[test]
+    fn test_around() {
+        let equator = Graticule::<GeoSurface>::new(degrees!(0), degrees!(179.5), meters!(0));
+        let bounds = GeoBox::around(&equator, meters!(kilometers!(111.2)));
+        assert_relative_eq!(bounds.north, 1., epsilon = 0.01);
+        assert_relative_eq!(bounds.south, -1., epsilon = 0.01);
+        assert_relative_eq!(bounds.east, 180.5, epsilon = 0.01);
+
+        let pole = Graticule::<GeoSurface>::new(degrees!(89.5), degrees!(10), meters!(0));
+        let bounds = GeoBox::around(&pole, meters!(kilometers!(111.2)));
+        assert_relative_eq!(bounds.north, 90.);
+        assert_eq!((bounds.west, bounds.east), (-180., 180.));
+    }
+
+    #[test]
+    fn test_covers() {
+        let bounds = GeoBox::new(-1., -1., 2., 2.);
+        assert!(bounds.covers(&GeoBox::new(0., 0., 1., 1.)));
+        assert!(bounds.covers(&bounds));
+        assert!(!bounds.covers(&GeoBox::new(0., 0., 1., 3.)));
+    }
+}
@@ -13,11 +13,13 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 pub(crate) mod cartesian;
+pub(crate) mod geo_box;
 pub(crate) mod graticule;
 pub(crate) mod origin;
 
 pub use crate::{
     cartesian::{Cartesian, CartesianOrigin},
+    geo_box::GeoBox,
     graticule::{Graticule, GraticuleOrigin},
     origin::{geo_center::GeoCenter, geo_surface::GeoSurface, target::Target},
 };
@@ -0,0 +1,117 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::Ray;
+use nalgebra::Point3;
+
+// Fraction of the current clearance that we are willing to step. Clearance is measured
+// vertically, so a full step could carry us through the side of a steep slope.
+const CLEARANCE_STEP_FRACTION: f64 = 0.5;
+
+// Number of bisections to refine a hit once we know it is between two samples.
+const REFINE_ITERATIONS: usize = 24;
+
+/// March along `ray` against a height field, returning the distance along the ray to the
+/// first point where `clearance` is no longer positive, if that happens within `max_distance`.
+///
+/// `clearance` gives the height of a point above the surface; negative when underground. The
+/// march steps by a fraction of the clearance, but never less than `min_step`, so features that
+/// are thinner than `min_step` may be missed. Distances are in the units of the ray, with the
+/// ray direction normalized. Errors from `clearance` are passed through.
+pub fn height_field_vs_ray<E, F>(
+    ray: &Ray,
+    max_distance: f64,
+    min_step: f64,
+    mut clearance: F,
+) -> Result<Option<f64>, E>
+where
+    F: FnMut(&Point3<f64>) -> Result<f64, E>,
+{
+    assert!(min_step > 0_f64, "height field march needs a positive step");
+    let direction = ray.direction().normalize();
+    let at = |t: f64| ray.origin() + direction * t;
+
+    let mut t0 = 0_f64;
+    let mut c0 = clearance(ray.origin())?;
+    if c0 <= 0_f64 {
+        return Ok(Some(0_f64));
+    }
+    while t0 < max_distance {
+        let t1 = (t0 + (c0 * CLEARANCE_STEP_FRACTION).max(min_step)).min(max_distance);
+        let c1 = clearance(&at(t1))?;
+        if c1 <= 0_f64 {
+            // The surface is crossed somewhere in (t0, t1]; bisect to find it.
+            let (mut lo, mut hi) = (t0, t1);
+            for _ in 0..REFINE_ITERATIONS {
+                let mid = (lo + hi) / 2_f64;
+                if clearance(&at(mid))? <= 0_f64 {
+                    hi = mid;
+                } else {
+                    lo = mid;
+                }
+            }
+            return Ok(Some(hi));
+        }
+        t0 = t1;
+        c0 = c1;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nalgebra::Vector3;
+    use std::convert::Infallible;
+
+    // A flat plain at y = 0, with a 10 unit high wall between x = 50 and x = 60.
+    fn plain_with_wall(p: &Point3<f64>) -> Result<f64, Infallible> {
+        let ground = if p.x >= 50_f64 && p.x <= 60_f64 {
+            10_f64
+        } else {
+            0_f64
+        };
+        Ok(p.y - ground)
+    }
+
+    #[test]
+    fn test_ray_hits_wall() {
+        let ray = Ray::new(Point3::new(0f64, 5f64, 0f64), Vector3::x());
+        let hit = height_field_vs_ray(&ray, 100f64, 0.25f64, plain_with_wall).unwrap();
+        assert!((hit.unwrap() - 50f64).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_ray_clears_wall() {
+        let ray = Ray::new(
+            Point3::new(0f64, 11f64, 0f64),
+            Vector3::new(1f64, 0f64, 0f64),
+        );
+        let hit = height_field_vs_ray(&ray, 100f64, 0.25f64, plain_with_wall).unwrap();
+        assert!(hit.is_none());
+
+        // Pointed down at the plain, the ray passes over the wall and lands beyond it.
+        let ray = Ray::new(
+            Point3::new(0f64, 30f64, 0f64),
+            Vector3::new(10f64, -1f64, 0f64),
+        );
+        let hit = height_field_vs_ray(&ray, 1000f64, 0.25f64, plain_with_wall).unwrap();
+        let expect = (300f64 * 300f64 + 30f64 * 30f64).sqrt();
+        assert!((hit.unwrap() - expect).abs() < 0.001);
+
+        // But not if the segment ends before it gets there.
+        let hit = height_field_vs_ray(&ray, 200f64, 0.25f64, plain_with_wall).unwrap();
+        assert!(hit.is_none());
+    }
+}
@@ -13,9 +13,11 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 mod circle_plane;
+mod height_field_ray;
 mod sphere_plane;
 mod sphere_ray;
 
 pub use circle_plane::{circle_vs_plane, CirclePlaneIntersection};
+pub use height_field_ray::height_field_vs_ray;
 pub use sphere_plane::{sphere_vs_plane, PlaneSide, SpherePlaneIntersection};
 pub use sphere_ray::sphere_vs_ray;
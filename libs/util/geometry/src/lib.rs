@@ -20,6 +20,7 @@ mod axis_aligned_bounding_box;
 mod circle;
 mod cylinder;
 pub mod intersect;
+pub mod planar;
 mod plane;
 mod ray;
 mod sphere;
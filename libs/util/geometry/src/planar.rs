@@ -0,0 +1,122 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use std::f64::consts::PI;
+
+// Helpers for points on a plane, given as [x, y] pairs. These are mostly used for small
+// areas of the planet, flattened with `local_offset`.
+
+/// Offset of `p` from `origin` on a plane tangent to a sphere of `radius` at `origin`, as
+/// [east, north] in the units of `radius`. Points are given as [latitude, longitude] in
+/// radians. This is a flat approximation, which is fine for a few hundred miles, but not for
+/// anything spanning a large fraction of the globe.
+pub fn local_offset(origin: [f64; 2], p: [f64; 2], radius: f64) -> [f64; 2] {
+    let mut dlon = (p[1] - origin[1]) % (2. * PI);
+    if dlon > PI {
+        dlon -= 2. * PI;
+    } else if dlon < -PI {
+        dlon += 2. * PI;
+    }
+    [dlon * radius * origin[0].cos(), (p[0] - origin[0]) * radius]
+}
+
+/// Distance from `p` to the nearest point on the segment from `a` to `b`.
+pub fn segment_distance(a: [f64; 2], b: [f64; 2], p: [f64; 2]) -> f64 {
+    let ab = [b[0] - a[0], b[1] - a[1]];
+    let ap = [p[0] - a[0], p[1] - a[1]];
+    let len2 = ab[0] * ab[0] + ab[1] * ab[1];
+    let t = if len2 > 0. {
+        ((ap[0] * ab[0] + ap[1] * ab[1]) / len2).clamp(0., 1.)
+    } else {
+        0.
+    };
+    (ap[0] - t * ab[0]).hypot(ap[1] - t * ab[1])
+}
+
+/// True if `p` is inside the polygon. The last point joins back to the first. Fewer than
+/// three points do not enclose anything, so contain nothing.
+pub fn polygon_contains(points: &[[f64; 2]], p: [f64; 2]) -> bool {
+    if points.len() < 3 {
+        return false;
+    }
+    let mut inside = false;
+    let mut j = points.len() - 1;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[j]);
+        if (a[1] > p[1]) != (b[1] > p[1])
+            && p[0] < (b[0] - a[0]) * (p[1] - a[1]) / (b[1] - a[1]) + a[0]
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Distance from `p` to the edge of the polygon; negative inside. Polygons with fewer than
+/// three points are treated as infinitely far away.
+pub fn polygon_distance(points: &[[f64; 2]], p: [f64; 2]) -> f64 {
+    if points.len() < 3 {
+        return f64::MAX;
+    }
+    let mut distance = f64::MAX;
+    let mut j = points.len() - 1;
+    for i in 0..points.len() {
+        distance = distance.min(segment_distance(points[i], points[j], p));
+        j = i;
+    }
+    if polygon_contains(points, p) {
+        -distance
+    } else {
+        distance
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_segment_distance() {
+        assert_relative_eq!(segment_distance([0., 0.], [10., 0.], [5., 3.]), 3.);
+        assert_relative_eq!(segment_distance([0., 0.], [10., 0.], [13., 4.]), 5.);
+        assert_relative_eq!(segment_distance([1., 1.], [1., 1.], [4., 5.]), 5.);
+    }
+
+    #[test]
+    fn test_polygon_distance() {
+        let square = [[0., 0.], [0., 10.], [10., 10.], [10., 0.]];
+        assert!(polygon_contains(&square, [5., 5.]));
+        assert!(!polygon_contains(&square, [15., 5.]));
+        assert_relative_eq!(polygon_distance(&square, [2., 5.]), -2.);
+        assert_relative_eq!(polygon_distance(&square, [15., 5.]), 5.);
+    }
+
+    #[test]
+    fn test_degenerate_polygon() {
+        assert!(!polygon_contains(&[], [0., 0.]));
+        assert!(!polygon_contains(&[[0., 0.], [1., 1.]], [0.5, 0.5]));
+        assert_eq!(polygon_distance(&[], [0., 0.]), f64::MAX);
+        assert_eq!(polygon_distance(&[[0., 0.]], [0., 0.]), f64::MAX);
+    }
+
+    #[test]
+    fn test_local_offset_wraps_longitude() {
+        let origin = [0., PI - 0.001];
+        let east = local_offset(origin, [0.001, -PI + 0.001], 1000.);
+        assert_relative_eq!(east[0], 2., epsilon = 1e-9);
+        assert_relative_eq!(east[1], 1., epsilon = 1e-9);
+    }
+}
@@ -0,0 +1,327 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{heap::HeapRef, value::Value};
+use std::{collections::BTreeMap, fmt::Write};
+
+// Everything in this file is filled in by nitrous_injector at macro expansion time, so that
+// tooling can describe the scripting surface without having to construct any of it.
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ScriptApiKind {
+    Resource,
+    Component,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ArgDoc {
+    pub name: &'static str,
+    pub ty: &'static str,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MethodDoc {
+    pub name: &'static str,
+    pub args: &'static [ArgDoc],
+    /// Empty if the method returns nothing of interest.
+    pub ret: &'static str,
+    pub fallible: bool,
+    pub doc: &'static str,
+}
+
+impl MethodDoc {
+    pub fn signature(&self) -> String {
+        let mut out = format!(
+            "{}({})",
+            self.name,
+            self.args
+                .iter()
+                .map(|arg| format!("{}: {}", arg.name, arg.ty))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        if !self.ret.is_empty() {
+            write!(out, " -> {}", self.ret).unwrap();
+        }
+        out
+    }
+
+    pub fn help(&self) -> String {
+        let mut out = self.signature();
+        if self.fallible {
+            out += "  [may fail]";
+        }
+        if !self.doc.is_empty() {
+            out += "\n";
+            out += self.doc;
+        }
+        out
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PropertyDoc {
+    pub name: &'static str,
+    pub ty: &'static str,
+    pub doc: &'static str,
+}
+
+/// Submitted by `#[derive(NitrousResource)]` and `#[derive(NitrousComponent)]`.
+#[derive(Debug)]
+pub struct ScriptTypeDoc {
+    pub type_name: &'static str,
+    /// The `module_path!()` where the type was declared.
+    pub module_path: &'static str,
+    pub kind: ScriptApiKind,
+    /// The `#[Name]` of a component. Resources are named when inserted, so this is empty.
+    pub script_name: &'static str,
+    pub doc: &'static str,
+    pub properties: &'static [PropertyDoc],
+}
+inventory::collect!(ScriptTypeDoc);
+
+/// Submitted by `#[inject_nitrous_resource]` and `#[inject_nitrous_component]`.
+#[derive(Debug)]
+pub struct ScriptMethodsDoc {
+    pub type_name: &'static str,
+    /// The `module_path!()` of the impl block, which may differ from the type's module.
+    pub module_path: &'static str,
+    pub methods: &'static [MethodDoc],
+}
+inventory::collect!(ScriptMethodsDoc);
+
+/// The full description of one scriptable type.
+#[derive(Clone, Debug)]
+pub struct ScriptApi {
+    pub type_name: &'static str,
+    pub module_path: &'static str,
+    pub kind: ScriptApiKind,
+    pub script_name: &'static str,
+    pub doc: &'static str,
+    pub properties: Vec<PropertyDoc>,
+    pub methods: Vec<MethodDoc>,
+}
+
+impl ScriptApi {
+    /// Every scriptable type linked into the current binary, resources first.
+    pub fn collect() -> Vec<ScriptApi> {
+        let (apis, collisions) = Self::merge(
+            inventory::iter::<ScriptTypeDoc>,
+            inventory::iter::<ScriptMethodsDoc>,
+        );
+        for collision in collisions {
+            log::warn!("{}", collision);
+        }
+        apis
+    }
+
+    // Method impls may live in a different module than their type, so types are matched
+    // by crate and name. Types of the same name in different crates are kept apart; two in
+    // the same crate cannot be told apart, so are reported instead of silently merged.
+    fn merge<'a>(
+        type_docs: impl IntoIterator<Item = &'a ScriptTypeDoc>,
+        method_docs: impl IntoIterator<Item = &'a ScriptMethodsDoc>,
+    ) -> (Vec<ScriptApi>, Vec<String>) {
+        let mut types: BTreeMap<_, ScriptApi> = BTreeMap::new();
+        let mut collisions = Vec::new();
+        for ty in type_docs {
+            let key = (crate_of(ty.module_path), ty.type_name);
+            if let Some(prior) = types.get(&key) {
+                collisions.push(format!(
+                    "script type {} is declared in both {} and {}; only the first is documented",
+                    ty.type_name, prior.module_path, ty.module_path
+                ));
+                continue;
+            }
+            types.insert(
+                key,
+                ScriptApi {
+                    type_name: ty.type_name,
+                    module_path: ty.module_path,
+                    kind: ty.kind,
+                    script_name: ty.script_name,
+                    doc: ty.doc,
+                    properties: ty.properties.to_vec(),
+                    methods: Vec::new(),
+                },
+            );
+        }
+        for methods in method_docs {
+            let key = (crate_of(methods.module_path), methods.type_name);
+            if let Some(api) = types.get_mut(&key) {
+                api.methods.extend_from_slice(methods.methods);
+            }
+        }
+        let mut out = types.into_values().collect::<Vec<_>>();
+        for api in &mut out {
+            api.methods.sort_by_key(|m| m.name);
+        }
+        out.sort_by_key(|api| (api.kind, api.script_name, api.type_name, api.module_path));
+        (out, collisions)
+    }
+
+    pub fn to_markdown(&self, out: &mut String) {
+        match self.kind {
+            ScriptApiKind::Resource => writeln!(out, "### `{}`\n", self.type_name),
+            ScriptApiKind::Component => writeln!(
+                out,
+                "### `@entity.{}` ({})\n",
+                self.script_name, self.type_name
+            ),
+        }
+        .unwrap();
+        if !self.doc.is_empty() {
+            writeln!(out, "{}\n", self.doc).unwrap();
+        }
+        if !self.properties.is_empty() {
+            writeln!(out, "| Property | Type | Description |").unwrap();
+            writeln!(out, "|----------|------|-------------|").unwrap();
+            for prop in &self.properties {
+                writeln!(
+                    out,
+                    "| `{}` | {} | {} |",
+                    prop.name,
+                    prop.ty,
+                    prop.doc.replace('\n', " ")
+                )
+                .unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+        for method in &self.methods {
+            writeln!(out, "* `{}`", method.signature()).unwrap();
+            if method.fallible {
+                writeln!(out, "  <br>*May fail.*").unwrap();
+            }
+            for line in method.doc.lines() {
+                writeln!(out, "  {}", line).unwrap();
+            }
+        }
+        writeln!(out).unwrap();
+    }
+}
+
+fn crate_of(module_path: &str) -> &str {
+    module_path.split("::").next().unwrap_or(module_path)
+}
+
+/// Render the whole scripting surface as a Markdown document.
+pub fn script_api_markdown() -> String {
+    let mut out = String::from("# Nitrogen Script API\n\n");
+    let mut kind = None;
+    for api in ScriptApi::collect() {
+        if kind != Some(api.kind) {
+            kind = Some(api.kind);
+            out += match api.kind {
+                ScriptApiKind::Resource => "## Resources\n\n",
+                ScriptApiKind::Component => "## Components\n\n",
+            };
+        }
+        api.to_markdown(&mut out);
+    }
+    out
+}
+
+/// Produce help text for a value reached from a script, e.g. `help(camera.increase_fov)`.
+pub fn describe_value(value: &Value, heap: HeapRef) -> Option<String> {
+    let world = heap.world();
+    match value {
+        Value::ResourceMethod(lookup, name) => lookup
+            .get_ref(world)?
+            .method_docs()
+            .iter()
+            .find(|m| m.name == name)
+            .map(|m| m.help()),
+        Value::ComponentMethod(entity, lookup, name) => lookup
+            .get_ref(*entity, world)?
+            .method_docs()
+            .iter()
+            .find(|m| m.name == name)
+            .map(|m| m.help()),
+        Value::Resource(lookup) => {
+            let resource = lookup.get_ref(world)?;
+            Some(describe_members(
+                &resource.resource_type_name(),
+                resource.property_docs(),
+                resource.method_docs(),
+            ))
+        }
+        Value::Component(entity, lookup) => {
+            let component = lookup.get_ref(*entity, world)?;
+            Some(describe_members(
+                component.component_name(),
+                component.property_docs(),
+                component.method_docs(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn describe_members(name: &str, properties: &[PropertyDoc], methods: &[MethodDoc]) -> String {
+    let mut out = format!("{}:", name);
+    for prop in properties {
+        write!(out, "\n  {}: {}", prop.name, prop.ty).unwrap();
+    }
+    for method in methods {
+        write!(out, "\n  {}", method.signature()).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn type_doc(type_name: &'static str, module_path: &'static str) -> ScriptTypeDoc {
+        ScriptTypeDoc {
+            type_name,
+            module_path,
+            kind: ScriptApiKind::Resource,
+            script_name: "",
+            doc: "",
+            properties: &[],
+        }
+    }
+
+    const FROB: &[MethodDoc] = &[MethodDoc {
+        name: "frob",
+        args: &[],
+        ret: "",
+        fallible: false,
+        doc: "",
+    }];
+
+    #[test]
+    fn test_same_name_in_different_crates() {
+        let types = [
+            type_doc("Config", "alpha::config"),
+            type_doc("Config", "beta"),
+            type_doc("Config", "alpha::other"),
+        ];
+        let methods = [ScriptMethodsDoc {
+            type_name: "Config",
+            module_path: "beta::methods",
+            methods: FROB,
+        }];
+        let (apis, collisions) = ScriptApi::merge(&types, &methods);
+        assert_eq!(apis.len(), 2);
+        let alpha = apis.iter().find(|api| api.module_path == "alpha::config");
+        assert!(alpha.unwrap().methods.is_empty());
+        let beta = apis.iter().find(|api| api.module_path == "beta").unwrap();
+        assert_eq!(beta.methods.len(), 1);
+        assert_eq!(collisions.len(), 1);
+        assert!(collisions[0].contains("alpha::other"));
+    }
+}
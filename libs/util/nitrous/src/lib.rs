@@ -12,6 +12,7 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+mod api;
 mod ast;
 mod exec;
 mod heap;
@@ -26,6 +27,10 @@ pub mod reexport {
 }
 
 pub use crate::{
+    api::{
+        describe_value, script_api_markdown, ArgDoc, MethodDoc, PropertyDoc, ScriptApi,
+        ScriptApiKind, ScriptMethodsDoc, ScriptTypeDoc,
+    },
     ast::NitrousAst,
     exec::{ExecutionContext, NitrousExecutor, YieldState},
     heap::{EntityName, Heap, HeapMut, HeapRef, NamedEntityMut},
@@ -40,6 +45,7 @@ pub use nitrous_injector::{
 };
 // Injector deps
 pub use anyhow;
+pub use inventory;
 pub use log;
 pub use ordered_float;
 
@@ -12,7 +12,11 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
-use crate::{heap::HeapMut, value::Value};
+use crate::{
+    api::{MethodDoc, PropertyDoc},
+    heap::HeapMut,
+    value::Value,
+};
 use anyhow::{anyhow, ensure, Result};
 use bevy_ecs::{prelude::*, system::Resource};
 use std::{collections::HashMap, fmt::Debug, sync::Arc};
@@ -32,6 +36,8 @@ pub trait ScriptResource: Resource + 'static {
     fn put(&mut self, name: &str, value: Value) -> Result<()>;
     fn get(&self, name: &str) -> Result<Value>;
     fn names(&self) -> Vec<&str>;
+    fn method_docs(&self) -> &'static [MethodDoc];
+    fn property_docs(&self) -> &'static [PropertyDoc];
 }
 
 /// Bridges from a name (as in a script) to ScriptResouce. Effectively it stores the T
@@ -111,6 +117,8 @@ pub trait ScriptComponent: Send + Sync + 'static {
     fn put(&mut self, entity: Entity, name: &str, value: Value) -> Result<()>;
     fn get(&self, entity: Entity, name: &str) -> Result<Value>;
     fn names(&self) -> Vec<&str>;
+    fn method_docs(&self) -> &'static [MethodDoc];
+    fn property_docs(&self) -> &'static [PropertyDoc];
 }
 
 type ComponentLookupRefFunc =
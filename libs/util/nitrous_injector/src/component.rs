@@ -13,7 +13,8 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 use crate::injector_common::{
-    find_properties_in_struct, make_property_get_arm, make_property_put_arm, Scalar,
+    doc_comment, find_properties_in_struct, make_property_doc, make_property_get_arm,
+    make_property_put_arm, Scalar,
 };
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
@@ -26,7 +27,8 @@ pub(crate) struct ComponentModel {
     component_name: String,
     ident: Ident,
     generics: Generics,
-    properties: Vec<(Ident, Scalar)>,
+    doc: String,
+    properties: Vec<(Ident, Scalar, String)>,
     getter_arms: Vec<Arm>,
     putter_arms: Vec<Arm>,
 }
@@ -37,6 +39,7 @@ pub(crate) fn parse(input: TokenStream) -> Ast {
 
 pub(crate) fn analyze(ast: Ast) -> ComponentModel {
     let properties = find_properties_in_struct(&ast);
+    let doc = doc_comment(&ast.attrs);
     let mut component_name = String::new();
     for attr in ast.attrs {
         if attr.path.is_ident("Name") {
@@ -47,6 +50,7 @@ pub(crate) fn analyze(ast: Ast) -> ComponentModel {
     ComponentModel {
         component_name,
         ident: ast.ident.clone(),
+        doc,
         generics: ast.generics,
         properties,
         getter_arms: Vec::new(),
@@ -58,12 +62,12 @@ pub(crate) fn lower(mut model: ComponentModel) -> ComponentModel {
     model.getter_arms = model
         .properties
         .iter()
-        .map(|(name, ty)| make_property_get_arm(&name.to_string(), name, ty))
+        .map(|(name, ty, _)| make_property_get_arm(&name.to_string(), name, ty))
         .collect::<Vec<Arm>>();
     model.putter_arms = model
         .properties
         .iter()
-        .map(|(name, ty)| make_property_put_arm(&name.to_string(), name, ty))
+        .map(|(name, ty, _)| make_property_put_arm(&name.to_string(), name, ty))
         .collect::<Vec<Arm>>();
     model
 }
@@ -72,12 +76,36 @@ pub(crate) fn codegen(model: ComponentModel) -> TokenStream {
     let ComponentModel {
         ident,
         component_name,
+        doc,
+        properties,
         getter_arms,
         putter_arms,
         ..
     } = model;
     let (impl_generics, ty_generics, where_clause) = model.generics.split_for_impl();
+    let property_docs = properties
+        .iter()
+        .map(|(name, ty, doc)| make_property_doc(name, ty, doc))
+        .collect::<Vec<_>>();
+    let registration = if model.generics.params.is_empty() {
+        quote! {
+            ::nitrous::inventory::submit! {
+                ::nitrous::ScriptTypeDoc {
+                    type_name: stringify!(#ident),
+                    module_path: module_path!(),
+                    kind: ::nitrous::ScriptApiKind::Component,
+                    script_name: #component_name,
+                    doc: #doc,
+                    properties: &[#(#property_docs),*],
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
     proc_macro::TokenStream::from(quote! {
+        #registration
+
         impl #impl_generics ::nitrous::ScriptComponent for #ident #ty_generics #where_clause
         {
             fn component_name(&self) -> &'static str {
@@ -114,6 +142,14 @@ pub(crate) fn codegen(model: ComponentModel) -> TokenStream {
             fn names(&self) -> Vec<&str> {
                 self.__names_inner__()
             }
+
+            fn method_docs(&self) -> &'static [::nitrous::MethodDoc] {
+                Self::__method_docs__()
+            }
+
+            fn property_docs(&self) -> &'static [::nitrous::PropertyDoc] {
+                &[#(#property_docs),*]
+            }
         }
     })
 }
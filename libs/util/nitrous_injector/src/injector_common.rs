@@ -18,8 +18,8 @@ use std::borrow::Borrow;
 use syn::{
     parse2,
     visit::{self, Visit},
-    Arm, Data, DeriveInput, Expr, FnArg, GenericArgument, Ident, ImplItemMethod, ItemFn, ItemImpl,
-    Pat, PathArguments, ReturnType, Type, TypePath,
+    Arm, Attribute, Data, DeriveInput, Expr, FnArg, GenericArgument, Ident, ImplItemMethod, ItemFn,
+    ItemImpl, Lit, Meta, Pat, PathArguments, ReturnType, Type, TypePath,
 };
 
 pub(crate) fn make_augment_method(item: ItemFn) -> TokenStream2 {
@@ -31,9 +31,12 @@ pub(crate) fn make_augment_method(item: ItemFn) -> TokenStream2 {
 
 pub(crate) type Ast = ItemImpl;
 
+/// Name, arguments, return type and doc comment of a #[method].
+pub(crate) type MethodDef = (Ident, Vec<ArgDef>, RetType, String);
+
 pub(crate) struct InjectModel {
     pub(crate) item: ItemImpl,
-    pub(crate) methods: Vec<(Ident, Vec<ArgDef>, RetType)>,
+    pub(crate) methods: Vec<MethodDef>,
     pub(crate) _getters: Vec<Ident>,
     pub(crate) _setters: Vec<Ident>,
 }
@@ -56,6 +59,7 @@ pub(crate) struct Ir {
     pub(crate) put_arms: Vec<Arm>,
     pub(crate) names: Vec<String>,
     pub(crate) list_items: Vec<String>,
+    pub(crate) method_docs: Vec<TokenStream2>,
 }
 
 impl Ir {
@@ -67,21 +71,21 @@ impl Ir {
             put_arms: Vec::new(),
             names: Vec::new(),
             list_items: Vec::new(),
+            method_docs: Vec::new(),
         }
     }
 }
 
-pub(crate) fn lower_methods<F>(
-    methods: Vec<(Ident, Vec<ArgDef>, RetType)>,
-    ir: &mut Ir,
-    make_get_arm: F,
-) where
+pub(crate) fn lower_methods<F>(methods: Vec<MethodDef>, ir: &mut Ir, make_get_arm: F)
+where
     F: Fn(&str, &str) -> Arm,
 {
     let type_name = ir.item.self_ty.clone().into_token_stream().to_string();
-    for (ident, args, ret) in methods {
+    for (ident, args, ret, doc) in methods {
         let name = format!("{}", ident);
         ir.names.push(name.clone());
+        ir.method_docs
+            .push(lower_method_doc(&name, &args, &ret, &doc));
         ir.list_items.push(format!(
             "{}({})",
             name,
@@ -101,6 +105,45 @@ pub(crate) fn lower_methods<F>(
     }
 }
 
+fn lower_method_doc(name: &str, args: &[ArgDef], ret: &RetType, doc: &str) -> TokenStream2 {
+    let arg_docs = args
+        .iter()
+        .filter_map(|arg| {
+            let arg_name = arg.name.to_string();
+            arg.ty
+                .script_type()
+                .map(|ty| quote! { ::nitrous::ArgDoc { name: #arg_name, ty: #ty } })
+        })
+        .collect::<Vec<_>>();
+    let ret_ty = ret.script_type();
+    let fallible = matches!(ret, RetType::ResultRaw(_));
+    quote! {
+        ::nitrous::MethodDoc {
+            name: #name,
+            args: &[#(#arg_docs),*],
+            ret: #ret_ty,
+            fallible: #fallible,
+            doc: #doc,
+        }
+    }
+}
+
+/// Collect the text of any doc comments on an item.
+pub(crate) fn doc_comment(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(nv)) => match nv.lit {
+                Lit::Str(s) => Some(s.value().trim().to_owned()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 pub(crate) fn lower_list<F>(ir: &mut Ir, make_get_arm: F)
 where
     F: Fn(&str, &str) -> Arm,
@@ -264,7 +307,7 @@ fn lower_method_call(name: &str, item: Ident, arg_exprs: &[Expr], ret: RetType)
     .unwrap()
 }
 
-pub(crate) fn find_properties_in_struct(ast: &DeriveInput) -> Vec<(Ident, Scalar)> {
+pub(crate) fn find_properties_in_struct(ast: &DeriveInput) -> Vec<(Ident, Scalar, String)> {
     let mut properties = Vec::new();
     if let Data::Struct(data) = &ast.data {
         for field in &data.fields {
@@ -272,7 +315,7 @@ pub(crate) fn find_properties_in_struct(ast: &DeriveInput) -> Vec<(Ident, Scalar
                 if attr.path.is_ident("property") {
                     let ident = field.ident.as_ref().unwrap().to_owned();
                     let scalar = Scalar::from_type(&field.ty);
-                    properties.push((ident, scalar));
+                    properties.push((ident, scalar, doc_comment(&field.attrs)));
                 }
             }
         }
@@ -280,6 +323,12 @@ pub(crate) fn find_properties_in_struct(ast: &DeriveInput) -> Vec<(Ident, Scalar
     properties
 }
 
+pub(crate) fn make_property_doc(name: &Ident, ty: &Scalar, doc: &str) -> TokenStream2 {
+    let name_str = name.to_string();
+    let ty_str = ty.script_type().expect("property type");
+    quote! { ::nitrous::PropertyDoc { name: #name_str, ty: #ty_str, doc: #doc } }
+}
+
 pub(crate) fn make_property_get_arm(name_str: &str, name: &Ident, ty: &Scalar) -> Arm {
     parse2(match ty {
         Scalar::Boolean => quote! { #name_str => { Ok(::nitrous::Value::Boolean(self.#name)) } },
@@ -311,7 +360,7 @@ pub(crate) struct ArgDef {
 }
 
 pub(crate) struct CollectorVisitor {
-    pub(crate) methods: Vec<(Ident, Vec<ArgDef>, RetType)>,
+    pub(crate) methods: Vec<MethodDef>,
     pub(crate) getters: Vec<Ident>,
     pub(crate) setters: Vec<Ident>,
 }
@@ -350,7 +399,8 @@ impl<'ast> Visit<'ast> for CollectorVisitor {
                     })
                     .collect::<Vec<_>>();
                 let ret = RetType::from_return_type(&node.sig.output);
-                self.methods.push((node.sig.ident.clone(), args, ret));
+                let doc = doc_comment(&node.attrs);
+                self.methods.push((node.sig.ident.clone(), args, ret, doc));
                 break;
             } else if attr.path.is_ident("getter") {
                 self.getters.push(node.sig.ident.clone());
@@ -381,6 +431,22 @@ pub(crate) enum Scalar {
 }
 
 impl Scalar {
+    /// The name of the type as seen from a script, or None if the argument is not
+    /// provided by the script (e.g. the heap).
+    pub(crate) fn script_type(&self) -> Option<&'static str> {
+        Some(match self {
+            Scalar::Boolean => "bool",
+            Scalar::Integer => "int",
+            Scalar::Float => "float",
+            Scalar::String | Scalar::StrRef => "string",
+            Scalar::GraticuleSurface | Scalar::GraticuleTarget => "graticule",
+            Scalar::Value => "any",
+            Scalar::Unit => "",
+            Scalar::Selfish => "self",
+            Scalar::HeapMut | Scalar::HeapRef => return None,
+        })
+    }
+
     pub(crate) fn from_type(ty: &Type) -> Self {
         if let Type::Path(p) = ty {
             Self::from_type_path(p)
@@ -465,6 +531,13 @@ pub(crate) enum RetType {
 }
 
 impl RetType {
+    pub(crate) fn script_type(&self) -> &'static str {
+        match self {
+            RetType::Nothing => "",
+            RetType::Raw(s) | RetType::ResultRaw(s) => s.script_type().unwrap_or_default(),
+        }
+    }
+
     pub(crate) fn from_return_type(ret_ty: &ReturnType) -> Self {
         match ret_ty {
             ReturnType::Default => RetType::Nothing,
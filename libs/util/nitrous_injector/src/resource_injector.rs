@@ -54,9 +54,24 @@ pub(crate) fn codegen(ir: Ir) -> TokenStream {
         put_arms,
         names,
         list_items,
+        method_docs,
     } = ir;
     let ty = &item.self_ty;
     let (impl_generics, _ty_generics, where_clause) = item.generics.split_for_impl();
+    // Generic types cannot be registered statically, but still answer method_docs.
+    let registration = if item.generics.params.is_empty() {
+        quote! {
+            ::nitrous::inventory::submit! {
+                ::nitrous::ScriptMethodsDoc {
+                    type_name: stringify!(#ty),
+                    module_path: module_path!(),
+                    methods: &[#(#method_docs),*],
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
     let ts2 = quote! {
         impl #impl_generics #ty #where_clause {
             fn __call_method_inner__(&mut self, name: &str, args: &[::nitrous::Value], heap: ::nitrous::HeapMut) -> ::nitrous::anyhow::Result<::nitrous::CallResult> {
@@ -98,8 +113,14 @@ pub(crate) fn codegen(ir: Ir) -> TokenStream {
                 let out = items.join("\n");
                 ::nitrous::CallResult::Val(::nitrous::Value::String(out))
             }
+
+            fn __method_docs__() -> &'static [::nitrous::MethodDoc] {
+                &[#(#method_docs),*]
+            }
         }
 
+        #registration
+
         #item
     };
     TokenStream::from(ts2)
@@ -0,0 +1,208 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{anyhow, Result};
+use nitrous::{
+    inject_nitrous_resource, method, HeapMut, LocalNamespace, NitrousResource, NitrousScript, Value,
+};
+use std::{collections::HashMap, sync::Arc};
+
+/// Returned by `on` so that a handler can later be removed with `off`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct EventHandlerId(i64);
+
+struct EventHandler {
+    id: EventHandlerId,
+    script: NitrousScript,
+}
+
+/// Named events that may be raised by Rust systems or by scripts and handled by scripts.
+///
+/// Events are queued when emitted and delivered at the start of `SimStage::RunScript`, so
+/// an event emitted in `Simulate` is handled on the following tick. Each handler runs as a
+/// fresh script with `event` set to the event name and with the emitted arguments in its
+/// locals: named arguments from Rust keep their names and positional arguments from
+/// scripts are bound as `arg0`, `arg1`, and so on.
+#[derive(Default, NitrousResource)]
+pub struct EventBus {
+    next_id: i64,
+    handlers: HashMap<String, Vec<EventHandler>>,
+    pending: Vec<(String, LocalNamespace)>,
+}
+
+impl std::fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventBus")
+            .field("handlers", &self.handlers.keys().collect::<Vec<_>>())
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+#[inject_nitrous_resource]
+impl EventBus {
+    /// Queue an event for delivery, with the given locals visible to every handler.
+    pub fn emit<S: Into<String>>(&mut self, name: S, args: LocalNamespace) {
+        self.pending.push((name.into(), args));
+    }
+
+    /// Register `script` to run whenever the event `name` is emitted.
+    pub fn subscribe(&mut self, name: &str, script: NitrousScript) -> EventHandlerId {
+        self.next_id += 1;
+        let id = EventHandlerId(self.next_id);
+        self.handlers
+            .entry(name.to_owned())
+            .or_default()
+            .push(EventHandler { id, script });
+        id
+    }
+
+    /// Remove a handler. Returns false if no such handler was registered.
+    pub fn unsubscribe(&mut self, id: EventHandlerId) -> bool {
+        let mut found = false;
+        for handlers in self.handlers.values_mut() {
+            let before = handlers.len();
+            handlers.retain(|h| h.id != id);
+            found |= handlers.len() != before;
+        }
+        self.handlers.retain(|_, handlers| !handlers.is_empty());
+        found
+    }
+
+    /// Pair every pending event with its handlers, draining the queue. Events with no
+    /// handlers are dropped.
+    pub(crate) fn take_deliveries(&mut self) -> Vec<(LocalNamespace, NitrousScript)> {
+        let mut out = Vec::new();
+        for (name, mut locals) in self.pending.drain(..) {
+            if let Some(handlers) = self.handlers.get(&name) {
+                locals.put("event", Value::from_str(&name));
+                for handler in handlers {
+                    out.push((locals.clone(), handler.script.clone()));
+                }
+            }
+        }
+        out
+    }
+
+    /// Build locals from positional script arguments.
+    pub fn positional_args(args: &[Value]) -> LocalNamespace {
+        let mut locals = LocalNamespace::empty();
+        for (i, arg) in args.iter().enumerate() {
+            locals.put(format!("arg{}", i), arg.to_owned());
+        }
+        locals
+    }
+
+    #[method]
+    fn on(&mut self, name: &str, script: &str) -> Result<i64> {
+        Ok(self.subscribe(name, NitrousScript::compile(script)?).0)
+    }
+
+    #[method]
+    fn off(&mut self, id: i64) -> bool {
+        self.unsubscribe(EventHandlerId(id))
+    }
+
+    #[method]
+    fn handler_count(&self, name: &str) -> i64 {
+        self.handlers.get(name).map(|h| h.len()).unwrap_or(0) as i64
+    }
+
+    /// `emit(name, args...)`, added to every script's locals.
+    pub(crate) fn emit_builtin() -> Value {
+        Value::RustMethod(Arc::new(|args, mut heap: HeapMut| {
+            let name = args
+                .first()
+                .ok_or_else(|| anyhow!("emit: expected an event name"))?
+                .to_str()?
+                .to_owned();
+            let locals = Self::positional_args(&args[1..]);
+            heap.resource_mut::<EventBus>().emit(name, locals);
+            Ok(Value::True())
+        }))
+    }
+
+    /// `on(name, script)`, added to every script's locals.
+    pub(crate) fn on_builtin() -> Value {
+        Value::RustMethod(Arc::new(|args, mut heap: HeapMut| {
+            if args.len() != 2 {
+                return Err(anyhow!("on: expected an event name and a script"));
+            }
+            let id = heap
+                .resource_mut::<EventBus>()
+                .on(args[0].to_str()?, args[1].to_str()?)?;
+            Ok(Value::Integer(id))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{EventBus, Runtime, ScriptCompletions};
+    use anyhow::Result;
+    use nitrous::{LocalNamespace, Value};
+
+    fn run_tick(runtime: &mut Runtime) -> Vec<Value> {
+        runtime.resource_mut::<ScriptCompletions>().clear();
+        runtime.run_sim_once();
+        runtime
+            .resource::<ScriptCompletions>()
+            .iter()
+            .map(|c| c.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_script_emit_and_handle() -> Result<()> {
+        let mut runtime = Runtime::default();
+        runtime.run_string(
+            r#"
+                on("gear_down", "arg0 + 1");
+                emit("gear_down", 41)
+            "#,
+        )?;
+        run_tick(&mut runtime);
+        assert_eq!(run_tick(&mut runtime), vec![Value::Integer(42)]);
+        assert!(run_tick(&mut runtime).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rust_emit_and_unsubscribe() -> Result<()> {
+        let mut runtime = Runtime::default();
+        runtime.run_string(r#"events.on("out_of_fuel", "who")"#)?;
+        assert_eq!(run_tick(&mut runtime), vec![Value::Integer(1)]);
+        assert_eq!(
+            runtime
+                .resource::<EventBus>()
+                .handlers
+                .get("out_of_fuel")
+                .map(|h| h.len()),
+            Some(1)
+        );
+
+        let mut args = LocalNamespace::empty();
+        args.put("who", Value::from_str("player"));
+        runtime.resource_mut::<EventBus>().emit("out_of_fuel", args);
+        assert_eq!(run_tick(&mut runtime), vec![Value::from_str("player")]);
+
+        runtime.run_string("events.off(1)")?;
+        assert_eq!(run_tick(&mut runtime), vec![Value::True()]);
+        runtime
+            .resource_mut::<EventBus>()
+            .emit("out_of_fuel", LocalNamespace::empty());
+        assert!(run_tick(&mut runtime).is_empty());
+        Ok(())
+    }
+}
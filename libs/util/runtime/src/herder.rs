@@ -12,14 +12,15 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{event_bus::EventBus, timers::ScriptTimers};
 use ansi::{ansi, Color};
 use anyhow::Result;
 use bevy_ecs::prelude::*;
 use itertools::*;
 use log::{info, trace, warn};
 use nitrous::{
-    ExecutionContext, HeapMut, HeapRef, LocalNamespace, NitrousExecutor, NitrousScript, Value,
-    YieldState,
+    describe_value, ExecutionContext, HeapMut, HeapRef, LocalNamespace, NitrousExecutor,
+    NitrousScript, Value, YieldState,
 };
 use once_cell::sync::Lazy;
 use std::sync::{Arc, Mutex};
@@ -46,6 +47,26 @@ component. As with resources, methods are called by appending parentheses.
 
 Examples:
     @player.throttle.set_detent(4)
+
+Pass a resource, component, or method to `help` to see what it accepts.
+
+Examples:
+    help(camera)
+    help(@player.throttle.set_detent)
+
+Scripts may react to named game events with `on`, and raise their own with `emit`.
+Any arguments to `emit` are passed to the handler as arg0, arg1, and so on.
+
+Examples:
+    on("out_of_fuel", "exit()")
+    emit("gear_down", 1)
+
+Use `after` and `every` to run a script later, or repeatedly, in sim time. Both return
+a handle that can be passed to `cancel`.
+
+Examples:
+    let t := every(5, "mission.check_over_target()")
+    cancel(t)
 "#;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -84,6 +105,8 @@ pub enum ScriptRunKind {
     String,
     Precompiled,
     Binding,
+    Event,
+    Timer,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -121,6 +144,15 @@ impl ExecutionMetadata {
                 Ok(Value::True())
             })),
         );
+        self.context
+            .locals_mut()
+            .put_if_absent("emit", EventBus::emit_builtin());
+        self.context
+            .locals_mut()
+            .put_if_absent("on", EventBus::on_builtin());
+        for (name, builtin) in ScriptTimers::builtins() {
+            self.context.locals_mut().put_if_absent(name, builtin);
+        }
         if self.kind == ScriptRunKind::Interactive {
             #[allow(unstable_name_collisions)]
             let item_list: Value = (String::new()
@@ -138,7 +170,14 @@ impl ExecutionMetadata {
             );
             self.context.locals_mut().put_if_absent(
                 "help",
-                Value::RustMethod(Arc::new(move |_, _| Ok(GUIDE.to_owned().into()))),
+                Value::RustMethod(Arc::new(move |args, heap| {
+                    Ok(match args.first() {
+                        None => GUIDE.to_owned(),
+                        Some(value) => describe_value(value, heap.as_ref())
+                            .unwrap_or_else(|| format!("No help available for {}", value)),
+                    }
+                    .into())
+                })),
             );
         }
     }
@@ -278,6 +317,13 @@ impl ScriptHerder {
     #[inline]
     pub(crate) fn sys_run_sim_scripts(world: &mut World) {
         world.resource_scope(|world, mut herder: Mut<ScriptHerder>| {
+            // Start handlers for any events that were emitted since the last tick.
+            for (locals, script) in world.resource_mut::<EventBus>().take_deliveries() {
+                herder.run_with_locals(locals, script, ScriptRunKind::Event);
+            }
+            for (locals, script) in world.resource_mut::<ScriptTimers>().take_due() {
+                herder.run_with_locals(locals, script, ScriptRunKind::Timer);
+            }
             herder._run_scripts(HeapMut::wrap(world), ScriptRunPhase::Sim);
         });
     }
@@ -341,3 +387,55 @@ impl ScriptHerder {
         self.gthread = next_gthreads;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Runtime;
+    use nitrous::{inject_nitrous_resource, method, NitrousResource};
+
+    #[derive(Debug, Default, NitrousResource)]
+    struct Radio {
+        mhz: f64,
+    }
+
+    #[inject_nitrous_resource]
+    impl Radio {
+        /// Tune to a frequency in MHz.
+        #[method]
+        fn tune(&mut self, mhz: f64) -> f64 {
+            self.mhz = mhz;
+            mhz
+        }
+    }
+
+    fn run_help(runtime: &mut Runtime, script: &str) -> ScriptResult {
+        runtime.resource_mut::<ScriptCompletions>().clear();
+        runtime.run_interactive(script).unwrap();
+        runtime.run_sim_once();
+        runtime.resource::<ScriptCompletions>()[0].result.clone()
+    }
+
+    #[test]
+    fn test_help_for_method() {
+        let mut runtime = Runtime::default();
+        runtime.insert_named_resource("radio", Radio::default());
+        let help = run_help(&mut runtime, "help(radio.tune)").unwrap();
+        assert_eq!(
+            help,
+            Value::from_str("tune(mhz: float) -> float\nTune to a frequency in MHz.")
+        );
+    }
+
+    #[test]
+    fn test_help_for_unknown_name() {
+        let mut runtime = Runtime::default();
+        runtime.insert_named_resource("radio", Radio::default());
+        assert!(run_help(&mut runtime, "help(radio.transmit)").is_error());
+        assert!(run_help(&mut runtime, "help(television)").is_error());
+        assert_eq!(
+            run_help(&mut runtime, "help(42)").unwrap(),
+            Value::from_str("No help available for 42")
+        );
+    }
+}
@@ -13,9 +13,12 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 mod dump_schedule;
+mod event_bus;
 mod herder;
+mod profiler;
 mod runtime;
 mod startup;
+mod timers;
 
 pub mod reexport {
     pub use log;
@@ -23,12 +26,15 @@ pub mod reexport {
 }
 
 pub use crate::{
+    event_bus::{EventBus, EventHandlerId},
     herder::{
         ExecutionMetadata, ExitRequest, ScriptCompletion, ScriptCompletions, ScriptHerder,
         ScriptQueue, ScriptReceipt, ScriptResult, ScriptRunKind, ScriptRunPhase, ERROR_REPORTS,
     },
+    profiler::{ProfileSink, ProfileStats, Profiler},
     runtime::{Extension, FrameStage, Runtime, RuntimeStep, ShutdownStage, SimStage, StartupStage},
     startup::StartupOpts,
+    timers::ScriptTimers,
 };
 
 use bevy_ecs::prelude::*;
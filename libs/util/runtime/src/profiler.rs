@@ -0,0 +1,321 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{ensure, Result};
+use nitrous::{inject_nitrous_resource, method, NitrousResource};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The default number of samples over which we compute rolling statistics.
+const DEFAULT_WINDOW: usize = 120;
+
+/// Rolling statistics over the most recent samples of a single timed item.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProfileStats {
+    pub last: Duration,
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    pub samples: usize,
+}
+
+#[derive(Debug, Default)]
+struct ProfileSeries {
+    samples: VecDeque<Duration>,
+}
+
+impl ProfileSeries {
+    fn push(&mut self, window: usize, elapsed: Duration) {
+        while self.samples.len() >= window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(elapsed);
+    }
+
+    fn truncate(&mut self, window: usize) {
+        while self.samples.len() > window {
+            self.samples.pop_front();
+        }
+    }
+
+    fn stats(&self) -> ProfileStats {
+        if self.samples.is_empty() {
+            return ProfileStats::default();
+        }
+        let mut min = Duration::MAX;
+        let mut max = Duration::ZERO;
+        let mut total = Duration::ZERO;
+        for &sample in &self.samples {
+            min = min.min(sample);
+            max = max.max(sample);
+            total += sample;
+        }
+        ProfileStats {
+            last: *self.samples.back().unwrap(),
+            min,
+            avg: total / self.samples.len() as u32,
+            max,
+            samples: self.samples.len(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ProfileData {
+    window: usize,
+    series: BTreeMap<String, ProfileSeries>,
+}
+
+/// A cheap, clonable handle for recording timings from anywhere: the runtime's schedules,
+/// tracing layers on other threads, or GPU timestamp readback.
+///
+/// Keys are of the form `category/name`, where the category is the schedule that the
+/// timing was recorded in (`sim`, `frame`) or `gpu` for render pass timings. The bare
+/// category name is used for the total time of the schedule.
+#[derive(Clone, Debug)]
+pub struct ProfileSink {
+    data: Arc<Mutex<ProfileData>>,
+}
+
+impl ProfileSink {
+    fn new(window: usize) -> Self {
+        Self {
+            data: Arc::new(Mutex::new(ProfileData {
+                window,
+                series: BTreeMap::new(),
+            })),
+        }
+    }
+
+    pub fn record(&self, category: &str, name: &str, elapsed: Duration) {
+        self.record_key(&format!("{}/{}", category, name), elapsed);
+    }
+
+    pub fn record_key(&self, key: &str, elapsed: Duration) {
+        let mut data = self.data.lock().unwrap();
+        let window = data.window;
+        if let Some(series) = data.series.get_mut(key) {
+            series.push(window, elapsed);
+        } else {
+            let mut series = ProfileSeries::default();
+            series.push(window, elapsed);
+            data.series.insert(key.to_owned(), series);
+        }
+    }
+
+    pub fn stats(&self, key: &str) -> Option<ProfileStats> {
+        self.data.lock().unwrap().series.get(key).map(|s| s.stats())
+    }
+
+    /// All keys with their current statistics, in key order.
+    pub fn all_stats(&self) -> Vec<(String, ProfileStats)> {
+        self.data
+            .lock()
+            .unwrap()
+            .series
+            .iter()
+            .map(|(k, s)| (k.to_owned(), s.stats()))
+            .collect()
+    }
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1_000.
+}
+
+/// Always-available wall time profiler for the sim and frame schedules. The runtime records
+/// the total time of every schedule run. If the tracing layer from tracelog is installed,
+/// it records every system in those schedules as well. The Gpu records render pass
+/// timings here when the adapter supports timestamp queries.
+#[derive(Debug, NitrousResource)]
+pub struct Profiler {
+    sink: ProfileSink,
+
+    /// The number of individual system rows to show in overlays.
+    #[property]
+    overlay_rows: i64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            sink: ProfileSink::new(DEFAULT_WINDOW),
+            overlay_rows: 0,
+        }
+    }
+}
+
+#[inject_nitrous_resource]
+impl Profiler {
+    pub fn sink(&self) -> ProfileSink {
+        self.sink.clone()
+    }
+
+    pub fn stats(&self, key: &str) -> Option<ProfileStats> {
+        self.sink.stats(key)
+    }
+
+    #[method]
+    pub fn window(&self) -> i64 {
+        self.sink.data.lock().unwrap().window as i64
+    }
+
+    #[method]
+    pub fn set_window(&self, window: i64) -> Result<()> {
+        ensure!(
+            window > 0,
+            "profile window must contain at least one sample"
+        );
+        let mut data = self.sink.data.lock().unwrap();
+        data.window = window as usize;
+        for series in data.series.values_mut() {
+            series.truncate(window as usize);
+        }
+        Ok(())
+    }
+
+    #[method]
+    pub fn reset(&self) {
+        self.sink.data.lock().unwrap().series.clear();
+    }
+
+    #[method]
+    pub fn toggle_detail(&mut self) {
+        self.overlay_rows = if self.overlay_rows > 0 { 0 } else { 8 };
+    }
+
+    #[method]
+    pub fn average_ms(&self, key: &str) -> f64 {
+        self.sink.stats(key).map(|s| ms(s.avg)).unwrap_or(0.)
+    }
+
+    #[method]
+    pub fn max_ms(&self, key: &str) -> f64 {
+        self.sink.stats(key).map(|s| ms(s.max)).unwrap_or(0.)
+    }
+
+    /// Show min/avg/max for everything we have timed, optionally limited to keys that
+    /// start with the given prefix.
+    #[method]
+    pub fn report(&self, prefix: &str) -> String {
+        let mut out = format!(
+            "{:<64} {:>8} {:>8} {:>8}\n",
+            "name", "min ms", "avg ms", "max ms"
+        );
+        for (key, stats) in self.sink.all_stats() {
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            writeln!(
+                out,
+                "{:<64} {:>8.3} {:>8.3} {:>8.3}",
+                key,
+                ms(stats.min),
+                ms(stats.avg),
+                ms(stats.max)
+            )
+            .ok();
+        }
+        out
+    }
+
+    /// One line per schedule total, followed by the slowest `overlay_rows` systems.
+    pub fn overlay_lines(&self) -> Vec<String> {
+        let all = self.sink.all_stats();
+        let mut out = Vec::new();
+        let mut totals = Vec::new();
+        for category in ["frame", "sim"] {
+            if let Some((_, stats)) = all.iter().find(|(k, _)| k == category) {
+                totals.push((category, *stats));
+            }
+        }
+        // The GPU does not have a single total; sum the passes instead.
+        let mut gpu = None;
+        for (_, stats) in all.iter().filter(|(k, _)| k.starts_with("gpu/")) {
+            let total = gpu.get_or_insert_with(ProfileStats::default);
+            total.min += stats.min;
+            total.avg += stats.avg;
+            total.max += stats.max;
+        }
+        if let Some(gpu) = gpu {
+            totals.push(("gpu", gpu));
+        }
+        for (category, stats) in totals {
+            out.push(format!(
+                "{}: {:.2} / {:.2} / {:.2} ms",
+                category,
+                ms(stats.min),
+                ms(stats.avg),
+                ms(stats.max)
+            ));
+        }
+        if self.overlay_rows > 0 {
+            let mut systems = all
+                .iter()
+                .filter(|(k, _)| k.starts_with("sim/") || k.starts_with("frame/"))
+                .collect::<Vec<_>>();
+            systems.sort_by_key(|(_, stats)| Reverse(stats.avg));
+            for (key, stats) in systems.iter().take(self.overlay_rows as usize) {
+                out.push(format!("{:.2} ms  {}", ms(stats.avg), key));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rolling_stats() -> Result<()> {
+        let profiler = Profiler::default();
+        profiler.set_window(3)?;
+        let sink = profiler.sink();
+        for i in 1..=5 {
+            sink.record("sim", "sys_a", Duration::from_millis(i));
+        }
+        let stats = profiler.stats("sim/sys_a").unwrap();
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.min, Duration::from_millis(3));
+        assert_eq!(stats.avg, Duration::from_millis(4));
+        assert_eq!(stats.max, Duration::from_millis(5));
+        assert_eq!(stats.last, Duration::from_millis(5));
+
+        profiler.reset();
+        assert!(profiler.stats("sim/sys_a").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlay_lines() {
+        let mut profiler = Profiler::default();
+        let sink = profiler.sink();
+        sink.record_key("frame", Duration::from_millis(16));
+        sink.record("frame", "slow", Duration::from_millis(10));
+        sink.record("frame", "fast", Duration::from_millis(1));
+        sink.record("gpu", "world", Duration::from_millis(2));
+        assert_eq!(profiler.overlay_lines().len(), 2);
+        profiler.toggle_detail();
+        let lines = profiler.overlay_lines();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[2].ends_with("frame/slow"));
+        assert!(lines[3].ends_with("frame/fast"));
+    }
+}
@@ -14,9 +14,12 @@
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 use crate::{
     dump_schedule::dump_schedule,
+    event_bus::EventBus,
     herder::{
         ExitRequest, ScriptCompletions, ScriptHerder, ScriptQueue, ScriptReceipt, ScriptRunKind,
     },
+    profiler::{ProfileSink, Profiler},
+    timers::ScriptTimers,
 };
 use anyhow::Result;
 use bevy_ecs::{
@@ -28,7 +31,8 @@ use nitrous::{
     inject_nitrous_resource, method, Heap, HeapMut, LocalNamespace, NamedEntityMut,
     NitrousResource, NitrousScript, ScriptResource,
 };
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, time::Instant};
+use tracing::info_span;
 
 /// Interface for extending the Runtime.
 pub trait Extension {
@@ -94,6 +98,7 @@ pub struct Runtime {
     frame_schedule: Schedule,
     shutdown_schedule: Schedule,
     dump_schedules: bool,
+    profile: ProfileSink,
 }
 
 impl Default for Runtime {
@@ -123,6 +128,7 @@ impl Default for Runtime {
         let shutdown_schedule =
             Schedule::default().with_stage(ShutdownStage::Cleanup, SystemStage::single_threaded());
 
+        let profiler = Profiler::default();
         let mut runtime = Self {
             heap: Heap::default(),
             startup_schedule,
@@ -130,6 +136,7 @@ impl Default for Runtime {
             frame_schedule,
             shutdown_schedule,
             dump_schedules: false,
+            profile: profiler.sink(),
         };
 
         runtime
@@ -138,7 +145,10 @@ impl Default for Runtime {
             .insert_resource(ScriptCompletions::new())
             .insert_resource(ScriptQueue::default())
             .insert_resource(TaskPool::default())
-            .insert_named_resource("runtime", RuntimeResource::default());
+            .insert_named_resource("runtime", RuntimeResource::default())
+            .insert_named_resource("events", EventBus::default())
+            .insert_named_resource("timers", ScriptTimers::default())
+            .insert_named_resource("profiler", profiler);
 
         runtime
     }
@@ -157,12 +167,23 @@ impl Runtime {
 
     #[inline]
     pub fn run_sim_once(&mut self) {
-        self.sim_schedule.run_once(self.heap.world_mut());
+        let start = Instant::now();
+        {
+            // Note: the profiler's tracing layer uses this span to find the schedule.
+            let _span = info_span!("schedule", name = "sim").entered();
+            self.sim_schedule.run_once(self.heap.world_mut());
+        }
+        self.profile.record_key("sim", start.elapsed());
     }
 
     #[inline]
     pub fn run_frame_once(&mut self) {
-        self.frame_schedule.run_once(self.heap.world_mut());
+        let start = Instant::now();
+        {
+            let _span = info_span!("schedule", name = "frame").entered();
+            self.frame_schedule.run_once(self.heap.world_mut());
+        }
+        self.profile.record_key("frame", start.elapsed());
     }
 
     #[inline]
@@ -0,0 +1,249 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{anyhow, ensure, Result};
+use nitrous::{
+    inject_nitrous_resource, method, HeapMut, LocalNamespace, NitrousResource, NitrousScript, Value,
+};
+use std::{sync::Arc, time::Duration};
+
+struct ScriptTimer {
+    id: i64,
+    due: Duration,
+    period: Option<Duration>,
+    script: NitrousScript,
+}
+
+/// Scripts scheduled to run after a delay, or repeatedly, in sim time.
+///
+/// Timers only move forward when the sim clock is advanced, which `TimeStep` does once per
+/// sim tick. Time compression therefore speeds them up and pausing stops them. Due timers
+/// are started at the top of `SimStage::RunScript`, with `timer` set to their handle.
+#[derive(Default, NitrousResource)]
+pub struct ScriptTimers {
+    now: Duration,
+    next_id: i64,
+    timers: Vec<ScriptTimer>,
+}
+
+impl std::fmt::Debug for ScriptTimers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScriptTimers")
+            .field("now", &self.now)
+            .field("timers", &self.timers.len())
+            .finish()
+    }
+}
+
+fn seconds_to_duration(seconds: f64) -> Result<Duration> {
+    ensure!(
+        seconds.is_finite() && seconds >= 0.,
+        "timer delay must be a non-negative number of seconds, not {}",
+        seconds
+    );
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+#[inject_nitrous_resource]
+impl ScriptTimers {
+    /// Move the timer clock forward by one sim step.
+    pub fn advance(&mut self, dt: Duration) {
+        self.now += dt;
+    }
+
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Schedule `script` to run once, `delay` from now.
+    pub fn schedule_after(&mut self, delay: Duration, script: NitrousScript) -> i64 {
+        self.push(self.now + delay, None, script)
+    }
+
+    /// Schedule `script` to run every `period`, starting one period from now.
+    pub fn schedule_every(&mut self, period: Duration, script: NitrousScript) -> Result<i64> {
+        ensure!(!period.is_zero(), "repeating timers need a non-zero period");
+        Ok(self.push(self.now + period, Some(period), script))
+    }
+
+    fn push(&mut self, due: Duration, period: Option<Duration>, script: NitrousScript) -> i64 {
+        self.next_id += 1;
+        self.timers.push(ScriptTimer {
+            id: self.next_id,
+            due,
+            period,
+            script,
+        });
+        self.next_id
+    }
+
+    /// Collect every timer that has come due. One-shot timers are removed and repeating
+    /// timers are moved to their next deadline. A repeating timer that has fallen more than
+    /// a period behind fires once rather than once per missed period.
+    pub(crate) fn take_due(&mut self) -> Vec<(LocalNamespace, NitrousScript)> {
+        let now = self.now;
+        let mut out = Vec::new();
+        self.timers.retain_mut(|timer| {
+            if timer.due > now {
+                return true;
+            }
+            let mut locals = LocalNamespace::empty();
+            locals.put("timer", Value::Integer(timer.id));
+            out.push((locals, timer.script.clone()));
+            if let Some(period) = timer.period {
+                while timer.due <= now {
+                    timer.due += period;
+                }
+                true
+            } else {
+                false
+            }
+        });
+        out
+    }
+
+    #[method]
+    fn after(&mut self, seconds: f64, script: &str) -> Result<i64> {
+        Ok(self.schedule_after(
+            seconds_to_duration(seconds)?,
+            NitrousScript::compile(script)?,
+        ))
+    }
+
+    #[method]
+    fn every(&mut self, seconds: f64, script: &str) -> Result<i64> {
+        self.schedule_every(
+            seconds_to_duration(seconds)?,
+            NitrousScript::compile(script)?,
+        )
+    }
+
+    #[method]
+    pub fn cancel(&mut self, id: i64) -> bool {
+        let before = self.timers.len();
+        self.timers.retain(|timer| timer.id != id);
+        self.timers.len() != before
+    }
+
+    #[method]
+    fn active_count(&self) -> i64 {
+        self.timers.len() as i64
+    }
+
+    #[method]
+    fn sim_seconds(&self) -> f64 {
+        self.now.as_secs_f64()
+    }
+
+    fn make_builtin(repeating: bool) -> Value {
+        let name = if repeating { "every" } else { "after" };
+        Value::RustMethod(Arc::new(move |args, mut heap: HeapMut| {
+            if args.len() != 2 {
+                return Err(anyhow!("{}: expected seconds and a script", name));
+            }
+            let mut timers = heap.resource_mut::<ScriptTimers>();
+            let id = if repeating {
+                timers.every(args[0].to_numeric()?, args[1].to_str()?)?
+            } else {
+                timers.after(args[0].to_numeric()?, args[1].to_str()?)?
+            };
+            Ok(Value::Integer(id))
+        }))
+    }
+
+    /// Builtins `after(seconds, script)`, `every(seconds, script)` and `cancel(handle)`,
+    /// added to every script's locals.
+    pub(crate) fn builtins() -> [(&'static str, Value); 3] {
+        [
+            ("after", Self::make_builtin(false)),
+            ("every", Self::make_builtin(true)),
+            (
+                "cancel",
+                Value::RustMethod(Arc::new(|args, mut heap: HeapMut| {
+                    let id = args
+                        .first()
+                        .ok_or_else(|| anyhow!("cancel: expected a timer handle"))?
+                        .to_int()?;
+                    Ok(Value::from_bool(
+                        heap.resource_mut::<ScriptTimers>().cancel(id),
+                    ))
+                })),
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Runtime, ScriptCompletions, ScriptTimers};
+    use anyhow::Result;
+    use nitrous::Value;
+    use std::time::Duration;
+
+    fn run_tick(runtime: &mut Runtime) -> Vec<Value> {
+        runtime
+            .resource_mut::<ScriptTimers>()
+            .advance(Duration::from_millis(500));
+        runtime.resource_mut::<ScriptCompletions>().clear();
+        runtime.run_sim_once();
+        runtime
+            .resource::<ScriptCompletions>()
+            .iter()
+            .map(|c| c.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_after_and_every() -> Result<()> {
+        let mut runtime = Runtime::default();
+        runtime.run_string(
+            r#"
+                after(1, "7");
+                every(0.5, "timer")
+            "#,
+        )?;
+        // Timers are registered by this tick, so the clock starts from here.
+        assert_eq!(run_tick(&mut runtime), vec![Value::Integer(2)]);
+        assert_eq!(run_tick(&mut runtime), vec![Value::Integer(2)]);
+        let fired = run_tick(&mut runtime);
+        assert_eq!(fired.len(), 2);
+        assert!(fired.contains(&Value::Integer(7)));
+        assert_eq!(run_tick(&mut runtime), vec![Value::Integer(2)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel() -> Result<()> {
+        let mut runtime = Runtime::default();
+        runtime.run_string(r#"every(0.5, "cancel(timer)")"#)?;
+        run_tick(&mut runtime);
+        assert_eq!(runtime.resource::<ScriptTimers>().active_count(), 1);
+        assert_eq!(run_tick(&mut runtime), vec![Value::True()]);
+        assert_eq!(runtime.resource::<ScriptTimers>().active_count(), 0);
+        assert!(run_tick(&mut runtime).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_time_no_timers() -> Result<()> {
+        let mut runtime = Runtime::default();
+        runtime.run_string(r#"after(0.5, "1")"#)?;
+        runtime.run_sim_once();
+        runtime.resource_mut::<ScriptCompletions>().clear();
+        runtime.run_sim_once();
+        runtime.run_sim_once();
+        assert!(runtime.resource::<ScriptCompletions>().is_empty());
+        Ok(())
+    }
+}
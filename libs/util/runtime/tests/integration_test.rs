@@ -16,7 +16,7 @@ use anyhow::Result;
 use bevy_ecs::prelude::*;
 use nitrous::{
     inject_nitrous_component, inject_nitrous_resource, method, NitrousComponent, NitrousResource,
-    Value,
+    ScriptApi, ScriptApiKind, Value,
 };
 use runtime::{Runtime, ScriptCompletions, ScriptHerder, ScriptRunPhase};
 use std::collections::HashMap;
@@ -49,6 +49,7 @@ impl Default for Globals {
 
 #[inject_nitrous_resource]
 impl Globals {
+    /// Add v to float_resource.
     #[method]
     fn add_float(&self, v: f64) -> f64 {
         self.float_resource + v
@@ -158,3 +159,36 @@ fn integration_test() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn script_api_test() -> Result<()> {
+    let apis = ScriptApi::collect();
+    let globals = apis.iter().find(|api| api.type_name == "Globals").unwrap();
+    assert_eq!(globals.kind, ScriptApiKind::Resource);
+    assert_eq!(globals.properties.len(), 4);
+    let add_float = globals
+        .methods
+        .iter()
+        .find(|m| m.name == "add_float")
+        .unwrap();
+    assert_eq!(add_float.signature(), "add_float(v: float) -> float");
+    assert_eq!(add_float.doc, "Add v to float_resource.");
+    let item = apis.iter().find(|api| api.type_name == "Item").unwrap();
+    assert_eq!(item.script_name, "item");
+
+    let mut runtime = Runtime::default();
+    runtime.insert_named_resource("globals", Globals::default());
+    let receipt = runtime.run_interactive("help(globals.add_float)")?;
+    runtime.run_sim_once();
+    let completion = runtime
+        .resource::<ScriptCompletions>()
+        .iter()
+        .find(|c| c.receipt == receipt)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        completion,
+        Value::from_str("add_float(v: float) -> float\nAdd v to float_resource.")
+    );
+    Ok(())
+}
@@ -16,7 +16,7 @@ use anyhow::Result;
 use bevy_ecs::prelude::*;
 use fullscreen::{FullscreenBuffer, FullscreenVertex};
 use global_data::{GlobalParametersBuffer, GlobalsStep};
-use gpu::{Gpu, GpuStep};
+use gpu::{Gpu, GpuStep, GpuTimestamps};
 use log::trace;
 use runtime::{Extension, Runtime};
 use shader_shared::Group;
@@ -147,6 +147,7 @@ impl CompositeRenderPass {
         world: Res<WorldRenderPass>,
         ui: Res<UiRenderPass>,
         gpu: Res<Gpu>,
+        mut timestamps: ResMut<GpuTimestamps>,
         maybe_surface: Res<Option<wgpu::SurfaceTexture>>,
         maybe_encoder: ResMut<Option<wgpu::CommandEncoder>>,
     ) {
@@ -155,13 +156,17 @@ impl CompositeRenderPass {
                 let view = surface_texture
                     .texture
                     .create_view(&::wgpu::TextureViewDescriptor::default());
-                let render_pass_desc_ref = wgpu::RenderPassDescriptor {
-                    label: Some("screen-composite-render-pass"),
-                    color_attachments: &[Some(Gpu::color_attachment(&view))],
-                    depth_stencil_attachment: Some(gpu.depth_stencil_attachment()),
-                };
-                let rpass = encoder.begin_render_pass(&render_pass_desc_ref);
-                let _rpass = composite.composite_scene(rpass, &fullscreen, &globals, &world, &ui);
+                {
+                    let render_pass_desc_ref = wgpu::RenderPassDescriptor {
+                        label: Some("screen-composite-render-pass"),
+                        color_attachments: &[Some(Gpu::color_attachment(&view))],
+                        depth_stencil_attachment: Some(gpu.depth_stencil_attachment()),
+                    };
+                    let rpass = encoder.begin_render_pass(&render_pass_desc_ref);
+                    let _rpass =
+                        composite.composite_scene(rpass, &fullscreen, &globals, &world, &ui);
+                }
+                timestamps.write(encoder, "composite");
             }
         }
     }
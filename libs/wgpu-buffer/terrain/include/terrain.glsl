@@ -102,6 +102,17 @@ terrain_graticule_to_tile_st(vec2 graticule_rad, TileInfo tile) {
     return tile_st;
 }
 
+// The index is cleared to slot zero, so a lookup where no tile is loaded still finds a slot;
+// check that the tile in it actually covers the point before using its data.
+bool
+terrain_tile_covers(vec2 graticule_rad, TileInfo tile) {
+    if (tile.angular_extent_as <= 0.0) {
+        return false;
+    }
+    vec2 tile_st = terrain_graticule_to_tile_st(graticule_rad, tile);
+    return all(greaterThanEqual(tile_st, vec2(0.0))) && all(lessThanEqual(tile_st, vec2(1.0)));
+}
+
 vec2
 terrain_graticule_to_st(vec2 graticule_rad, TileInfo tile) {
     vec2 tile_st = terrain_graticule_to_tile_st(graticule_rad, tile);
@@ -180,3 +191,32 @@ terrain_color_in_tile(vec2 graticule_rad, TileInfo tile, texture2DArray atlas_te
     );
 }
 ///////////////////////////////////////////////////////////////////////////////
+
+///////////////////////////////////////////////////////////////////////////////
+/// Polar stereographic lookup
+// Note: these values are duplicated in tile/polar.rs.
+const float POLAR_POLE_OFFSET_AS = 90.0 * 60.0 * 60.0;
+const float POLAR_LIMIT_DEG = 60.0;
+const float POLAR_BLEND_START_DEG = 70.0;
+const float POLAR_BLEND_END_DEG = 75.0;
+const float POLAR_ARCSECONDS_PER_RADIAN = 206264.80624709636;
+
+// How much polar data should replace spherical data at this graticule.
+float
+terrain_polar_weight(vec2 graticule_rad) {
+    return smoothstep(POLAR_BLEND_START_DEG, POLAR_BLEND_END_DEG, abs(degrees(graticule_rad.x)));
+}
+
+// Polar tiles are stored on a stereographic plane, with both poles side by side, in the same
+// arcsecond units as the spherical layout. Return the plane (y, x) in the same form as a
+// graticule so that the index and tile lookups above can be used unchanged.
+vec2
+terrain_polar_graticule(vec2 graticule_rad) {
+    float lat = graticule_rad.x;
+    float lon = graticule_rad.y;
+    float rho_as = 2.0 * tan((radians(90.0) - abs(lat)) / 2.0) * POLAR_ARCSECONDS_PER_RADIAN;
+    float y_sign = lat > 0.0 ? -1.0 : 1.0;
+    float center_x_as = lat > 0.0 ? -POLAR_POLE_OFFSET_AS : POLAR_POLE_OFFSET_AS;
+    vec2 plane_as = vec2(y_sign * rho_as * cos(lon), center_x_as + rho_as * sin(lon));
+    return plane_as / POLAR_ARCSECONDS_PER_RADIAN;
+}
@@ -0,0 +1,150 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+#include <wgpu-buffer/shader_shared/include/consts.glsl>
+#include <wgpu-buffer/shader_shared/include/buffer_helpers.glsl>
+#include <wgpu-buffer/terrain/include/terrain.glsl>
+
+const uint WORKGROUP_WIDTH = 65536;
+
+// Must match overlay.rs
+const uint KIND_FLATTEN = 1;
+const uint KIND_CRATER = 2;
+const uint KIND_EMBANKMENT = 3;
+const float EMBANKMENT_SIDE_SLOPE = 2.0;
+const float CRATER_RIM_FRACTION = 0.25;
+const float CRATER_RIM_EXTENT = 1.5;
+// physical_constants::EARTH_RADIUS, rather than the rounder atmosphere radius.
+const float OVERLAY_EARTH_RADIUS_M = 6356766.0;
+
+struct OverlayInfo {
+    float center_lat;
+    float center_lon;
+    float bound_radius;
+    uint kind;
+    // Flatten: target height; crater: depth; embankment: height
+    float height;
+    // Flatten: margin; crater: radius; embankment: width
+    float size;
+    // Embankment: far end, in meters east and north of the center
+    float end[2];
+    uint point_start;
+    uint point_count;
+    uint pad[2];
+};
+
+layout(local_size_x = 64, local_size_y = 2, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) buffer Vertices { TerrainVertex vertices[]; };
+layout(set = 1, binding = 0) readonly buffer Overlays { OverlayInfo overlays[]; };
+layout(set = 1, binding = 1) readonly buffer OverlayPoints { vec2 overlay_points[]; };
+layout(set = 1, binding = 2) uniform OverlayCount { uvec4 overlay_count; };
+
+float
+segment_distance(vec2 a, vec2 b, vec2 p)
+{
+    vec2 ab = b - a;
+    vec2 ap = p - a;
+    float len2 = dot(ab, ab);
+    float t = len2 > 0.0 ? clamp(dot(ap, ab) / len2, 0.0, 1.0) : 0.0;
+    return length(ap - t * ab);
+}
+
+// Distance to the edge of the overlay's polygon; negative inside.
+float
+polygon_distance(OverlayInfo overlay, vec2 p)
+{
+    float dist = 1e30;
+    bool inside = false;
+    uint j = overlay.point_start + overlay.point_count - 1;
+    for (uint i = overlay.point_start; i < overlay.point_start + overlay.point_count; ++i) {
+        vec2 a = overlay_points[i];
+        vec2 b = overlay_points[j];
+        dist = min(dist, segment_distance(a, b, p));
+        if ((a.y > p.y) != (b.y > p.y) && p.x < (b.x - a.x) * (p.y - a.y) / (b.y - a.y) + a.x) {
+            inside = !inside;
+        }
+        j = i;
+    }
+    return inside ? -dist : dist;
+}
+
+float
+apply_overlay(OverlayInfo overlay, vec2 v_graticule, float height)
+{
+    float dlon = v_graticule.y - overlay.center_lon;
+    if (dlon > PI) {
+        dlon -= 2.0 * PI;
+    } else if (dlon < -PI) {
+        dlon += 2.0 * PI;
+    }
+    vec2 p = vec2(
+        dlon * OVERLAY_EARTH_RADIUS_M * cos(overlay.center_lat),
+        (v_graticule.x - overlay.center_lat) * OVERLAY_EARTH_RADIUS_M
+    );
+    if (length(p) > overlay.bound_radius) {
+        return height;
+    }
+
+    if (overlay.kind == KIND_FLATTEN) {
+        float d = polygon_distance(overlay, p);
+        if (d <= 0.0) {
+            return overlay.height;
+        }
+        if (d < overlay.size) {
+            return mix(height, overlay.height, smoothstep(0.0, 1.0, 1.0 - d / overlay.size));
+        }
+    } else if (overlay.kind == KIND_CRATER) {
+        float r = length(p) / overlay.size;
+        float rim = overlay.height * CRATER_RIM_FRACTION;
+        if (r < 1.0) {
+            return height - overlay.height * (1.0 - r * r) + rim * r * r;
+        }
+        if (r < CRATER_RIM_EXTENT) {
+            return height + rim * (1.0 - smoothstep(0.0, 1.0, (r - 1.0) / (CRATER_RIM_EXTENT - 1.0)));
+        }
+    } else if (overlay.kind == KIND_EMBANKMENT) {
+        float d = segment_distance(vec2(0.0), arr_to_vec2(overlay.end), p) - overlay.size / 2.0;
+        float side = abs(overlay.height) * EMBANKMENT_SIDE_SLOPE;
+        if (d <= 0.0) {
+            return height + overlay.height;
+        }
+        if (d < side) {
+            return height + overlay.height * (1.0 - d / side);
+        }
+    }
+    return height;
+}
+
+void
+main()
+{
+    // One invocation per vertex.
+    uint i = gl_GlobalInvocationID.x + gl_GlobalInvocationID.y * WORKGROUP_WIDTH;
+
+    vec2 v_graticule = arr_to_vec2(vertices[i].graticule);
+    vec3 v_normal = arr_to_vec3(vertices[i].normal);
+    vec3 v_surface = arr_to_vec3(vertices[i].surface_position);
+    float prior_height = dot(arr_to_vec3(vertices[i].position) - v_surface, v_normal);
+
+    // Overlays apply in order, on top of whatever the tile sets gave us.
+    float height = prior_height;
+    for (uint j = 0; j < overlay_count.x; ++j) {
+        height = apply_overlay(overlays[j], v_graticule, height);
+    }
+    if (height != prior_height) {
+        vertices[i].position = vec3_to_arr(v_surface + height * v_normal);
+    }
+}
@@ -12,15 +12,18 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+mod overlay;
 mod patch;
 mod tables;
 
 pub mod tile;
 
 use crate::{
+    overlay::OverlayDisplacement,
     patch::PatchManager,
     tile::{
         null_tile_set::NullHeightTileSet,
+        polar_tile_set::{PolarColorTileSet, PolarHeightTileSet, PolarNormalsTileSet},
         spherical_tile_set::{
             SphericalColorTileSet, SphericalHeightTileSet, SphericalNormalsTileSet,
         },
@@ -28,8 +31,12 @@ use crate::{
     },
 };
 pub use crate::{
+    overlay::{HeightOverlay, OverlayShape, TerrainOverlays},
     patch::{PatchWinding, TerrainVertex},
-    tile::{ColorsTileSet, HeightsTileSet, NormalsTileSet, TileSet},
+    tile::{
+        ColorsTileSet, HeightsTileSet, NormalsTileSet, TerrainElevation, TerrainHit, TileSet,
+        TileStreaming,
+    },
 };
 
 use absolute_unit::{Length, Meters};
@@ -154,6 +161,7 @@ pub struct VisiblePatch {
 #[derive(Debug, NitrousResource)]
 pub struct TerrainBuffer {
     patch_manager: PatchManager,
+    overlay_displacement: OverlayDisplacement,
 
     toggle_pin_camera: bool,
     pinned_camera: Option<ScreenCamera>,
@@ -198,6 +206,8 @@ impl Extension for TerrainBuffer {
             )?;
         builder.inject_into_runtime(runtime)?;
 
+        runtime.load_extension::<TerrainOverlays>()?;
+        runtime.insert_named_resource("tile_streaming", TileStreaming::default());
         runtime.insert_named_resource("terrain", terrain);
         runtime.run_string(
             r#"
@@ -306,6 +316,7 @@ impl TerrainBuffer {
             gpu_detail.subdivisions,
             gpu,
         )?;
+        let overlay_displacement = OverlayDisplacement::new(&patch_manager, gpu);
 
         let deferred_texture_pipeline =
             gpu.device()
@@ -576,6 +587,7 @@ impl TerrainBuffer {
 
         Ok(Self {
             patch_manager,
+            overlay_displacement,
             toggle_pin_camera: false,
             pinned_camera: None,
             visible_regions: Vec::new(),
@@ -865,11 +877,20 @@ impl TerrainBuffer {
     fn sys_apply_patches_to_height_tiles(
         terrain: Res<TerrainBuffer>,
         camera: Res<ScreenCamera>,
+        streaming: Res<TileStreaming>,
         mut catalog: ResMut<Catalog>,
         mut heights_ts_query: Query<&mut SphericalHeightTileSet>,
+        mut polar_heights_ts_query: Query<&mut PolarHeightTileSet>,
     ) {
         for mut tile_set in heights_ts_query.iter_mut() {
-            tile_set.begin_visibility_update();
+            tile_set.begin_visibility_update(&streaming);
+            for visible_patch in &terrain.visible_regions {
+                tile_set.note_required(visible_patch);
+            }
+            tile_set.finish_visibility_update(&camera, &mut catalog);
+        }
+        for mut tile_set in polar_heights_ts_query.iter_mut() {
+            tile_set.begin_visibility_update(&streaming);
             for visible_patch in &terrain.visible_regions {
                 tile_set.note_required(visible_patch);
             }
@@ -880,11 +901,20 @@ impl TerrainBuffer {
     fn sys_apply_patches_to_normal_tiles(
         terrain: Res<TerrainBuffer>,
         camera: Res<ScreenCamera>,
+        streaming: Res<TileStreaming>,
         mut catalog: ResMut<Catalog>,
         mut normals_ts_query: Query<&mut SphericalNormalsTileSet>,
+        mut polar_normals_ts_query: Query<&mut PolarNormalsTileSet>,
     ) {
         for mut tile_set in normals_ts_query.iter_mut() {
-            tile_set.begin_visibility_update();
+            tile_set.begin_visibility_update(&streaming);
+            for visible_patch in &terrain.visible_regions {
+                tile_set.note_required(visible_patch);
+            }
+            tile_set.finish_visibility_update(&camera, &mut catalog);
+        }
+        for mut tile_set in polar_normals_ts_query.iter_mut() {
+            tile_set.begin_visibility_update(&streaming);
             for visible_patch in &terrain.visible_regions {
                 tile_set.note_required(visible_patch);
             }
@@ -895,11 +925,20 @@ impl TerrainBuffer {
     fn sys_apply_patches_to_color_tiles(
         terrain: Res<TerrainBuffer>,
         camera: Res<ScreenCamera>,
+        streaming: Res<TileStreaming>,
         mut catalog: ResMut<Catalog>,
         mut colors_ts_query: Query<&mut SphericalColorTileSet>,
+        mut polar_colors_ts_query: Query<&mut PolarColorTileSet>,
     ) {
         for mut tile_set in colors_ts_query.iter_mut() {
-            tile_set.begin_visibility_update();
+            tile_set.begin_visibility_update(&streaming);
+            for visible_patch in &terrain.visible_regions {
+                tile_set.note_required(visible_patch);
+            }
+            tile_set.finish_visibility_update(&camera, &mut catalog);
+        }
+        for mut tile_set in polar_colors_ts_query.iter_mut() {
+            tile_set.begin_visibility_update(&streaming);
             for visible_patch in &terrain.visible_regions {
                 tile_set.note_required(visible_patch);
             }
@@ -912,19 +951,33 @@ impl TerrainBuffer {
         mut heights_ts_query: Query<&mut SphericalHeightTileSet>,
         mut normals_ts_query: Query<&mut SphericalNormalsTileSet>,
         mut colors_ts_query: Query<&mut SphericalColorTileSet>,
+        mut polar_heights_ts_query: Query<&mut PolarHeightTileSet>,
+        mut polar_normals_ts_query: Query<&mut PolarNormalsTileSet>,
+        mut polar_colors_ts_query: Query<&mut PolarColorTileSet>,
+        mut streaming: ResMut<TileStreaming>,
         gpu: Res<Gpu>,
         maybe_encoder: ResMut<Option<wgpu::CommandEncoder>>,
     ) {
         if let Some(encoder) = maybe_encoder.into_inner() {
             terrain.patch_manager.encode_uploads(&gpu, encoder);
+            streaming.begin_frame();
             for mut tile_set in heights_ts_query.iter_mut() {
-                tile_set.encode_uploads(&gpu, encoder);
+                tile_set.encode_uploads(&mut streaming, &gpu, encoder);
             }
             for mut tile_set in normals_ts_query.iter_mut() {
-                tile_set.encode_uploads(&gpu, encoder);
+                tile_set.encode_uploads(&mut streaming, &gpu, encoder);
             }
             for mut tile_set in colors_ts_query.iter_mut() {
-                tile_set.encode_uploads(&gpu, encoder);
+                tile_set.encode_uploads(&mut streaming, &gpu, encoder);
+            }
+            for mut tile_set in polar_heights_ts_query.iter_mut() {
+                tile_set.encode_uploads(&mut streaming, &gpu, encoder);
+            }
+            for mut tile_set in polar_normals_ts_query.iter_mut() {
+                tile_set.encode_uploads(&mut streaming, &gpu, encoder);
+            }
+            for mut tile_set in polar_colors_ts_query.iter_mut() {
+                tile_set.encode_uploads(&mut streaming, &gpu, encoder);
             }
         }
     }
@@ -933,6 +986,9 @@ impl TerrainBuffer {
         heights_ts_query: Query<&SphericalHeightTileSet>,
         normals_ts_query: Query<&SphericalNormalsTileSet>,
         colors_ts_query: Query<&SphericalColorTileSet>,
+        polar_heights_ts_query: Query<&PolarHeightTileSet>,
+        polar_normals_ts_query: Query<&PolarNormalsTileSet>,
+        polar_colors_ts_query: Query<&PolarColorTileSet>,
         maybe_encoder: ResMut<Option<wgpu::CommandEncoder>>,
     ) {
         if let Some(encoder) = maybe_encoder.into_inner() {
@@ -945,16 +1001,31 @@ impl TerrainBuffer {
             for tile_set in colors_ts_query.iter() {
                 tile_set.paint_atlas_index(encoder);
             }
+            for tile_set in polar_heights_ts_query.iter() {
+                tile_set.paint_atlas_index(encoder);
+            }
+            for tile_set in polar_normals_ts_query.iter() {
+                tile_set.paint_atlas_index(encoder);
+            }
+            for tile_set in polar_colors_ts_query.iter() {
+                tile_set.paint_atlas_index(encoder);
+            }
         }
     }
 
     fn sys_terrain_tesselate(
-        terrain: ResMut<TerrainBuffer>,
+        mut terrain: ResMut<TerrainBuffer>,
         null_ts_query: Query<&NullHeightTileSet, Without<SphericalHeightTileSet>>,
         heights_ts_query: Query<&SphericalHeightTileSet>,
+        polar_heights_ts_query: Query<&PolarHeightTileSet>,
+        overlays: Res<TerrainOverlays>,
+        gpu: Res<Gpu>,
         maybe_encoder: ResMut<Option<wgpu::CommandEncoder>>,
     ) {
         if let Some(encoder) = maybe_encoder.into_inner() {
+            terrain
+                .overlay_displacement
+                .encode_uploads(&overlays, &gpu, encoder);
             terrain.patch_manager.tessellate(encoder);
 
             for tile_set in null_ts_query.iter() {
@@ -971,6 +1042,20 @@ impl TerrainBuffer {
                     encoder,
                 );
             }
+            // Polar heights blend with the spherical heights, so must come after.
+            for tile_set in polar_heights_ts_query.iter() {
+                tile_set.displace_height(
+                    terrain.patch_manager.target_vertex_count(),
+                    terrain.patch_manager.displace_height_bind_group(),
+                    encoder,
+                );
+            }
+            // Overlays modify whatever height the tile sets settled on.
+            terrain.overlay_displacement.displace_height(
+                terrain.patch_manager.target_vertex_count(),
+                terrain.patch_manager.displace_height_bind_group(),
+                encoder,
+            );
         }
     }
 
@@ -1056,13 +1141,15 @@ impl TerrainBuffer {
         terrain: Res<TerrainBuffer>,
         normals_ts_query: Query<&SphericalNormalsTileSet>,
         colors_ts_query: Query<&SphericalColorTileSet>,
+        polar_normals_ts_query: Query<&PolarNormalsTileSet>,
+        polar_colors_ts_query: Query<&PolarColorTileSet>,
         globals: Res<GlobalParametersBuffer>,
         maybe_encoder: ResMut<Option<wgpu::CommandEncoder>>,
     ) {
         if let Some(encoder) = maybe_encoder.into_inner() {
             terrain.clear_accumulator(&globals, encoder);
-            terrain.accumulate_normals(normals_ts_query, &globals, encoder);
-            terrain.accumulate_colors(colors_ts_query, &globals, encoder);
+            terrain.accumulate_normals(normals_ts_query, polar_normals_ts_query, &globals, encoder);
+            terrain.accumulate_colors(colors_ts_query, polar_colors_ts_query, &globals, encoder);
         }
     }
 
@@ -1087,6 +1174,7 @@ impl TerrainBuffer {
     fn accumulate_normals(
         &self,
         normals_ts_query: Query<&SphericalNormalsTileSet>,
+        polar_normals_ts_query: Query<&PolarNormalsTileSet>,
         globals: &GlobalParametersBuffer,
         encoder: &mut wgpu::CommandEncoder,
     ) {
@@ -1098,11 +1186,21 @@ impl TerrainBuffer {
                 encoder,
             );
         }
+        // Polar normals blend over the spherical normals near the poles.
+        for tile_set in polar_normals_ts_query.iter() {
+            tile_set.accumulate_normals(
+                &self.acc_extent,
+                globals,
+                &self.accumulate_common_bind_group,
+                encoder,
+            );
+        }
     }
 
     fn accumulate_colors(
         &self,
         colors_ts_query: Query<&SphericalColorTileSet>,
+        polar_colors_ts_query: Query<&PolarColorTileSet>,
         globals: &GlobalParametersBuffer,
         encoder: &mut wgpu::CommandEncoder,
     ) {
@@ -1114,12 +1212,23 @@ impl TerrainBuffer {
                 encoder,
             );
         }
+        for tile_set in polar_colors_ts_query.iter() {
+            tile_set.accumulate_colors(
+                &self.acc_extent,
+                globals,
+                &self.accumulate_common_bind_group,
+                encoder,
+            );
+        }
     }
 
     fn sys_handle_capture_snapshot(
         mut heights_ts_query: Query<&mut SphericalHeightTileSet>,
         mut normals_ts_query: Query<&mut SphericalNormalsTileSet>,
         mut colors_ts_query: Query<&mut SphericalColorTileSet>,
+        mut polar_heights_ts_query: Query<&mut PolarHeightTileSet>,
+        mut polar_normals_ts_query: Query<&mut PolarNormalsTileSet>,
+        mut polar_colors_ts_query: Query<&mut PolarColorTileSet>,
         mut gpu: ResMut<Gpu>,
     ) {
         for mut tile_set in heights_ts_query.iter_mut() {
@@ -1131,12 +1240,24 @@ impl TerrainBuffer {
         for mut tile_set in colors_ts_query.iter_mut() {
             tile_set.snapshot_index(&mut gpu);
         }
+        for mut tile_set in polar_heights_ts_query.iter_mut() {
+            tile_set.snapshot_index(&mut gpu);
+        }
+        for mut tile_set in polar_normals_ts_query.iter_mut() {
+            tile_set.snapshot_index(&mut gpu);
+        }
+        for mut tile_set in polar_colors_ts_query.iter_mut() {
+            tile_set.snapshot_index(&mut gpu);
+        }
     }
 
     fn sys_shutdown_safely(
         mut heights_ts_query: Query<&mut SphericalHeightTileSet>,
         mut normals_ts_query: Query<&mut SphericalNormalsTileSet>,
         mut colors_ts_query: Query<&mut SphericalColorTileSet>,
+        mut polar_heights_ts_query: Query<&mut PolarHeightTileSet>,
+        mut polar_normals_ts_query: Query<&mut PolarNormalsTileSet>,
+        mut polar_colors_ts_query: Query<&mut PolarColorTileSet>,
     ) {
         for mut tile_set in heights_ts_query.iter_mut() {
             tile_set.shutdown_safely();
@@ -1147,6 +1268,15 @@ impl TerrainBuffer {
         for mut tile_set in colors_ts_query.iter_mut() {
             tile_set.shutdown_safely();
         }
+        for mut tile_set in polar_heights_ts_query.iter_mut() {
+            tile_set.shutdown_safely();
+        }
+        for mut tile_set in polar_normals_ts_query.iter_mut() {
+            tile_set.shutdown_safely();
+        }
+        for mut tile_set in polar_colors_ts_query.iter_mut() {
+            tile_set.shutdown_safely();
+        }
     }
 
     pub fn accumulator_extent(&self) -> &wgpu::Extent3d {
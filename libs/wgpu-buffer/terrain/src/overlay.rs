@@ -0,0 +1,774 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{patch::PatchManager, tile::TerrainElevation};
+use absolute_unit::{degrees, meters, Angle, Length, Meters, Radians};
+use anyhow::{bail, ensure, Result};
+use bevy_ecs::prelude::*;
+use geodesy::{GeoSurface, Graticule, GraticuleOrigin};
+use geometry::planar::{local_offset, polygon_distance, segment_distance};
+use gpu::Gpu;
+use log::warn;
+use nitrous::{inject_nitrous_resource, method, NitrousResource};
+use physical_constants::EARTH_RADIUS;
+use runtime::{Extension, Runtime};
+use shader_shared::Group;
+use std::{collections::BTreeMap, mem, num::NonZeroU64, sync::Arc};
+use zerocopy::{AsBytes, FromBytes};
+
+// Capacity of the GPU overlay buffers. Overlays past these limits still apply to CPU height
+// queries, but are not drawn.
+const MAX_GPU_OVERLAYS: usize = 256;
+const MAX_GPU_OVERLAY_POINTS: usize = 4096;
+
+// Embankment sides fall away at 2 horizontal to 1 vertical.
+const EMBANKMENT_SIDE_SLOPE: f64 = 2.;
+
+// Craters throw up a rim a quarter of their depth high, which fades out by 1.5 radii.
+const CRATER_RIM_FRACTION: f64 = 0.25;
+const CRATER_RIM_EXTENT: f64 = 1.5;
+
+const KIND_FLATTEN: u32 = 1;
+const KIND_CRATER: u32 = 2;
+const KIND_EMBANKMENT: u32 = 3;
+
+/// The shape of a local change to the terrain height. All distances are in meters, and
+/// all points are in meters east and north of the overlay's center.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OverlayShape {
+    /// Set the ground inside `points` to `height` above sea level, blending back to the
+    /// natural ground over `margin` meters outside of the polygon.
+    Flatten {
+        points: Vec<[f64; 2]>,
+        height: f64,
+        margin: f64,
+    },
+    /// Dig a bowl of `radius` and `depth` at the center, with a low rim around it.
+    Crater { radius: f64, depth: f64 },
+    /// Raise a bank of `height` and `width` from the center to `end`, with sloped sides.
+    Embankment {
+        end: [f64; 2],
+        width: f64,
+        height: f64,
+    },
+}
+
+/// A local change to the terrain height, centered at a point on the surface. Overlays apply
+/// on top of the layer pack heights, in both the GPU displacement pass and CPU queries
+/// through `TerrainElevation`.
+///
+/// Attach one to an entity to have it follow that entity's lifetime, or register one
+/// through the `terrain_overlays` resource.
+#[derive(Clone, Debug, Component)]
+pub struct HeightOverlay {
+    center_lat: Angle<Radians>,
+    center_lon: Angle<Radians>,
+    shape: OverlayShape,
+}
+
+impl HeightOverlay {
+    pub fn new<Origin: GraticuleOrigin>(center: &Graticule<Origin>, shape: OverlayShape) -> Self {
+        Self {
+            center_lat: center.lat::<Radians>(),
+            center_lon: center.lon::<Radians>(),
+            shape,
+        }
+    }
+
+    pub fn flatten<Origin: GraticuleOrigin>(
+        center: &Graticule<Origin>,
+        points: Vec<[f64; 2]>,
+        height: Length<Meters>,
+        margin: Length<Meters>,
+    ) -> Result<Self> {
+        ensure!(
+            points.len() >= 3,
+            "a flattened area needs at least 3 points"
+        );
+        ensure!(
+            margin.f64() >= 0.,
+            "a flattened area needs a non-negative margin"
+        );
+        Ok(Self::new(
+            center,
+            OverlayShape::Flatten {
+                points,
+                height: height.f64(),
+                margin: margin.f64(),
+            },
+        ))
+    }
+
+    /// A flattened rectangle, as for a runway, `length` long along `heading` (in degrees
+    /// clockwise from north) and `width` wide.
+    pub fn flatten_rect<Origin: GraticuleOrigin>(
+        center: &Graticule<Origin>,
+        heading_deg: f64,
+        length: Length<Meters>,
+        width: Length<Meters>,
+        height: Length<Meters>,
+        margin: Length<Meters>,
+    ) -> Result<Self> {
+        let (sin, cos) = heading_deg.to_radians().sin_cos();
+        let along = [sin * length.f64() / 2., cos * length.f64() / 2.];
+        let across = [cos * width.f64() / 2., -sin * width.f64() / 2.];
+        let points = [(1., 1.), (1., -1.), (-1., -1.), (-1., 1.)]
+            .iter()
+            .map(|(a, b)| [a * along[0] + b * across[0], a * along[1] + b * across[1]])
+            .collect();
+        Self::flatten(center, points, height, margin)
+    }
+
+    pub fn crater<Origin: GraticuleOrigin>(
+        center: &Graticule<Origin>,
+        radius: Length<Meters>,
+        depth: Length<Meters>,
+    ) -> Result<Self> {
+        ensure!(radius.f64() > 0., "a crater needs a positive radius");
+        Ok(Self::new(
+            center,
+            OverlayShape::Crater {
+                radius: radius.f64(),
+                depth: depth.f64(),
+            },
+        ))
+    }
+
+    pub fn embankment<Origin: GraticuleOrigin>(
+        start: &Graticule<Origin>,
+        end: &Graticule<Origin>,
+        width: Length<Meters>,
+        height: Length<Meters>,
+    ) -> Result<Self> {
+        ensure!(width.f64() > 0., "an embankment needs a positive width");
+        let mut overlay = Self::new(
+            start,
+            OverlayShape::Embankment {
+                end: [0., 0.],
+                width: width.f64(),
+                height: height.f64(),
+            },
+        );
+        let end_offset =
+            overlay.local_offset(end.lat::<Radians>().f64(), end.lon::<Radians>().f64());
+        if let OverlayShape::Embankment { end, .. } = &mut overlay.shape {
+            *end = end_offset;
+        }
+        Ok(overlay)
+    }
+
+    pub fn center(&self) -> Graticule<GeoSurface> {
+        Graticule::new(self.center_lat, self.center_lon, meters!(0))
+    }
+
+    pub fn shape(&self) -> &OverlayShape {
+        &self.shape
+    }
+
+    /// Distance from the center, in meters, past which this overlay has no effect.
+    pub fn bound_radius(&self) -> f64 {
+        match &self.shape {
+            OverlayShape::Flatten { points, margin, .. } => {
+                points.iter().map(|p| p[0].hypot(p[1])).fold(0., f64::max) + margin
+            }
+            OverlayShape::Crater { radius, .. } => radius * CRATER_RIM_EXTENT,
+            OverlayShape::Embankment { end, width, height } => {
+                end[0].hypot(end[1]) + width / 2. + height.abs() * EMBANKMENT_SIDE_SLOPE
+            }
+        }
+    }
+
+    // Meters east and north of our center. This is a flat approximation, which is fine at
+    // the size of an airbase, but not for anything spanning a large fraction of the globe.
+    fn local_offset(&self, lat: f64, lon: f64) -> [f64; 2] {
+        local_offset(
+            [self.center_lat.f64(), self.center_lon.f64()],
+            [lat, lon],
+            EARTH_RADIUS.f64(),
+        )
+    }
+
+    /// Apply this overlay to `height` at the given latitude and longitude in radians.
+    pub fn apply(&self, lat: f64, lon: f64, height: f64) -> f64 {
+        let p = self.local_offset(lat, lon);
+        if p[0].hypot(p[1]) > self.bound_radius() {
+            return height;
+        }
+        match &self.shape {
+            OverlayShape::Flatten {
+                points,
+                height: target,
+                margin,
+            } => {
+                let d = polygon_distance(points, p);
+                if d <= 0. {
+                    *target
+                } else if d < *margin {
+                    height + (target - height) * smoothstep(1. - d / margin)
+                } else {
+                    height
+                }
+            }
+            OverlayShape::Crater { radius, depth } => height + crater_profile(*radius, *depth, p),
+            OverlayShape::Embankment {
+                end,
+                width,
+                height: bank,
+            } => {
+                let d = segment_distance([0., 0.], *end, p) - width / 2.;
+                let side = bank.abs() * EMBANKMENT_SIDE_SLOPE;
+                if d <= 0. {
+                    height + bank
+                } else if d < side {
+                    height + bank * (1. - d / side)
+                } else {
+                    height
+                }
+            }
+        }
+    }
+}
+
+fn smoothstep(t: f64) -> f64 {
+    let t = t.clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+// Height change at `p` from a crater centered on the origin: a parabolic bowl that rises to
+// a rim at the crater's edge, then falls smoothly back to the ground outside.
+fn crater_profile(radius: f64, depth: f64, p: [f64; 2]) -> f64 {
+    let r = p[0].hypot(p[1]) / radius;
+    let rim = depth * CRATER_RIM_FRACTION;
+    if r < 1. {
+        -depth * (1. - r * r) + rim * r * r
+    } else if r < CRATER_RIM_EXTENT {
+        rim * (1. - smoothstep((r - 1.) / (CRATER_RIM_EXTENT - 1.)))
+    } else {
+        0.
+    }
+}
+
+// Parse "east,north;east,north;..." into points.
+fn parse_points(points: &str) -> Result<Vec<[f64; 2]>> {
+    let mut out = Vec::new();
+    for pair in points.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+        let parts = pair.split(',').map(str::trim).collect::<Vec<_>>();
+        if parts.len() != 2 {
+            bail!("expected a point as 'east,north' in meters, not '{}'", pair);
+        }
+        out.push([parts[0].parse()?, parts[1].parse()?]);
+    }
+    Ok(out)
+}
+
+fn surface(lat_deg: f64, lon_deg: f64) -> Graticule<GeoSurface> {
+    Graticule::new(degrees!(lat_deg), degrees!(lon_deg), meters!(0))
+}
+
+/// All of the height overlays in the world, from both scripts and entities.
+///
+/// Scripts add overlays with the shape methods, which return a handle that can be passed
+/// to `remove`. Entities with a `HeightOverlay` component are picked up every sim step.
+/// Scripted overlays apply first, in the order they were added, then entity overlays.
+/// Flattened areas replace the height under them, so a crater inside a runway needs to be
+/// added after the runway to show up.
+///
+/// Overlays only move the terrain mesh and change height queries. The normal tiles are not
+/// recomputed under them, so the ground is still lit as if it had its natural shape.
+#[derive(Debug, NitrousResource)]
+pub struct TerrainOverlays {
+    next_id: i64,
+    scripted: BTreeMap<i64, HeightOverlay>,
+    merged: Arc<Vec<HeightOverlay>>,
+    generation: u64,
+    dirty: bool,
+}
+
+impl Extension for TerrainOverlays {
+    fn init(runtime: &mut Runtime) -> Result<()> {
+        if runtime.maybe_resource::<TerrainOverlays>().is_some() {
+            return Ok(());
+        }
+        runtime.insert_named_resource("terrain_overlays", TerrainOverlays::default());
+        runtime.add_sim_system(Self::sys_merge_overlays);
+        Ok(())
+    }
+}
+
+impl Default for TerrainOverlays {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            scripted: BTreeMap::new(),
+            merged: Arc::new(Vec::new()),
+            generation: 0,
+            dirty: true,
+        }
+    }
+}
+
+#[inject_nitrous_resource]
+impl TerrainOverlays {
+    /// Add an overlay and return its handle.
+    pub fn add(&mut self, overlay: HeightOverlay) -> i64 {
+        self.next_id += 1;
+        self.scripted.insert(self.next_id, overlay);
+        self.dirty = true;
+        self.next_id
+    }
+
+    /// All overlays, in the order they apply.
+    pub fn overlays(&self) -> &Arc<Vec<HeightOverlay>> {
+        &self.merged
+    }
+
+    /// Bumped every time the set of overlays changes.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn sys_merge_overlays(
+        mut overlays: ResMut<TerrainOverlays>,
+        query: Query<&HeightOverlay>,
+        changed: Query<(), Changed<HeightOverlay>>,
+        removed: RemovedComponents<HeightOverlay>,
+        elevation: Option<ResMut<TerrainElevation>>,
+    ) {
+        let entities_changed = !changed.is_empty() || removed.iter().next().is_some();
+        if !overlays.dirty && !entities_changed {
+            return;
+        }
+        let mut merged = overlays.scripted.values().cloned().collect::<Vec<_>>();
+        merged.extend(query.iter().cloned());
+        overlays.merged = Arc::new(merged);
+        overlays.generation += 1;
+        overlays.dirty = false;
+        if let Some(mut elevation) = elevation {
+            elevation.set_overlays(overlays.merged.clone());
+        }
+    }
+
+    /// Flatten a polygon around a center in degrees, given as "east,north;..." in meters from
+    /// the center, to `height` meters above sea level.
+    #[method]
+    pub fn flatten_polygon(
+        &mut self,
+        lat: f64,
+        lon: f64,
+        points: &str,
+        height: f64,
+        margin: f64,
+    ) -> Result<i64> {
+        Ok(self.add(HeightOverlay::flatten(
+            &surface(lat, lon),
+            parse_points(points)?,
+            meters!(height),
+            meters!(margin),
+        )?))
+    }
+
+    /// Flatten a rectangle, as for a runway, centered at a point in degrees and running
+    /// `length` meters along `heading` degrees.
+    #[allow(clippy::too_many_arguments)]
+    #[method]
+    pub fn flatten_rect(
+        &mut self,
+        lat: f64,
+        lon: f64,
+        heading: f64,
+        length: f64,
+        width: f64,
+        height: f64,
+        margin: f64,
+    ) -> Result<i64> {
+        Ok(self.add(HeightOverlay::flatten_rect(
+            &surface(lat, lon),
+            heading,
+            meters!(length),
+            meters!(width),
+            meters!(height),
+            meters!(margin),
+        )?))
+    }
+
+    #[method]
+    pub fn crater(&mut self, lat: f64, lon: f64, radius: f64, depth: f64) -> Result<i64> {
+        Ok(self.add(HeightOverlay::crater(
+            &surface(lat, lon),
+            meters!(radius),
+            meters!(depth),
+        )?))
+    }
+
+    #[method]
+    pub fn embankment(
+        &mut self,
+        lat0: f64,
+        lon0: f64,
+        lat1: f64,
+        lon1: f64,
+        width: f64,
+        height: f64,
+    ) -> Result<i64> {
+        Ok(self.add(HeightOverlay::embankment(
+            &surface(lat0, lon0),
+            &surface(lat1, lon1),
+            meters!(width),
+            meters!(height),
+        )?))
+    }
+
+    #[method]
+    pub fn remove(&mut self, id: i64) -> bool {
+        let removed = self.scripted.remove(&id).is_some();
+        self.dirty |= removed;
+        removed
+    }
+
+    #[method]
+    pub fn clear(&mut self) {
+        self.dirty |= !self.scripted.is_empty();
+        self.scripted.clear();
+    }
+
+    #[method]
+    pub fn list(&self) -> String {
+        let mut out = String::new();
+        for (id, overlay) in &self.scripted {
+            let center = overlay.center();
+            out += &format!("{}: {} {:?}\n", id, center, overlay.shape());
+        }
+        out
+    }
+}
+
+// GPU side copy of an overlay. Shape parameters are packed per kind; see
+// displace_overlays.comp.glsl for the details.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, Copy, Clone, Default, Debug)]
+struct OverlayInfo {
+    center_lat: f32,
+    center_lon: f32,
+    bound_radius: f32,
+    kind: u32,
+    height: f32,
+    size: f32,
+    end: [f32; 2],
+    point_start: u32,
+    point_count: u32,
+    pad: [u32; 2],
+}
+
+/// Applies the height overlays to the terrain mesh after the tile sets have displaced it.
+#[derive(Debug)]
+pub(crate) struct OverlayDisplacement {
+    overlays_buffer: Arc<wgpu::Buffer>,
+    points_buffer: Arc<wgpu::Buffer>,
+    count_buffer: Arc<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+    overlay_count: u32,
+    uploaded_generation: Option<u64>,
+}
+
+impl OverlayDisplacement {
+    pub(crate) fn new(patch_manager: &PatchManager, gpu: &Gpu) -> Self {
+        let overlays_size = (mem::size_of::<OverlayInfo>() * MAX_GPU_OVERLAYS) as u64;
+        let points_size = (mem::size_of::<[f32; 2]>() * MAX_GPU_OVERLAY_POINTS) as u64;
+        let count_size = mem::size_of::<[u32; 4]>() as u64;
+        let make_buffer = |label, size, usage| {
+            Arc::new(gpu.device().create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: usage | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }))
+        };
+        let overlays_buffer = make_buffer(
+            "terrain-overlays-buffer",
+            overlays_size,
+            wgpu::BufferUsages::STORAGE,
+        );
+        let points_buffer = make_buffer(
+            "terrain-overlay-points-buffer",
+            points_size,
+            wgpu::BufferUsages::STORAGE,
+        );
+        let count_buffer = make_buffer(
+            "terrain-overlay-count-buffer",
+            count_size,
+            wgpu::BufferUsages::UNIFORM,
+        );
+
+        let storage_entry = |binding, size| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: NonZeroU64::new(size),
+            },
+            count: None,
+        };
+        let bind_group_layout =
+            gpu.device()
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("terrain-overlays-bind-group-layout"),
+                    entries: &[
+                        storage_entry(0, overlays_size),
+                        storage_entry(1, points_size),
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: NonZeroU64::new(count_size),
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+        let bind_group = gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("terrain-overlays-bind-group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: overlays_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: points_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: count_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline = gpu
+            .device()
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("terrain-overlays-displace-pipeline"),
+                layout: Some(&gpu.device().create_pipeline_layout(
+                    &wgpu::PipelineLayoutDescriptor {
+                        label: Some("terrain-overlays-displace-pipeline-layout"),
+                        push_constant_ranges: &[],
+                        bind_group_layouts: &[
+                            patch_manager.displace_height_bind_group_layout(),
+                            &bind_group_layout,
+                        ],
+                    },
+                )),
+                module: &gpu.create_shader_module(
+                    "displace_overlays.comp",
+                    include_bytes!("../target/displace_overlays.comp.spirv"),
+                ),
+                entry_point: "main",
+            });
+
+        Self {
+            overlays_buffer,
+            points_buffer,
+            count_buffer,
+            bind_group,
+            pipeline,
+            overlay_count: 0,
+            uploaded_generation: None,
+        }
+    }
+
+    /// Copy the overlays to the GPU, if they have changed since the last upload.
+    pub(crate) fn encode_uploads(
+        &mut self,
+        overlays: &TerrainOverlays,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if self.uploaded_generation == Some(overlays.generation()) {
+            return;
+        }
+        self.uploaded_generation = Some(overlays.generation());
+
+        let mut infos = Vec::new();
+        let mut points = Vec::new();
+        for overlay in overlays.overlays().iter() {
+            if infos.len() == MAX_GPU_OVERLAYS {
+                warn!(
+                    "too many terrain overlays; only drawing the first {}",
+                    MAX_GPU_OVERLAYS
+                );
+                break;
+            }
+            let mut info = OverlayInfo {
+                center_lat: overlay.center_lat.f64() as f32,
+                center_lon: overlay.center_lon.f64() as f32,
+                bound_radius: overlay.bound_radius() as f32,
+                ..Default::default()
+            };
+            match &overlay.shape {
+                OverlayShape::Flatten {
+                    points: polygon,
+                    height,
+                    margin,
+                } => {
+                    if points.len() + polygon.len() > MAX_GPU_OVERLAY_POINTS {
+                        warn!("too many terrain overlay points; skipping a flattened area");
+                        continue;
+                    }
+                    info.kind = KIND_FLATTEN;
+                    info.height = *height as f32;
+                    info.size = *margin as f32;
+                    info.point_start = points.len() as u32;
+                    info.point_count = polygon.len() as u32;
+                    points.extend(polygon.iter().map(|p| [p[0] as f32, p[1] as f32]));
+                }
+                OverlayShape::Crater { radius, depth } => {
+                    info.kind = KIND_CRATER;
+                    info.height = *depth as f32;
+                    info.size = *radius as f32;
+                }
+                OverlayShape::Embankment { end, width, height } => {
+                    info.kind = KIND_EMBANKMENT;
+                    info.height = *height as f32;
+                    info.size = *width as f32;
+                    info.end = [end[0] as f32, end[1] as f32];
+                }
+            }
+            infos.push(info);
+        }
+
+        self.overlay_count = infos.len() as u32;
+        gpu.upload_slice_to(
+            "terrain-overlays-upload",
+            &infos,
+            self.overlays_buffer.clone(),
+            encoder,
+        );
+        gpu.upload_slice_to(
+            "terrain-overlay-points-upload",
+            &points,
+            self.points_buffer.clone(),
+            encoder,
+        );
+        gpu.upload_slice_to(
+            "terrain-overlay-count-upload",
+            &[self.overlay_count, 0, 0, 0],
+            self.count_buffer.clone(),
+            encoder,
+        );
+    }
+
+    pub(crate) fn displace_height(
+        &self,
+        vertex_count: u32,
+        mesh_bind_group: &wgpu::BindGroup,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if self.overlay_count == 0 {
+            return;
+        }
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("terrain-overlays-displace-cpass"),
+        });
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(Group::TerrainDisplaceMesh.index(), mesh_bind_group, &[]);
+        cpass.set_bind_group(Group::TerrainDisplaceTileSet.index(), &self.bind_group, &[]);
+        const WORKGROUP_WIDTH: u32 = 65536;
+        let wg_x = (vertex_count % WORKGROUP_WIDTH).max(1);
+        let wg_y = (vertex_count / WORKGROUP_WIDTH).max(1);
+        cpass.dispatch_workgroups(wg_x, wg_y, 1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::assert_relative_eq;
+    use std::f64::consts::PI;
+
+    // Latitude and longitude in radians of a point `east` and `north` meters from `center`.
+    fn offset(center: &Graticule<GeoSurface>, east: f64, north: f64) -> (f64, f64) {
+        let lat = center.lat::<Radians>().f64();
+        let lon = center.lon::<Radians>().f64();
+        let radius = EARTH_RADIUS.f64();
+        (lat + north / radius, lon + east / (radius * lat.cos()))
+    }
+
+    #[test]
+    fn test_flatten_rect() -> Result<()> {
+        let center = surface(45., 7.);
+        let runway = HeightOverlay::flatten_rect(
+            &center,
+            90.,
+            meters!(2000),
+            meters!(50),
+            meters!(300),
+            meters!(100),
+        )?;
+        // On the runway, along its length to the east.
+        let (lat, lon) = offset(&center, 900., 10.);
+        assert_relative_eq!(runway.apply(lat, lon, 350.), 300.);
+        // Off the north edge, half way through the margin.
+        let (lat, lon) = offset(&center, 0., 75.);
+        assert_relative_eq!(runway.apply(lat, lon, 400.), 350., epsilon = 0.5);
+        // Past the margin, and past the end of the runway.
+        let (lat, lon) = offset(&center, 0., 200.);
+        assert_relative_eq!(runway.apply(lat, lon, 400.), 400.);
+        let (lat, lon) = offset(&center, 1200., 0.);
+        assert_relative_eq!(runway.apply(lat, lon, 400.), 400.);
+        Ok(())
+    }
+
+    #[test]
+    fn test_crater_and_embankment() -> Result<()> {
+        let center = surface(-10., 179.99999);
+        let crater = HeightOverlay::crater(&center, meters!(10), meters!(4))?;
+        let (lat, lon) = offset(&center, 0., 0.);
+        assert_relative_eq!(crater.apply(lat, lon, 100.), 96.);
+        // The rim, across the date line from the center.
+        let (lat, lon) = offset(&center, 10., 0.);
+        assert!(lon > PI);
+        assert_relative_eq!(crater.apply(lat, lon, 100.), 101., epsilon = 0.01);
+        let (lat, lon) = offset(&center, 20., 0.);
+        assert_relative_eq!(crater.apply(lat, lon, 100.), 100.);
+
+        let (lat1, lon1) = offset(&center, 0., 100.);
+        let end = Graticule::<GeoSurface>::new(
+            degrees!(lat1.to_degrees()),
+            degrees!(lon1.to_degrees()),
+            meters!(0),
+        );
+        let bank = HeightOverlay::embankment(&center, &end, meters!(10), meters!(3))?;
+        let (lat, lon) = offset(&center, 2., 50.);
+        assert_relative_eq!(bank.apply(lat, lon, 0.), 3., epsilon = 0.01);
+        let (lat, lon) = offset(&center, 8., 50.);
+        assert_relative_eq!(bank.apply(lat, lon, 0.), 1.5, epsilon = 0.01);
+        let (lat, lon) = offset(&center, 0., 120.);
+        assert_relative_eq!(bank.apply(lat, lon, 0.), 0.);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_points() -> Result<()> {
+        assert_eq!(
+            parse_points("0,0; 10, 0;10,10;")?,
+            vec![[0., 0.], [10., 0.], [10., 10.]]
+        );
+        assert!(parse_points("0,0;10").is_err());
+        Ok(())
+    }
+}
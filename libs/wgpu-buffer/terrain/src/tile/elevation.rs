@@ -0,0 +1,712 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    overlay::{HeightOverlay, TerrainOverlays},
+    tile::{
+        layer_pack::unpack_tile, polar::PolarProjection, tile_builder::TileSetBuilder,
+        DataSetCoordinates, DataSetDataKind, LayerPack, TerrainLevel, TileCompression,
+        TilePixelFormat, TILE_PHYSICAL_SIZE,
+    },
+};
+use absolute_unit::{degrees, meters, ArcSeconds, Length, Meters, Radians};
+use anyhow::{ensure, Result};
+use catalog::{Catalog, FileId};
+use fxhash::FxHashMap;
+use geodesy::{Cartesian, GeoCenter, GeoSurface, Graticule, GraticuleOrigin};
+use geometry::{
+    intersect::{height_field_vs_ray, sphere_vs_ray},
+    Ray, Sphere,
+};
+use log::{info, warn};
+use nalgebra::{Point3, Vector3};
+use nitrous::{inject_nitrous_resource, method, HeapRef, NitrousResource};
+use parking_lot::Mutex;
+use physical_constants::EARTH_RADIUS;
+use runtime::{Extension, Runtime};
+use std::{ops::Range, sync::Arc};
+
+// Each decompressed tile is 512KiB, so this holds 32MiB of heights; far more than a handful
+// of vehicles need, even if they are spread out.
+const DEFAULT_CACHE_CAPACITY: usize = 64;
+
+// Nothing in the height data is higher than this, so rays above it cannot hit the ground.
+const MAX_TERRAIN_HEIGHT_M: f64 = 9_000.;
+
+// The smallest step we take when marching a ray over the terrain; about one arcsecond, which
+// is the finest sample spacing we build tiles for.
+const DEFAULT_MARCH_STEP_M: f64 = 30.;
+
+// Line of sight stops this short of the far end, so that targets sitting on the ground are
+// not hidden by the ground they are sitting on.
+const LINE_OF_SIGHT_MARGIN_M: f64 = 2.;
+
+// The tiles of one layer pack, by base lat/lon in arcseconds.
+#[derive(Debug)]
+struct ElevationLayer {
+    file_id: FileId,
+    compression: TileCompression,
+    angular_extent_as: i32,
+    // File extent and checksum of each tile.
+    tiles: FxHashMap<(i32, i32), (Range<usize>, Option<u32>)>,
+}
+
+impl ElevationLayer {
+    fn new(layer_pack: &LayerPack, catalog: &Catalog) -> Result<Self> {
+        ensure!(
+            layer_pack
+                .pixel_format()
+                .is_none_or(|format| format == TilePixelFormat::R16Sint),
+            "layer pack for level {} does not hold heights",
+            layer_pack.terrain_level().offset()
+        );
+        let mut tiles = FxHashMap::default();
+        for entry in layer_pack.entries(catalog)? {
+            tiles.insert(
+                entry.base,
+                (
+                    entry.extent.start as usize..entry.extent.end as usize,
+                    entry.checksum,
+                ),
+            );
+        }
+        Ok(Self {
+            file_id: layer_pack.file_id(),
+            compression: layer_pack.tile_compression(),
+            angular_extent_as: layer_pack.angular_extent_as(),
+            tiles,
+        })
+    }
+}
+
+// Tiles at every level are aligned to the root tile, so we can find the base of the tile that
+// would contain a point without walking the tree.
+fn tile_base(root_as: i32, extent_as: i32, lat_as: f64, lon_as: f64) -> (i32, i32) {
+    let lat = ((lat_as - root_as as f64) / extent_as as f64).floor() as i32;
+    let lon = ((lon_as - root_as as f64) / extent_as as f64).floor() as i32;
+    (root_as + lat * extent_as, root_as + lon * extent_as)
+}
+
+// Latitude and longitude in arcseconds, with longitude wrapped into [-180, 180) degrees.
+fn arcseconds_of<Origin: GraticuleOrigin>(grat: &Graticule<Origin>) -> (f64, f64) {
+    const HALF_TURN_AS: f64 = 180. * 3600.;
+    let mut lon_as = grat.lon::<ArcSeconds>().f64() % (2. * HALF_TURN_AS);
+    if lon_as >= HALF_TURN_AS {
+        lon_as -= 2. * HALF_TURN_AS;
+    } else if lon_as < -HALF_TURN_AS {
+        lon_as += 2. * HALF_TURN_AS;
+    }
+    (grat.lat::<ArcSeconds>().f64(), lon_as)
+}
+
+// All levels of one height data set, coarsest first.
+#[derive(Debug)]
+struct ElevationDataSet {
+    prefix: String,
+    coordinates: DataSetCoordinates,
+    layers: Vec<ElevationLayer>,
+}
+
+impl ElevationDataSet {
+    fn new(prefix: &str, coordinates: DataSetCoordinates, catalog: &Catalog) -> Result<Self> {
+        let mut layer_packs = Vec::new();
+        let layer_glob = format!("{}-L??.mip", prefix);
+        for layer_fid in catalog.find_glob_with_extension(&layer_glob, Some("mip"))? {
+            layer_packs.push(LayerPack::new(layer_fid, catalog)?);
+        }
+        layer_packs.sort_by_key(|lp| *lp.terrain_level());
+        ensure!(!layer_packs.is_empty(), "no layer packs for {}", prefix);
+        let mut layers = Vec::with_capacity(layer_packs.len());
+        for (i, lp) in layer_packs.iter().enumerate() {
+            ensure!(
+                lp.terrain_level() == &TerrainLevel::new(i),
+                "missing level {} in {}",
+                i,
+                prefix
+            );
+            layers.push(ElevationLayer::new(lp, catalog)?);
+        }
+        Ok(Self {
+            prefix: prefix.to_owned(),
+            coordinates,
+            layers,
+        })
+    }
+
+    // Find the finest tile that covers the given point, if any.
+    fn finest_tile(&self, root_as: i32, lat_as: f64, lon_as: f64) -> Option<(usize, (i32, i32))> {
+        for (level, layer) in self.layers.iter().enumerate().rev() {
+            let base = tile_base(root_as, layer.angular_extent_as, lat_as, lon_as);
+            if layer.tiles.contains_key(&base) {
+                return Some((level, base));
+            }
+        }
+        None
+    }
+}
+
+// Identifies a tile by data set, level, and base.
+type TileKey = (usize, usize, (i32, i32));
+
+#[derive(Debug)]
+struct CachedTile {
+    samples: Vec<i16>,
+    last_used: u64,
+}
+
+// A small least-recently-used cache of decompressed tiles. Queries tend to come from a few
+// places that move slowly with respect to the tile size, so almost every lookup hits.
+#[derive(Debug)]
+struct TileCache {
+    capacity: usize,
+    clock: u64,
+    tiles: FxHashMap<TileKey, CachedTile>,
+}
+
+impl TileCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            tiles: FxHashMap::default(),
+        }
+    }
+
+    fn get(&mut self, key: &TileKey) -> Option<&[i16]> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.tiles.get_mut(key).map(|tile| {
+            tile.last_used = clock;
+            tile.samples.as_slice()
+        })
+    }
+
+    fn insert(&mut self, key: TileKey, samples: Vec<i16>) {
+        while self.tiles.len() >= self.capacity.max(1) {
+            let oldest = *self
+                .tiles
+                .iter()
+                .min_by_key(|(_, tile)| tile.last_used)
+                .map(|(key, _)| key)
+                .expect("non-empty cache");
+            self.tiles.remove(&oldest);
+        }
+        self.clock += 1;
+        self.tiles.insert(
+            key,
+            CachedTile {
+                samples,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.tiles.len() > capacity {
+            let oldest = *self
+                .tiles
+                .iter()
+                .min_by_key(|(_, tile)| tile.last_used)
+                .map(|(key, _)| key)
+                .expect("non-empty cache");
+            self.tiles.remove(&oldest);
+        }
+    }
+}
+
+// Bilinearly interpolate a height tile at the given s (longitude) and t (latitude), which span
+// [0,1) over the 510 inner samples of the tile. This mirrors terrain_sample_bilinear_in_tile in
+// terrain.glsl so that the heights we report match the mesh that the GPU displaces.
+fn sample_bilinear(samples: &[i16], tile_s: f64, tile_t: f64) -> f64 {
+    debug_assert_eq!(samples.len(), TILE_PHYSICAL_SIZE * TILE_PHYSICAL_SIZE);
+    let inner = (TILE_PHYSICAL_SIZE - 2) as f64;
+    let u = tile_s.clamp(0., 1.) * inner + 1. - 0.5;
+    let v = tile_t.clamp(0., 1.) * inner + 1. - 0.5;
+    let i = (u.floor() as usize).min(TILE_PHYSICAL_SIZE - 2);
+    let j = (v.floor() as usize).min(TILE_PHYSICAL_SIZE - 2);
+    let a = u - i as f64;
+    let b = v - j as f64;
+    let at = |x: usize, y: usize| samples[y * TILE_PHYSICAL_SIZE + x] as f64;
+    at(i, j) * (1. - a) * (1. - b)
+        + at(i + 1, j) * a * (1. - b)
+        + at(i, j + 1) * (1. - a) * b
+        + at(i + 1, j + 1) * a * b
+}
+
+fn decode_tile(
+    packed: &[u8],
+    checksum: Option<u32>,
+    compression: TileCompression,
+) -> Result<Vec<i16>> {
+    let raw = unpack_tile(
+        packed,
+        checksum,
+        compression,
+        TilePixelFormat::R16Sint,
+        TilePixelFormat::R16Sint,
+    )?;
+    Ok(raw
+        .chunks_exact(2)
+        .map(|c| i16::from_ne_bytes([c[0], c[1]]))
+        .collect())
+}
+
+/// Where a ray first touches the terrain.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainHit {
+    pub position: Cartesian<GeoCenter, Meters>,
+    pub distance: Length<Meters>,
+}
+
+/// Answers "how high is the ground here?" on the CPU, from the same layer packs that the
+/// GPU uses to displace the terrain mesh. Each query is answered from the finest level that
+/// has a tile covering the point. Where there are several height data sets, their heights
+/// are summed, as they are when displacing the mesh. Any `TerrainOverlays` are then applied
+/// on top.
+///
+/// Tiles are read from the Catalog on demand and kept in a small LRU cache, so queries that
+/// move around the world will occasionally stall on a read and decompression.
+///
+/// Rays and line of sight are marched over the terrain in steps no smaller than `march_step`
+/// meters, so ridges thinner than that may be missed.
+#[derive(Debug, NitrousResource)]
+pub struct TerrainElevation {
+    #[property]
+    march_step: f64,
+
+    data_sets: Vec<ElevationDataSet>,
+    root_base_as: i32,
+    cache: Mutex<TileCache>,
+    overlays: Arc<Vec<HeightOverlay>>,
+}
+
+impl Extension for TerrainElevation {
+    fn init(runtime: &mut Runtime) -> Result<()> {
+        runtime.load_extension::<TerrainOverlays>()?;
+        let elevation = TerrainElevation::from_catalog(runtime.resource::<Catalog>())?;
+        runtime.insert_named_resource("elevation", elevation);
+        Ok(())
+    }
+}
+
+#[inject_nitrous_resource]
+impl TerrainElevation {
+    pub fn from_catalog(catalog: &Catalog) -> Result<Self> {
+        let mut data_sets = Vec::new();
+        for desc in TileSetBuilder::discover_tiles(catalog)?.descriptors() {
+            if desc.kind != DataSetDataKind::Height {
+                continue;
+            }
+            match ElevationDataSet::new(&desc.prefix, desc.coordinates, catalog) {
+                Ok(data_set) => data_sets.push(data_set),
+                Err(e) => warn!("skipping elevation data in {}: {}", desc.prefix, e),
+            }
+        }
+        if data_sets.is_empty() {
+            info!("no height data found; terrain elevation will be at sea level everywhere");
+        }
+        Ok(Self {
+            march_step: DEFAULT_MARCH_STEP_M,
+            data_sets,
+            root_base_as: TerrainLevel::base().lat::<ArcSeconds>().f64().round() as i32,
+            cache: Mutex::new(TileCache::new(DEFAULT_CACHE_CAPACITY)),
+            overlays: Arc::new(Vec::new()),
+        })
+    }
+
+    /// Set the number of decompressed tiles to keep around.
+    pub fn set_cache_capacity(&self, capacity: usize) {
+        self.cache.lock().set_capacity(capacity);
+    }
+
+    /// Replace the height overlays to apply on top of the layer pack heights.
+    pub fn set_overlays(&mut self, overlays: Arc<Vec<HeightOverlay>>) {
+        self.overlays = overlays;
+    }
+
+    /// The height of the ground above the geoid at the given point. Points with no height
+    /// data are at sea level.
+    pub fn elevation<Origin: GraticuleOrigin>(
+        &self,
+        grat: &Graticule<Origin>,
+        catalog: &Catalog,
+    ) -> Result<Length<Meters>> {
+        let (lat_as, lon_as) = arcseconds_of(grat);
+
+        // Spherical data sets add together. Polar data sets then fade in over the top near
+        // the poles, the same way that the GPU tile sets are blended.
+        let mut height = 0.;
+        for (set_offset, data_set) in self.data_sets.iter().enumerate() {
+            if data_set.coordinates == DataSetCoordinates::Spherical {
+                height += self
+                    .sample_data_set(set_offset, lat_as, lon_as, catalog)?
+                    .unwrap_or(0.);
+            }
+        }
+        let weight = PolarProjection::blend_weight(grat);
+        if weight > 0. {
+            if let Some((y_as, x_as)) = PolarProjection::project(grat) {
+                for (set_offset, data_set) in self.data_sets.iter().enumerate() {
+                    if data_set.coordinates != DataSetCoordinates::CartesianPolar {
+                        continue;
+                    }
+                    if let Some(polar) = self.sample_data_set(set_offset, y_as, x_as, catalog)? {
+                        height += (polar - height) * weight;
+                    }
+                }
+            }
+        }
+        if !self.overlays.is_empty() {
+            let lat = grat.lat::<Radians>().f64();
+            let lon = grat.lon::<Radians>().f64();
+            for overlay in self.overlays.iter() {
+                height = overlay.apply(lat, lon, height);
+            }
+        }
+        Ok(meters!(height))
+    }
+
+    // Sample the finest tile of a data set at the given tile coordinates, if we have one.
+    fn sample_data_set(
+        &self,
+        set_offset: usize,
+        lat_as: f64,
+        lon_as: f64,
+        catalog: &Catalog,
+    ) -> Result<Option<f64>> {
+        let data_set = &self.data_sets[set_offset];
+        let (level, base) = match data_set.finest_tile(self.root_base_as, lat_as, lon_as) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let layer = &data_set.layers[level];
+        let extent = layer.angular_extent_as as f64;
+        let tile_s = (lon_as - base.1 as f64) / extent;
+        let tile_t = (lat_as - base.0 as f64) / extent;
+
+        let key = (set_offset, level, base);
+        let mut cache = self.cache.lock();
+        if cache.get(&key).is_none() {
+            let (extent, checksum) = layer.tiles[&base].clone();
+            let packed = catalog.read_slice(layer.file_id, extent)?;
+            cache.insert(key, decode_tile(&packed, checksum, layer.compression)?);
+        }
+        Ok(Some(sample_bilinear(
+            cache.get(&key).expect("just cached"),
+            tile_s,
+            tile_t,
+        )))
+    }
+
+    // Height of a point above the ground; negative if underground.
+    fn clearance(&self, pt: &Point3<f64>, catalog: &Catalog) -> Result<f64> {
+        let grat = Graticule::<GeoCenter>::from(Cartesian::<GeoCenter, Meters>::from(*pt));
+        let ground = EARTH_RADIUS.f64() + self.elevation(&grat, catalog)?.f64();
+        Ok(pt.coords.norm() - ground)
+    }
+
+    /// Find the first place that the ray from `origin` along `direction` meets the ground,
+    /// if it does so within `max_distance`.
+    pub fn intersect_ray(
+        &self,
+        origin: &Cartesian<GeoCenter, Meters>,
+        direction: &Vector3<f64>,
+        max_distance: Length<Meters>,
+        catalog: &Catalog,
+    ) -> Result<Option<TerrainHit>> {
+        ensure!(direction.norm() > 0., "a terrain ray needs a direction");
+        let direction = direction.normalize();
+        let mut start = origin.point64();
+        let mut skipped = 0.;
+
+        // Skip straight to where the ray enters the shell that holds all terrain, if it does.
+        let shell = Sphere::from_center_and_radius(
+            &Point3::origin(),
+            EARTH_RADIUS.f64() + MAX_TERRAIN_HEIGHT_M,
+        );
+        if start.coords.norm() > shell.radius() {
+            match sphere_vs_ray(&shell, &Ray::new(start, direction)) {
+                Some(entry) => {
+                    skipped = (entry - start).norm();
+                    start = entry;
+                }
+                None => return Ok(None),
+            }
+        }
+        if skipped > max_distance.f64() {
+            return Ok(None);
+        }
+
+        let ray = Ray::new(start, direction);
+        let hit = height_field_vs_ray(
+            &ray,
+            max_distance.f64() - skipped,
+            self.march_step.max(1.),
+            |pt| self.clearance(pt, catalog),
+        )?;
+        Ok(hit.map(|t| TerrainHit {
+            position: Cartesian::from(start + direction * t),
+            distance: meters!(skipped + t),
+        }))
+    }
+
+    /// Find the first place that the segment from `a` to `b` meets the ground, if any.
+    pub fn intersect_segment(
+        &self,
+        a: &Cartesian<GeoCenter, Meters>,
+        b: &Cartesian<GeoCenter, Meters>,
+        catalog: &Catalog,
+    ) -> Result<Option<TerrainHit>> {
+        let delta = b.vec64() - a.vec64();
+        self.intersect_ray(a, &delta, meters!(delta.norm()), catalog)
+    }
+
+    /// True if the terrain does not block the view from `a` to `b`. The last couple of meters
+    /// next to `b` are not checked, so that objects on the ground can be seen.
+    pub fn has_line_of_sight(
+        &self,
+        a: &Cartesian<GeoCenter, Meters>,
+        b: &Cartesian<GeoCenter, Meters>,
+        catalog: &Catalog,
+    ) -> Result<bool> {
+        let delta = b.vec64() - a.vec64();
+        let distance = delta.norm() - LINE_OF_SIGHT_MARGIN_M;
+        if distance <= 0. {
+            return Ok(true);
+        }
+        Ok(self
+            .intersect_ray(a, &delta, meters!(distance), catalog)?
+            .is_none())
+    }
+
+    /// The finest terrain level with height data at the given point, if any.
+    pub fn finest_level<Origin: GraticuleOrigin>(
+        &self,
+        grat: &Graticule<Origin>,
+    ) -> Option<TerrainLevel> {
+        let (lat_as, lon_as) = arcseconds_of(grat);
+        self.data_sets
+            .iter()
+            .filter(|data_set| data_set.coordinates == DataSetCoordinates::Spherical)
+            .filter_map(|data_set| data_set.finest_tile(self.root_base_as, lat_as, lon_as))
+            .map(|(level, _)| TerrainLevel::new(level))
+            .max()
+    }
+
+    /// Ground height in meters at a latitude and longitude in degrees.
+    #[method]
+    pub fn elevation_at(&self, latitude: f64, longitude: f64, heap: HeapRef) -> Result<f64> {
+        let grat =
+            Graticule::<GeoSurface>::new(degrees!(latitude), degrees!(longitude), meters!(0));
+        Ok(self.elevation(&grat, heap.resource::<Catalog>())?.f64())
+    }
+
+    /// True if the ground does not block the view between two points, given as latitude and
+    /// longitude in degrees and altitude above sea level in meters.
+    #[allow(clippy::too_many_arguments)]
+    #[method]
+    pub fn line_of_sight(
+        &self,
+        lat0: f64,
+        lon0: f64,
+        alt0: f64,
+        lat1: f64,
+        lon1: f64,
+        alt1: f64,
+        heap: HeapRef,
+    ) -> Result<bool> {
+        let a = Graticule::<GeoSurface>::new(degrees!(lat0), degrees!(lon0), meters!(alt0));
+        let b = Graticule::<GeoSurface>::new(degrees!(lat1), degrees!(lon1), meters!(alt1));
+        self.has_line_of_sight(
+            &a.cartesian::<Meters>(),
+            &b.cartesian::<Meters>(),
+            heap.resource::<Catalog>(),
+        )
+    }
+
+    /// Number of decompressed tiles currently held in the cache.
+    #[method]
+    pub fn cached_tile_count(&self) -> i64 {
+        self.cache.lock().tiles.len() as i64
+    }
+
+    #[method]
+    pub fn clear_cache(&self) {
+        self.cache.lock().tiles.clear();
+    }
+
+    /// List the height data sets that we are able to query.
+    #[method]
+    pub fn data_sets(&self) -> String {
+        self.data_sets
+            .iter()
+            .map(|data_set| format!("{}: {} levels", data_set.prefix, data_set.layers.len()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use absolute_unit::Degrees;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_bilinear_matches_samples() {
+        // Height rises by one meter per sample to the east and ten per sample to the north.
+        let mut samples = vec![0i16; TILE_PHYSICAL_SIZE * TILE_PHYSICAL_SIZE];
+        for y in 0..TILE_PHYSICAL_SIZE {
+            for x in 0..TILE_PHYSICAL_SIZE {
+                samples[y * TILE_PHYSICAL_SIZE + x] = (x + 10 * y) as i16;
+            }
+        }
+        // A linear field should be reproduced exactly, away from the clamped edges.
+        let inner = (TILE_PHYSICAL_SIZE - 2) as f64;
+        for &(s, t) in &[(0.25, 0.5), (0.5, 0.5), (0.1234, 0.9), (0.75, 0.01)] {
+            let x = s * inner + 0.5;
+            let y = t * inner + 0.5;
+            assert_relative_eq!(
+                sample_bilinear(&samples, s, t),
+                x + 10. * y,
+                epsilon = 0.0001
+            );
+        }
+    }
+
+    #[test]
+    fn test_lru_evicts_oldest() {
+        let mut cache = TileCache::new(2);
+        cache.insert((0, 0, (0, 0)), vec![0]);
+        cache.insert((0, 1, (0, 0)), vec![1]);
+        assert!(cache.get(&(0, 0, (0, 0))).is_some());
+        cache.insert((0, 2, (0, 0)), vec![2]);
+        assert!(cache.get(&(0, 1, (0, 0))).is_none());
+        assert!(cache.get(&(0, 0, (0, 0))).is_some());
+        assert!(cache.get(&(0, 2, (0, 0))).is_some());
+        cache.set_capacity(1);
+        assert_eq!(cache.tiles.len(), 1);
+        assert!(cache.get(&(0, 2, (0, 0))).is_some());
+    }
+
+    // Sea level everywhere, with a 500m ridge running north-south across the equator at 0E.
+    fn ridge_elevation(catalog: &Catalog) -> Result<TerrainElevation> {
+        let mut elevation = TerrainElevation::from_catalog(catalog)?;
+        let ridge = HeightOverlay::embankment(
+            &Graticule::<GeoSurface>::new(degrees!(-0.01), degrees!(0), meters!(0)),
+            &Graticule::<GeoSurface>::new(degrees!(0.01), degrees!(0), meters!(0)),
+            meters!(100),
+            meters!(500),
+        )?;
+        elevation.set_overlays(Arc::new(vec![ridge]));
+        Ok(elevation)
+    }
+
+    fn at(lat: f64, lon: f64, alt: f64) -> Cartesian<GeoCenter, Meters> {
+        Graticule::<GeoSurface>::new(degrees!(lat), degrees!(lon), meters!(alt))
+            .cartesian::<Meters>()
+    }
+
+    #[test]
+    fn test_ray_hits_ground() -> Result<()> {
+        let catalog = Catalog::empty("test");
+        let elevation = ridge_elevation(&catalog)?;
+
+        // From inside the terrain shell.
+        let origin = at(0.05, 0.05, 1000.);
+        let down = -origin.vec64().normalize();
+        let hit = elevation
+            .intersect_ray(&origin, &down, meters!(2000), &catalog)?
+            .expect("hit");
+        assert_relative_eq!(hit.distance.f64(), 1000., epsilon = 0.1);
+
+        // From above the shell, the distance includes the part we skipped.
+        let origin = at(0.05, 0.05, 20_000.);
+        let hit = elevation
+            .intersect_ray(&origin, &down, meters!(30_000), &catalog)?
+            .expect("hit");
+        assert_relative_eq!(hit.distance.f64(), 20_000., epsilon = 0.1);
+        assert_relative_eq!(
+            (hit.position.vec64() - at(0.05, 0.05, 0.).vec64()).norm(),
+            0.,
+            epsilon = 0.1
+        );
+
+        // But not if the ground is further than we asked to look.
+        assert!(elevation
+            .intersect_ray(&origin, &down, meters!(15_000), &catalog)?
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ray_above_shell_misses() -> Result<()> {
+        let catalog = Catalog::empty("test");
+        let elevation = ridge_elevation(&catalog)?;
+        let origin = at(0., 0., 20_000.);
+        let up = origin.vec64().normalize();
+        let north = (Vector3::y() - up * up.y).normalize();
+        assert!(elevation
+            .intersect_ray(&origin, &north, meters!(100_000), &catalog)?
+            .is_none());
+        assert!(elevation
+            .intersect_ray(&origin, &up, meters!(100_000), &catalog)?
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ridge_blocks_line_of_sight() -> Result<()> {
+        let catalog = Catalog::empty("test");
+        let elevation = ridge_elevation(&catalog)?;
+        let west = at(0., -0.01, 100.);
+        let east = at(0., 0.01, 100.);
+        assert!(!elevation.has_line_of_sight(&west, &east, &catalog)?);
+        let hit = elevation
+            .intersect_segment(&west, &east, &catalog)?
+            .expect("hit the ridge");
+        // We hit the western flank, where it is as high as the line.
+        let grat = Graticule::<GeoCenter>::from(hit.position);
+        assert!(grat.lon::<Degrees>().f64() < 0.);
+        assert_relative_eq!(
+            elevation.elevation(&grat, &catalog)?.f64(),
+            100.,
+            epsilon = 1.
+        );
+
+        // Over the top of the ridge is clear.
+        let (west, east) = (at(0., -0.01, 1000.), at(0., 0.01, 1000.));
+        assert!(elevation.has_line_of_sight(&west, &east, &catalog)?);
+        assert!(elevation
+            .intersect_segment(&west, &east, &catalog)?
+            .is_none());
+
+        // The ground a target sits on does not hide it.
+        let target = at(0., 0.02, 0.);
+        assert!(elevation.has_line_of_sight(&west, &target, &catalog)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tile_base_alignment() {
+        let extent = 509 * 16;
+        let root = TerrainLevel::base().lat::<ArcSeconds>().f64().round() as i32;
+        let (lat, lon) = tile_base(root, extent, 1.5, -1.5);
+        assert!(lat as f64 <= 1.5 && 1.5 < (lat + extent) as f64);
+        assert!(lon as f64 <= -1.5 && -1.5 < (lon + extent) as f64);
+        assert_eq!((lat - root) % extent, 0);
+        assert_eq!((lon - root) % extent, 0);
+    }
+}
@@ -12,14 +12,13 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
-use crate::tile::{TerrainLevel, TileCompression};
-use anyhow::{ensure, Result};
+use crate::tile::{TerrainLevel, TileCompression, TilePixelFormat, TILE_PHYSICAL_SIZE};
+use anyhow::{bail, ensure, Result};
 use catalog::{Catalog, FileId};
 use packed_struct::packed_struct;
 use std::{
-    borrow::Cow,
     fs::File,
-    io::{Seek, SeekFrom, Write},
+    io::{Read, Seek, SeekFrom, Write},
     mem,
     ops::Range,
     path::Path,
@@ -31,6 +30,9 @@ use zerocopy::AsBytes;
 // to the loading process. Instead of leaving loose files in the catalog, we
 // pack them up by layers, leaving our catalog fast and allowing us to reference
 // tiles by coordinate and level, rather than by hashing a string.
+//
+// Headers, index items, and tile pixels are all written in the host's native byte order,
+// which is also the order that tiles are uploaded to the GPU in.
 
 #[packed_struct]
 pub struct LayerPackHeader {
@@ -57,61 +59,216 @@ pub struct LayerPackIndexItem {
     tile_end: u64,
 }
 
+// Version 2 extends the version 1 header, so that readers can check the version before
+// they know how large the header is.
+#[packed_struct]
+pub struct LayerPackHeaderV2 {
+    magic: [u8; 3],
+    version: u8,
+    angular_extent_as: i32,
+    tile_count: u32,
+    tile_level: u16,
+    tile_compression: u16,
+    index_start: u32,
+    tile_start: u32,
+    pixel_format: u16,
+    flags: u16,
+    // Only meaningful if HAS_HEIGHT_RANGE is set in flags.
+    min_height: i16,
+    max_height: i16,
+}
+
+#[packed_struct]
+pub struct LayerPackIndexItemV2 {
+    base_lat_as: i32,
+    base_lon_as: i32,
+    index_in_parent: u32,
+
+    // CRC32 of the tile data as stored, before decompression.
+    checksum: u32,
+
+    // Relative to file start, no offset needed.
+    tile_start: u64,
+    tile_end: u64,
+}
+
 const HEADER_MAGIC: [u8; 3] = [b'L', b'P', b'K'];
-const HEADER_VERSION: u8 = 1;
+const HEADER_VERSION_1: u8 = 1;
+const HEADER_VERSION: u8 = 2;
 
-#[derive(Debug)]
-pub struct LayerPack {
-    // Map from base lat/lon in arcseconds, to start and end offsets in the file.
-    layer_pack_fid: FileId,
+const HAS_HEIGHT_RANGE: u16 = 0x0001;
+
+/// One entry in the index of a layer pack.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LayerPackEntry {
+    pub base: (i32, i32),
+    pub index_in_parent: u32,
+    pub extent: Range<u64>,
+    // Version 1 packs do not store checksums.
+    pub checksum: Option<u32>,
+}
+
+// The header of any version of layer pack, normalized.
+#[derive(Clone, Debug)]
+struct PackLayout {
+    version: u8,
     terrain_level: TerrainLevel,
     angular_extent_as: i32,
-    index_extent: Range<usize>,
     tile_count: usize,
     tile_compression: TileCompression,
+    pixel_format: Option<TilePixelFormat>,
+    height_range: Option<(i16, i16)>,
+    index_extent: Range<usize>,
 }
 
-impl LayerPack {
-    pub fn new(layer_pack_fid: FileId, catalog: &Catalog) -> Result<Self> {
-        let header_raw =
-            catalog.read_slice(layer_pack_fid, 0..mem::size_of::<LayerPackHeader>())?;
-        let header = LayerPackHeader::overlay(&header_raw)?;
-        ensure!(header.magic() == HEADER_MAGIC);
-        ensure!(header.version() == HEADER_VERSION);
+impl PackLayout {
+    fn read(mut read: impl FnMut(Range<usize>) -> Result<Vec<u8>>) -> Result<Self> {
+        let v1_raw = read(0..mem::size_of::<LayerPackHeader>())?;
+        let v1 = LayerPackHeader::overlay(&v1_raw)?;
+        ensure!(v1.magic() == HEADER_MAGIC, "not a layer pack");
+        let (header_size, pixel_format, height_range) = match v1.version() {
+            HEADER_VERSION_1 => (mem::size_of::<LayerPackHeader>(), None, None),
+            HEADER_VERSION => {
+                let v2_raw = read(0..mem::size_of::<LayerPackHeaderV2>())?;
+                let v2 = LayerPackHeaderV2::overlay(&v2_raw)?;
+                let height_range = if v2.flags() & HAS_HEIGHT_RANGE != 0 {
+                    Some((v2.min_height(), v2.max_height()))
+                } else {
+                    None
+                };
+                (
+                    mem::size_of::<LayerPackHeaderV2>(),
+                    Some(TilePixelFormat::from_u16(v2.pixel_format())?),
+                    height_range,
+                )
+            }
+            version => bail!("unsupported layer pack version {}", version),
+        };
+        ensure!(
+            v1.index_start() as usize >= header_size && v1.tile_start() >= v1.index_start(),
+            "layer pack header offsets are out of order"
+        );
+        let index_extent = v1.index_start() as usize..v1.tile_start() as usize;
+        let item_size = Self::index_item_size(v1.version());
+        ensure!(
+            index_extent.len() % item_size == 0,
+            "layer pack index is not a whole number of items"
+        );
+        Ok(Self {
+            version: v1.version(),
+            terrain_level: TerrainLevel::new(v1.tile_level() as usize),
+            angular_extent_as: v1.angular_extent_as(),
+            tile_count: index_extent.len() / item_size,
+            tile_compression: TileCompression::from_u16(v1.tile_compression())?,
+            pixel_format,
+            height_range,
+            index_extent,
+        })
+    }
+
+    fn index_item_size(version: u8) -> usize {
+        if version == HEADER_VERSION_1 {
+            mem::size_of::<LayerPackIndexItem>()
+        } else {
+            mem::size_of::<LayerPackIndexItemV2>()
+        }
+    }
+
+    fn parse_index(&self, index_bytes: &[u8]) -> Result<Vec<LayerPackEntry>> {
+        Ok(if self.version == HEADER_VERSION_1 {
+            LayerPackIndexItem::overlay_slice(index_bytes)?
+                .iter()
+                .map(|item| LayerPackEntry {
+                    base: (item.base_lat_as(), item.base_lon_as()),
+                    index_in_parent: item.index_in_parent(),
+                    extent: item.tile_start()..item.tile_end(),
+                    checksum: None,
+                })
+                .collect()
+        } else {
+            LayerPackIndexItemV2::overlay_slice(index_bytes)?
+                .iter()
+                .map(|item| LayerPackEntry {
+                    base: (item.base_lat_as(), item.base_lon_as()),
+                    index_in_parent: item.index_in_parent(),
+                    extent: item.tile_start()..item.tile_end(),
+                    checksum: Some(item.checksum()),
+                })
+                .collect()
+        })
+    }
+}
+
+/// Check, decompress, and re-encode a tile as stored in a layer pack, into the given format.
+pub(crate) fn unpack_tile(
+    packed: &[u8],
+    checksum: Option<u32>,
+    compression: TileCompression,
+    stored_format: TilePixelFormat,
+    target_format: TilePixelFormat,
+) -> Result<Vec<u8>> {
+    if let Some(expect) = checksum {
+        let actual = crc32fast::hash(packed);
         ensure!(
-            (header.tile_start() - header.index_start()) as usize
-                % mem::size_of::<LayerPackIndexItem>()
-                == 0
+            actual == expect,
+            "tile checksum mismatch: {:08X} != {:08X}",
+            actual,
+            expect
         );
+    }
+    let raw_size = TILE_PHYSICAL_SIZE * TILE_PHYSICAL_SIZE * stored_format.bytes_per_pixel();
+    let raw = compression.decompress(packed, raw_size)?;
+    ensure!(
+        raw.len() == raw_size,
+        "tile is {} bytes, expected {}",
+        raw.len(),
+        raw_size
+    );
+    stored_format.convert(&raw, target_format)
+}
+
+#[derive(Debug)]
+pub struct LayerPack {
+    layer_pack_fid: FileId,
+    layout: PackLayout,
+}
+
+impl LayerPack {
+    pub fn new(layer_pack_fid: FileId, catalog: &Catalog) -> Result<Self> {
+        let layout = PackLayout::read(|extent| {
+            Ok(catalog.read_slice(layer_pack_fid, extent)?.into_owned())
+        })?;
         Ok(Self {
             layer_pack_fid,
-            angular_extent_as: header.angular_extent_as(),
-            terrain_level: TerrainLevel::new(header.tile_level() as usize),
-            index_extent: header.index_start() as usize..header.tile_start() as usize,
-            tile_count: (header.tile_start() - header.index_start()) as usize
-                / mem::size_of::<LayerPackIndexItem>(),
-            tile_compression: TileCompression::from_u16(header.tile_compression()),
+            layout,
         })
     }
 
-    pub(crate) fn index_bytes<'a>(&self, catalog: &'a Catalog) -> Result<Cow<'a, [u8]>> {
-        catalog.read_slice(self.layer_pack_fid, self.index_extent.clone())
+    pub(crate) fn entries(&self, catalog: &Catalog) -> Result<Vec<LayerPackEntry>> {
+        let index_bytes =
+            catalog.read_slice(self.layer_pack_fid, self.layout.index_extent.clone())?;
+        self.layout.parse_index(&index_bytes)
     }
 
     pub fn tile_compression(&self) -> TileCompression {
-        self.tile_compression
+        self.layout.tile_compression
+    }
+
+    /// The format of stored tiles, if the pack records it.
+    pub fn pixel_format(&self) -> Option<TilePixelFormat> {
+        self.layout.pixel_format
     }
 
     pub fn angular_extent_as(&self) -> i32 {
-        self.angular_extent_as
+        self.layout.angular_extent_as
     }
 
     pub fn terrain_level(&self) -> &TerrainLevel {
-        &self.terrain_level
+        &self.layout.terrain_level
     }
 
     pub fn tile_count(&self) -> usize {
-        self.tile_count
+        self.layout.tile_count
     }
 
     pub fn file_id(&self) -> FileId {
@@ -119,7 +276,120 @@ impl LayerPack {
     }
 }
 
+/// A layer pack read directly from disk, for tools that need to inspect packs without
+/// setting up a catalog. Only the header and index are loaded up front.
+#[derive(Debug)]
+pub struct LayerPackFile {
+    stream: File,
+    layout: PackLayout,
+    file_size: u64,
+    entries: Vec<LayerPackEntry>,
+}
+
+impl LayerPackFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut stream = File::open(path)?;
+        let file_size = stream.metadata()?.len();
+        let mut read = |extent: Range<usize>| -> Result<Vec<u8>> {
+            ensure!(
+                extent.end as u64 <= file_size,
+                "layer pack is truncated: {:?}",
+                path
+            );
+            let mut buf = vec![0u8; extent.len()];
+            stream.seek(SeekFrom::Start(extent.start as u64))?;
+            stream.read_exact(&mut buf)?;
+            Ok(buf)
+        };
+        let layout = PackLayout::read(&mut read)?;
+        let header_raw = read(0..mem::size_of::<LayerPackHeader>())?;
+        ensure!(
+            LayerPackHeader::overlay(&header_raw)?.tile_count() as usize == layout.tile_count,
+            "layer pack index size does not match the tile count in {:?}",
+            path
+        );
+        let index_raw = read(layout.index_extent.clone())?;
+        let entries = layout.parse_index(&index_raw)?;
+        Ok(Self {
+            stream,
+            layout,
+            file_size,
+            entries,
+        })
+    }
+
+    pub fn version(&self) -> u8 {
+        self.layout.version
+    }
+
+    pub fn terrain_level(&self) -> &TerrainLevel {
+        &self.layout.terrain_level
+    }
+
+    pub fn angular_extent_as(&self) -> i32 {
+        self.layout.angular_extent_as
+    }
+
+    pub fn tile_compression(&self) -> TileCompression {
+        self.layout.tile_compression
+    }
+
+    /// The format of stored tiles, if the pack records it.
+    pub fn pixel_format(&self) -> Option<TilePixelFormat> {
+        self.layout.pixel_format
+    }
+
+    /// The lowest and highest sample in a height pack, if the pack records it.
+    pub fn height_range(&self) -> Option<(i16, i16)> {
+        self.layout.height_range
+    }
+
+    /// The region of the file that tile data must lie within.
+    pub fn tile_data_extent(&self) -> Range<u64> {
+        self.layout.index_extent.end as u64..self.file_size
+    }
+
+    pub fn entries(&self) -> &[LayerPackEntry] {
+        &self.entries
+    }
+
+    /// Read the stored (possibly compressed) bytes of a tile.
+    pub fn read_tile(&mut self, entry: &LayerPackEntry) -> Result<Vec<u8>> {
+        let data_extent = self.tile_data_extent();
+        ensure!(
+            entry.extent.start <= entry.extent.end
+                && entry.extent.start >= data_extent.start
+                && entry.extent.end <= data_extent.end,
+            "tile at {:?} is outside of the tile data",
+            entry.base
+        );
+        let mut data = vec![0u8; (entry.extent.end - entry.extent.start) as usize];
+        self.stream.seek(SeekFrom::Start(entry.extent.start))?;
+        self.stream.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Read a tile, check it, and undo its compression, giving the samples as stored.
+    pub fn decode_tile(&mut self, entry: &LayerPackEntry) -> Result<Vec<u8>> {
+        let stored = self.read_tile(entry)?;
+        if let Some(expect) = entry.checksum {
+            let actual = crc32fast::hash(&stored);
+            ensure!(
+                actual == expect,
+                "tile at {:?} has checksum {:08X}, expected {:08X}",
+                entry.base,
+                actual,
+                expect
+            );
+        }
+        self.layout.tile_compression.decompress(&stored, 0)
+    }
+}
+
+/// Writes a version 2 layer pack.
 pub struct LayerPackBuilder {
+    header: LayerPackHeaderV2,
+
     // Relative to file start, no offset needed.
     index_cursor: u64,
 
@@ -136,14 +406,15 @@ impl LayerPackBuilder {
         tile_count: usize,
         tile_level: u32,
         tile_compression: TileCompression,
+        pixel_format: TilePixelFormat,
         angular_extent_as: i32,
     ) -> Result<Self> {
         let mut stream = File::create(path)?;
 
         // Emit the header
-        let index_start = mem::size_of::<LayerPackHeader>();
-        let tile_start = index_start + mem::size_of::<LayerPackIndexItem>() * tile_count;
-        let header = LayerPackHeader {
+        let index_start = mem::size_of::<LayerPackHeaderV2>();
+        let tile_start = index_start + mem::size_of::<LayerPackIndexItemV2>() * tile_count;
+        let header = LayerPackHeaderV2 {
             magic: HEADER_MAGIC,
             version: HEADER_VERSION,
             angular_extent_as,
@@ -152,33 +423,48 @@ impl LayerPackBuilder {
             tile_compression: tile_compression as u16,
             index_start: index_start as u32,
             tile_start: tile_start as u32,
+            pixel_format: pixel_format as u16,
+            flags: 0,
+            min_height: 0,
+            max_height: 0,
         };
         stream.write_all(header.as_bytes())?;
 
         Ok(Self {
-            // reservations: Vec::new(),
-            // reserve_cursor: 0,
+            header,
             index_cursor: index_start as u64,
             tile_cursor: tile_start as u64,
             stream,
         })
     }
 
+    /// Record the range of samples in a height pack, so that readers can bound the layer
+    /// without loading any tiles.
+    pub fn set_height_range(&mut self, min_height: i16, max_height: i16) -> Result<()> {
+        self.header.flags |= HAS_HEIGHT_RANGE;
+        self.header.min_height = min_height;
+        self.header.max_height = max_height;
+        self.stream.seek(SeekFrom::Start(0))?;
+        self.stream.write_all(self.header.as_bytes())?;
+        Ok(())
+    }
+
     pub fn push_tile(&mut self, base: (i32, i32), index_in_parent: u32, data: &[u8]) -> Result<()> {
         if data.is_empty() {
             return Ok(());
         }
 
         self.stream.seek(SeekFrom::Start(self.index_cursor))?;
-        let index_item = LayerPackIndexItem {
+        let index_item = LayerPackIndexItemV2 {
             base_lat_as: base.0,
             base_lon_as: base.1,
             index_in_parent,
+            checksum: crc32fast::hash(data),
             tile_start: self.tile_cursor,
             tile_end: self.tile_cursor + data.len() as u64,
         };
         self.stream.write_all(index_item.as_bytes())?;
-        self.index_cursor += mem::size_of::<LayerPackIndexItem>() as u64;
+        self.index_cursor += mem::size_of::<LayerPackIndexItemV2>() as u64;
 
         self.stream.seek(SeekFrom::Start(self.tile_cursor))?;
         self.stream.write_all(data)?;
@@ -187,3 +473,130 @@ impl LayerPackBuilder {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tile::DataSetDataKind;
+    use std::env;
+
+    #[test]
+    fn test_read_v1() -> Result<()> {
+        // Lay out a version 1 pack by hand, as the old builder wrote them.
+        let raw = (0..TILE_PHYSICAL_SIZE * TILE_PHYSICAL_SIZE * 3)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>();
+        let packed = TileCompression::Bz2.compress(&raw)?;
+        let index_start = mem::size_of::<LayerPackHeader>();
+        let tile_start = index_start + mem::size_of::<LayerPackIndexItem>() * 2;
+        let header = LayerPackHeader {
+            magic: HEADER_MAGIC,
+            version: HEADER_VERSION_1,
+            angular_extent_as: 4321,
+            tile_count: 2,
+            tile_level: 5,
+            tile_compression: TileCompression::Bz2 as u16,
+            index_start: index_start as u32,
+            tile_start: tile_start as u32,
+        };
+        let mut content = header.as_bytes().to_vec();
+        for (i, base) in [(10, 20), (-30, 40)].iter().enumerate() {
+            let start = (tile_start + packed.len() * i) as u64;
+            let item = LayerPackIndexItem {
+                base_lat_as: base.0,
+                base_lon_as: base.1,
+                index_in_parent: i as u32,
+                tile_start: start,
+                tile_end: start + packed.len() as u64,
+            };
+            content.extend_from_slice(item.as_bytes());
+        }
+        content.extend_from_slice(&packed);
+        content.extend_from_slice(&packed);
+
+        let layout = PackLayout::read(|extent| Ok(content[extent].to_vec()))?;
+        assert_eq!(layout.version, HEADER_VERSION_1);
+        assert_eq!(layout.terrain_level.offset(), 5);
+        assert_eq!(layout.angular_extent_as, 4321);
+        assert_eq!(layout.tile_count, 2);
+        assert_eq!(layout.tile_compression, TileCompression::Bz2);
+        assert_eq!(layout.pixel_format, None);
+        assert_eq!(layout.height_range, None);
+
+        let entries = layout.parse_index(&content[layout.index_extent.clone()])?;
+        assert_eq!(
+            entries,
+            vec![
+                LayerPackEntry {
+                    base: (10, 20),
+                    index_in_parent: 0,
+                    extent: tile_start as u64..(tile_start + packed.len()) as u64,
+                    checksum: None,
+                },
+                LayerPackEntry {
+                    base: (-30, 40),
+                    index_in_parent: 1,
+                    extent: (tile_start + packed.len()) as u64
+                        ..(tile_start + packed.len() * 2) as u64,
+                    checksum: None,
+                },
+            ]
+        );
+
+        // Without a stored format, tiles are read in the format that the kind used to use.
+        let stored_format = layout
+            .pixel_format
+            .unwrap_or_else(|| TilePixelFormat::legacy(DataSetDataKind::Color));
+        assert_eq!(stored_format, TilePixelFormat::Rgb8Unorm);
+        let extent = entries[1].extent.start as usize..entries[1].extent.end as usize;
+        let tile = unpack_tile(
+            &content[extent],
+            entries[1].checksum,
+            layout.tile_compression,
+            stored_format,
+            TilePixelFormat::native(DataSetDataKind::Color),
+        )?;
+        assert_eq!(tile.len(), TILE_PHYSICAL_SIZE * TILE_PHYSICAL_SIZE * 4);
+        assert_eq!(&tile[4..8], &[raw[3], raw[4], raw[5], 255]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_and_read_v2() -> Result<()> {
+        let path = env::temp_dir().join(format!("layer-pack-test-{}.mip", std::process::id()));
+        let raw = vec![7u8; TILE_PHYSICAL_SIZE * TILE_PHYSICAL_SIZE * 2];
+        let packed = TileCompression::Lz4.compress(&raw)?;
+        {
+            let mut builder = LayerPackBuilder::new(
+                &path,
+                1,
+                3,
+                TileCompression::Lz4,
+                TilePixelFormat::R16Sint,
+                1234,
+            )?;
+            builder.push_tile((10, 20), 2, &packed)?;
+            builder.set_height_range(-5, 1799)?;
+        }
+        let mut pack = LayerPackFile::open(&path)?;
+        assert_eq!(pack.version(), HEADER_VERSION);
+        assert_eq!(pack.terrain_level().offset(), 3);
+        assert_eq!(pack.pixel_format(), Some(TilePixelFormat::R16Sint));
+        assert_eq!(pack.height_range(), Some((-5, 1799)));
+        let entry = pack.entries()[0].clone();
+        assert_eq!(entry.base, (10, 20));
+        assert_eq!(pack.decode_tile(&entry)?, raw);
+
+        let stored = pack.read_tile(&entry)?;
+        assert!(unpack_tile(
+            &stored,
+            entry.checksum.map(|c| c ^ 1),
+            TileCompression::Lz4,
+            TilePixelFormat::R16Sint,
+            TilePixelFormat::R16Sint
+        )
+        .is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -12,24 +12,38 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+mod elevation;
 mod index_paint_vertex;
 mod layer_pack;
 pub(crate) mod null_tile_set;
+mod polar;
+pub(crate) mod polar_tile_set;
 mod quad_tree;
 mod spherical_common;
 pub(crate) mod spherical_tile_set;
+mod streaming;
 pub(crate) mod tile_builder;
 mod tile_info;
 
+pub use elevation::{TerrainElevation, TerrainHit};
 pub(crate) use layer_pack::LayerPack;
-pub use layer_pack::{LayerPackBuilder, LayerPackHeader, LayerPackIndexItem};
+pub use layer_pack::{
+    LayerPackBuilder, LayerPackEntry, LayerPackFile, LayerPackHeader, LayerPackHeaderV2,
+    LayerPackIndexItem, LayerPackIndexItemV2,
+};
+pub use polar::{PolarProjection, Pole};
+pub use streaming::TileStreaming;
 pub use tile_builder::{ColorsTileSet, HeightsTileSet, NormalsTileSet, TileSet};
 
 use absolute_unit::{arcseconds, meters, scalar, Angle, ArcSeconds};
 use anyhow::{bail, Result};
+use bzip2::read::{BzDecoder, BzEncoder};
 use geodesy::{GeoCenter, Graticule};
 use lazy_static::lazy_static;
-use std::ops::Range;
+use std::{io::Read, ops::Range};
+
+// Slower than the default, but we compress once and load many times.
+const ZSTD_LEVEL: i32 = 19;
 
 // The physical number of pixels in the tile.
 pub const TILE_PHYSICAL_SIZE: usize = 512;
@@ -47,16 +61,133 @@ pub const TILE_EXTENT: i64 = TILE_SAMPLES - 1;
 pub enum TileCompression {
     None = 0,
     Bz2 = 1,
+    Zstd = 2,
+    Lz4 = 3,
 }
 
 impl TileCompression {
-    pub fn from_u16(i: u16) -> Self {
-        match i {
+    pub fn from_u16(i: u16) -> Result<Self> {
+        Ok(match i {
             0 => TileCompression::None,
             1 => TileCompression::Bz2,
-            _ => panic!("not a valid tile-compression"),
+            2 => TileCompression::Zstd,
+            3 => TileCompression::Lz4,
+            _ => bail!("not a valid tile-compression: {}", i),
+        })
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "none" => TileCompression::None,
+            "bz2" => TileCompression::Bz2,
+            "zstd" => TileCompression::Zstd,
+            "lz4" => TileCompression::Lz4,
+            _ => bail!(
+                "unknown tile compression {}: expected none, bz2, zstd, or lz4",
+                name
+            ),
+        })
+    }
+
+    pub fn compress(&self, raw: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::None => raw.to_owned(),
+            Self::Bz2 => {
+                let mut compressed = Vec::new();
+                BzEncoder::new(raw, bzip2::Compression::best()).read_to_end(&mut compressed)?;
+                compressed
+            }
+            Self::Zstd => zstd::bulk::compress(raw, ZSTD_LEVEL)?,
+            Self::Lz4 => lz4_flex::compress_prepend_size(raw),
+        })
+    }
+
+    /// Undo compress. The raw size is only a hint, for codecs that can use one.
+    pub fn decompress(&self, packed: &[u8], raw_size: usize) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::None => packed.to_owned(),
+            Self::Bz2 => {
+                let mut decompressed = Vec::with_capacity(raw_size);
+                BzDecoder::new(packed).read_to_end(&mut decompressed)?;
+                decompressed
+            }
+            Self::Zstd => zstd::stream::decode_all(packed)?,
+            Self::Lz4 => lz4_flex::decompress_size_prepended(packed)?,
+        })
+    }
+}
+
+/// The layout of samples in a stored tile. Layer packs before version 2 did not record this,
+/// so readers have to assume the format that was used for the data set kind.
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TilePixelFormat {
+    R16Sint = 1,
+    Rg16Sint = 2,
+    Rgb8Unorm = 3,
+    Rgba8Unorm = 4,
+}
+
+impl TilePixelFormat {
+    pub fn from_u16(i: u16) -> Result<Self> {
+        Ok(match i {
+            1 => Self::R16Sint,
+            2 => Self::Rg16Sint,
+            3 => Self::Rgb8Unorm,
+            4 => Self::Rgba8Unorm,
+            _ => bail!("not a valid tile pixel format: {}", i),
+        })
+    }
+
+    /// The format that version 1 layer packs stored for each kind.
+    pub fn legacy(kind: DataSetDataKind) -> Self {
+        match kind {
+            DataSetDataKind::Color => Self::Rgb8Unorm,
+            DataSetDataKind::Normal => Self::Rg16Sint,
+            DataSetDataKind::Height => Self::R16Sint,
+        }
+    }
+
+    /// The format that the atlas wants, so that tiles can be uploaded as they are loaded.
+    pub fn native(kind: DataSetDataKind) -> Self {
+        match kind {
+            DataSetDataKind::Color => Self::Rgba8Unorm,
+            DataSetDataKind::Normal => Self::Rg16Sint,
+            DataSetDataKind::Height => Self::R16Sint,
         }
     }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            Self::R16Sint => 2,
+            Self::Rg16Sint => 4,
+            Self::Rgb8Unorm => 3,
+            Self::Rgba8Unorm => 4,
+        }
+    }
+
+    /// Re-encode tile samples into another format. Only adding or dropping alpha is supported.
+    pub fn convert(&self, data: &[u8], target: Self) -> Result<Vec<u8>> {
+        Ok(match (self, target) {
+            (a, b) if *a == b => data.to_owned(),
+            (Self::Rgb8Unorm, Self::Rgba8Unorm) => {
+                let mut out = vec![255u8; data.len() / 3 * 4];
+                for (i, c) in data.chunks_exact(3).enumerate() {
+                    out[i * 4..i * 4 + 3].copy_from_slice(c);
+                }
+                out
+            }
+            (Self::Rgba8Unorm, Self::Rgb8Unorm) => data
+                .chunks_exact(4)
+                .flat_map(|c| [c[0], c[1], c[2]])
+                .collect(),
+            _ => bail!(
+                "cannot convert tile samples from {:?} to {:?}",
+                self,
+                target
+            ),
+        })
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -13,7 +13,10 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 use crate::{
-    tile::tile_builder::{HeightsTileSet, TileSet},
+    tile::{
+        tile_builder::{HeightsTileSet, TileSet},
+        TileStreaming,
+    },
     VisiblePatch,
 };
 use bevy_ecs::prelude::*;
@@ -90,7 +93,7 @@ impl TileSet for NullHeightTileSet {
         self
     }
 
-    fn begin_visibility_update(&mut self) {
+    fn begin_visibility_update(&mut self, _streaming: &TileStreaming) {
         // self.common.begin_visibility_update();
     }
 
@@ -102,7 +105,12 @@ impl TileSet for NullHeightTileSet {
         // self.common.finish_visibility_update(catalog);
     }
 
-    fn encode_uploads(&mut self, _gpu: &Gpu, _encoder: &mut wgpu::CommandEncoder) {
+    fn encode_uploads(
+        &mut self,
+        _streaming: &mut TileStreaming,
+        _gpu: &Gpu,
+        _encoder: &mut wgpu::CommandEncoder,
+    ) {
         // self.common.encode_uploads(gpu, encoder);
     }
 
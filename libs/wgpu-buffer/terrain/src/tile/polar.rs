@@ -0,0 +1,220 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use absolute_unit::{arcseconds, meters, radians, ArcSeconds, Degrees, Radians};
+use geodesy::{GeoSurface, Graticule, GraticuleOrigin};
+use std::f64::consts::PI;
+
+// CartesianPolar data sets are stored on a polar stereographic plane instead of on the lat/lon
+// grid, so that tiles near the poles are not pinched down to slivers. Both poles share one
+// plane, side by side, so that a polar data set can use the same quad tree, layer packs, and
+// index texture as a spherical one: tile bases and extents are in the same arcsecond units,
+// with plane y in place of latitude and plane x in place of longitude.
+//
+// The plane is scaled so that, at the pole, one unit is one arcsecond of latitude.
+//
+// Note: these values are duplicated in terrain.glsl.
+const ARCSECONDS_PER_RADIAN: f64 = 180. * 60. * 60. / PI;
+
+// Distance on the plane from the origin to each pole.
+const POLE_OFFSET_AS: f64 = 90. * 60. * 60.;
+
+// Polar tiles cover everything poleward of this latitude.
+const POLAR_LIMIT_DEG: f64 = 60.;
+
+// Polar tiles fade in over spherical tiles between these latitudes.
+const POLAR_BLEND_START_DEG: f64 = 70.;
+const POLAR_BLEND_END_DEG: f64 = 75.;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Pole {
+    North,
+    South,
+}
+
+impl Pole {
+    pub fn all() -> [Pole; 2] {
+        [Self::North, Self::South]
+    }
+
+    // Plane x coordinate of the pole; y is always 0.
+    fn center_x_as(&self) -> f64 {
+        match self {
+            Self::North => -POLE_OFFSET_AS,
+            Self::South => POLE_OFFSET_AS,
+        }
+    }
+
+    // Which way plane y runs with respect to the prime meridian.
+    fn y_sign(&self) -> f64 {
+        match self {
+            Self::North => -1.,
+            Self::South => 1.,
+        }
+    }
+}
+
+/// Maps between graticules and the polar stereographic plane used by CartesianPolar tiles.
+pub struct PolarProjection;
+
+impl PolarProjection {
+    /// Distance on the plane from a pole to the edge of its polar tiles, in arcseconds.
+    pub fn radius_as() -> f64 {
+        Self::rho_as((90. - POLAR_LIMIT_DEG).to_radians())
+    }
+
+    /// Latitude in degrees beyond which polar tiles exist.
+    pub fn limit_latitude() -> f64 {
+        POLAR_LIMIT_DEG
+    }
+
+    /// Plane (y, x) in arcseconds of the given pole.
+    pub fn pole_plane_coordinates(pole: Pole) -> (f64, f64) {
+        (0., pole.center_x_as())
+    }
+
+    fn rho_as(colatitude: f64) -> f64 {
+        2. * (colatitude / 2.).tan() * ARCSECONDS_PER_RADIAN
+    }
+
+    /// Project to plane (y, x) in arcseconds, or None if the point has no polar tiles.
+    pub fn project<Origin: GraticuleOrigin>(grat: &Graticule<Origin>) -> Option<(f64, f64)> {
+        let lat = grat.lat::<Radians>().f64();
+        let lon = grat.lon::<Radians>().f64();
+        if lat.abs().to_degrees() < POLAR_LIMIT_DEG {
+            return None;
+        }
+        let pole = if lat > 0. { Pole::North } else { Pole::South };
+        let rho = Self::rho_as(PI / 2. - lat.abs());
+        Some((
+            pole.y_sign() * rho * lon.cos(),
+            pole.center_x_as() + rho * lon.sin(),
+        ))
+    }
+
+    /// The point on the planet at plane (y, x) in arcseconds, or None if that part of the
+    /// plane is not covered by either pole.
+    pub fn unproject(y_as: f64, x_as: f64) -> Option<Graticule<GeoSurface>> {
+        for pole in Pole::all() {
+            let dx = x_as - pole.center_x_as();
+            let dy = y_as * pole.y_sign();
+            let rho = (dx * dx + dy * dy).sqrt();
+            if rho > Self::radius_as() {
+                continue;
+            }
+            let colatitude = 2. * (rho / (2. * ARCSECONDS_PER_RADIAN)).atan();
+            let lat = match pole {
+                Pole::North => PI / 2. - colatitude,
+                Pole::South => colatitude - PI / 2.,
+            };
+            let lon = if rho > 0. { dx.atan2(dy) } else { 0. };
+            return Some(Graticule::new(radians!(lat), radians!(lon), meters!(0)));
+        }
+        None
+    }
+
+    /// How much polar data should replace spherical data at the given latitude.
+    pub fn blend_weight<Origin: GraticuleOrigin>(grat: &Graticule<Origin>) -> f64 {
+        let lat = grat.lat::<Degrees>().f64().abs();
+        let t = ((lat - POLAR_BLEND_START_DEG) / (POLAR_BLEND_END_DEG - POLAR_BLEND_START_DEG))
+            .clamp(0., 1.);
+        t * t * (3. - 2. * t)
+    }
+
+    /// True if the plane square with the given base (y, x) and extent in arcseconds touches
+    /// the area covered by either pole.
+    pub fn intersects_region(base: (f64, f64), extent: f64) -> bool {
+        Pole::all().iter().any(|pole| {
+            let (cy, cx) = (0f64, pole.center_x_as());
+            let ny = cy.clamp(base.0, base.0 + extent);
+            let nx = cx.clamp(base.1, base.1 + extent);
+            let (dy, dx) = (ny - cy, nx - cx);
+            (dy * dy + dx * dx).sqrt() <= Self::radius_as()
+        })
+    }
+
+    /// True if the graticule is within the polar blend band or closer to a pole.
+    pub fn is_blended<Origin: GraticuleOrigin>(grat: &Graticule<Origin>) -> bool {
+        grat.lat::<Degrees>().f64().abs() > POLAR_BLEND_START_DEG
+    }
+
+    /// Plane coordinates as the pseudo-graticule that tile lookups expect.
+    pub fn plane_graticule(y_as: f64, x_as: f64) -> Graticule<GeoSurface> {
+        Graticule::new(arcseconds!(y_as), arcseconds!(x_as), meters!(0))
+    }
+
+    /// The lat/lon of the pseudo-graticule, as arcseconds of plane (y, x).
+    pub fn plane_coordinates<Origin: GraticuleOrigin>(grat: &Graticule<Origin>) -> (f64, f64) {
+        (
+            grat.lat::<ArcSeconds>().f64(),
+            grat.lon::<ArcSeconds>().f64(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use absolute_unit::degrees;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_polar_round_trip() {
+        for &(lat, lon) in &[
+            (89.5, 12.),
+            (65., -170.),
+            (-72., 45.),
+            (-89.99, 179.),
+            (90., 0.),
+        ] {
+            let grat = Graticule::<GeoSurface>::new(degrees!(lat), degrees!(lon), meters!(0));
+            let (y, x) = PolarProjection::project(&grat).unwrap();
+            assert!(PolarProjection::intersects_region((y, x), 1.));
+            let back = PolarProjection::unproject(y, x).unwrap();
+            assert_relative_eq!(back.lat::<Degrees>().f64(), lat, epsilon = 0.000_001);
+            if lat.abs() < 90. {
+                assert_relative_eq!(back.lon::<Degrees>().f64(), lon, epsilon = 0.000_001);
+            }
+        }
+        let equator = Graticule::<GeoSurface>::new(degrees!(10), degrees!(0), meters!(0));
+        assert!(PolarProjection::project(&equator).is_none());
+        assert!(PolarProjection::unproject(0., 0.).is_none());
+    }
+
+    #[test]
+    fn test_plane_scale_at_pole() {
+        // One arcsecond from the pole is about one plane unit.
+        let grat =
+            Graticule::<GeoSurface>::new(arcseconds!(90. * 3600. - 1.), arcseconds!(0), meters!(0));
+        let (y, x) = PolarProjection::project(&grat).unwrap();
+        assert_relative_eq!(y, -1., epsilon = 0.000_001);
+        assert_relative_eq!(x, -POLE_OFFSET_AS, epsilon = 0.000_001);
+    }
+
+    #[test]
+    fn test_blend_weight() {
+        let at = |lat: f64| {
+            PolarProjection::blend_weight(&Graticule::<GeoSurface>::new(
+                degrees!(lat),
+                degrees!(0),
+                meters!(0),
+            ))
+        };
+        assert_relative_eq!(at(0.), 0.);
+        assert_relative_eq!(at(POLAR_BLEND_START_DEG), 0.);
+        assert_relative_eq!(at(-72.5), 0.5);
+        assert_relative_eq!(at(POLAR_BLEND_END_DEG), 1.);
+        assert_relative_eq!(at(89.), 1.);
+    }
+}
@@ -13,7 +13,7 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 use crate::tile::{
-    ChildIndex, LayerPack, LayerPackIndexItem, TerrainLevel, TileCompression, TILE_EXTENT,
+    ChildIndex, LayerPack, TerrainLevel, TileCompression, TilePixelFormat, TILE_EXTENT,
 };
 use absolute_unit::{Angle, ArcSeconds};
 use anyhow::{ensure, Result};
@@ -50,6 +50,7 @@ impl QuadTreeId {
 #[derive(Debug)]
 struct QuadTreeNode {
     span: Range<usize>,
+    checksum: Option<u32>,
     children: [QuadTreeId; 4],
     base: (i32, i32), // lat, lon
     level: u8,
@@ -90,6 +91,9 @@ pub(crate) struct QuadTree {
     votes: HashMap<QuadTreeId, NodeVotes>,
     additions: Vec<QuadTreeId>,
     generation: u32,
+
+    // Do not walk below this level, regardless of the requested resolution.
+    max_level: usize,
 }
 
 impl QuadTree {
@@ -115,6 +119,7 @@ impl QuadTree {
             votes: HashMap::new(),
             additions: Vec::new(),
             generation: 0,
+            max_level: usize::MAX,
         };
 
         let mut acc = FxHashMap::default();
@@ -138,20 +143,20 @@ impl QuadTree {
 
         // Note: we have to overlay manually because the data may not be mapped if the item was a raw file.
         let mut id_update_cursor = self.nodes.len();
-        let index_bytes = self.layer_packs[layer_num].index_bytes(catalog).unwrap();
-        let raw_index = LayerPackIndexItem::overlay_slice(&index_bytes)?;
-        for item in raw_index {
-            let base = (item.base_lat_as(), item.base_lon_as());
+        let index = self.layer_packs[layer_num].entries(catalog)?;
+        for item in &index {
+            let base = item.base;
             // FIXME: are we making a decision to not support 32bit here?
-            let span = item.tile_start() as usize..item.tile_end() as usize;
+            let span = item.extent.start as usize..item.extent.end as usize;
             let id = QuadTreeId::new(self.nodes.len());
             self.nodes.push(QuadTreeNode {
                 span,
+                checksum: item.checksum,
                 children: [QuadTreeId::empty(); 4],
                 base,
                 level: layer_num as u8,
             });
-            let parent_index = ChildIndex::from_index(item.index_in_parent() as usize);
+            let parent_index = ChildIndex::from_index(item.index_in_parent as usize);
             let parent_base = match parent_index {
                 ChildIndex::SouthWest => base,
                 ChildIndex::SouthEast => (base.0, base.1 - extent),
@@ -171,8 +176,8 @@ impl QuadTree {
 
         acc.clear();
         if layer_num != 12 {
-            for item in raw_index {
-                let base = (item.base_lat_as(), item.base_lon_as());
+            for item in &index {
+                let base = item.base;
                 let id = QuadTreeId::new(id_update_cursor);
                 id_update_cursor += 1;
                 acc.insert(base, id);
@@ -229,6 +234,15 @@ impl QuadTree {
         self.layer_packs[level].tile_compression()
     }
 
+    pub(crate) fn pixel_format(&self, id: &QuadTreeId) -> Option<TilePixelFormat> {
+        let level = self.nodes[id.offset()].level as usize;
+        self.layer_packs[level].pixel_format()
+    }
+
+    pub(crate) fn checksum(&self, id: &QuadTreeId) -> Option<u32> {
+        self.nodes[id.offset()].checksum
+    }
+
     pub(crate) fn file_id(&self, id: &QuadTreeId) -> FileId {
         let level = self.nodes[id.offset()].level as usize;
         self.layer_packs[level].file_id()
@@ -260,6 +274,10 @@ impl QuadTree {
         )
     }
 
+    pub(crate) fn set_max_level(&mut self, max_level: usize) {
+        self.max_level = max_level;
+    }
+
     pub(crate) fn begin_update(&mut self) {
         self.generation = self.generation.wrapping_add(1);
         self.additions.clear();
@@ -306,6 +324,9 @@ impl QuadTree {
         if (self.angular_extent_as(&id) as f64 / TILE_EXTENT as f64) < resolution.f64() / 2. {
             return;
         }
+        if self.nodes[id.offset()].level as usize >= self.max_level {
+            return;
+        }
 
         // If we have not yet reached full refinement, continue walking down.
         let children = self.nodes[id.offset()].children;
@@ -13,7 +13,8 @@
 // You should have received a copy of the GNU General Public License
 // along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
 
-// Common functionality shared by spherical tile sets.
+// Common functionality shared by spherical tile sets, and by polar tile sets, which lay out
+// their tiles on the polar plane in the same arcsecond units as spherical tiles.
 // This includes:
 //   * Background tile loading
 //   * Tile use discovery
@@ -22,25 +23,26 @@
 use crate::{
     tile::{
         index_paint_vertex::IndexPaintVertex,
+        layer_pack::unpack_tile,
+        polar::PolarProjection,
         quad_tree::{QuadTree, QuadTreeId},
+        streaming::{TileSetStreamStats, TileStreaming},
         tile_info::TileInfo,
-        DataSetDataKind, TerrainLevel, TileCompression, TILE_PHYSICAL_SIZE,
+        DataSetCoordinates, DataSetDataKind, TerrainLevel, TilePixelFormat,
     },
     VisiblePatch,
 };
 use absolute_unit::arcseconds;
 use anyhow::Result;
-use bzip2::read::BzDecoder;
 use catalog::Catalog;
 use crossbeam::channel::{self, Receiver, Sender};
 use geometry::Aabb;
 use gpu::{texture_format_size, Gpu};
 use image::{ImageBuffer, Rgb};
+use nitrous::make_symbol;
 use std::{
-    collections::{BTreeMap, BinaryHeap},
-    env,
-    io::Read,
-    mem,
+    collections::{BTreeMap, BinaryHeap, VecDeque},
+    env, mem,
     num::{NonZeroU32, NonZeroU64},
     ops::Range,
     path::PathBuf,
@@ -49,9 +51,6 @@ use std::{
 };
 use zerocopy::LayoutVerified;
 
-// FIXME: this should be system dependent and configurable
-const MAX_CONCURRENT_READS: usize = 5;
-
 const TILE_SIZE: u32 = 512;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -93,7 +92,10 @@ impl TileState {
 
 #[derive(Debug)]
 pub(crate) struct SphericalTileSetCommon {
+    // The name of our entity, used to look up our settings in TileStreaming.
+    name: String,
     kind: DataSetDataKind,
+    coordinates: DataSetCoordinates,
 
     index_texture_format: wgpu::TextureFormat,
     index_texture_extent: wgpu::Extent3d,
@@ -124,6 +126,9 @@ pub(crate) struct SphericalTileSetCommon {
     // A list of all free offsets in the atlas.
     atlas_free_list: Vec<usize>,
 
+    // The number of atlas slots we are allowed to fill, which may be fewer than we have.
+    atlas_slot_limit: usize,
+
     // The full tree of possible tiles.
     tile_tree: QuadTree,
 
@@ -139,15 +144,19 @@ pub(crate) struct SphericalTileSetCommon {
     // frequently contain repeats and dead tiles that have since moved out of view.
     tile_load_queue: BinaryHeap<(u32, QuadTreeId)>,
 
-    // Number of async read slots currently being utilized. We will ideally set this higher
-    // on machines with more disk parallelism.
-    // TODO: figure out what disk the catalog is coming from and use some heuristics
+    // Number of async read slots currently being utilized. A slot is held until the tile is
+    // uploaded, so that reads back off when the upload budget is the bottleneck. The limit
+    // is set from TileStreaming, so can be raised on machines with more disk parallelism.
     tile_read_count: usize,
+    max_concurrent_reads: usize,
 
     // Tile transfer from the background read thread to the main thread.
     tile_sender: Sender<(QuadTreeId, Vec<u8>)>,
     tile_receiver: Receiver<(QuadTreeId, Vec<u8>)>,
 
+    // Tiles that have been read, but that did not fit in a frame's upload budget.
+    tile_upload_queue: VecDeque<(QuadTreeId, Vec<u8>)>,
+
     // Set to some to capture the index as a png
     maybe_snapshot_index: Option<PathBuf>,
 }
@@ -157,6 +166,7 @@ impl SphericalTileSetCommon {
         catalog: &Catalog,
         prefix: &str,
         kind: DataSetDataKind,
+        coordinates: DataSetCoordinates,
         tile_cache_size: u32,
         gpu: &Gpu,
     ) -> Result<Self> {
@@ -442,17 +452,22 @@ impl SphericalTileSetCommon {
             bind_group_layout,
             bind_group,
 
+            name: make_symbol(prefix),
             kind,
+            coordinates,
 
             atlas_tile_map: vec![None; tile_cache_size as usize],
             atlas_free_list: (0..tile_cache_size as usize).collect(),
+            atlas_slot_limit: tile_cache_size as usize,
 
             tile_tree,
             tile_state: BTreeMap::new(),
             tile_load_queue: BinaryHeap::new(),
             tile_read_count: 0,
+            max_concurrent_reads: 1,
             tile_sender,
             tile_receiver,
+            tile_upload_queue: VecDeque::new(),
 
             maybe_snapshot_index: None,
         })
@@ -499,17 +514,56 @@ impl SphericalTileSetCommon {
         // If we got an addition, the tile should have been removed by the tree.
         assert!(!self.tile_state.contains_key(&qtid));
 
-        let state = if let Some(atlas_slot) = self.atlas_free_list.pop() {
-            assert!(self.atlas_tile_map[atlas_slot].is_none());
-            self.atlas_tile_map[atlas_slot] = Some(qtid);
-            self.tile_load_queue.push((votes, qtid));
-            TileState::Pending(atlas_slot)
-        } else {
-            TileState::NoSpace
+        let state = match self.take_atlas_slot() {
+            Some(atlas_slot) => {
+                self.atlas_tile_map[atlas_slot] = Some(qtid);
+                self.tile_load_queue.push((votes, qtid));
+                TileState::Pending(atlas_slot)
+            }
+            None => TileState::NoSpace,
         };
         self.tile_state.insert(qtid, state);
     }
 
+    fn atlas_slots_in_use(&self) -> usize {
+        self.atlas_tile_map.len() - self.atlas_free_list.len()
+    }
+
+    fn take_atlas_slot(&mut self) -> Option<usize> {
+        if self.atlas_slots_in_use() >= self.atlas_slot_limit {
+            return None;
+        }
+        let atlas_slot = self.atlas_free_list.pop()?;
+        assert!(self.atlas_tile_map[atlas_slot].is_none());
+        Some(atlas_slot)
+    }
+
+    fn set_atlas_slot_limit(&mut self, limit: usize) {
+        self.atlas_slot_limit = limit.clamp(1, self.atlas_tile_map.len());
+
+        // Drop tiles until we fit. Since we allocate QuadTreeId breadth first, walking
+        // backwards releases the finest tiles first.
+        let mut excess = self
+            .atlas_slots_in_use()
+            .saturating_sub(self.atlas_slot_limit);
+        if excess == 0 {
+            return;
+        }
+        for (_, state) in self.tile_state.iter_mut().rev() {
+            if excess == 0 {
+                break;
+            }
+            if *state == TileState::NoSpace {
+                continue;
+            }
+            let atlas_slot = state.atlas_slot();
+            self.atlas_tile_map[atlas_slot] = None;
+            self.atlas_free_list.push(atlas_slot);
+            *state = TileState::NoSpace;
+            excess -= 1;
+        }
+    }
+
     fn deallocate_atlas_slot(&mut self, qtid: QuadTreeId) {
         // If the tile went out of scope, it must have been in scope before.
         assert!(self.tile_state.contains_key(&qtid));
@@ -528,7 +582,11 @@ impl SphericalTileSetCommon {
         self.atlas_free_list.push(atlas_slot);
     }
 
-    pub(crate) fn begin_visibility_update(&mut self) {
+    pub(crate) fn begin_visibility_update(&mut self, streaming: &TileStreaming) {
+        let budget = streaming.budget(&self.name, self.kind);
+        self.tile_tree.set_max_level(budget.max_level);
+        self.max_concurrent_reads = budget.max_concurrent_reads;
+        self.set_atlas_slot_limit(budget.atlas_slots.unwrap_or(self.atlas_tile_map.len()));
         self.tile_tree.begin_update();
     }
 
@@ -536,25 +594,56 @@ impl SphericalTileSetCommon {
         // Assuming 30m is 1"
         let angular_resolution = arcseconds!(visible_patch.edge_length.f64() / 30.0);
 
-        // Find an aabb for the given triangle.
+        if let Some(aabb) = self.required_window(visible_patch) {
+            self.tile_tree.note_required(&aabb, angular_resolution);
+        }
+    }
+
+    // Find an aabb for the given triangle in the coordinates of our tiles, if we have tiles there.
+    fn required_window(&self, visible_patch: &VisiblePatch) -> Option<Aabb<i32, 2>> {
         let g0 = &visible_patch.g0;
         let g1 = &visible_patch.g1;
         let g2 = &visible_patch.g2;
-        let min_lat = g0.latitude.min(g1.latitude).min(g2.latitude);
-        let max_lat = g0.latitude.max(g1.latitude).max(g2.latitude);
-        let min_lon = g0.longitude.min(g1.longitude).min(g2.longitude);
-        let max_lon = g0.longitude.max(g1.longitude).max(g2.longitude);
-        let aabb = Aabb::new(
-            [
-                arcseconds!(min_lat).round() as i32,
-                arcseconds!(min_lon).round() as i32,
-            ],
-            [
-                arcseconds!(max_lat).round() as i32,
-                arcseconds!(max_lon).round() as i32,
-            ],
-        );
-        self.tile_tree.note_required(&aabb, angular_resolution);
+        Some(match self.coordinates {
+            DataSetCoordinates::Spherical => {
+                let min_lat = g0.latitude.min(g1.latitude).min(g2.latitude);
+                let max_lat = g0.latitude.max(g1.latitude).max(g2.latitude);
+                let min_lon = g0.longitude.min(g1.longitude).min(g2.longitude);
+                let max_lon = g0.longitude.max(g1.longitude).max(g2.longitude);
+                Aabb::new(
+                    [
+                        arcseconds!(min_lat).round() as i32,
+                        arcseconds!(min_lon).round() as i32,
+                    ],
+                    [
+                        arcseconds!(max_lat).round() as i32,
+                        arcseconds!(max_lon).round() as i32,
+                    ],
+                )
+            }
+            DataSetCoordinates::CartesianPolar => {
+                // Polar tiles are only drawn where they blend in over the spherical tiles.
+                if [g0, g1, g2]
+                    .iter()
+                    .all(|g| PolarProjection::blend_weight(*g) <= 0.)
+                {
+                    return None;
+                }
+                let p0 = PolarProjection::project(g0)?;
+                let p1 = PolarProjection::project(g1)?;
+                let p2 = PolarProjection::project(g2)?;
+                Aabb::new(
+                    [
+                        p0.0.min(p1.0).min(p2.0).round() as i32,
+                        p0.1.min(p1.1).min(p2.1).round() as i32,
+                    ],
+                    [
+                        p0.0.max(p1.0).max(p2.0).round() as i32,
+                        p0.1.max(p1.1).max(p2.1).round() as i32,
+                    ],
+                )
+            }
+        })
     }
 
     pub(crate) fn finish_visibility_update(&mut self, catalog: &mut Catalog) {
@@ -571,6 +660,27 @@ impl SphericalTileSetCommon {
             self.allocate_atlas_slot(votes, qtid);
         }
 
+        // If slots have come free, either from removals or because we were allowed more,
+        // give them to tiles that went without, coarsest first.
+        if !self.atlas_free_list.is_empty() && self.atlas_slots_in_use() < self.atlas_slot_limit {
+            let starved = self
+                .tile_state
+                .iter()
+                .filter(|(_, state)| **state == TileState::NoSpace)
+                .map(|(qtid, _)| *qtid)
+                .collect::<Vec<_>>();
+            for qtid in starved {
+                match self.take_atlas_slot() {
+                    Some(atlas_slot) => {
+                        self.atlas_tile_map[atlas_slot] = Some(qtid);
+                        self.tile_load_queue.push((0, qtid));
+                        self.tile_state.insert(qtid, TileState::Pending(atlas_slot));
+                    }
+                    None => break,
+                }
+            }
+        }
+
         // FIXME: precompute this
         let raw_tile_size = self.atlas_texture_extent.width as usize
             * self.atlas_texture_extent.height as usize
@@ -578,7 +688,7 @@ impl SphericalTileSetCommon {
 
         // Kick off any loads, if there is space remaining.
         let mut reads_started_count = 0;
-        while !self.tile_load_queue.is_empty() && self.tile_read_count < MAX_CONCURRENT_READS {
+        while !self.tile_load_queue.is_empty() && self.tile_read_count < self.max_concurrent_reads {
             let (_, qtid) = self.tile_load_queue.pop().expect("checked is_empty");
 
             // There may be many frames between when a thing is inserted in the load queue and when
@@ -598,6 +708,11 @@ impl SphericalTileSetCommon {
             // Do the read in a disconnected thread and send it back on an mpsc queue.
             let fid = self.tile_tree.file_id(&qtid);
             let compression = self.tile_tree.tile_compression(&qtid);
+            let checksum = self.tile_tree.checksum(&qtid);
+            let stored_format = self
+                .tile_tree
+                .pixel_format(&qtid)
+                .unwrap_or_else(|| TilePixelFormat::legacy(self.kind));
             let extent = self.tile_tree.file_extent(&qtid);
             let closure_kind = self.kind;
             let closer_sender = self.tile_sender.clone();
@@ -607,31 +722,21 @@ impl SphericalTileSetCommon {
                 // after the main loop can call this method, but before resources are dropped.
                 let packed_data: &'static [u8] = unsafe { mem::transmute(packed_data) };
                 rayon::spawn(move || {
-                    let data = match compression {
-                        TileCompression::None => packed_data.to_vec(),
-                        TileCompression::Bz2 => {
-                            let mut decompressed = Vec::with_capacity(raw_tile_size);
-                            BzDecoder::new(packed_data)
-                                .read_to_end(&mut decompressed)
-                                .expect("compress buffer");
-                            assert_eq!(decompressed.len(), raw_tile_size);
-                            decompressed
+                    let data = match unpack_tile(
+                        packed_data,
+                        checksum,
+                        compression,
+                        stored_format,
+                        TilePixelFormat::native(closure_kind),
+                    ) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            // Show a hole rather than taking down the game.
+                            log::error!("failed to load terrain tile {:?}: {}", qtid, e);
+                            vec![0u8; raw_tile_size]
                         }
                     };
-                    // FIXME: encode this in the header
-                    // FIXME: do upscale on the GPU somehow?
-                    let data = if closure_kind == DataSetDataKind::Color {
-                        // Re-encode from rgb to rgba
-                        let mut data2 = vec![255u8; TILE_PHYSICAL_SIZE * TILE_PHYSICAL_SIZE * 4];
-                        for (i, c) in data.chunks(3).enumerate() {
-                            for j in 0..3 {
-                                data2[i * 4 + j] = c[j];
-                            }
-                        }
-                        data2
-                    } else {
-                        data
-                    };
+                    assert_eq!(data.len(), raw_tile_size);
                     closer_sender.send((qtid, data)).ok();
                 });
             } else {
@@ -649,26 +754,42 @@ impl SphericalTileSetCommon {
         );
     }
 
-    pub(crate) fn encode_uploads(&mut self, gpu: &Gpu, encoder: &mut wgpu::CommandEncoder) {
+    pub(crate) fn encode_uploads(
+        &mut self,
+        streaming: &mut TileStreaming,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
         // FIXME: precompute this
         let raw_tile_size = self.atlas_texture_extent.width as usize
             * self.atlas_texture_extent.height as usize
             * texture_format_size(self.atlas_texture_format) as usize;
 
         // Check for any completed reads.
-        let mut reads_ended_count = 0;
-        while let Ok((qtid, data)) = self.tile_receiver.try_recv() {
-            self.tile_read_count -= 1;
-            reads_ended_count += 1;
+        while let Ok(tile) = self.tile_receiver.try_recv() {
+            self.tile_upload_queue.push_back(tile);
+        }
 
+        // Upload as many as fit in this frame's budget.
+        let mut reads_ended_count = 0;
+        let mut uploaded_tiles = 0;
+        while let Some((qtid, _)) = self.tile_upload_queue.front() {
             // If the reading tile has gone out of view in the time since it was enqueued, we
             // may have lost our atlas slot. That's fine, just dump the bytes on the floor.
-            let maybe_state = self.tile_state.get(&qtid);
-            if maybe_state.is_none() || !maybe_state.unwrap().is_reading() {
+            let maybe_state = self.tile_state.get(qtid);
+            let is_current = maybe_state.map(|state| state.is_reading()) == Some(true);
+            if is_current && !streaming.try_upload(raw_tile_size) {
+                break;
+            }
+            let (qtid, data) = self.tile_upload_queue.pop_front().expect("checked front");
+            self.tile_read_count -= 1;
+            reads_ended_count += 1;
+            if !is_current {
                 continue;
             }
+            uploaded_tiles += 1;
 
-            let atlas_slot = maybe_state.unwrap().atlas_slot();
+            let atlas_slot = self.tile_state[&qtid].atlas_slot();
             self.tile_state.insert(qtid, TileState::Active(atlas_slot));
 
             assert_eq!(data.len(), raw_tile_size);
@@ -732,8 +853,24 @@ impl SphericalTileSetCommon {
         let iextent_lon = index_ang_extent.1.f32() / 2.;
         let mut active_atlas_slots = 0;
         let mut max_active_level = 0;
+        let mut stats = TileSetStreamStats {
+            kind: Some(self.kind),
+            atlas_capacity: self.atlas_tile_map.len(),
+            atlas_slots: self.atlas_slot_limit,
+            atlas_in_use: self.atlas_slots_in_use(),
+            waiting_upload: self.tile_upload_queue.len(),
+            uploaded_tiles,
+            uploaded_bytes: uploaded_tiles * raw_tile_size,
+            ..Default::default()
+        };
         let mut tris = Vec::new();
         for (qtid, tile_state) in self.tile_state.iter() {
+            match tile_state {
+                TileState::NoSpace => stats.no_space += 1,
+                TileState::Pending(_) => stats.pending += 1,
+                TileState::Reading(_) => stats.reading += 1,
+                TileState::Active(_) => {}
+            }
             if let TileState::Active(slot) = tile_state {
                 active_atlas_slots += 1;
                 let level = self.tile_tree.level(qtid);
@@ -783,13 +920,18 @@ impl SphericalTileSetCommon {
             active_atlas_slots,
             max_active_level
         );
+        stats.active = active_atlas_slots;
+        stats.max_active_level = max_active_level as usize;
+        streaming.record_stats(&self.name, stats);
     }
 
     pub(crate) fn shutdown_safely(&mut self) {
         // We have entered shutdown, but the system is still running, so nothing reachable has yet
         // been dropped. We need to pump our background jobs clean (including their unsafe mapped
         // pointers) before the system starts dropping things (like the owning Mmaps) on the main
-        // thread.
+        // thread. Tiles waiting on upload have already finished reading.
+        self.tile_read_count -= self.tile_upload_queue.len();
+        self.tile_upload_queue.clear();
         while self.tile_read_count > 0 {
             let _rv = self.tile_receiver.recv();
             self.tile_read_count -= 1;
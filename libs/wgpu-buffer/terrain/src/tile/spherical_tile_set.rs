@@ -16,7 +16,7 @@ use crate::{
     tile::{
         spherical_common::SphericalTileSetCommon,
         tile_builder::{ColorsTileSet, HeightsTileSet, NormalsTileSet, TileSet},
-        DataSetDataKind,
+        DataSetCoordinates, DataSetDataKind, TileStreaming,
     },
     VisiblePatch,
 };
@@ -30,8 +30,6 @@ use nitrous::{inject_nitrous_component, method, NitrousComponent};
 use shader_shared::Group;
 use std::any::Any;
 
-// TODO: tweak load depth of each type of tile... we don't need as much height data as normal data
-
 #[derive(Debug, Component, NitrousComponent)]
 #[Name = "spherical_height_tile_set"]
 pub(crate) struct SphericalHeightTileSet {
@@ -53,6 +51,7 @@ impl SphericalHeightTileSet {
             catalog,
             prefix,
             DataSetDataKind::Height,
+            DataSetCoordinates::Spherical,
             tile_cache_size,
             gpu,
         )?;
@@ -99,8 +98,8 @@ impl TileSet for SphericalHeightTileSet {
         self
     }
 
-    fn begin_visibility_update(&mut self) {
-        self.common.begin_visibility_update();
+    fn begin_visibility_update(&mut self, streaming: &TileStreaming) {
+        self.common.begin_visibility_update(streaming);
     }
 
     fn note_required(&mut self, visible_patch: &VisiblePatch) {
@@ -111,8 +110,13 @@ impl TileSet for SphericalHeightTileSet {
         self.common.finish_visibility_update(catalog);
     }
 
-    fn encode_uploads(&mut self, gpu: &Gpu, encoder: &mut wgpu::CommandEncoder) {
-        self.common.encode_uploads(gpu, encoder);
+    fn encode_uploads(
+        &mut self,
+        streaming: &mut TileStreaming,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        self.common.encode_uploads(streaming, gpu, encoder);
     }
 
     fn snapshot_index(&mut self, gpu: &mut Gpu) {
@@ -173,6 +177,7 @@ impl SphericalColorTileSet {
             catalog,
             prefix,
             DataSetDataKind::Color,
+            DataSetCoordinates::Spherical,
             tile_cache_size,
             gpu,
         )?;
@@ -220,8 +225,8 @@ impl TileSet for SphericalColorTileSet {
         self
     }
 
-    fn begin_visibility_update(&mut self) {
-        self.common.begin_visibility_update()
+    fn begin_visibility_update(&mut self, streaming: &TileStreaming) {
+        self.common.begin_visibility_update(streaming)
     }
 
     fn note_required(&mut self, visible_patch: &VisiblePatch) {
@@ -232,8 +237,13 @@ impl TileSet for SphericalColorTileSet {
         self.common.finish_visibility_update(catalog)
     }
 
-    fn encode_uploads(&mut self, gpu: &Gpu, encoder: &mut wgpu::CommandEncoder) {
-        self.common.encode_uploads(gpu, encoder);
+    fn encode_uploads(
+        &mut self,
+        streaming: &mut TileStreaming,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        self.common.encode_uploads(streaming, gpu, encoder);
     }
 
     fn snapshot_index(&mut self, gpu: &mut Gpu) {
@@ -297,6 +307,7 @@ impl SphericalNormalsTileSet {
             catalog,
             prefix,
             DataSetDataKind::Normal,
+            DataSetCoordinates::Spherical,
             tile_cache_size,
             gpu,
         )?;
@@ -344,8 +355,8 @@ impl TileSet for SphericalNormalsTileSet {
         self
     }
 
-    fn begin_visibility_update(&mut self) {
-        self.common.begin_visibility_update();
+    fn begin_visibility_update(&mut self, streaming: &TileStreaming) {
+        self.common.begin_visibility_update(streaming);
     }
 
     fn note_required(&mut self, visible_patch: &VisiblePatch) {
@@ -356,8 +367,13 @@ impl TileSet for SphericalNormalsTileSet {
         self.common.finish_visibility_update(catalog);
     }
 
-    fn encode_uploads(&mut self, gpu: &Gpu, encoder: &mut wgpu::CommandEncoder) {
-        self.common.encode_uploads(gpu, encoder);
+    fn encode_uploads(
+        &mut self,
+        streaming: &mut TileStreaming,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        self.common.encode_uploads(streaming, gpu, encoder);
     }
 
     fn snapshot_index(&mut self, gpu: &mut Gpu) {
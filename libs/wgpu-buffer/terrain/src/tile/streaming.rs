@@ -0,0 +1,328 @@
+// This file is part of Nitrogen.
+//
+// Nitrogen is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Nitrogen is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Nitrogen.  If not, see <http://www.gnu.org/licenses/>.
+use crate::tile::DataSetDataKind;
+use anyhow::{ensure, Result};
+use nitrous::{inject_nitrous_resource, method, NitrousResource};
+use std::{collections::BTreeMap, fmt::Write};
+
+// Default number of tile reads that each tile set may have in flight at once.
+const DEFAULT_CONCURRENT_READS: i64 = 5;
+
+// Default bytes of tile data to copy to the GPU per frame, across all tile sets. This is
+// a couple dozen of the largest (RGBA) tiles.
+const DEFAULT_UPLOAD_BYTES_PER_FRAME: i64 = 24 << 20;
+
+// Levels at or beyond this are never limited.
+const UNLIMITED_LEVEL: i64 = 64;
+
+/// How one tile set should stream this frame, after applying any overrides.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct TileSetBudget {
+    pub(crate) max_concurrent_reads: usize,
+    pub(crate) atlas_slots: Option<usize>,
+    pub(crate) max_level: usize,
+}
+
+/// Live streaming counters for a single tile set, as of its last upload.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TileSetStreamStats {
+    pub(crate) kind: Option<DataSetDataKind>,
+    pub(crate) atlas_capacity: usize,
+    pub(crate) atlas_slots: usize,
+    pub(crate) atlas_in_use: usize,
+    pub(crate) active: usize,
+    pub(crate) pending: usize,
+    pub(crate) reading: usize,
+    pub(crate) no_space: usize,
+    pub(crate) waiting_upload: usize,
+    pub(crate) max_active_level: usize,
+    pub(crate) uploaded_tiles: usize,
+    pub(crate) uploaded_bytes: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct TileSetOverrides {
+    max_concurrent_reads: Option<usize>,
+    atlas_slots: Option<usize>,
+}
+
+/// Knobs for how aggressively terrain tiles are streamed from disk and onto the GPU.
+///
+/// The properties here apply to every tile set. Read concurrency and atlas slots can also
+/// be overridden for a single tile set, by the tile set name shown in `report()`. All
+/// changes take effect on the next frame.
+///
+/// The atlas capacity of each tile set is fixed by the GPU detail level when the tile set
+/// is created; `set_atlas_slots` can only use fewer slots than that, not more. Lowering
+/// the slot count drops the finest resident tiles first.
+#[derive(Debug, NitrousResource)]
+pub struct TileStreaming {
+    /// Tile reads that each tile set may have in flight at once.
+    #[property]
+    max_concurrent_reads: i64,
+
+    /// Bytes of tile data to copy to the GPU per frame, across all tile sets. At least one
+    /// tile is always uploaded per frame, so a tiny budget slows streaming but cannot stop it.
+    #[property]
+    upload_bytes_per_frame: i64,
+
+    /// Deepest terrain level to load for each kind of data.
+    #[property]
+    height_max_level: i64,
+    #[property]
+    normal_max_level: i64,
+    #[property]
+    color_max_level: i64,
+
+    overrides: BTreeMap<String, TileSetOverrides>,
+    stats: BTreeMap<String, TileSetStreamStats>,
+    frame_uploaded_bytes: usize,
+    frame_uploaded_tiles: usize,
+    last_frame_uploaded_bytes: usize,
+}
+
+impl Default for TileStreaming {
+    fn default() -> Self {
+        Self {
+            max_concurrent_reads: DEFAULT_CONCURRENT_READS,
+            upload_bytes_per_frame: DEFAULT_UPLOAD_BYTES_PER_FRAME,
+            height_max_level: UNLIMITED_LEVEL,
+            normal_max_level: UNLIMITED_LEVEL,
+            color_max_level: UNLIMITED_LEVEL,
+            overrides: BTreeMap::new(),
+            stats: BTreeMap::new(),
+            frame_uploaded_bytes: 0,
+            frame_uploaded_tiles: 0,
+            last_frame_uploaded_bytes: 0,
+        }
+    }
+}
+
+#[inject_nitrous_resource]
+impl TileStreaming {
+    /// The settings for the named tile set, with overrides applied.
+    pub(crate) fn budget(&self, name: &str, kind: DataSetDataKind) -> TileSetBudget {
+        let overrides = self.overrides.get(name).copied().unwrap_or_default();
+        let max_level = match kind {
+            DataSetDataKind::Height => self.height_max_level,
+            DataSetDataKind::Normal => self.normal_max_level,
+            DataSetDataKind::Color => self.color_max_level,
+        };
+        TileSetBudget {
+            max_concurrent_reads: overrides
+                .max_concurrent_reads
+                .unwrap_or_else(|| self.max_concurrent_reads.max(1) as usize),
+            atlas_slots: overrides.atlas_slots,
+            max_level: max_level.clamp(0, UNLIMITED_LEVEL) as usize,
+        }
+    }
+
+    /// Reset the per-frame upload budget. Called once per frame, before any tile set uploads.
+    pub(crate) fn begin_frame(&mut self) {
+        self.last_frame_uploaded_bytes = self.frame_uploaded_bytes;
+        self.frame_uploaded_bytes = 0;
+        self.frame_uploaded_tiles = 0;
+    }
+
+    /// Check whether there is budget left for a tile of `bytes`, and charge for it if so.
+    pub(crate) fn try_upload(&mut self, bytes: usize) -> bool {
+        let budget = self.upload_bytes_per_frame.max(0) as usize;
+        if self.frame_uploaded_tiles > 0 && self.frame_uploaded_bytes + bytes > budget {
+            return false;
+        }
+        self.frame_uploaded_bytes += bytes;
+        self.frame_uploaded_tiles += 1;
+        true
+    }
+
+    pub(crate) fn record_stats(&mut self, name: &str, stats: TileSetStreamStats) {
+        self.stats.insert(name.to_owned(), stats);
+    }
+
+    fn check_name(&self, name: &str) -> Result<()> {
+        ensure!(
+            self.stats.contains_key(name),
+            "no tile set named {}; try one of: {}",
+            name,
+            self.stats.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+        Ok(())
+    }
+
+    /// Set the number of reads the named tile set may have in flight.
+    #[method]
+    pub fn set_concurrent_reads(&mut self, name: &str, reads: i64) -> Result<()> {
+        self.check_name(name)?;
+        ensure!(reads > 0, "a tile set needs at least one read slot");
+        self.overrides
+            .entry(name.to_owned())
+            .or_default()
+            .max_concurrent_reads = Some(reads as usize);
+        Ok(())
+    }
+
+    /// Limit the named tile set to using `slots` of its atlas.
+    #[method]
+    pub fn set_atlas_slots(&mut self, name: &str, slots: i64) -> Result<()> {
+        self.check_name(name)?;
+        let capacity = self.stats[name].atlas_capacity;
+        ensure!(
+            slots > 0 && slots as usize <= capacity,
+            "{} has {} atlas slots; pick a count between 1 and that",
+            name,
+            capacity
+        );
+        self.overrides
+            .entry(name.to_owned())
+            .or_default()
+            .atlas_slots = Some(slots as usize);
+        Ok(())
+    }
+
+    /// Return the named tile set to the global settings.
+    #[method]
+    pub fn clear_overrides(&mut self, name: &str) -> bool {
+        self.overrides.remove(name).is_some()
+    }
+
+    /// Bytes of tile data copied to the GPU over the last frame.
+    #[method]
+    pub fn uploaded_bytes(&self) -> i64 {
+        self.last_frame_uploaded_bytes as i64
+    }
+
+    /// Tiles, across all tile sets, that are read but waiting on upload budget.
+    #[method]
+    pub fn waiting_uploads(&self) -> i64 {
+        self.stats.values().map(|s| s.waiting_upload as i64).sum()
+    }
+
+    /// A table of the current streaming state of every tile set.
+    #[method]
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{:<32} {:>6} {:>11} {:>6} {:>5} {:>5} {:>5} {:>5} {:>4} {:>5} {:>5} {:>9}",
+            "tile set",
+            "kind",
+            "atlas",
+            "active",
+            "pend",
+            "read",
+            "wait",
+            "nospc",
+            "lvl",
+            "reads",
+            "tiles",
+            "upload"
+        )
+        .ok();
+        for (name, stats) in &self.stats {
+            let kind = stats
+                .kind
+                .map(|kind| kind.name())
+                .unwrap_or_else(|| "-".to_owned());
+            let budget = stats
+                .kind
+                .map(|kind| self.budget(name, kind).max_concurrent_reads)
+                .unwrap_or(0);
+            writeln!(
+                out,
+                "{:<32} {:>6} {:>4}/{:>3}/{:>3} {:>6} {:>5} {:>5} {:>5} {:>5} {:>4} {:>5} {:>5} {:>8}K",
+                name,
+                kind,
+                stats.atlas_in_use,
+                stats.atlas_slots,
+                stats.atlas_capacity,
+                stats.active,
+                stats.pending,
+                stats.reading,
+                stats.waiting_upload,
+                stats.no_space,
+                stats.max_active_level,
+                budget,
+                stats.uploaded_tiles,
+                stats.uploaded_bytes / 1024,
+            )
+            .ok();
+        }
+        writeln!(
+            out,
+            "uploaded {}K last frame of a {}K budget",
+            self.last_frame_uploaded_bytes / 1024,
+            self.upload_bytes_per_frame.max(0) / 1024
+        )
+        .ok();
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_upload_budget_always_allows_one_tile() {
+        let mut streaming = TileStreaming {
+            upload_bytes_per_frame: 1000,
+            ..Default::default()
+        };
+        streaming.begin_frame();
+        assert!(streaming.try_upload(4000));
+        assert!(!streaming.try_upload(10));
+        streaming.begin_frame();
+        assert_eq!(streaming.uploaded_bytes(), 4000);
+        assert!(streaming.try_upload(600));
+        assert!(streaming.try_upload(400));
+        assert!(!streaming.try_upload(1));
+    }
+
+    #[test]
+    fn test_overrides_apply_per_tile_set() -> Result<()> {
+        let mut streaming = TileStreaming {
+            normal_max_level: 9,
+            ..Default::default()
+        };
+        let stats = TileSetStreamStats {
+            kind: Some(DataSetDataKind::Normal),
+            atlas_capacity: 64,
+            ..Default::default()
+        };
+        streaming.record_stats("srtm_normals", stats);
+        assert!(streaming.set_atlas_slots("srtm_normals", 65).is_err());
+        assert!(streaming.set_concurrent_reads("srtm_heights", 2).is_err());
+        streaming.set_atlas_slots("srtm_normals", 32)?;
+        streaming.set_concurrent_reads("srtm_normals", 2)?;
+        assert_eq!(
+            streaming.budget("srtm_normals", DataSetDataKind::Normal),
+            TileSetBudget {
+                max_concurrent_reads: 2,
+                atlas_slots: Some(32),
+                max_level: 9,
+            }
+        );
+        assert!(streaming.clear_overrides("srtm_normals"));
+        assert_eq!(
+            streaming.budget("srtm_normals", DataSetDataKind::Normal),
+            TileSetBudget {
+                max_concurrent_reads: DEFAULT_CONCURRENT_READS as usize,
+                atlas_slots: None,
+                max_level: 9,
+            }
+        );
+        Ok(())
+    }
+}
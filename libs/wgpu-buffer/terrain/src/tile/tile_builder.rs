@@ -47,10 +47,11 @@
 use crate::{
     tile::{
         null_tile_set::NullHeightTileSet,
+        polar_tile_set::{PolarColorTileSet, PolarHeightTileSet, PolarNormalsTileSet},
         spherical_tile_set::{
             SphericalColorTileSet, SphericalHeightTileSet, SphericalNormalsTileSet,
         },
-        DataSetCoordinates, DataSetDataKind,
+        DataSetCoordinates, DataSetDataKind, TileStreaming,
     },
     VisiblePatch,
 };
@@ -71,10 +72,15 @@ pub trait TileSet: Debug + Send + Sync + 'static {
 
     // Maintain runtime visibility tracking based on VisiblePatch notifications derived
     // from the global geometry calculations.
-    fn begin_visibility_update(&mut self);
+    fn begin_visibility_update(&mut self, streaming: &TileStreaming);
     fn note_required(&mut self, visible_patch: &VisiblePatch);
     fn finish_visibility_update(&mut self, camera: &ScreenCamera, catalog: &mut Catalog);
-    fn encode_uploads(&mut self, gpu: &Gpu, encoder: &mut wgpu::CommandEncoder);
+    fn encode_uploads(
+        &mut self,
+        streaming: &mut TileStreaming,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+    );
 
     // Indicate that the current index should be written to the debug file.
     fn snapshot_index(&mut self, gpu: &mut Gpu);
@@ -136,6 +142,9 @@ pub(crate) enum GenericTileSet {
     SphericalHeights(SphericalHeightTileSet),
     SphericalNormals(SphericalNormalsTileSet),
     SphericalColors(SphericalColorTileSet),
+    PolarHeights(PolarHeightTileSet),
+    PolarNormals(PolarNormalsTileSet),
+    PolarColors(PolarColorTileSet),
     NullHeights(NullHeightTileSet),
 }
 
@@ -189,6 +198,10 @@ impl TileSetBuilder {
         Ok(Self { descriptors })
     }
 
+    pub(crate) fn descriptors(&self) -> &[TileSetDescriptor] {
+        &self.descriptors
+    }
+
     pub(crate) fn build_parallel(
         mut self,
         displace_height_bind_group_layout: &wgpu::BindGroupLayout,
@@ -242,15 +255,52 @@ impl TileSetBuilder {
                     );
                     desc.tile_set = Some(tile_set);
                 }
-                (DataSetCoordinates::CartesianPolar, _) => {
-                    panic!("unimplemented polar tiles")
+                (DataSetCoordinates::CartesianPolar, DataSetDataKind::Height) => {
+                    let tile_set = GenericTileSet::PolarHeights(
+                        PolarHeightTileSet::new(
+                            displace_height_bind_group_layout,
+                            catalog,
+                            &desc.prefix,
+                            tile_cache_size,
+                            gpu,
+                        )
+                        .unwrap(),
+                    );
+                    desc.tile_set = Some(tile_set);
+                }
+                (DataSetCoordinates::CartesianPolar, DataSetDataKind::Normal) => {
+                    let tile_set = GenericTileSet::PolarNormals(
+                        PolarNormalsTileSet::new(
+                            accumulate_common_bind_group_layout,
+                            catalog,
+                            &desc.prefix,
+                            globals,
+                            tile_cache_size,
+                            gpu,
+                        )
+                        .unwrap(),
+                    );
+                    desc.tile_set = Some(tile_set);
+                }
+                (DataSetCoordinates::CartesianPolar, DataSetDataKind::Color) => {
+                    let tile_set = GenericTileSet::PolarColors(
+                        PolarColorTileSet::new(
+                            accumulate_common_bind_group_layout,
+                            catalog,
+                            &desc.prefix,
+                            globals,
+                            tile_cache_size,
+                            gpu,
+                        )
+                        .unwrap(),
+                    );
+                    desc.tile_set = Some(tile_set);
                 }
             });
-        if !self
-            .descriptors
-            .iter()
-            .any(|v| v.kind == DataSetDataKind::Height)
-        {
+        // Polar heights blend over the spherical heights, so we need something underneath.
+        if !self.descriptors.iter().any(|v| {
+            v.kind == DataSetDataKind::Height && v.coordinates == DataSetCoordinates::Spherical
+        }) {
             self.descriptors.push(TileSetDescriptor {
                 prefix: "null_tile_set".to_owned(),
                 kind: DataSetDataKind::Height,
@@ -276,6 +326,15 @@ impl TileSetBuilder {
                 GenericTileSet::SphericalColors(tile_set) => runtime
                     .spawn_named(&make_symbol(desc.prefix))?
                     .insert_named(tile_set)?,
+                GenericTileSet::PolarHeights(tile_set) => runtime
+                    .spawn_named(&make_symbol(desc.prefix))?
+                    .insert_named(tile_set)?,
+                GenericTileSet::PolarNormals(tile_set) => runtime
+                    .spawn_named(&make_symbol(desc.prefix))?
+                    .insert_named(tile_set)?,
+                GenericTileSet::PolarColors(tile_set) => runtime
+                    .spawn_named(&make_symbol(desc.prefix))?
+                    .insert_named(tile_set)?,
                 GenericTileSet::NullHeights(tile_set) => {
                     runtime.spawn_named(desc.prefix)?.insert_named(tile_set)?
                 }
@@ -15,7 +15,7 @@
 use anyhow::Result;
 use bevy_ecs::prelude::*;
 use global_data::{GlobalParametersBuffer, GlobalsStep};
-use gpu::{DisplayConfig, Gpu, GpuStep};
+use gpu::{DisplayConfig, Gpu, GpuStep, GpuTimestamps};
 use log::trace;
 use runtime::{Extension, Runtime};
 use shader_shared::Group;
@@ -425,17 +425,21 @@ impl UiRenderPass {
         widgets: Res<WidgetBuffer>,
         paint: Res<PaintContext>,
         world: Res<WorldRenderPass>,
+        mut timestamps: ResMut<GpuTimestamps>,
         maybe_encoder: ResMut<Option<wgpu::CommandEncoder>>,
     ) {
         if let Some(encoder) = maybe_encoder.into_inner() {
-            let (color_attachments, depth_stencil_attachment) = ui.offscreen_target();
-            let render_pass_desc_ref = wgpu::RenderPassDescriptor {
-                label: Some(concat!("non-screen-render-pass-ui-draw-offscreen",)),
-                color_attachments: &color_attachments,
-                depth_stencil_attachment,
-            };
-            let rpass = encoder.begin_render_pass(&render_pass_desc_ref);
-            let _rpass = ui.render_ui(rpass, &globals, &widgets, &paint, &world);
+            {
+                let (color_attachments, depth_stencil_attachment) = ui.offscreen_target();
+                let render_pass_desc_ref = wgpu::RenderPassDescriptor {
+                    label: Some(concat!("non-screen-render-pass-ui-draw-offscreen",)),
+                    color_attachments: &color_attachments,
+                    depth_stencil_attachment,
+                };
+                let rpass = encoder.begin_render_pass(&render_pass_desc_ref);
+                let _rpass = ui.render_ui(rpass, &globals, &widgets, &paint, &world);
+            }
+            timestamps.write(encoder, "ui");
         }
     }
 
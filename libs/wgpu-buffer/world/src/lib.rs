@@ -17,7 +17,7 @@ use atmosphere::AtmosphereBuffer;
 use bevy_ecs::prelude::*;
 use fullscreen::{FullscreenBuffer, FullscreenVertex};
 use global_data::{GlobalParametersBuffer, GlobalsStep};
-use gpu::{DisplayConfig, Gpu, GpuStep};
+use gpu::{DisplayConfig, Gpu, GpuStep, GpuTimestamps};
 use log::trace;
 use nitrous::{inject_nitrous_resource, method, NitrousResource};
 use runtime::{report, Extension, Runtime};
@@ -584,18 +584,23 @@ impl WorldRenderPass {
         atmosphere: Res<AtmosphereBuffer>,
         stars: Res<StarsBuffer>,
         terrain: Res<TerrainBuffer>,
+        mut timestamps: ResMut<GpuTimestamps>,
         maybe_encoder: ResMut<Option<wgpu::CommandEncoder>>,
     ) {
         if let Some(encoder) = maybe_encoder.into_inner() {
-            let (color_attachments, depth_stencil_attachment) = world.offscreen_target_cleared();
-            let render_pass_desc_ref = wgpu::RenderPassDescriptor {
-                label: Some("offscreen-draw-world"),
-                color_attachments: &color_attachments,
-                depth_stencil_attachment,
-            };
-            let rpass = encoder.begin_render_pass(&render_pass_desc_ref);
-            let _rpass =
-                world.render_world(rpass, &globals, &fullscreen, &atmosphere, &stars, &terrain);
+            {
+                let (color_attachments, depth_stencil_attachment) =
+                    world.offscreen_target_cleared();
+                let render_pass_desc_ref = wgpu::RenderPassDescriptor {
+                    label: Some("offscreen-draw-world"),
+                    color_attachments: &color_attachments,
+                    depth_stencil_attachment,
+                };
+                let rpass = encoder.begin_render_pass(&render_pass_desc_ref);
+                let _rpass =
+                    world.render_world(rpass, &globals, &fullscreen, &atmosphere, &stars, &terrain);
+            }
+            timestamps.write(encoder, "world");
         }
     }
 
@@ -18,7 +18,8 @@ use anyhow::{anyhow, Result};
 use atmosphere::AtmosphereBuffer;
 use bevy_ecs::prelude::*;
 use camera::{
-    ArcBallController, ArcBallSystem, CameraSystem, ScreenCamera, ScreenCameraController,
+    ArcBallController, ArcBallSystem, CameraPath, CameraSystem, FreeCamera, Locations,
+    ScreenCamera, ScreenCameraController, VehicleCameraSystem,
 };
 use catalog::{Catalog, CatalogOpts};
 use composite::CompositeRenderPass;
@@ -33,14 +34,15 @@ use measure::WorldSpaceFrame;
 use nitrous::{inject_nitrous_resource, HeapMut, NitrousResource};
 use orrery::Orrery;
 use platform_dirs::AppDirs;
-use runtime::{ExitRequest, Extension, Runtime, StartupOpts};
+use runtime::{ExitRequest, Extension, Profiler, Runtime, StartupOpts};
 use stars::StarsBuffer;
-use std::{fs::create_dir_all, time::Instant};
+use std::fs::create_dir_all;
 use structopt::StructOpt;
 use terminal_size::{terminal_size, Width};
-use terrain::TerrainBuffer;
+use terrain::{TerrainBuffer, TerrainElevation};
 use tracelog::{TraceLog, TraceLogOpts};
 use ui::UiRenderPass;
+use vector_map::VectorMap;
 use vehicle::{
     AirbrakeEffector, BayEffector, FlapsEffector, GearEffector, HookEffector, PitchInceptor,
     PowerSystem, RollInceptor, YawInceptor,
@@ -65,6 +67,26 @@ bindings.bind("+PageDown", "camera.decrease_fov(pressed)");
 bindings.bind("Shift+LBracket", "camera.decrease_exposure()");
 bindings.bind("Shift+RBracket", "camera.increase_exposure()");
 
+// Debug camera: F8 flies off from the current view, F7 goes back to the arcball.
+bindings.bind("F8", "@free_camera.free.jump_to(camera.attached()); camera.attach('free_camera'); input_target.push('free_camera', true)");
+bindings.bind("F7", "camera.attach('camera'); input_target.remove('free_camera')");
+bindings.bind_in("free_camera", "+w", "@free_camera.free.move_forward(pressed)");
+bindings.bind_in("free_camera", "+s", "@free_camera.free.move_backward(pressed)");
+bindings.bind_in("free_camera", "+a", "@free_camera.free.move_left(pressed)");
+bindings.bind_in("free_camera", "+d", "@free_camera.free.move_right(pressed)");
+bindings.bind_in("free_camera", "+r", "@free_camera.free.move_up(pressed)");
+bindings.bind_in("free_camera", "+f", "@free_camera.free.move_down(pressed)");
+bindings.bind_in("free_camera", "+Up", "@free_camera.free.pitch_down(pressed)");
+bindings.bind_in("free_camera", "+Down", "@free_camera.free.pitch_up(pressed)");
+bindings.bind_in("free_camera", "+Left", "@free_camera.free.yaw_left(pressed)");
+bindings.bind_in("free_camera", "+Right", "@free_camera.free.yaw_right(pressed)");
+bindings.bind_in("free_camera", "+z", "@free_camera.free.roll_left(pressed)");
+bindings.bind_in("free_camera", "+c", "@free_camera.free.roll_right(pressed)");
+bindings.bind_in("free_camera", "+mouse1", "@free_camera.free.look(pressed)");
+bindings.bind_in("free_camera", "mouseMotion", "@free_camera.free.handle_mousemotion(dx, dy)");
+bindings.bind_in("free_camera", "Equals", "@free_camera.free.faster()");
+bindings.bind_in("free_camera", "Minus", "@free_camera.free.slower()");
+
 // By default start out pointing at Mt Everest.
 let location := "Everest";
 @camera.arcball.set_target(@camera.arcball.notable_location(location));
@@ -98,9 +120,12 @@ struct VisibleWidgets {
     camera_direction: Entity,
     camera_position: Entity,
     camera_fov: Entity,
-    fps_label: Entity,
+    profile_lines: Vec<Entity>,
 }
 
+// Labels are single line, so the profiler overlay is a stack of them.
+const PROFILE_OVERLAY_LINES: usize = 12;
+
 #[derive(Debug, NitrousResource)]
 struct DemoUx {
     visible_widgets: VisibleWidgets,
@@ -117,6 +142,7 @@ impl Extension for DemoUx {
             r#"
                 bindings.bind("Escape", "exit()");
                 bindings.bind("q", "exit()");
+                bindings.bind("F3", "profiler.toggle_detail()");
             "#,
         )?;
         Ok(())
@@ -169,26 +195,33 @@ impl DemoUx {
             .to_owned();
         *heap.get_mut::<LayoutPacking>(controls_id) = controls_packing;
 
-        let fps_label = Label::new("")
-            .with_font(
-                heap.resource::<PaintContext>()
-                    .font_context
-                    .font_id_for_name("sans"),
-            )
-            .with_color(&Color::from([255, 0, 0]))
-            .with_size(Size::from_pts(13.0))
-            .with_pre_blended_text()
-            .wrapped("fps_label", heap.as_mut())?;
+        let mut profile_box = LayoutNode::new_vbox("profile_box", heap.as_mut())?;
+        let profile_id = profile_box.id();
+        let mut profile_lines = Vec::with_capacity(PROFILE_OVERLAY_LINES);
+        for i in 0..PROFILE_OVERLAY_LINES {
+            let line = Label::new("")
+                .with_font(
+                    heap.resource::<PaintContext>()
+                        .font_context
+                        .font_id_for_name("sans"),
+                )
+                .with_color(&Color::from([255, 0, 0]))
+                .with_size(Size::from_pts(13.0))
+                .with_pre_blended_text()
+                .wrapped(&format!("profile_line{}", i), heap.as_mut())?;
+            profile_box.push_widget(line)?;
+            profile_lines.push(line);
+        }
         heap.resource_mut::<WidgetBuffer>()
             .root_mut()
-            .push_widget(fps_label)?;
-        heap.get_mut::<LayoutPacking>(fps_label).float_bottom();
+            .push_layout(profile_box)?;
+        heap.get_mut::<LayoutPacking>(profile_id).float_bottom();
         Ok(VisibleWidgets {
             sim_time,
             camera_direction,
             camera_position,
             camera_fov,
-            fps_label,
+            profile_lines,
         })
     }
 
@@ -196,13 +229,13 @@ impl DemoUx {
         query: Query<(&ArcBallController, &ScreenCameraController)>,
         mut labels: Query<&mut Label>,
         camera: Res<ScreenCamera>,
-        timestep: Res<TimeStep>,
+        profiler: Res<Profiler>,
         orrery: Res<Orrery>,
         system: ResMut<DemoUx>,
     ) {
         for (arcball, _) in query.iter() {
             system
-                .track_visible_state(&mut labels, *timestep.sim_time(), &orrery, arcball, &camera)
+                .track_visible_state(&mut labels, &profiler, &orrery, arcball, &camera)
                 .ok();
         }
     }
@@ -210,7 +243,7 @@ impl DemoUx {
     pub fn track_visible_state(
         &self,
         labels: &mut Query<&mut Label>,
-        now: Instant,
+        profiler: &Profiler,
         orrery: &Orrery,
         arcball: &ArcBallController,
         camera: &ScreenCamera,
@@ -227,13 +260,12 @@ impl DemoUx {
         labels
             .get_mut(self.visible_widgets.camera_fov)?
             .set_text(format!("FoV: {}", degrees!(camera.fov_y())));
-        let frame_time = now.elapsed();
-        let ts = format!(
-            "frame: {}.{}ms",
-            frame_time.as_secs() * 1000 + u64::from(frame_time.subsec_millis()),
-            frame_time.subsec_micros(),
-        );
-        labels.get_mut(self.visible_widgets.fps_label)?.set_text(ts);
+        let mut lines = profiler.overlay_lines().into_iter();
+        for &line in &self.visible_widgets.profile_lines {
+            labels
+                .get_mut(line)?
+                .set_text(lines.next().unwrap_or_default());
+        }
         Ok(())
     }
 }
@@ -255,6 +287,7 @@ fn simulation_main(mut runtime: Runtime) -> Result<()> {
         .ok_or_else(|| anyhow!("unable to find app directories"))?;
     create_dir_all(&app_dirs.config_dir)?;
     create_dir_all(&app_dirs.state_dir)?;
+    let bindings_path = app_dirs.config_dir.join("bindings.json");
 
     let opt = runtime.resource::<Opt>().to_owned();
     runtime
@@ -277,6 +310,8 @@ fn simulation_main(mut runtime: Runtime) -> Result<()> {
         .load_extension::<GlobalParametersBuffer>()?
         .load_extension::<StarsBuffer>()?
         .load_extension::<TerrainBuffer>()?
+        .load_extension::<TerrainElevation>()?
+        .load_extension::<VectorMap>()?
         .load_extension::<WorldRenderPass>()?
         .load_extension::<WidgetBuffer>()?
         .load_extension::<UiRenderPass>()?
@@ -289,7 +324,10 @@ fn simulation_main(mut runtime: Runtime) -> Result<()> {
         .load_extension::<Timeline>()?
         .load_extension::<TimeStep>()?
         .load_extension::<CameraSystem>()?
+        .load_extension::<Locations>()?
+        .load_extension::<CameraPath>()?
         .load_extension::<ArcBallSystem>()?
+        .load_extension::<VehicleCameraSystem>()?
         .load_extension::<PitchInceptor>()?
         .load_extension::<RollInceptor>()?
         .load_extension::<YawInceptor>()?
@@ -300,6 +338,11 @@ fn simulation_main(mut runtime: Runtime) -> Result<()> {
         .load_extension::<GearEffector>()?
         .load_extension::<HookEffector>()?;
 
+    // User binding profiles are layered over the defaults set up above.
+    runtime
+        .resource_mut::<EventMapper>()
+        .use_profile_file(&bindings_path)?;
+
     // We need at least one entity with a camera controller for the screen camera
     // before the sim is fully ready to run.
     let _player_ent = runtime
@@ -308,6 +351,10 @@ fn simulation_main(mut runtime: Runtime) -> Result<()> {
         .insert_named(ArcBallController::default())?
         .insert(ScreenCameraController::default())
         .id();
+    runtime
+        .spawn_named("free_camera")?
+        .insert(WorldSpaceFrame::default())
+        .insert_named(FreeCamera::default())?;
 
     runtime.run_startup();
     while runtime.resource::<ExitRequest>().still_running() {